@@ -1,28 +1,49 @@
 use std::ops::ControlFlow;
-use std::sync::atomic::Ordering;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use egui::color_picker::Alpha;
 use egui::mutex::RwLock;
+use egui::scroll_area::ScrollBarVisibility;
 use egui::special_emojis::GITHUB;
-use egui::{Color32, Key, OpenUrl, Pos2, Rect, RichText, ScrollArea, Sense, Ui, Vec2, Window};
+use egui::{
+    pos2, Align, Align2, Color32, CursorIcon, FontId, Key, Label, Modifiers, OpenUrl, Pos2, Rect,
+    RichText, ScrollArea, Sense, TextEdit, TextStyle, Ui, UiBuilder, Vec2, Window,
+};
 use egui_extras::{Column, TableBuilder};
-use ewebsock::{WsEvent, WsMessage, WsReceiver};
-use log::{error, trace};
+use ewebsock::{WsEvent, WsMessage, WsReceiver, WsSender};
+use log::{error, warn};
 use serde_json::Deserializer;
+use xls_protocol::address::{self, cell_name, column_letters};
+use xls_protocol::{Stats, StatsUpdate, Viewport, FIRST_SHEET};
 
-use crate::cell_cache::{Cell, CellCache, Loader};
+use crate::appearance::Appearance;
+use crate::cell_cache::{update_cells, CellCache, CellContent, Loader};
+use crate::clipboard;
+use crate::column_labels::ColumnLabels;
+use crate::column_statistics::EntireColumns;
+use crate::column_widths::{self, ColumnWidths};
+use crate::comments::{Comments, MAX_COMMENT_CHARS};
+use crate::data_usage;
+use crate::find::Find;
 use crate::http::streaming_request;
+use crate::meta::Deployment;
+use crate::moderation::{self, Moderation};
+use crate::my_edits;
+use crate::pinned::{PinnedRows, MAX_PINNED};
 use crate::reference::ReferenceWindow;
+use crate::renderer::{CellRenderer, Renderers};
+use crate::scratchpad::Scratchpad;
+use crate::selection::{Fill, Selection};
+use crate::settings::Settings;
+use crate::sheets::{SheetView, Sheets, MAX_SHEETS};
+use crate::snippets::Snippets;
+use crate::walkthrough::Walkthrough;
 
-#[derive(serde::Deserialize, Default, Debug, Clone)]
-pub struct Stats {
-    pub filled_total: u64,
-    pub filled_this_hour: u64,
-    pub filled_today: u64,
-    pub filled_this_week: u64,
-    pub currently_active_users: u64,
-}
+/// Text copied to the clipboard, with the top left cell and the raw values of the cells it was
+/// copied from.
+type Copied = (String, (usize, usize), Vec<Vec<String>>);
 
 pub struct SpreadsheetApp {
     focused_row: usize,
@@ -31,12 +52,107 @@ pub struct SpreadsheetApp {
     last_key_time: f64,
     num_cols: usize,
     num_rows: usize,
-    loader: Arc<Loader>,
     ws_receiver: WsReceiver,
+    /// The websocket URL, to connect again when the server asks to.
+    ws_url: String,
+    /// What the deployment allows.
+    deployment: Deployment,
+    loader: Rc<Loader>,
     stats: Arc<RwLock<Stats>>,
+    /// Stops streaming the statistics, `None` while they aren't streamed in the reduced update
+    /// mode.
+    stats_stream: Option<Arc<AtomicBool>>,
+    /// The cells of the active sheet.
     cell_cache: CellCache,
+    sheets: Sheets,
     editing_cell: Option<u64>,
     reference_open: bool,
+    reference: ReferenceWindow,
+    column_labels: ColumnLabels,
+    entire_columns: EntireColumns,
+    column_widths: ColumnWidths,
+    /// The column being renamed with the label typed so far.
+    renaming_column: Option<(u32, String)>,
+    comments: Comments,
+    /// The cell being commented with the comment typed so far.
+    commenting: Option<(u64, String)>,
+    /// The other corner of the selection, `None` if only the focused cell is selected.
+    selection_anchor: Option<(usize, usize)>,
+    /// The cell where the selection was grabbed to move it and the cell it is dragged over.
+    moving: Option<((usize, usize), (usize, usize))>,
+    /// What was copied to the clipboard last, so formulas pasted from it are repointed.
+    copied: Option<Copied>,
+    my_edits_open: bool,
+    /// Dims the cells the user didn't edit in this session.
+    only_my_edits: bool,
+    appearance: Appearance,
+    appearance_open: bool,
+    settings_open: bool,
+    /// Shows the memory taken by the cells and the data received, for debugging.
+    debug_overlay: bool,
+    /// The address typed into the name box, with whether it is invalid.
+    name_box: (String, bool),
+    /// The settings pasted to import them, with why they couldn't be imported.
+    settings_import: (String, Option<String>),
+    /// A row the table scrolls to in the next frame, with where it ends up.
+    scroll_to_row: Option<(usize, Align)>,
+    /// Where the table scrolls to in the next frame after scrolling the row numbers.
+    scroll_offset: Option<f32>,
+    /// Where the table was scrolled to in the last frame, kept by the sheet when the user
+    /// switches to another one.
+    table_offset: f32,
+    renderers: Renderers,
+    walkthrough: Walkthrough,
+    pinned: PinnedRows,
+    walkthrough_open: bool,
+    scratchpad: Scratchpad,
+    scratchpad_open: bool,
+    snippets: Snippets,
+    snippets_open: bool,
+    find: Find,
+    find_open: bool,
+    moderation: Moderation,
+    moderation_open: bool,
+    /// The region shown without anything else when the app is embedded with `?embed=A1:F20`.
+    embed: Option<Selection>,
+}
+
+/// Opens the websocket that streams the cells, the ui is repainted when messages arrive.
+fn connect(ctx: &egui::Context, url: &str) -> (WsSender, WsReceiver) {
+    let ctx = ctx.clone();
+    let wakeup = move || ctx.request_repaint();
+    ewebsock::connect_with_wakeup(url, Default::default(), wakeup).unwrap()
+}
+
+/// Streams the statistics from the server into `stats`, until the returned flag is set.
+fn stream_stats(ctx: &egui::Context, stats: &Arc<RwLock<Stats>>) -> Arc<AtomicBool> {
+    let server = CellCache::API_HOST.unwrap_or("http://localhost:3000");
+    let stop = Arc::new(AtomicBool::new(false));
+    let egui_ctx = ctx.clone();
+    let stats = stats.clone();
+    let stopped = stop.clone();
+    let handle_chunk = Arc::new(move |current_chunk: String| {
+        if stopped.load(Ordering::Relaxed) {
+            return ControlFlow::Break(());
+        }
+        data_usage::record_stats(current_chunk.len());
+        let stream = Deserializer::from_str(&current_chunk).into_iter::<StatsUpdate>();
+        for maybe_value in stream {
+            match maybe_value {
+                Ok(update) => {
+                    stats.write().apply(&update);
+                }
+                Err(err) => {
+                    error!("an error occurred while reading stats: {err}");
+                    return ControlFlow::Break(());
+                }
+            }
+        }
+        egui_ctx.request_repaint();
+        ControlFlow::Continue(())
+    });
+    streaming_request(format!("{}/api/stats", server), handle_chunk);
+    stop
 }
 
 pub fn is_mobile(ctx: &egui::Context) -> bool {
@@ -45,49 +161,56 @@ pub fn is_mobile(ctx: &egui::Context) -> bool {
 }
 
 impl SpreadsheetApp {
-    const DEFAULT_COLS: usize = 26;
+    const DEFAULT_COLS: usize = address::COLUMNS as usize;
     const DEFAULT_ROWS: usize = 40_000_000; // 26*40_000_000 = 1_040_000_000 cells
     const DEFAULT_ROW_HEIGHT: f32 = 18.0;
+    /// Most rows of an embedded region, so it fits in the cache.
+    const MAX_EMBED_ROWS: usize = 100;
 
     /// Called once before the first frame.
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         // This is also where you can customize the look and feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
         egui_extras::install_image_loaders(&cc.egui_ctx);
+        let appearance = Appearance::load();
+        appearance.apply_theme(&cc.egui_ctx);
         let server = CellCache::API_HOST.unwrap_or("http://localhost:3000");
 
-        // Refresh stats
+        // Refresh stats, unless the user saves data
+        data_usage::load();
         let stats = Arc::new(RwLock::new(Stats::default()));
-        {
-            let egui_ctx = cc.egui_ctx.clone();
-            let stats = stats.clone();
-            let handle_chunk = Arc::new(move |current_chunk: String| {
-                let stream = Deserializer::from_str(&current_chunk).into_iter::<Stats>();
-                for maybe_value in stream {
-                    match maybe_value {
-                        Ok(value) => {
-                            *stats.write() = value;
-                        }
-                        Err(err) => {
-                            error!("an error occurred while reading stats: {err}");
-                            return ControlFlow::Break(());
-                        }
-                    }
-                }
-                egui_ctx.request_repaint();
-                ControlFlow::Continue(())
-            });
-            streaming_request(format!("{}/api/stats", server), handle_chunk);
-        }
+        let stats_stream = (!data_usage::reduced()).then(|| stream_stats(&cc.egui_ctx, &stats));
 
         // Change stream connection
-        let (ws_sender, ws_receiver) = {
-            let egui_ctx = cc.egui_ctx.clone();
-            let wakeup = move || egui_ctx.request_repaint();
-            let url = format!("{}/api/spreadsheet", server);
-            ewebsock::connect_with_wakeup(&url, Default::default(), wakeup).unwrap()
+        // Private deployments require the token the page was opened with.
+        let ws_url = match query_param(cc, "token") {
+            Some(token) => format!("{server}/api/spreadsheet?token={token}"),
+            None => format!("{server}/api/spreadsheet"),
         };
-        let loader = Arc::new(Loader::new(ws_sender));
+        let (ws_sender, ws_receiver) = connect(&cc.egui_ctx, &ws_url);
+        let loader = Rc::new(Loader::new(ws_sender));
+        let mut cell_cache = CellCache::new(
+            loader.clone(),
+            FIRST_SHEET,
+            Self::DEFAULT_COLS,
+            Self::DEFAULT_ROWS,
+        );
+        let pinned = PinnedRows::load();
+        cell_cache.pin(pinned.ids(Self::DEFAULT_COLS));
+
+        let embed = query_param(cc, "embed")
+            .and_then(|address| Selection::parse(&address))
+            .map(|region| {
+                let clamp = |range: &std::ops::RangeInclusive<usize>, max: usize| {
+                    (*range.start()).min(max - 1)..=(*range.end()).min(max - 1)
+                };
+                let rows = clamp(&region.rows, Self::DEFAULT_ROWS);
+                let end = (*rows.end()).min(rows.start() + Self::MAX_EMBED_ROWS - 1);
+                Selection {
+                    rows: *rows.start()..=end,
+                    cols: clamp(&region.cols, Self::DEFAULT_COLS),
+                }
+            });
 
         SpreadsheetApp {
             focused_row: 0,
@@ -97,11 +220,616 @@ impl SpreadsheetApp {
             num_cols: Self::DEFAULT_COLS,
             num_rows: Self::DEFAULT_ROWS,
             stats,
-            loader: loader.clone(),
+            stats_stream,
             ws_receiver,
-            cell_cache: CellCache::new(loader, Self::DEFAULT_COLS, Self::DEFAULT_ROWS),
+            ws_url,
+            deployment: Deployment::load(server, cc.egui_ctx.clone()),
+            loader: loader.clone(),
+            cell_cache,
+            sheets: Sheets::default(),
             editing_cell: None,
             reference_open: false,
+            reference: ReferenceWindow::default(),
+            column_labels: ColumnLabels::load(server, cc.egui_ctx.clone()),
+            entire_columns: EntireColumns::new(server),
+            column_widths: ColumnWidths::load(),
+            renaming_column: None,
+            comments: Comments::new(server, (Self::DEFAULT_COLS * Self::DEFAULT_ROWS) as u64),
+            commenting: None,
+            selection_anchor: None,
+            copied: None,
+            moving: None,
+            my_edits_open: false,
+            only_my_edits: false,
+            appearance,
+            appearance_open: false,
+            settings_open: false,
+            debug_overlay: false,
+            name_box: (String::new(), false),
+            settings_import: (String::new(), None),
+            scroll_to_row: None,
+            scroll_offset: None,
+            table_offset: 0.0,
+            renderers: Renderers::default(),
+            walkthrough: Walkthrough::default(),
+            pinned,
+            walkthrough_open: false,
+            scratchpad: Scratchpad::default(),
+            scratchpad_open: false,
+            snippets: Snippets::load(),
+            snippets_open: false,
+            find: Find::default(),
+            find_open: false,
+            moderation: Moderation::new(server, (Self::DEFAULT_COLS * Self::DEFAULT_ROWS) as u64),
+            moderation_open: false,
+            embed,
+        }
+    }
+
+    /// Shows only the cells of `region`, updated live but not editable, for embedding the
+    /// sheet in other sites.
+    fn embed_ui(&mut self, ctx: &egui::Context, region: &Selection) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.appearance.apply(ui);
+            let row_height = self.appearance.row_height(ui, Self::DEFAULT_ROW_HEIGHT);
+            TableBuilder::new(ui)
+                .striped(self.appearance.striped)
+                .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+                .columns(Column::remainder().clip(true), region.cols.clone().count())
+                .body(|body| {
+                    body.rows(row_height, region.rows.clone().count(), |mut row| {
+                        let row_index = region.rows.start() + row.index();
+                        for col_index in region.cols.clone() {
+                            let id = address::id(row_index as i64, col_index as i64) as u64;
+                            let cell = self.cell_cache.get(id);
+                            row.col(|ui| {
+                                let rect = ui.available_rect_before_wrap();
+                                ui.painter().rect_filled(rect, 0.0, cell.background_color());
+                                let padding = Vec2::new(self.appearance.cell_padding, 0.0);
+                                ui.scope_builder(
+                                    UiBuilder::new()
+                                        .max_rect(rect.shrink2(padding))
+                                        .layout(*ui.layout()),
+                                    |ui| cell.ui(ui, &self.renderers),
+                                );
+                                if self.appearance.grid_lines {
+                                    ui.painter().rect_stroke(
+                                        rect,
+                                        0.0,
+                                        ui.visuals().widgets.noninteractive.bg_stroke,
+                                    );
+                                }
+                            });
+                        }
+                    });
+                });
+        });
+    }
+
+    /// Draws the cells `renderer` matches with it instead of the default label, renderers
+    /// registered first take precedence.
+    pub fn register_renderer(&mut self, renderer: impl CellRenderer + 'static) {
+        self.renderers.register(renderer);
+    }
+
+    /// Lists the cells the user edited in this session, clicking one jumps to it.
+    fn my_edits_panel(&mut self, ctx: &egui::Context) {
+        if !self.my_edits_open {
+            return;
+        }
+        egui::SidePanel::right("my_edits").show(ctx, |ui| {
+            ui.heading("My Edits");
+            ui.checkbox(&mut self.only_my_edits, "Dim the cells I didn't edit");
+            let edits = my_edits::recent();
+            if edits.is_empty() {
+                ui.weak("The cells you edit are marked with a dot and listed here.");
+            }
+            ScrollArea::vertical().show(ui, |ui| {
+                for id in edits {
+                    let cell = self.cell_cache.get(id);
+                    let text = format!("{}  {}", cell_name(id as i64), cell);
+                    if ui
+                        .add(Label::new(text).truncate().sense(Sense::click()))
+                        .clicked()
+                    {
+                        self.go_to_cell(id);
+                    }
+                }
+            });
+        });
+    }
+
+    /// Focuses a cell and scrolls to it.
+    fn go_to_cell(&mut self, id: u64) {
+        let (row, col) = address::position(id as i64);
+        self.focused_row = row as usize;
+        self.focused_col = col as usize;
+        self.selection_anchor = None;
+        self.scroll_to_row = Some((self.focused_row, Align::Center));
+    }
+
+    /// Finds cells containing a text, opened with Ctrl+F.
+    fn find_window(&mut self, ctx: &egui::Context) {
+        if ctx.input_mut(|i| i.consume_key(Modifiers::COMMAND, Key::F)) {
+            self.find_open = true;
+            self.find.focus();
+        }
+        let focused = address::id(self.focused_row as i64, self.focused_col as i64) as u64;
+        let mut found = None;
+        Window::new("Find")
+            .open(&mut self.find_open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                found = self.find.ui(ui, &self.cell_cache, focused);
+            });
+        if let Some(id) = found {
+            self.go_to_cell(id);
+            // The Enter that went to the cell doesn't move the focus down.
+            self.last_key_time = ctx.input(|i| i.time);
+        }
+    }
+
+    /// Lets the user copy the personal settings and import copied ones, e.g. from another
+    /// browser.
+    fn settings_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.settings_open;
+        Window::new("Settings")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(
+                    "Your appearance settings, column labels, pinned rows and snippets are stored \
+                     in this browser.",
+                );
+                if ui.button("📋 Copy Settings").clicked() {
+                    let settings = Settings {
+                        appearance: self.appearance.clone(),
+                        column_labels: self.column_labels.personal().clone(),
+                        pinned_rows: self.pinned.rows().to_vec(),
+                        snippets: Some(self.snippets.snippets().to_vec()),
+                    };
+                    ctx.copy_text(settings.to_json());
+                }
+                ui.separator();
+                ui.label("Paste copied settings to use them here:");
+                let (json, error) = &mut self.settings_import;
+                ui.add(
+                    egui::TextEdit::multiline(json)
+                        .code_editor()
+                        .desired_rows(4),
+                );
+                if let Some(error) = error {
+                    ui.colored_label(ui.visuals().error_fg_color, error.as_str());
+                }
+                if ui
+                    .add_enabled(!json.trim().is_empty(), egui::Button::new("Import"))
+                    .clicked()
+                {
+                    match Settings::from_json(json) {
+                        Ok(settings) => {
+                            self.appearance.replace(ctx, settings.appearance);
+                            self.column_labels.set_personal(settings.column_labels);
+                            self.pinned.replace(PinnedRows::from(settings.pinned_rows));
+                            self.cell_cache.pin(self.pinned.ids(self.num_cols));
+                            if let Some(snippets) = settings.snippets {
+                                self.snippets.replace(snippets);
+                            }
+                            self.settings_import = (String::new(), None);
+                        }
+                        Err(e) => *error = Some(e),
+                    }
+                }
+                ui.separator();
+                ui.checkbox(&mut self.debug_overlay, "Show debug overlay");
+            });
+        self.settings_open = open;
+    }
+
+    /// Shows the memory taken by the cells in the corner of the window.
+    fn debug_overlay(&self, ctx: &egui::Context) {
+        if !self.debug_overlay {
+            return;
+        }
+        let (cells, bytes) = self.cell_cache.memory_usage();
+        let (websocket, stats) = data_usage::received();
+        egui::Area::new(egui::Id::new("debug_overlay"))
+            .anchor(Align2::RIGHT_BOTTOM, Vec2::new(-8.0, -8.0))
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.monospace(format!(
+                        "Cells: {cells} in {} of {}",
+                        data_usage::format_bytes(bytes as u64),
+                        data_usage::format_bytes(CellCache::MEMORY_BUDGET as u64),
+                    ));
+                    ui.monospace(format!(
+                        "Received: {}",
+                        data_usage::format_bytes(websocket + stats)
+                    ));
+                });
+            });
+    }
+
+    /// Shows the address of the selection, typing a cell like `Z1500000` or a range like `A1:C3`
+    /// and Enter goes there. Ctrl+G focuses it.
+    fn name_box(&mut self, ui: &mut Ui, selection: &Selection) {
+        let id = ui.make_persistent_id("name_box");
+        if ui.input_mut(|i| i.consume_key(Modifiers::COMMAND, Key::G)) {
+            ui.memory_mut(|memory| memory.request_focus(id));
+        }
+        let (address, invalid) = &mut self.name_box;
+        if !ui.memory(|memory| memory.has_focus(id)) {
+            *address = selection.address();
+            *invalid = false;
+        }
+        let mut name_box = TextEdit::singleline(address)
+            .id(id)
+            .font(TextStyle::Monospace)
+            .desired_width(120.0);
+        if *invalid {
+            name_box = name_box.text_color(ui.visuals().error_fg_color);
+        }
+        let response = ui
+            .add(name_box)
+            .on_hover_text("Type a cell like Z1500000 or a range like A1:C3 to go there (Ctrl+G)");
+        if response.changed() {
+            *invalid = false;
+        }
+        if response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+            let address = address.clone();
+            if !self.go_to(&address, ui.input(|i| i.time)) {
+                self.name_box.1 = true;
+                response.request_focus();
+            }
+        }
+    }
+
+    /// Focuses a cell like `Z1500000` or selects a range like `A1:C3` and scrolls to it, returns
+    /// `false` if the address isn't in the sheet.
+    fn go_to(&mut self, address: &str, now: f64) -> bool {
+        let Some(selection) = Selection::parse(address) else {
+            return false;
+        };
+        if *selection.rows.end() >= self.num_rows || *selection.cols.end() >= self.num_cols {
+            return false;
+        }
+        (self.focused_row, self.focused_col) = selection.top_left();
+        self.selection_anchor =
+            (!selection.is_single_cell()).then_some((*selection.rows.end(), *selection.cols.end()));
+        self.scroll_to_row = Some((self.focused_row, Align::Center));
+        // The Enter that confirmed the address doesn't move the focus down.
+        self.last_key_time = now;
+        true
+    }
+
+    /// Opens a new connection to the server, the cells are requested again once it is open.
+    fn reconnect(&mut self, ctx: &egui::Context) {
+        let (ws_sender, ws_receiver) = connect(ctx, &self.ws_url);
+        self.loader.replace_sender(ws_sender);
+        self.ws_receiver = ws_receiver;
+    }
+
+    /// Shows the part of the sheet the presenter of a walkthrough is looking at.
+    fn follow(&mut self, viewport: Viewport) {
+        let (row, col) = address::position(viewport.focused as i64);
+        self.focused_row = row as usize;
+        self.focused_col = col as usize;
+        self.selection_anchor = None;
+        self.scroll_to_row = Some((viewport.top_row as usize, Align::Min));
+    }
+
+    /// Lets the user pin the selected rows, jump to pinned rows and unpin them.
+    fn pinned_menu(&mut self, ui: &mut Ui) {
+        let rows = self.selection().rows;
+        let full = self.pinned.rows().len() >= MAX_PINNED;
+        let pin = ui
+            .add_enabled(
+                !full,
+                egui::Button::new(format!("Pin rows {}–{}", rows.start(), rows.end())),
+            )
+            .on_hover_text("Keep the selected rows up to date wherever you scroll")
+            .on_disabled_hover_text(format!("At most {MAX_PINNED} regions can be pinned"));
+        if pin.clicked() && self.pinned.pin(rows) {
+            self.cell_cache.pin(self.pinned.ids(self.num_cols));
+            ui.close_menu();
+        }
+        let mut unpin = None;
+        for (index, rows) in self.pinned.rows().iter().enumerate() {
+            ui.horizontal(|ui| {
+                if ui
+                    .button(format!("Rows {}–{}", rows.start(), rows.end()))
+                    .clicked()
+                {
+                    self.focused_row = *rows.start();
+                    self.selection_anchor = None;
+                    self.scroll_to_row = Some((*rows.start(), Align::Min));
+                    ui.close_menu();
+                }
+                if ui.small_button("✖").on_hover_text("Unpin").clicked() {
+                    unpin = Some(index);
+                }
+            });
+        }
+        if let Some(index) = unpin {
+            self.pinned.unpin(index);
+            self.cell_cache.pin(self.pinned.ids(self.num_cols));
+        }
+    }
+
+    /// Shows the data received from the server and lets the user switch to reduced updates.
+    fn data_usage_menu(&mut self, ui: &mut Ui) {
+        let (websocket, stats) = data_usage::received();
+        let total = data_usage::format_bytes(websocket + stats);
+        let label = match data_usage::reduced() {
+            true => format!("⬇ {total} (reduced)"),
+            false => format!("⬇ {total}"),
+        };
+        ui.menu_button(label, |ui| {
+            ui.label(format!("Cells: {}", data_usage::format_bytes(websocket)));
+            ui.label(format!("Statistics: {}", data_usage::format_bytes(stats)));
+            let mut reduced = data_usage::reduced();
+            let toggled = ui
+                .checkbox(&mut reduced, "Reduced updates")
+                .on_hover_text(
+                    "Pauses the statistics and fetches cells less eagerly while scrolling, \
+                     for metered connections",
+                )
+                .changed();
+            if toggled {
+                data_usage::set_reduced(reduced);
+                match self.stats_stream.take() {
+                    Some(stop) => stop.store(true, Ordering::Relaxed),
+                    None => self.stats_stream = Some(stream_stats(ui.ctx(), &self.stats)),
+                }
+            }
+        });
+    }
+
+    fn selection(&self) -> Selection {
+        let focus = (self.focused_row, self.focused_col);
+        Selection::spanning(self.selection_anchor.unwrap_or(focus), focus)
+    }
+
+    /// Copies the first cells of the selection into the rest of it. Like in desktop spreadsheets a
+    /// single cell is filled from the cell above (or left of) it.
+    fn fill_selection(&mut self, fill: Fill) {
+        let mut selection = self.selection();
+        if selection.is_single_cell() {
+            let (row, col) = selection.top_left();
+            selection = match fill {
+                Fill::Down => Selection::spanning((row.saturating_sub(1), col), (row, col)),
+                Fill::Right => Selection::spanning((row, col.saturating_sub(1)), (row, col)),
+            };
+        }
+        self.cell_cache.fill(&selection, fill, self.num_cols);
+    }
+
+    /// Copies the selection to the clipboard and pastes the clipboard at the focused cell, unless
+    /// a cell is edited or another text field has the focus.
+    fn clipboard(&mut self, ctx: &egui::Context) {
+        let text_focused = ctx
+            .memory(|memory| memory.focused())
+            .is_some_and(|id| egui::TextEdit::load_state(ctx, id).is_some());
+        if self.editing_cell.is_some() || text_focused {
+            return;
+        }
+        for event in ctx.input(|i| i.events.clone()) {
+            match event {
+                egui::Event::Copy => ctx.copy_text(self.copy_selection()),
+                egui::Event::Paste(text) => self.paste(&text),
+                _ => {}
+            }
+        }
+    }
+
+    /// The values of the selected cells as tab-separated values.
+    fn copy_selection(&mut self) -> String {
+        let selection = self.selection();
+        let cells: Vec<Vec<_>> = selection
+            .rows
+            .clone()
+            .map(|row| {
+                selection
+                    .cols
+                    .clone()
+                    .map(|col| {
+                        let id = address::id(row as i64, col as i64) as u64;
+                        self.cell_cache.get(id)
+                    })
+                    .collect()
+            })
+            .collect();
+        let values = |value: fn(&CellContent) -> String| -> Vec<Vec<String>> {
+            cells
+                .iter()
+                .map(|row| row.iter().map(|cell| value(cell)).collect())
+                .collect()
+        };
+        let tsv = clipboard::to_tsv(&values(|cell| cell.to_string()));
+        let raw_values = values(|cell| cell.write_buffer.read().clone());
+        self.copied = Some((tsv.clone(), selection.top_left(), raw_values));
+        tsv
+    }
+
+    /// Saves pasted tab-separated values into the cells starting at the focused cell and selects
+    /// them, values that don't fit into the sheet are dropped. Cells copied within the sheet are
+    /// pasted with their formulas, whose references move along with them.
+    fn paste(&mut self, text: &str) {
+        let top_left = (self.focused_row, self.focused_col);
+        let mut bottom_right = top_left;
+        let rows = match &self.copied {
+            Some((copied, (row, col), raw_values)) if copied == text => {
+                let rows = top_left.0 as i64 - *row as i64;
+                let cols = top_left.1 as i64 - *col as i64;
+                raw_values
+                    .iter()
+                    .map(|values| {
+                        values
+                            .iter()
+                            .map(|value| clipboard::repoint(value, rows, cols))
+                            .collect()
+                    })
+                    .collect()
+            }
+            _ => clipboard::parse_tsv(text),
+        };
+        for (row_offset, values) in rows.into_iter().enumerate() {
+            for (col_offset, value) in values.into_iter().enumerate() {
+                let (row, col) = (top_left.0 + row_offset, top_left.1 + col_offset);
+                if row >= self.num_rows || col >= self.num_cols {
+                    continue;
+                }
+                let cell = self
+                    .cell_cache
+                    .get(address::id(row as i64, col as i64) as u64);
+                *cell.write_buffer.write() = value;
+                cell.save();
+                bottom_right = (bottom_right.0.max(row), bottom_right.1.max(col));
+            }
+        }
+        self.selection_anchor = (bottom_right != top_left).then_some(bottom_right);
+    }
+
+    /// Moves the selected cells where they were dropped, the selection follows them.
+    fn drop_selection(&mut self, grabbed: (usize, usize), target: (usize, usize)) {
+        let selection = self.selection();
+        let destination = selection.moved(grabbed, target, self.num_rows, self.num_cols);
+        if destination == selection {
+            return;
+        }
+        self.cell_cache
+            .move_cells(&selection, &destination, self.num_cols);
+        let (from_row, from_col) = selection.top_left();
+        let (to_row, to_col) = destination.top_left();
+        let follow =
+            |(row, col): (usize, usize)| (row - from_row + to_row, col - from_col + to_col);
+        (self.focused_row, self.focused_col) = follow((self.focused_row, self.focused_col));
+        self.selection_anchor = self.selection_anchor.map(follow);
+    }
+
+    /// Lets the user label the column that is being renamed.
+    fn rename_column_window(&mut self, ctx: &egui::Context) {
+        let Some((column, label)) = &mut self.renaming_column else {
+            return;
+        };
+        let mut open = true;
+        let mut done = false;
+        Window::new(format!("Rename Column {}", column_letters(*column as i64)))
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.text_edit_singleline(label);
+                ui.horizontal(|ui| {
+                    let save = ui.button("Save").on_hover_text("Only you see the label");
+                    if save.clicked() {
+                        self.column_labels.set(*column, label);
+                        done = true;
+                    }
+                    let share = ui
+                        .add_enabled(self.deployment.writes_allowed(), egui::Button::new("Share"))
+                        .on_hover_text("Everyone sees the label, share an empty label to remove it")
+                        .on_disabled_hover_text("This spreadsheet is read-only");
+                    if share.clicked() {
+                        self.column_labels.set(*column, "");
+                        self.column_labels.share(*column, label);
+                        done = true;
+                    }
+                    let reset = ui.button("Reset").on_hover_text("Remove your label");
+                    if reset.clicked() {
+                        self.column_labels.set(*column, "");
+                        done = true;
+                    }
+                });
+            });
+        if !open || done {
+            self.renaming_column = None;
+        }
+    }
+
+    /// The tabs to switch between the sheets and add new ones.
+    fn sheet_tabs(&mut self, ctx: &egui::Context) {
+        // The backends keep the first sheet only for now.
+        if !self.deployment.experimental("sheets") {
+            return;
+        }
+        let mut selected = None;
+        egui::TopBottomPanel::bottom("sheet_tabs").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for (index, name) in self.sheets.names().enumerate() {
+                    if ui
+                        .selectable_label(index == self.sheets.active(), name)
+                        .clicked()
+                    {
+                        selected = Some(index);
+                    }
+                }
+                let add = ui
+                    .add_enabled(
+                        self.sheets.names().count() < MAX_SHEETS,
+                        egui::Button::new("+"),
+                    )
+                    .on_hover_text("Add a sheet")
+                    .on_disabled_hover_text(format!("At most {MAX_SHEETS} sheets can be added"));
+                if add.clicked() {
+                    selected = self
+                        .sheets
+                        .add(self.loader.clone(), self.num_cols, self.num_rows);
+                }
+            });
+        });
+        if let Some(index) = selected {
+            self.switch_sheet(index);
+        }
+    }
+
+    /// Shows sheet `index` where the user left it.
+    fn switch_sheet(&mut self, index: usize) {
+        let view = SheetView {
+            scroll_offset: self.table_offset,
+            focused: (self.focused_row, self.focused_col),
+        };
+        let Some(view) = self.sheets.switch(index, &mut self.cell_cache, view) else {
+            return;
+        };
+        (self.focused_row, self.focused_col) = view.focused;
+        self.scroll_offset = Some(view.scroll_offset);
+        self.editing_cell = None;
+        self.selection_anchor = None;
+        self.moving = None;
+    }
+
+    /// Lets the user attach a comment to the cell being commented.
+    fn comment_window(&mut self, ctx: &egui::Context) {
+        let Some((id, comment)) = &mut self.commenting else {
+            return;
+        };
+        let mut open = true;
+        let mut done = false;
+        Window::new(format!("Comment on {}", cell_name(*id as i64)))
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.add(
+                    TextEdit::multiline(comment)
+                        .char_limit(MAX_COMMENT_CHARS)
+                        .desired_rows(3),
+                );
+                ui.horizontal(|ui| {
+                    let save = ui.button("Save").on_hover_text("Everyone sees the comment");
+                    if save.clicked() {
+                        self.comments.comment(*id, comment);
+                        done = true;
+                    }
+                    if ui.button("Remove").clicked() {
+                        self.comments.comment(*id, "");
+                        done = true;
+                    }
+                });
+            });
+        if !open || done {
+            self.commenting = None;
         }
     }
 }
@@ -109,31 +837,49 @@ impl SpreadsheetApp {
 impl eframe::App for SpreadsheetApp {
     /// Called each time the UI needs repainting, which may be many times per second.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.num_rows = (self.deployment.rows() as usize).min(Self::DEFAULT_ROWS);
         while let Some(event) = self.ws_receiver.try_recv() {
-            match event {
-                WsEvent::Message(WsMessage::Text(update)) => {
-                    let parsed = serde_json::from_str::<Cell>(&update);
-                    match parsed {
-                        Ok(cell) => {
-                            self.cell_cache.set(cell.id, cell.into());
-                        }
-                        Err(e) => {
-                            trace!("error parsing cell update: {:?} {:?}", update, e);
-                        }
-                    }
-                }
-                WsEvent::Opened => {
-                    self.loader.is_open.store(true, Ordering::Relaxed);
-                    self.loader.fetch(0..2600);
+            match &event {
+                WsEvent::Message(WsMessage::Text(message)) => {
+                    data_usage::record_websocket(message.len())
                 }
-                WsEvent::Closed => {
-                    self.loader.is_open.store(false, Ordering::Relaxed);
+                WsEvent::Message(WsMessage::Binary(message)) => {
+                    data_usage::record_websocket(message.len())
                 }
-                _ => {
-                    error!("unexpected event: {:?}", event);
+                _ => {}
+            }
+            if let WsEvent::Message(WsMessage::Text(message)) = &event {
+                if let Some(viewport) = self.walkthrough.followed(message) {
+                    self.follow(viewport);
+                    continue;
                 }
             }
+            let opened = matches!(event, WsEvent::Opened);
+            let closed = matches!(event, WsEvent::Closed);
+            self.cell_cache.handle_event(event);
+            if opened {
+                self.walkthrough.reconnected(&self.loader);
+            }
+            if closed && self.cell_cache.reconnects() {
+                self.reconnect(ctx);
+            }
         }
+        self.cell_cache.swap_staged();
+        if self.cell_cache.connection_stale(ctx.input(|i| i.time)) {
+            warn!("no heartbeat from the server, reconnecting");
+            self.reconnect(ctx);
+        }
+        if let Some(interval) = self.cell_cache.heartbeat_interval() {
+            // Notices a stale connection even if nothing else repaints the ui.
+            ctx.request_repaint_after(interval);
+        }
+        self.cell_cache.show_pending_edits();
+        if let Some(region) = self.embed.clone() {
+            self.embed_ui(ctx, &region);
+            return;
+        }
+        self.clipboard(ctx);
+        self.find_window(ctx);
 
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
@@ -163,18 +909,113 @@ impl eframe::App for SpreadsheetApp {
                     Window::new("Formula Reference")
                         .open(&mut self.reference_open)
                         .show(ctx, |ui| {
-                            let mut rw = ReferenceWindow {};
-                            rw.ui(ui);
+                            self.reference.ui(ui);
                         });
                     if ui.button("？ Help").clicked() {
                         self.reference_open = true;
                     }
+                    ui.toggle_value(&mut self.my_edits_open, "✏ My Edits");
+                    ui.menu_button("📌 Pinned", |ui| self.pinned_menu(ui));
+                    Window::new("Appearance")
+                        .open(&mut self.appearance_open)
+                        .resizable(false)
+                        .show(ctx, |ui| {
+                            self.appearance.ui(ui);
+                        });
+                    if ui.button("🎨 Appearance").clicked() {
+                        self.appearance_open = true;
+                    }
+                    Window::new("Live Walkthrough")
+                        .open(&mut self.walkthrough_open)
+                        .resizable(false)
+                        .show(ctx, |ui| {
+                            self.walkthrough.ui(ui, &self.loader);
+                        });
+                    if ui.button("🎥 Walkthrough").clicked() {
+                        self.walkthrough_open = true;
+                    }
+                    let mut publish = false;
+                    Window::new("Scratchpad")
+                        .open(&mut self.scratchpad_open)
+                        .resizable(false)
+                        .show(ctx, |ui| {
+                            let target = (self.focused_row, self.focused_col);
+                            publish = self.scratchpad.ui(ui, target);
+                        });
+                    if publish && self.deployment.writes_allowed() {
+                        update_cells(self.scratchpad.publish_updates(
+                            (self.focused_row, self.focused_col),
+                            self.num_rows,
+                            self.num_cols,
+                            self.cell_cache.sheet(),
+                        ));
+                    }
+                    if ui.button("🧪 Scratchpad").clicked() {
+                        self.scratchpad_open = true;
+                    }
+                    Window::new("Snippets")
+                        .open(&mut self.snippets_open)
+                        .show(ctx, |ui| {
+                            self.snippets.ui(ui);
+                        });
+                    if ui.button("🔍 Find").clicked() {
+                        self.find_open = true;
+                        self.find.focus();
+                    }
+                    ui.menu_button("✂ Snippets", |ui| {
+                        if self.snippets.menu(ui) {
+                            self.snippets_open = true;
+                        }
+                    });
+                    let focused = address::id(self.focused_row as i64, self.focused_col as i64);
+                    let regions = self.selection().regions(self.num_cols);
+                    Window::new("Moderation")
+                        .open(&mut self.moderation_open)
+                        .resizable(false)
+                        .show(ctx, |ui| {
+                            self.moderation.ui(ui, focused as u64, regions);
+                        });
+                    if ui.button("🛡 Moderation").clicked() {
+                        self.moderation_open = true;
+                    }
+                    if ui.button("⚙ Settings").clicked() {
+                        self.settings_open = true;
+                    }
+                    let mut high_contrast = self.appearance.high_contrast;
+                    if ui
+                        .toggle_value(&mut high_contrast, "◑ High Contrast")
+                        .changed()
+                    {
+                        self.appearance.set_high_contrast(ctx, high_contrast);
+                    }
+                    self.data_usage_menu(ui);
                 });
             });
         });
 
+        self.rename_column_window(ctx);
+        self.comment_window(ctx);
+        self.settings_window(ctx);
+        self.sheet_tabs(ctx);
+        self.my_edits_panel(ctx);
+        self.debug_overlay(ctx);
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading(RichText::new("Billion Cell Spreadsheet").strong());
+            if let Some(error) = self.cell_cache.connection_error() {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            } else if self.deployment.auth_required() && !self.ws_url.contains("?token=") {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    "This spreadsheet is private, open it with a link that contains a valid token.",
+                );
+            }
+            if !self.deployment.writes_allowed() {
+                ui.weak("This spreadsheet is read-only");
+            }
+            if self.stats_stream.is_none() {
+                ui.weak("Statistics are paused to save data");
+            }
             ui.add_space(20.0);
 
             fn active_users(ui: &mut Ui, stats: &Stats) {
@@ -348,57 +1189,186 @@ impl eframe::App for SpreadsheetApp {
                     style.spacing.item_spacing = original_spacing;
                 });
 
-                let id = self.focused_row as u64 * self.num_cols as u64 + self.focused_col as u64;
+                let id = address::id(self.focused_row as i64, self.focused_col as i64) as u64;
                 let cell = self.cell_cache.get(id);
-                let color_response = egui::widgets::color_picker::color_edit_button_srgba(
-                    ui,
-                    &mut self.bg_color_picked,
-                    Alpha::BlendOrAdditive,
-                );
+                let writes_allowed = self.deployment.writes_allowed();
+                let color_response = ui
+                    .add_enabled_ui(writes_allowed, |ui| {
+                        egui::widgets::color_picker::color_edit_button_srgba(
+                            ui,
+                            &mut self.bg_color_picked,
+                            Alpha::BlendOrAdditive,
+                        )
+                    })
+                    .inner;
                 if color_response.changed() {
                     cell.set_background(self.bg_color_picked);
                 }
-            });
 
-            ScrollArea::horizontal().show(ui, |ui| {
-                TableBuilder::new(ui)
-                    .striped(true)
-                    .resizable(true)
-                    .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
-                    .column(Column::remainder())
-                    .columns(Column::initial(100.0).at_least(25.0).resizable(true).clip(true), self.num_cols)
-                    .header(Self::DEFAULT_ROW_HEIGHT + 3.0, |mut header| {
-                        let col_idx_to_label = |idx: usize| {
-                            if idx < 26 {
-                                format!("{}", (b'A' + idx as u8) as char)
-                            } else {
-                                format!(
-                                    "{}{}",
-                                    (b'A' + (idx / 26 - 1) as u8) as char,
-                                    (b'A' + (idx % 26) as u8) as char
-                                )
-                            }
-                        };
+                ui.horizontal(|ui| {
+                    if !writes_allowed {
+                        ui.disable();
+                    }
+                    let mut style = cell.style();
+                    let mut changed = ui
+                        .toggle_value(&mut style.bold, RichText::new("B").strong())
+                        .on_hover_text("Bold")
+                        .changed();
+                    changed |= ui
+                        .toggle_value(&mut style.italic, RichText::new("I").italics())
+                        .on_hover_text("Italic")
+                        .changed();
+                    let mut text_color = cell.text_color().unwrap_or(ui.visuals().text_color());
+                    let color_response = egui::widgets::color_picker::color_edit_button_srgba(
+                        ui,
+                        &mut text_color,
+                        Alpha::Opaque,
+                    );
+                    if color_response.on_hover_text("Text color").changed() {
+                        style.color = Some(i32::from_le_bytes(text_color.to_array()));
+                        changed = true;
+                    }
+                    if style.color.is_some() && ui.small_button("Default color").clicked() {
+                        style.color = None;
+                        changed = true;
+                    }
+                    if changed {
+                        cell.set_style(style);
+                    }
+                });
 
-                        header.col(|ui| {
-                            ui.strong("");
-                        });
+                let selection = self.selection();
+                ui.horizontal(|ui| {
+                    self.name_box(ui, &selection);
+                    // The letter of the column is in the name box even if the column has a label.
+                    if let Some(label) = self.column_labels.get(self.focused_col as u32) {
+                        if selection.is_single_cell() {
+                            ui.label(RichText::new(format!("({label})")).monospace());
+                        }
+                    }
+                    let auto_sum = ui.add_enabled(writes_allowed, egui::Button::new("Σ")).on_hover_text(
+                        "Sum up the selected cells, or the numbers above the focused cell into it",
+                    );
+                    if auto_sum.clicked() {
+                        self.cell_cache.auto_sum(&selection, self.num_cols);
+                    }
+                });
+            });
 
+            // The first row that is visible.
+            let mut top_row: Option<usize> = None;
+            // The row numbers stay put when scrolling horizontally. The cells are laid out first,
+            // so the row numbers follow their vertical scrolling in the same frame.
+            let area = ui.available_rect_before_wrap();
+            let mut numbers_ui = ui.new_child(UiBuilder::new().max_rect(area));
+            self.appearance.apply(&mut numbers_ui);
+            let row_numbers_width = numbers_ui.fonts(|fonts| {
+                let widest = (self.num_rows - 1).to_string();
+                let font = TextStyle::Body.resolve(numbers_ui.style());
+                fonts.layout_no_wrap(widest, font, Color32::WHITE).size().x
+            }) + 2.0 * numbers_ui.spacing().item_spacing.x;
+            let (_, cells) = area.split_left_right_at_x(area.left() + row_numbers_width);
+            let mut cells_ui = ui.new_child(UiBuilder::new().max_rect(cells));
+            ui.advance_cursor_after_rect(area);
+            let scrolled = ScrollArea::horizontal().show(&mut cells_ui, |ui| {
+                self.appearance.apply(ui);
+                let row_height = self.appearance.row_height(ui, Self::DEFAULT_ROW_HEIGHT);
+                let mut table = TableBuilder::new(ui)
+                    .striped(self.appearance.striped)
+                    .resizable(true)
+                    .cell_layout(egui::Layout::left_to_right(egui::Align::Center));
+                for col_index in 0..self.num_cols {
+                    let width = self.column_widths.get(col_index as u32);
+                    table = table.column(
+                        Column::initial(width)
+                            .at_least(column_widths::MIN_WIDTH)
+                            .resizable(true)
+                            .clip(true),
+                    );
+                }
+                if self.column_widths.take_reset() {
+                    table.reset();
+                }
+                if let Some((row, align)) = self.scroll_to_row.take() {
+                    table = table.scroll_to_row(row, Some(align));
+                } else if let Some(offset) = self.scroll_offset.take() {
+                    table = table.vertical_scroll_offset(offset);
+                }
+                table
+                    .header(row_height + 3.0, |mut header| {
                         for col_index in 0..self.num_cols {
                             header.col(|ui| {
-                                ui.strong(col_idx_to_label(col_index));
+                                self.column_widths
+                                    .shown(col_index as u32, ui.max_rect().width());
+                                let font = TextStyle::Body.resolve(ui.style());
+                                let letters = column_letters(col_index as i64);
+                                let label = self.column_labels.get(col_index as u32);
+                                let text =
+                                    RichText::new(label.as_deref().unwrap_or(&letters)).strong();
+                                let response = ui
+                                    .add(Label::new(text).sense(Sense::click()))
+                                    .on_hover_ui(|ui| {
+                                        ui.label(format!(
+                                            "Column {letters}, double click to rename"
+                                        ));
+                                        let loaded = self
+                                            .cell_cache
+                                            .loaded_statistics(col_index, self.num_cols);
+                                        ui.label(format!("Loaded cells: {}", loaded.describe()));
+                                        match self.entire_columns.describe(col_index as u32) {
+                                            Some(entire) => {
+                                                ui.label(format!("Entire column: {entire}"))
+                                            }
+                                            None => ui.weak(
+                                                "Right click to compute the entire column",
+                                            ),
+                                        };
+                                    });
+                                if response.double_clicked() {
+                                    let label = label.unwrap_or_default();
+                                    self.renaming_column = Some((col_index as u32, label));
+                                }
+                                let header_width = response.rect.width();
+                                response.context_menu(|ui| {
+                                    if ui.button("Compute for entire column").clicked() {
+                                        self.entire_columns.compute(ui.ctx().clone());
+                                        ui.close_menu();
+                                    }
+                                    if ui.button("Auto-fit width").clicked() {
+                                        let padding = 2.0 * self.appearance.cell_padding
+                                            + ui.spacing().item_spacing.x;
+                                        let values = self
+                                            .cell_cache
+                                            .loaded_values(col_index, self.num_cols);
+                                        let widths = ui.fonts(|fonts| {
+                                            values
+                                                .into_iter()
+                                                .map(|value| {
+                                                    let galley = fonts.layout_no_wrap(
+                                                        value,
+                                                        font.clone(),
+                                                        Color32::WHITE,
+                                                    );
+                                                    galley.size().x + padding
+                                                })
+                                                .collect::<Vec<_>>()
+                                        });
+                                        self.column_widths.fit(
+                                            col_index as u32,
+                                            widths.into_iter().chain([header_width + padding]),
+                                        );
+                                        ui.close_menu();
+                                    }
+                                });
                             });
                         }
                     })
                     .body(|body| {
-                        body.rows(Self::DEFAULT_ROW_HEIGHT, self.num_rows, |mut row| {
+                        body.rows(row_height, self.num_rows, |mut row| {
                             let row_index = row.index();
-                            row.col(|ui| {
-                                ui.strong(row_index.to_string());
-                            });
-
+                            top_row = Some(top_row.map_or(row_index, |top| top.min(row_index)));
                             for col_index in 0..self.num_cols {
-                                let id = row_index as u64 * self.num_cols as u64 + col_index as u64;
+                                let id = address::id(row_index as i64, col_index as i64) as u64;
                                 let cell = self.cell_cache.get(id);
                                 row.col(|ui| {
                                     let has_focus = row_index == self.focused_row
@@ -410,30 +1380,214 @@ impl eframe::App for SpreadsheetApp {
                                         Sense::click(),
                                     );
                                     ui.painter().rect_filled(rect, 0.0, cell.background_color());
-                                    let cell_response = cell.ui(ui);
+                                    // Moderators see who wrote each cell.
+                                    let resp = match self.moderation.get(ui.ctx(), id) {
+                                        Some(attribution) => {
+                                            ui.painter().text(
+                                                rect.right_bottom(),
+                                                Align2::RIGHT_BOTTOM,
+                                                moderation::short_ip(&attribution.ip),
+                                                FontId::monospace(8.0),
+                                                ui.visuals().weak_text_color(),
+                                            );
+                                            resp.on_hover_text(format!(
+                                                "Written by {} at {}",
+                                                attribution.ip, attribution.ts
+                                            ))
+                                        }
+                                        None => resp,
+                                    };
+                                    let padding = Vec2::new(self.appearance.cell_padding, 0.0);
+                                    let cell_response = ui
+                                        .scope_builder(
+                                            UiBuilder::new()
+                                                .max_rect(rect.shrink2(padding))
+                                                .layout(*ui.layout()),
+                                            |ui| cell.ui(ui, &self.renderers),
+                                        )
+                                        .inner;
+                                    if self.appearance.grid_lines {
+                                        ui.painter().rect_stroke(
+                                            rect,
+                                            0.0,
+                                            ui.visuals().widgets.noninteractive.bg_stroke,
+                                        );
+                                    }
+                                    let mine = my_edits::contains(id);
+                                    if self.only_my_edits && !mine {
+                                        ui.painter().rect_filled(
+                                            rect,
+                                            0.0,
+                                            ui.visuals().panel_fill.gamma_multiply(0.8),
+                                        );
+                                    }
+                                    if mine {
+                                        ui.painter().circle_filled(
+                                            rect.right_top() + Vec2::new(-3.0, 3.0),
+                                            2.0,
+                                            Color32::LIGHT_GREEN,
+                                        );
+                                    }
+                                    if cell.in_cycle() {
+                                        ui.painter().rect_stroke(
+                                            rect.shrink(1.0),
+                                            0.0,
+                                            egui::Stroke::new(1.0, Color32::RED),
+                                        );
+                                    }
+                                    // The server keeps the comments of the first sheet only.
+                                    let commentable = self.cell_cache.sheet() == FIRST_SHEET;
+                                    let comment = match commentable {
+                                        true => self.comments.get(ui.ctx(), id),
+                                        false => None,
+                                    };
+                                    let cell_area = resp.clone().union(cell_response.clone());
+                                    if let Some(comment) = &comment {
+                                        let corner = rect.left_top();
+                                        ui.painter().add(egui::Shape::convex_polygon(
+                                            vec![
+                                                corner,
+                                                corner + Vec2::new(6.0, 0.0),
+                                                corner + Vec2::new(0.0, 6.0),
+                                            ],
+                                            Color32::from_rgb(255, 165, 0),
+                                            egui::Stroke::NONE,
+                                        ));
+                                        cell_area.clone().on_hover_ui(|ui| {
+                                            ui.set_max_width(240.0);
+                                            ui.label(comment);
+                                        });
+                                    }
+                                    cell_area.context_menu(|ui| {
+                                        if !commentable {
+                                            ui.weak("Comments are kept on Sheet1 only");
+                                            return;
+                                        }
+                                        if !self.deployment.writes_allowed() {
+                                            ui.weak("This spreadsheet is read-only");
+                                            return;
+                                        }
+                                        let action = match comment {
+                                            Some(_) => "Edit comment",
+                                            None => "Add comment",
+                                        };
+                                        if ui.button(action).clicked() {
+                                            let comment = comment.clone().unwrap_or_default();
+                                            self.commenting = Some((id, comment));
+                                            ui.close_menu();
+                                        }
+                                    });
 
                                     // Adjust cell focus based on the new coordinates
                                     if has_focus {
                                         ui.painter().rect_stroke(
                                             rect,
                                             0.0,
-                                            egui::Stroke::new(1.0, Color32::LIGHT_BLUE),
+                                            self.appearance.focus_stroke(ui.visuals()),
                                         );
                                     }
 
+                                    // The outline of the selection can be grabbed to move it.
+                                    let selection = self.selection();
+                                    if selection.contains(row_index, col_index) {
+                                        if !selection.is_single_cell() {
+                                            ui.painter().rect_filled(
+                                                rect,
+                                                0.0,
+                                                self.appearance.selection_fill(ui.visuals()),
+                                            );
+                                        }
+                                        let strips =
+                                            outline(rect, &selection, row_index, col_index);
+                                        for (edge, strip) in strips.into_iter().enumerate() {
+                                            let grab = ui
+                                                .interact(
+                                                    strip,
+                                                    ui.make_persistent_id((id, "move", edge)),
+                                                    Sense::drag(),
+                                                )
+                                                .on_hover_cursor(CursorIcon::Grab);
+                                            if grab.hovered() {
+                                                ui.painter().rect_filled(
+                                                    strip,
+                                                    0.0,
+                                                    Color32::LIGHT_BLUE,
+                                                );
+                                            }
+                                            if grab.drag_started() && self.editing_cell.is_none() {
+                                                let grabbed = (row_index, col_index);
+                                                self.moving = Some((grabbed, grabbed));
+                                            }
+                                        }
+                                    }
+                                    if let Some((grabbed, target)) = &mut self.moving {
+                                        if ui.rect_contains_pointer(rect) {
+                                            *target = (row_index, col_index);
+                                        }
+                                        let destination = selection.moved(
+                                            *grabbed,
+                                            *target,
+                                            self.num_rows,
+                                            self.num_cols,
+                                        );
+                                        if destination.contains(row_index, col_index) {
+                                            ui.painter().rect_stroke(
+                                                rect.shrink(1.0),
+                                                0.0,
+                                                egui::Stroke::new(1.0, Color32::LIGHT_BLUE),
+                                            );
+                                        }
+                                    }
+
                                     ui.input(|i| {
                                         const KEY_DELAY: f64 = 0.01;
                                         let now = i.time;
                                         i.events.iter().for_each(|i| {
-                                            if let egui::Event::Key { key, pressed, .. } = i {
+                                            if let egui::Event::Key {
+                                                key, pressed, modifiers, ..
+                                            } = i
+                                            {
                                                 if now - self.last_key_time > KEY_DELAY && *pressed
                                                 {
+                                                    // Shift extends the selection.
+                                                    let navigates = matches!(
+                                                        key,
+                                                        Key::ArrowDown
+                                                            | Key::ArrowUp
+                                                            | Key::ArrowRight
+                                                            | Key::ArrowLeft
+                                                            | Key::PageDown
+                                                            | Key::PageUp
+                                                    );
+                                                    if navigates && self.editing_cell.is_none() {
+                                                        if modifiers.shift {
+                                                            self.selection_anchor.get_or_insert((
+                                                                self.focused_row,
+                                                                self.focused_col,
+                                                            ));
+                                                        } else {
+                                                            self.selection_anchor = None;
+                                                        }
+                                                    }
                                                     match key {
                                                         Key::Escape => {
                                                             if self.editing_cell.is_some() {
                                                                 cell.disable_edit(true);
+                                                            } else {
+                                                                self.selection_anchor = None;
+                                                                self.moving = None;
                                                             }
                                                         }
+                                                        Key::D | Key::R
+                                                            if modifiers.command
+                                                                && self.editing_cell.is_none() =>
+                                                        {
+                                                            self.fill_selection(match key {
+                                                                Key::D => Fill::Down,
+                                                                _ => Fill::Right,
+                                                            });
+                                                            self.last_key_time = now;
+                                                        }
                                                         Key::Enter => {
                                                             self.focused_row = (self.focused_row
                                                                 + 1)
@@ -499,6 +1653,14 @@ impl eframe::App for SpreadsheetApp {
                                     if resp.clicked()
                                         || (cell_response.clicked() && !cell_response.has_focus())
                                     {
+                                        if ui.input(|i| i.modifiers.shift) {
+                                            self.selection_anchor.get_or_insert((
+                                                self.focused_row,
+                                                self.focused_col,
+                                            ));
+                                        } else {
+                                            self.selection_anchor = None;
+                                        }
                                         self.focused_row = row_index;
                                         self.focused_col = col_index;
                                         self.bg_color_picked = cell.background_color();
@@ -512,21 +1674,130 @@ impl eframe::App for SpreadsheetApp {
                                     }
 
                                     // Edit the current cell
+                                    let inserting = has_focus
+                                        && (self.reference.inserted.is_some()
+                                            || self.snippets.inserted.is_some());
                                     if self.editing_cell.is_none()
+                                        && self.deployment.writes_allowed()
                                         && (resp.double_clicked()
                                         || cell_response.double_clicked()
+                                        || inserting
                                         || (resp.has_focus()
                                         && ui.input(|i| i.key_pressed(Key::Enter))))
                                     {
                                         cell_response.request_focus();
                                         cell.edit();
                                         self.editing_cell = Some(id);
+                                        // An example clicked in the formula reference.
+                                        if let Some(formula) = self.reference.inserted.take() {
+                                            *cell.write_buffer.write() = formula;
+                                        }
+                                        if let Some(text) = self.snippets.inserted.take() {
+                                            cell.insert_snippet(text);
+                                        }
+                                    }
+                                    if self.editing_cell == Some(id) {
+                                        cell.truncate(self.deployment.max_cell_chars());
                                     }
                                 });
                             }
+                        })
+                    })
+            });
+            self.column_widths
+                .save(ctx.input(|i| i.pointer.any_down()));
+            let cells_offset = scrolled.inner.state.offset.y;
+            self.table_offset = cells_offset;
+            let row_height = self.appearance.row_height(&numbers_ui, Self::DEFAULT_ROW_HEIGHT);
+            let numbers_offset = TableBuilder::new(&mut numbers_ui)
+                .id_salt("row_numbers")
+                .striped(self.appearance.striped)
+                .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+                .scroll_bar_visibility(ScrollBarVisibility::AlwaysHidden)
+                .vertical_scroll_offset(cells_offset)
+                .column(Column::exact(row_numbers_width))
+                .header(row_height + 3.0, |mut header| {
+                    header.col(|ui| {
+                        ui.strong("");
+                    });
+                })
+                .body(|body| {
+                    body.rows(row_height, self.num_rows, |mut row| {
+                        let row_index = row.index();
+                        row.col(|ui| {
+                            ui.strong(row_index.to_string());
                         });
                     });
-            });
+                })
+                .state
+                .offset
+                .y;
+            // Scrolling over the row numbers scrolls the cells.
+            if (numbers_offset - cells_offset).abs() > 0.5 {
+                self.scroll_offset = Some(numbers_offset);
+                ctx.request_repaint();
+            }
+
+            if let Some(top_row) = top_row {
+                let viewport = Viewport {
+                    top_row: top_row as u64,
+                    focused: address::id(self.focused_row as i64, self.focused_col as i64) as u64,
+                };
+                self.walkthrough.present(ctx, &self.loader, viewport);
+            }
+
+            // Drop the moved cells where the pointer is released.
+            if self.moving.is_some() {
+                ctx.set_cursor_icon(CursorIcon::Grabbing);
+                if ctx.input(|i| i.pointer.any_released()) {
+                    if let Some((grabbed, target)) = self.moving.take() {
+                        self.drop_selection(grabbed, target);
+                    }
+                }
+            }
         });
     }
 }
+
+/// A parameter of the URL the app was opened with.
+#[cfg(target_arch = "wasm32")]
+fn query_param(cc: &eframe::CreationContext<'_>, name: &str) -> Option<String> {
+    let location = &cc.integration_info.web_info.location;
+    location.query_map.get(name)?.first().cloned()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn query_param(_cc: &eframe::CreationContext<'_>, _name: &str) -> Option<String> {
+    None
+}
+
+/// Thin strips along the edges of a cell's `rect` that are part of the outline of `selection`.
+fn outline(rect: Rect, selection: &Selection, row: usize, col: usize) -> Vec<Rect> {
+    const WIDTH: f32 = 3.0;
+    let mut strips = vec![];
+    if row == *selection.rows.start() {
+        strips.push(Rect::from_min_max(
+            rect.min,
+            pos2(rect.max.x, rect.min.y + WIDTH),
+        ));
+    }
+    if row == *selection.rows.end() {
+        strips.push(Rect::from_min_max(
+            pos2(rect.min.x, rect.max.y - WIDTH),
+            rect.max,
+        ));
+    }
+    if col == *selection.cols.start() {
+        strips.push(Rect::from_min_max(
+            rect.min,
+            pos2(rect.min.x + WIDTH, rect.max.y),
+        ));
+    }
+    if col == *selection.cols.end() {
+        strips.push(Rect::from_min_max(
+            pos2(rect.max.x - WIDTH, rect.min.y),
+            rect.max,
+        ));
+    }
+    strips
+}
@@ -0,0 +1,237 @@
+//! How the grid looks beyond egui's light and dark themes, so embedders and streamers can match it
+//! to their context. The settings are stored in the browser.
+
+use egui::{Color32, Slider, Stroke, Theme, Ui, Visuals};
+use serde::{Deserialize, Serialize};
+
+use crate::storage;
+
+/// Key of the settings in the local storage of the browser.
+const STORAGE_KEY: &str = "appearance";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Appearance {
+    pub(crate) grid_lines: bool,
+    pub(crate) striped: bool,
+    /// Color of every other row as premultiplied RGBA, `None` for the color of the theme.
+    pub(crate) stripe_color: Option<[u8; 4]>,
+    /// Size of the text in cells, `None` for the size of the theme.
+    pub(crate) font_size: Option<f32>,
+    /// Space left and right of the content of cells.
+    pub(crate) cell_padding: f32,
+    /// Stronger colors and thicker outlines, for displays on which the default ones are hard
+    /// to see.
+    pub(crate) high_contrast: bool,
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Self {
+            grid_lines: false,
+            striped: true,
+            stripe_color: None,
+            font_size: None,
+            cell_padding: 0.0,
+            high_contrast: false,
+        }
+    }
+}
+
+impl Appearance {
+    pub(crate) fn load() -> Self {
+        storage::load(STORAGE_KEY)
+    }
+
+    /// Switches the high contrast theme on or off and stores the setting.
+    pub(crate) fn set_high_contrast(&mut self, ctx: &egui::Context, high_contrast: bool) {
+        self.high_contrast = high_contrast;
+        self.apply_theme(ctx);
+        storage::store(STORAGE_KEY, self);
+    }
+
+    /// Replaces all settings, e.g. with imported ones, and stores them.
+    pub(crate) fn replace(&mut self, ctx: &egui::Context, appearance: Appearance) {
+        *self = appearance;
+        self.apply_theme(ctx);
+        storage::store(STORAGE_KEY, self);
+    }
+
+    /// Sets the visuals of the light and dark theme.
+    pub(crate) fn apply_theme(&self, ctx: &egui::Context) {
+        for theme in [Theme::Dark, Theme::Light] {
+            let mut visuals = theme.default_visuals();
+            if self.high_contrast {
+                high_contrast(&mut visuals);
+            }
+            ctx.set_visuals_of(theme, visuals);
+        }
+    }
+
+    /// The outline of the focused cell.
+    pub(crate) fn focus_stroke(&self, visuals: &Visuals) -> Stroke {
+        match self.high_contrast {
+            true => Stroke::new(3.0, visuals.strong_text_color()),
+            false => Stroke::new(1.0, Color32::LIGHT_BLUE),
+        }
+    }
+
+    /// The background of selected cells.
+    pub(crate) fn selection_fill(&self, visuals: &Visuals) -> Color32 {
+        match self.high_contrast {
+            true => visuals.selection.bg_fill.gamma_multiply(0.6),
+            false => Color32::LIGHT_BLUE.gamma_multiply(0.2),
+        }
+    }
+
+    pub(crate) fn stripe_color(&self) -> Option<Color32> {
+        self.stripe_color
+            .map(|[r, g, b, a]| Color32::from_rgba_premultiplied(r, g, b, a))
+    }
+
+    /// Height of the rows for `default_height` with the theme's font size.
+    pub(crate) fn row_height(&self, ui: &Ui, default_height: f32) -> f32 {
+        let theme_size = egui::TextStyle::Body.resolve(ui.style()).size;
+        let font_size = self.font_size.unwrap_or(theme_size);
+        default_height + (font_size - theme_size).max(0.0)
+    }
+
+    /// Applies the settings to the style of the ui showing the grid.
+    pub(crate) fn apply(&self, ui: &mut Ui) {
+        if let Some(color) = self.stripe_color() {
+            ui.visuals_mut().faint_bg_color = color;
+        }
+        if let Some(size) = self.font_size {
+            for style in [egui::TextStyle::Body, egui::TextStyle::Monospace] {
+                if let Some(font) = ui.style_mut().text_styles.get_mut(&style) {
+                    font.size = size;
+                }
+            }
+        }
+    }
+
+    /// Lets the user change the settings, they are stored when they change.
+    pub(crate) fn ui(&mut self, ui: &mut Ui) {
+        let before = self.clone();
+        let mut high_contrast = self.high_contrast;
+        if ui.checkbox(&mut high_contrast, "High contrast").changed() {
+            self.set_high_contrast(ui.ctx(), high_contrast);
+        }
+        ui.checkbox(&mut self.grid_lines, "Grid lines");
+        ui.checkbox(&mut self.striped, "Striped rows");
+        ui.add_enabled_ui(self.striped, |ui| {
+            ui.horizontal(|ui| {
+                let mut color = self.stripe_color().unwrap_or(ui.visuals().faint_bg_color);
+                ui.label("Stripe color");
+                if ui.color_edit_button_srgba(&mut color).changed() {
+                    self.stripe_color = Some(color.to_array());
+                }
+                if ui.button("Reset").clicked() {
+                    self.stripe_color = None;
+                }
+            });
+        });
+        ui.horizontal(|ui| {
+            let mut size = self
+                .font_size
+                .unwrap_or(egui::TextStyle::Body.resolve(ui.style()).size);
+            ui.label("Font size");
+            if ui.add(Slider::new(&mut size, 8.0..=24.0)).changed() {
+                self.font_size = Some(size);
+            }
+            if ui.button("Reset").clicked() {
+                self.font_size = None;
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Cell padding");
+            ui.add(Slider::new(&mut self.cell_padding, 0.0..=10.0));
+        });
+        if *self != before {
+            storage::store(STORAGE_KEY, self);
+        }
+    }
+}
+
+/// Black and white text, strong selection colors and error colors that are readable on the
+/// background.
+fn high_contrast(visuals: &mut Visuals) {
+    let (text, background, stripes, error, warn, selection) = if visuals.dark_mode {
+        (
+            Color32::WHITE,
+            Color32::BLACK,
+            Color32::from_gray(35),
+            Color32::from_rgb(255, 110, 110),
+            Color32::from_rgb(255, 220, 0),
+            Color32::from_rgb(0, 110, 255),
+        )
+    } else {
+        (
+            Color32::BLACK,
+            Color32::WHITE,
+            Color32::from_gray(225),
+            Color32::from_rgb(170, 0, 0),
+            Color32::from_rgb(130, 70, 0),
+            Color32::from_rgb(0, 70, 200),
+        )
+    };
+    visuals.override_text_color = Some(text);
+    visuals.panel_fill = background;
+    visuals.window_fill = background;
+    visuals.extreme_bg_color = background;
+    visuals.faint_bg_color = stripes;
+    visuals.error_fg_color = error;
+    visuals.warn_fg_color = warn;
+    visuals.selection.bg_fill = selection;
+    visuals.selection.stroke = Stroke::new(2.0, text);
+    visuals.widgets.noninteractive.bg_stroke = Stroke::new(1.0, text);
+    for widget in [
+        &mut visuals.widgets.inactive,
+        &mut visuals.widgets.hovered,
+        &mut visuals.widgets.active,
+    ] {
+        widget.bg_stroke = Stroke::new(1.5, text);
+        widget.fg_stroke.color = text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn appearance_settings_keep_defaults_for_missing_fields() {
+        let appearance: Appearance = serde_json::from_value(json!({"grid_lines": true})).unwrap();
+        assert!(appearance.grid_lines);
+        assert!(appearance.striped);
+        assert_eq!(appearance.stripe_color(), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn high_contrast_errors_are_readable() {
+        /// The contrast ratio of two colors as defined by WCAG.
+        fn contrast(a: Color32, b: Color32) -> f32 {
+            let luminance = |color: Color32| {
+                let [r, g, b, _] = egui::Rgba::from(color).to_array();
+                0.2126 * r + 0.7152 * g + 0.0722 * b
+            };
+            let (a, b) = (luminance(a), luminance(b));
+            (a.max(b) + 0.05) / (a.min(b) + 0.05)
+        }
+
+        let ctx = egui::Context::default();
+        let appearance = Appearance {
+            high_contrast: true,
+            ..Appearance::default()
+        };
+        appearance.apply_theme(&ctx);
+        for theme in [egui::Theme::Dark, egui::Theme::Light] {
+            let visuals = ctx.style_of(theme).visuals.clone();
+            assert!(contrast(visuals.error_fg_color, visuals.panel_fill) >= 4.5);
+            assert!(contrast(visuals.error_fg_color, visuals.faint_bg_color) >= 4.5);
+        }
+    }
+}
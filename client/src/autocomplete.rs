@@ -0,0 +1,38 @@
+//! Suggests function names while a formula is typed into a cell.
+
+use xls_protocol::functions::FUNCTIONS;
+
+/// The name being typed at the end of `input`, if `input` is a formula.
+fn typed_name(input: &str) -> Option<&str> {
+    if !input.starts_with('=') {
+        return None;
+    }
+    let start = input
+        .rfind(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .map_or(0, |idx| idx + 1);
+    let name = &input[start..];
+    (!name.is_empty() && name.starts_with(|c: char| c.is_ascii_alphabetic())).then_some(name)
+}
+
+/// The functions whose name starts with what is being typed, as `(name, arguments)`.
+pub(crate) fn suggestions(input: &str) -> Vec<(&'static str, &'static str)> {
+    let Some(name) = typed_name(input) else {
+        return vec![];
+    };
+    let name = name.to_ascii_uppercase();
+    FUNCTIONS
+        .iter()
+        .filter(|function| function.name.starts_with(&name) && function.name != name)
+        .map(|function| (function.name, function.arguments))
+        .collect()
+}
+
+/// Replaces the name being typed at the end of `input` with `function` and an opening
+/// parenthesis.
+pub(crate) fn complete(input: &mut String, function: &str) {
+    if let Some(name) = typed_name(input) {
+        input.truncate(input.len() - name.len());
+        input.push_str(function);
+        input.push('(');
+    }
+}
@@ -1,46 +1,55 @@
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Display;
-use std::num::NonZeroUsize;
+use std::mem::size_of;
 use std::ops::Range;
 use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, Ordering};
 use std::time::Duration;
 
 use egui::mutex::{Mutex, RwLock};
+use egui::text::{CCursor, CCursorRange};
+use egui::widgets::text_edit::TextEditOutput;
 use egui::widgets::TextEdit;
-use egui::{Color32, Label, Response, Sense, Ui};
+use egui::{
+    show_tooltip_for, Align, Color32, Key, Label, Layout, Modifiers, Response, RichText, Sense,
+    TextStyle, Ui,
+};
 use ehttp::Request;
-use ewebsock::{WsMessage, WsSender};
-use log::{debug, trace, warn};
-use lru::LruCache;
-use serde_json::json;
+use ewebsock::{WsEvent, WsMessage, WsSender};
+use log::{debug, error, trace, warn};
+use xls_protocol::address::cell_name;
+use xls_protocol::{
+    Cell, CellStyle, ClientMessage, CloseReason, Heartbeat, PinnedRegions, Region, ServerMessage,
+    UpdateRequest, ValueType, CELL_IDS, FIRST_SHEET,
+};
 
+use crate::autocomplete;
+use crate::column_statistics::LoadedStatistics;
+use crate::data_usage;
 use crate::debouncer::Debouncer;
+use crate::formula_errors;
+use crate::highlight;
+use crate::my_edits;
+use crate::offline;
+use crate::renderer::{RenderedCell, Renderers};
+use crate::retry;
+use crate::selection::{Fill, Selection};
+use crate::snippets;
 
-/// The cell as it comes from the backend.
-#[derive(Debug, Clone, Eq, PartialEq, serde::Deserialize)]
-pub(crate) struct Cell {
-    pub(crate) id: u64,
-    pub(crate) raw_value: String,
-    pub(crate) computed_value: String,
-    pub(crate) background: i32,
-}
+mod sized_lru;
 
-/// A request to update a cell.
-#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
-pub(crate) struct UpdateCellRequest {
-    pub(crate) id: u64,
-    pub(crate) raw_value: String,
-    pub(crate) background: i32,
-}
+use sized_lru::SizedLru;
 
-impl From<&CellContent> for UpdateCellRequest {
+impl From<&CellContent> for UpdateRequest {
     fn from(cell: &CellContent) -> Self {
         Self {
-            id: cell.id,
+            id: cell.id as i64,
             raw_value: cell.write_buffer.read().clone(),
             background: cell.background.load(Ordering::Relaxed),
+            style: cell.style(),
+            ttl_secs: None,
+            sheet: cell.sheet,
         }
     }
 }
@@ -52,27 +61,59 @@ pub(crate) struct CellContent {
     pub(crate) write_buffer: RwLock<String>,
     pub(crate) old_write_buffer: Mutex<String>,
     pub(crate) background: AtomicI32,
+    /// How the text is drawn, encoded like the `style` column.
+    style: AtomicI64,
     pub(crate) is_editing: AtomicBool,
-    debounce_bg_change: Rc<Mutex<Debouncer>>,
+    /// The cells of the reference cycle this cell is part of.
+    pub(crate) cycle: Vec<u64>,
+    /// What `content` is.
+    pub(crate) value_type: ValueType,
+    /// The edit of the cell didn't reach the server yet, `content` is evaluated locally.
+    pub(crate) unsynced: AtomicBool,
+    /// A cell it references changed, `content` is evaluated locally until the server sends the
+    /// new value.
+    pub(crate) provisional: AtomicBool,
+    /// A snippet was inserted, its first hole is selected when the cell is shown.
+    select_hole: AtomicBool,
+    /// The latest change of the cell that arrived while it was edited, applied once the edit
+    /// is done.
+    pub(crate) remote: Mutex<Option<Cell>>,
+    /// Sends changes of the background and style, which come in bursts while a color is picked.
+    debounce_change: Rc<Mutex<Debouncer>>,
+    /// The tab the cell is on, its edits are sent for it.
+    sheet: u32,
 }
 
 /// We convert Cells from the backend into CellContent that we can edit.
 impl From<Cell> for CellContent {
     fn from(cell: Cell) -> Self {
         Self {
-            id: cell.id,
+            id: cell.id as u64,
             content: RwLock::new(cell.computed_value),
             write_buffer: RwLock::new(cell.raw_value.clone()),
             old_write_buffer: Mutex::new(cell.raw_value),
             is_editing: AtomicBool::new(false),
             background: AtomicI32::new(cell.background),
-            debounce_bg_change: Rc::new(Mutex::new(Debouncer::new())),
+            style: AtomicI64::new(cell.style.into()),
+            cycle: cell
+                .cycle
+                .unwrap_or_default()
+                .into_iter()
+                .map(|id| id as u64)
+                .collect(),
+            value_type: cell.value_type,
+            unsynced: AtomicBool::new(false),
+            provisional: AtomicBool::new(false),
+            select_hole: AtomicBool::new(false),
+            remote: Mutex::new(None),
+            debounce_change: Rc::new(Mutex::new(Debouncer::new())),
+            sheet: cell.sheet,
         }
     }
 }
 
 impl CellContent {
-    /// A new empty cell.
+    /// A new empty cell of the first sheet.
     pub(crate) fn empty(id: u64) -> Self {
         Self {
             id,
@@ -81,18 +122,70 @@ impl CellContent {
             content: RwLock::new(String::new()),
             is_editing: AtomicBool::new(false),
             background: AtomicI32::new(i32::from_le_bytes(Color32::TRANSPARENT.to_array())),
-            debounce_bg_change: Rc::new(Mutex::new(Debouncer::new())),
+            style: AtomicI64::new(0),
+            cycle: Vec::new(),
+            value_type: ValueType::default(),
+            unsynced: AtomicBool::new(false),
+            provisional: AtomicBool::new(false),
+            select_hole: AtomicBool::new(false),
+            remote: Mutex::new(None),
+            debounce_change: Rc::new(Mutex::new(Debouncer::new())),
+            sheet: FIRST_SHEET,
         }
     }
 
+    /// The cell on `sheet` instead.
+    pub(crate) fn on_sheet(self, sheet: u32) -> Self {
+        Self { sheet, ..self }
+    }
+
     pub(crate) fn background_color(&self) -> Color32 {
-        let rgba_premultiplied = i32::to_le_bytes(self.background.load(Ordering::Relaxed));
-        Color32::from_rgba_premultiplied(
-            rgba_premultiplied[0],
-            rgba_premultiplied[1],
-            rgba_premultiplied[2],
-            rgba_premultiplied[3],
-        )
+        color(self.background.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn style(&self) -> CellStyle {
+        CellStyle::from(self.style.load(Ordering::Relaxed))
+    }
+
+    /// The color of the text, `None` for the default color.
+    pub(crate) fn text_color(&self) -> Option<Color32> {
+        self.style().color.map(color)
+    }
+
+    /// `text` drawn in the style of the cell.
+    fn styled(&self, text: &str) -> RichText {
+        let style = self.style();
+        let mut text = RichText::new(text);
+        if style.bold {
+            text = text.strong();
+        }
+        if style.italic {
+            text = text.italics();
+        }
+        if let Some(rgba) = style.color {
+            text = text.color(color(rgba));
+        }
+        text
+    }
+
+    /// The bytes the cell takes in memory, roughly: its values are counted, the allocator's
+    /// overhead isn't.
+    pub(crate) fn size(&self) -> usize {
+        let remote = self.remote.lock().as_ref().map_or(0, |cell| {
+            size_of::<Cell>() + cell.raw_value.len() + cell.computed_value.len()
+        });
+        size_of::<Self>()
+            + size_of::<Mutex<Debouncer>>()
+            + self.content.read().len()
+            + self.write_buffer.read().len()
+            + self.old_write_buffer.lock().len()
+            + self.cycle.len() * size_of::<u64>()
+            + remote
+    }
+
+    /// Whether the formula of the cell depends on itself.
+    pub(crate) fn in_cycle(&self) -> bool {
+        !self.cycle.is_empty()
     }
 
     pub(crate) fn is_editing(&self) -> bool {
@@ -107,6 +200,21 @@ impl CellContent {
         self.is_editing.store(true, Ordering::SeqCst);
     }
 
+    /// Replaces the value being edited with a snippet, its first hole is selected.
+    pub(crate) fn insert_snippet(&self, text: String) {
+        *self.write_buffer.write() = text;
+        self.select_hole.store(true, Ordering::Relaxed);
+    }
+
+    /// Someone else changed the cell while it is edited: reverting the edit takes their change,
+    /// saving it overwrites their change.
+    pub(crate) fn change_while_editing(&self, cell: Cell) {
+        let mut old_value = self.old_write_buffer.lock();
+        old_value.clear();
+        old_value.push_str(&cell.raw_value);
+        *self.remote.lock() = Some(cell);
+    }
+
     /// We disable editing mode -- if the user clicks elsewhere.
     pub(crate) fn disable_edit(&self, revert: bool) {
         if revert {
@@ -121,7 +229,18 @@ impl CellContent {
     pub(crate) fn set_background(&self, color: Color32) {
         self.background
             .store(i32::from_le_bytes(color.to_array()), Ordering::Relaxed);
-        let mut debouncer = self.debounce_bg_change.lock();
+        self.send_change();
+    }
+
+    pub(crate) fn set_style(&self, style: CellStyle) {
+        self.style.store(style.into(), Ordering::Relaxed);
+        self.send_change();
+    }
+
+    /// Sends the background and style of the cell, once they stopped changing.
+    fn send_change(&self) {
+        my_edits::record(self.id);
+        let mut debouncer = self.debounce_change.lock();
         let cell_update = self.into();
         debouncer.debounce(Duration::from_millis(350), move || {
             update_cell(
@@ -134,10 +253,20 @@ impl CellContent {
         });
     }
 
+    /// Cuts what is typed into the cell to `max_chars`, the server truncates longer values.
+    pub(crate) fn truncate(&self, max_chars: usize) {
+        let mut write_buffer = self.write_buffer.write();
+        if let Some((index, _)) = write_buffer.char_indices().nth(max_chars) {
+            write_buffer.truncate(index);
+        }
+    }
+
     pub(crate) fn save(&self) {
         let mut old_value = self.old_write_buffer.lock();
         let new_value = self.write_buffer.read();
         if *old_value != *new_value {
+            self.unsynced.store(false, Ordering::Relaxed);
+            my_edits::record(self.id);
             update_cell(
                 format!(
                     "{}/api/spreadsheet",
@@ -150,30 +279,218 @@ impl CellContent {
         }
     }
 
-    /// We render the cell in the UI/Table.
-    pub fn ui(&self, ui: &mut Ui) -> Response {
+    /// The cell as handed to renderers.
+    fn rendered<'a>(&self, raw_value: &'a str, computed_value: &'a str) -> RenderedCell<'a> {
+        RenderedCell {
+            id: self.id,
+            raw_value,
+            computed_value,
+            value_type: self.value_type,
+        }
+    }
+
+    /// We render the cell in the UI/Table, with the first of the `renderers` matching it if any.
+    pub fn ui(&self, ui: &mut Ui, renderers: &Renderers) -> Response {
         if self.is_editing() {
+            // Chosen by the value before the edit, so the renderer doesn't change while typing.
+            let raw_value = self.old_write_buffer.lock().clone();
+            let computed_value = self.content.read().clone();
+            let cell = self.rendered(&raw_value, &computed_value);
             let mut content = self.write_buffer.write();
-            ui.add(TextEdit::singleline(&mut *content))
+            if let Some(renderer) = renderers.find(&cell) {
+                if let Some(response) = renderer.editor(ui, &cell, &mut content) {
+                    return response;
+                }
+            }
+            // Formulas are highlighted while they are typed.
+            let mut layouter = |ui: &Ui, text: &str, wrap_width: f32| {
+                let font = TextStyle::Body.resolve(ui.style());
+                let mut job = highlight::layout_job(text, font, ui.visuals());
+                job.wrap.max_width = wrap_width;
+                ui.fonts(|fonts| fonts.layout_job(job))
+            };
+            // Tab completes function names instead of moving the focus.
+            let mut output = TextEdit::singleline(&mut *content)
+                .lock_focus(true)
+                .layouter(&mut layouter)
+                .show(ui);
+            let suggestions = autocomplete::suggestions(&content);
+            let select = |output: &mut TextEditOutput, hole: Range<usize>| {
+                output.state.cursor.set_char_range(Some(CCursorRange::two(
+                    CCursor::new(hole.start),
+                    CCursor::new(hole.end),
+                )));
+                output.state.clone().store(ui.ctx(), output.response.id);
+            };
+            if self.select_hole.swap(false, Ordering::Relaxed) {
+                if let Some(hole) = snippets::next_hole(&content, 0) {
+                    select(&mut output, hole);
+                }
+            }
+            if let Some(remote) = self.remote.lock().as_ref() {
+                show_tooltip_for(
+                    ui.ctx(),
+                    ui.layer_id(),
+                    output.response.id.with("conflict"),
+                    &output.response.rect,
+                    |ui| {
+                        let changed = match remote.raw_value == remote.computed_value {
+                            true => remote.computed_value.clone(),
+                            false => format!("{} ({})", remote.computed_value, remote.raw_value),
+                        };
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!("This cell just changed to {changed}"),
+                        );
+                        ui.weak("Enter keeps yours, Escape takes theirs");
+                    },
+                );
+            } else if output.response.has_focus() && !suggestions.is_empty() {
+                if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Tab)) {
+                    autocomplete::complete(&mut content, suggestions[0].0);
+                    let end = CCursor::new(content.chars().count());
+                    output
+                        .state
+                        .cursor
+                        .set_char_range(Some(CCursorRange::one(end)));
+                    output.state.store(ui.ctx(), output.response.id);
+                } else {
+                    show_tooltip_for(
+                        ui.ctx(),
+                        ui.layer_id(),
+                        output.response.id.with("autocomplete"),
+                        &output.response.rect,
+                        |ui| {
+                            for (function, args) in suggestions {
+                                ui.monospace(format!("{function}({args})"));
+                            }
+                            ui.weak("Tab to complete");
+                        },
+                    );
+                }
+            } else if output.response.has_focus()
+                && !snippets::holes(&content).is_empty()
+                && ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Tab))
+            {
+                // Tab jumps to the next hole of an inserted snippet.
+                let cursor = output
+                    .state
+                    .cursor
+                    .char_range()
+                    .map_or(0, |range| range.primary.index.max(range.secondary.index));
+                if let Some(hole) = snippets::next_hole(&content, cursor) {
+                    select(&mut output, hole);
+                }
+            }
+            output.response
         } else {
             let content = self.content.read().to_string();
-            ui.add(Label::new(&content).sense(Sense::click()))
+            if self.unsynced.load(Ordering::Relaxed) {
+                return ui
+                    .add(Label::new(RichText::new(&content).italics().weak()).sense(Sense::click()))
+                    .on_hover_text("Not saved yet, the server is unreachable");
+            }
+            let raw_value = self.write_buffer.read().clone();
+            let cell = self.rendered(&raw_value, &content);
+            let response = if let Some(renderer) = renderers.find(&cell) {
+                renderer.ui(ui, &cell)
+            } else {
+                // Like in desktop spreadsheets numbers and dates are right aligned.
+                match self.value_type {
+                    ValueType::Number | ValueType::Date => {
+                        ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                            ui.add(Label::new(self.styled(&content)).sense(Sense::click()))
+                        })
+                        .inner
+                    }
+                    ValueType::Error => {
+                        let text = self.styled(&content).color(ui.visuals().error_fg_color);
+                        let response = ui.add(Label::new(text).sense(Sense::click()));
+                        match formula_errors::explain(&content, &raw_value) {
+                            Some(explanation) => response.on_hover_ui(|ui| {
+                                formula_errors::ui(ui, &content, &raw_value, &explanation)
+                            }),
+                            None => response,
+                        }
+                    }
+                    ValueType::String | ValueType::Bool => {
+                        ui.add(Label::new(self.styled(&content)).sense(Sense::click()))
+                    }
+                }
+            };
+            if self.in_cycle() {
+                let mut cells: Vec<String> =
+                    self.cycle.iter().map(|id| cell_name(*id as i64)).collect();
+                cells.sort();
+                response.on_hover_text(format!("Circular reference between {}", cells.join(", ")))
+            } else if self.provisional.load(Ordering::Relaxed) {
+                response.on_hover_text("Recomputed locally, waiting for the server")
+            } else {
+                response
+            }
         }
     }
 }
 
-/// Sends a PATCH request to the server to update a cell.
-fn update_cell(url: String, data: UpdateCellRequest) {
-    let request = Request::json(url, &data).unwrap();
-    ehttp::fetch(request, move |response| {
-        if let Ok(response) = response {
-            if !response.ok {
-                warn!("POST request failed: {:?}", response.text());
-            }
-        } else {
-            debug!("No response received");
+/// A color stored as little-endian premultiplied RGBA, like backgrounds and text colors.
+fn color(rgba_premultiplied: i32) -> Color32 {
+    let [r, g, b, a] = i32::to_le_bytes(rgba_premultiplied);
+    Color32::from_rgba_premultiplied(r, g, b, a)
+}
+
+/// Sends a POST request to the server to update a cell, it is retried with [`retry::POLICY`] and
+/// the update is sent again later if the server stays unreachable.
+pub(crate) fn update_cell(url: String, data: UpdateRequest) {
+    offline::forget(data.id);
+    retry::send(url, data);
+}
+
+/// Most cells above a cell that are summed up by [`CellCache::auto_sum`], like the server limits
+/// ranges.
+const MAX_SUM_CELLS: usize = 1000;
+
+/// Most updates the server accepts in one batch.
+const BATCH_SIZE: usize = 100;
+
+/// Sends several updates with as few requests as possible, the ones that don't reach the server
+/// are kept like single updates.
+pub(crate) fn update_cells(updates: Vec<UpdateRequest>) {
+    let host = CellCache::API_HOST.unwrap_or("http://localhost:3000");
+    for update in &updates {
+        my_edits::record(update.id as u64);
+    }
+    for batch in updates.chunks(BATCH_SIZE) {
+        let batch = batch.to_vec();
+        for update in &batch {
+            offline::forget(update.id);
         }
-    });
+        let request = Request::json(format!("{host}/api/spreadsheet/batch"), &batch).unwrap();
+        let keep = move |batch: Vec<UpdateRequest>| {
+            for update in batch {
+                offline::enqueue(format!("{host}/api/spreadsheet"), update);
+            }
+        };
+        ehttp::fetch(request, move |response| match response {
+            Ok(response) if response.status >= 500 => {
+                debug!(
+                    "Server unavailable ({}), keeping the updates",
+                    response.status
+                );
+                keep(batch);
+            }
+            Ok(response) => {
+                if !response.ok {
+                    warn!("POST request failed: {:?}", response.text());
+                } else {
+                    offline::retry_pending();
+                }
+            }
+            Err(e) => {
+                debug!("No response received ({e}), keeping the updates");
+                keep(batch);
+            }
+        });
+    }
 }
 
 /// Helper to display CellContent.
@@ -183,76 +500,659 @@ impl Display for CellContent {
     }
 }
 
+/// The sending half of the connection to the server.
+///
+/// Implemented by the websocket, and by a scripted fake server in tests.
+pub(crate) trait MessageSender {
+    fn send(&mut self, msg: WsMessage);
+}
+
+impl MessageSender for WsSender {
+    fn send(&mut self, msg: WsMessage) {
+        WsSender::send(self, msg)
+    }
+}
+
 pub(crate) struct Loader {
     pub(crate) is_open: AtomicBool,
-    ws_sender: Mutex<WsSender>,
+    ws_sender: Mutex<Box<dyn MessageSender>>,
 }
 
 impl Loader {
-    pub(crate) fn new(ws_sender: WsSender) -> Self {
+    pub(crate) fn new(ws_sender: impl MessageSender + 'static) -> Self {
         Self {
-            ws_sender: Mutex::new(ws_sender),
+            ws_sender: Mutex::new(Box::new(ws_sender)),
             is_open: AtomicBool::new(false),
         }
     }
 
-    pub(crate) fn fetch(&self, range: Range<u64>) -> bool {
+    /// Sends the messages over a new connection, e.g. after the server asked to reconnect.
+    pub(crate) fn replace_sender(&self, ws_sender: impl MessageSender + 'static) {
+        self.is_open.store(false, Ordering::Relaxed);
+        *self.ws_sender.lock() = Box::new(ws_sender);
+    }
+
+    /// Asks for the cells of `range` on `sheet`, returns whether the connection is open.
+    pub(crate) fn fetch(&self, range: Range<u64>, sheet: u32) -> bool {
+        self.send(region(range, sheet))
+    }
+
+    /// Sends a message, returns whether the connection is open.
+    pub(crate) fn send(&self, message: impl Into<ClientMessage>) -> bool {
         if !self.is_open.load(Ordering::Relaxed) {
             return false;
         }
-
-        let mut sender = self.ws_sender.lock();
-        sender.send(WsMessage::Text(
-            json!({"from": range.start, "to": range.end}).to_string(),
-        ));
+        let message = serde_json::to_string(&message.into()).unwrap();
+        self.ws_sender.lock().send(WsMessage::Text(message));
         true
     }
 }
 
-/// The CellCache stores a fixed number of cells in memory.
+fn region(range: Range<u64>, sheet: u32) -> Region {
+    Region {
+        from: range.start as i64,
+        to: range.end as i64,
+        sheet,
+    }
+}
+
+/// The CellCache stores cells in memory up to a budget of bytes.
 ///
 /// - It fetches cells from the backend as needed.
 /// - It always contains the cells that the user is currently looking at (and some more
 ///   since it also prefetches cells around the current view to make scrolling smooth).
 /// - It debounces fetching of new rows to avoid fetching too many cells at once.
+/// - It keeps the cells of pinned regions up to date, they aren't evicted.
 pub(crate) struct CellCache {
-    cells: Rc<Mutex<LruCache<u64, Rc<CellContent>>>>,
-    fetcher: Arc<Loader>,
+    /// The tab the cells are on, each tab has its own cache.
+    sheet: u32,
+    cells: Rc<Mutex<SizedLru>>,
+    pinned: Vec<Range<u64>>,
+    /// The cells of `pinned` with content.
+    pinned_cells: BTreeMap<u64, Rc<CellContent>>,
+    fetcher: Rc<Loader>,
     debouncer: Rc<RefCell<Debouncer>>,
     current_range: Option<Range<u64>>,
     prefetch_before_after_id: u64,
     max_cells: usize,
+    /// Why the server closed the connection, `None` while it is open or if the server didn't
+    /// tell.
+    close_reason: Option<CloseReason>,
+    /// Set when the server asked to reconnect because it is drained, the app connects again
+    /// once the server closed the connection.
+    reconnect_soon: bool,
+    /// The cells received since the connection opened, compared with the `seq` of heartbeats.
+    cells_received: u64,
+    /// Time between the heartbeats of the server, `None` until the first one arrived.
+    heartbeat_interval: Option<Duration>,
+    /// Set when a heartbeat arrived that [`Self::connection_stale`] didn't see yet.
+    heartbeat_arrived: bool,
+    /// Time of the ui when the last heartbeat was seen.
+    heartbeat_at: f64,
+    /// The cells the server sent since the frame started, with whether they are live changes
+    /// rather than part of a snapshot. They are shown together at the start of the next frame,
+    /// see [`Self::swap_staged`].
+    staged: BTreeMap<u64, (Cell, bool)>,
 }
 
 impl CellCache {
     pub(crate) const API_HOST: Option<&'static str> = option_env!("API_HOST");
+    /// Bytes the cells may take, the cells of a screen are kept even if they take more.
+    pub(crate) const MEMORY_BUDGET: usize = 8 * 1024 * 1024;
 
-    pub fn new(fetcher: Arc<Loader>, width: usize, height: usize) -> Self {
+    pub fn new(fetcher: Rc<Loader>, sheet: u32, width: usize, height: usize) -> Self {
         let prefetch_before_after_id = 100 * width as u64;
-        let lru_cache_size = NonZeroUsize::new(200 * width).unwrap();
 
         Self {
+            sheet,
             fetcher,
-            cells: Rc::new(Mutex::new(LruCache::new(lru_cache_size))),
+            cells: Rc::new(Mutex::new(SizedLru::new(
+                Self::MEMORY_BUDGET,
+                prefetch_before_after_id as usize,
+            ))),
+            pinned: vec![],
+            pinned_cells: BTreeMap::new(),
             debouncer: Rc::new(RefCell::new(Debouncer::new())),
             current_range: None,
             prefetch_before_after_id,
             max_cells: width * height,
+            close_reason: None,
+            reconnect_soon: false,
+            cells_received: 0,
+            heartbeat_interval: None,
+            heartbeat_arrived: false,
+            heartbeat_at: 0.0,
+            staged: BTreeMap::new(),
+        }
+    }
+
+    /// The tab the cells are on.
+    pub(crate) fn sheet(&self) -> u32 {
+        self.sheet
+    }
+
+    /// Takes over the connection from the cache of the sheet shown before and asks for the
+    /// cells of this sheet, with the same rows pinned.
+    pub(crate) fn take_over(&mut self, previous: &CellCache) {
+        self.close_reason = previous.close_reason;
+        self.reconnect_soon = previous.reconnect_soon;
+        self.cells_received = previous.cells_received;
+        self.heartbeat_interval = previous.heartbeat_interval;
+        self.heartbeat_at = previous.heartbeat_at;
+        self.staged.clear();
+        self.pinned = previous.pinned.clone();
+        let pinned = &self.pinned;
+        self.pinned_cells
+            .retain(|id, _| pinned.iter().any(|range| range.contains(id)));
+        self.refetch();
+    }
+
+    /// Applies an event of the websocket connection to the cache.
+    pub(crate) fn handle_event(&mut self, event: WsEvent) {
+        match event {
+            WsEvent::Message(WsMessage::Text(update)) => {
+                match serde_json::from_str::<ServerMessage>(&update) {
+                    Ok(ServerMessage::Snapshot(cells)) => {
+                        for cell in cells {
+                            self.stage(cell, false);
+                        }
+                    }
+                    Ok(ServerMessage::Cell(cell)) => self.stage(cell, true),
+                    Ok(ServerMessage::Error(error))
+                        if error.close.is_none()
+                            && matches!(error.code.as_str(), "busy" | "rate_limited") =>
+                    {
+                        // Feldera is busy with the regions of other clients or the regions
+                        // changed too often, no cells follow.
+                        let delay = Duration::from_secs(error.retry_after_secs.unwrap_or(1));
+                        debug!("server {}, asking again in {delay:?}", error.code);
+                        self.refetch_after(delay);
+                    }
+                    Ok(ServerMessage::Error(error)) => {
+                        if error.close.is_some() {
+                            warn!("server closes the connection: {}", error.error);
+                            self.close_reason = error.close;
+                        } else {
+                            debug!("server error: {}", error.error);
+                        }
+                    }
+                    Ok(ServerMessage::ReconnectSoon(notice)) => {
+                        debug!(
+                            "server closes the connection within {}s",
+                            notice.reconnect_within_secs
+                        );
+                        self.reconnect_soon = true;
+                    }
+                    Ok(ServerMessage::Heartbeat(heartbeat)) => self.heartbeat(heartbeat),
+                    // Followed by the walkthrough.
+                    Ok(ServerMessage::Presenter(_)) => {}
+                    Err(e) => {
+                        trace!("error parsing server message: {:?} {:?}", update, e);
+                    }
+                }
+            }
+            WsEvent::Opened => {
+                self.close_reason = None;
+                self.reconnect_soon = false;
+                self.cells_received = 0;
+                self.heartbeat_interval = None;
+                self.fetcher.is_open.store(true, Ordering::Relaxed);
+                // After reconnecting, the cells the user looks at are requested again.
+                self.refetch();
+            }
+            WsEvent::Closed => {
+                self.fetcher.is_open.store(false, Ordering::Relaxed);
+                self.heartbeat_interval = None;
+            }
+            _ => {
+                error!("unexpected event: {:?}", event);
+            }
         }
     }
 
+    /// Keeps a cell the server sent until the next frame starts, only its latest value is shown.
+    fn stage(&mut self, cell: Cell, live: bool) {
+        self.cells_received += 1;
+        if !CELL_IDS.contains(&cell.id) {
+            trace!("cell update with invalid id: {:?}", cell);
+            return;
+        }
+        if cell.sheet != self.sheet {
+            // Still on its way when the user switched to this tab.
+            trace!("cell of sheet {} on sheet {}", cell.sheet, self.sheet);
+            return;
+        }
+        let live = live
+            || self
+                .staged
+                .get(&(cell.id as u64))
+                .is_some_and(|(_, live)| *live);
+        self.staged.insert(cell.id as u64, (cell, live));
+    }
+
+    /// Shows the cells the server sent since the last frame, unless the user edits them. Called
+    /// before a frame is drawn, so it never shows a mix of old and new values.
+    pub(crate) fn swap_staged(&mut self) {
+        let mut live = vec![];
+        for (id, (cell, changed)) in std::mem::take(&mut self.staged) {
+            let editing = self
+                .cells
+                .lock()
+                .peek(&id)
+                .filter(|cell| cell.is_editing())
+                .cloned();
+            match editing {
+                Some(editing) => editing.change_while_editing(cell),
+                None => self.set(id, cell.into()),
+            }
+            if changed {
+                live.push(id);
+            }
+        }
+        // Snapshots contain the current values, only live changes can leave the cells
+        // referencing them behind.
+        for id in live {
+            self.recompute_dependents(id);
+        }
+    }
+
+    /// Evaluates the loaded formulas that reference a changed cell, directly or through other
+    /// formulas, so they don't lag behind it until the server sends their new values. Formulas
+    /// that can't be evaluated locally keep their value.
+    fn recompute_dependents(&self, id: u64) {
+        let cells = self.cells.lock();
+        let mut changed = vec![id];
+        let mut visited = BTreeSet::from([id]);
+        while let Some(id) = changed.pop() {
+            let name = cell_name(id as i64);
+            let candidates: Vec<Rc<CellContent>> = cells
+                .iter()
+                .filter(|(other, cell)| {
+                    let raw_value = cell.write_buffer.read();
+                    !visited.contains(other)
+                        && raw_value.starts_with('=')
+                        && raw_value.contains(&name)
+                        && !cell.is_editing()
+                        && !cell.in_cycle()
+                        && !cell.unsynced.load(Ordering::Relaxed)
+                })
+                .map(|(_, cell)| cell.clone())
+                .collect();
+            for cell in candidates {
+                // The name may be part of another name, e.g. `A1` of `AA10`.
+                let references = std::cell::Cell::new(false);
+                let lookup = |other| {
+                    references.set(references.get() || other == id);
+                    cells.peek(&other).map(|cell| cell.content.read().clone())
+                };
+                let value = offline::evaluate(&cell.write_buffer.read(), lookup);
+                if !references.get() {
+                    continue;
+                }
+                visited.insert(cell.id);
+                match value {
+                    Some(value) if *cell.content.read() != value => {
+                        *cell.content.write() = value;
+                        cell.provisional.store(true, Ordering::Relaxed);
+                        changed.push(cell.id);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Requests the cells the user looks at and the pinned cells again.
+    fn refetch(&self) {
+        self.fetcher
+            .fetch(self.current_range.clone().unwrap_or(0..2600), self.sheet);
+        if !self.pinned.is_empty() {
+            self.send_pinned();
+        }
+    }
+
+    /// Requests the cells the user looks at and the pinned cells again after `delay`, unless
+    /// the user scrolls to other cells in the meantime.
+    fn refetch_after(&self, delay: Duration) {
+        let fetcher = self.fetcher.clone();
+        let range = self.current_range.clone().unwrap_or(0..2600);
+        let sheet = self.sheet;
+        let pinned = self.pinned_regions();
+        self.debouncer.borrow_mut().debounce(delay, move || {
+            fetcher.fetch(range, sheet);
+            if !pinned.pinned.is_empty() {
+                fetcher.send(pinned);
+            }
+        });
+    }
+
+    /// Requests the cells again if some of them were missed or the server ignored the last
+    /// region, e.g. because the user scrolled too fast.
+    fn heartbeat(&mut self, heartbeat: Heartbeat) {
+        self.heartbeat_interval = Some(Duration::from_secs(heartbeat.interval_secs));
+        self.heartbeat_arrived = true;
+        let region_ignored = self
+            .current_range
+            .as_ref()
+            .is_some_and(|range| range.start as i64 != heartbeat.region.from);
+        if heartbeat.seq != self.cells_received || region_ignored {
+            debug!(
+                "out of sync with the server ({} of {} cells received, region {:?}), refetching",
+                self.cells_received, heartbeat.seq, heartbeat.region
+            );
+            self.cells_received = heartbeat.seq;
+            self.refetch();
+        }
+    }
+
+    /// Whether the connection is open but no heartbeat arrived for three heartbeat intervals,
+    /// the app should connect again then. `now` is the time of the ui in seconds.
+    pub(crate) fn connection_stale(&mut self, now: f64) -> bool {
+        if std::mem::take(&mut self.heartbeat_arrived) {
+            self.heartbeat_at = now;
+        }
+        let stale = self
+            .heartbeat_interval
+            .is_some_and(|interval| now - self.heartbeat_at > 3.0 * interval.as_secs_f64());
+        if stale {
+            // Until the new connection sends heartbeats.
+            self.heartbeat_interval = None;
+        }
+        stale
+    }
+
+    /// Time between the heartbeats of the server, `None` if it doesn't send any.
+    pub(crate) fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeat_interval
+    }
+
+    /// Whether the app should connect again once the server closed the connection.
+    pub(crate) fn reconnects(&self) -> bool {
+        self.reconnect_soon
+    }
+
+    /// What the user is told about why the server closed the connection, nothing while the app
+    /// reconnects.
+    pub(crate) fn connection_error(&self) -> Option<&'static str> {
+        if self.reconnect_soon {
+            return None;
+        }
+        let message = match self.close_reason? {
+            CloseReason::RateLimited => {
+                "Too many spreadsheets are open from your network, close some of them and reload \
+                 the page."
+            }
+            CloseReason::InvalidRegion => {
+                "The server didn't understand this version of the spreadsheet, reload the page."
+            }
+            CloseReason::ServerShutdown => "The server is restarting, reload the page in a moment.",
+            CloseReason::AuthRequired => {
+                "This spreadsheet is private, open it with a link that contains a valid token."
+            }
+        };
+        Some(message)
+    }
+
+    /// Shows the edits that didn't reach the server yet with their locally evaluated value.
+    pub(crate) fn show_pending_edits(&self) {
+        let cells = self.cells.lock();
+        for request in offline::pending() {
+            let Some(cell) = cells.peek(&(request.id as u64)) else {
+                continue;
+            };
+            if cell.is_editing() || cell.unsynced.load(Ordering::Relaxed) {
+                continue;
+            }
+            let lookup = |id| cells.peek(&id).map(|cell| cell.content.read().clone());
+            let value = offline::evaluate(&request.raw_value, lookup);
+            *cell.content.write() = value.unwrap_or(request.raw_value);
+            cell.unsynced.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The number of cells in memory and the bytes they take.
+    pub(crate) fn memory_usage(&self) -> (usize, usize) {
+        let cells = self.cells.lock();
+        (cells.len(), cells.bytes())
+    }
+
     pub fn set(&mut self, id: u64, c: CellContent) {
+        let c = Rc::new(c);
+        if self.is_pinned(id) {
+            self.pinned_cells.insert(id, c.clone());
+        }
         let mut cells = self.cells.lock();
-        cells.push(id, Rc::new(c));
+        cells.push(id, c);
+    }
+
+    /// Keeps the cells of `pinned` up to date until other regions are pinned.
+    pub(crate) fn pin(&mut self, pinned: Vec<Range<u64>>) {
+        self.pinned = pinned;
+        let pinned = &self.pinned;
+        self.pinned_cells
+            .retain(|id, _| pinned.iter().any(|range| range.contains(id)));
+        self.send_pinned();
+    }
+
+    fn is_pinned(&self, id: u64) -> bool {
+        self.pinned.iter().any(|range| range.contains(&id))
+    }
+
+    fn send_pinned(&self) {
+        self.fetcher.send(self.pinned_regions());
+    }
+
+    fn pinned_regions(&self) -> PinnedRegions {
+        let pinned = self
+            .pinned
+            .iter()
+            .map(|range| region(range.clone(), self.sheet))
+            .collect();
+        PinnedRegions { pinned }
+    }
+
+    /// Moves the cells of `from` to `to` in a sheet with `num_cols` columns, with their
+    /// backgrounds, clearing the cells of `from` that aren't overwritten.
+    pub(crate) fn move_cells(&mut self, from: &Selection, to: &Selection, num_cols: usize) {
+        update_cells(self.move_updates(from, to, num_cols));
+    }
+
+    fn move_updates(
+        &mut self,
+        from: &Selection,
+        to: &Selection,
+        num_cols: usize,
+    ) -> Vec<UpdateRequest> {
+        let sources: Vec<u64> = from.ids(num_cols).collect();
+        let destinations: Vec<u64> = to.ids(num_cols).collect();
+        let mut updates: Vec<UpdateRequest> = sources
+            .iter()
+            .filter(|id| !destinations.contains(id))
+            .map(|id| UpdateRequest {
+                id: *id as i64,
+                raw_value: String::new(),
+                background: 0,
+                style: CellStyle::default(),
+                ttl_secs: None,
+                sheet: self.sheet,
+            })
+            .collect();
+        for (source, destination) in sources.into_iter().zip(destinations) {
+            let mut update = UpdateRequest::from(&*self.get(source));
+            update.id = destination as i64;
+            updates.push(update);
+        }
+        updates
+    }
+
+    /// Copies the top (or left) cells of `selection` into the rest of it, in a sheet with
+    /// `num_cols` columns.
+    pub(crate) fn fill(&mut self, selection: &Selection, fill: Fill, num_cols: usize) {
+        update_cells(self.fill_updates(selection, fill, num_cols));
+    }
+
+    fn fill_updates(
+        &mut self,
+        selection: &Selection,
+        fill: Fill,
+        num_cols: usize,
+    ) -> Vec<UpdateRequest> {
+        let id = |(row, col): (usize, usize)| row as u64 * num_cols as u64 + col as u64;
+        let mut updates = vec![];
+        for row in selection.rows.clone() {
+            for col in selection.cols.clone() {
+                let source = match fill {
+                    Fill::Down => (*selection.rows.start(), col),
+                    Fill::Right => (row, *selection.cols.start()),
+                };
+                if source == (row, col) {
+                    continue;
+                }
+                let mut update = UpdateRequest::from(&*self.get(id(source)));
+                update.id = id((row, col)) as i64;
+                updates.push(update);
+            }
+        }
+        updates
+    }
+
+    /// Sums up the selection below each of its columns (or right of its row), or the numbers
+    /// above (or left of) a single selected cell into it, in a sheet with `num_cols` columns.
+    pub(crate) fn auto_sum(&mut self, selection: &Selection, num_cols: usize) {
+        update_cells(self.auto_sum_updates(selection, num_cols));
+    }
+
+    fn auto_sum_updates(&mut self, selection: &Selection, num_cols: usize) -> Vec<UpdateRequest> {
+        let id = |(row, col): (usize, usize)| row as u64 * num_cols as u64 + col as u64;
+        let sheet = self.sheet;
+        let sum = |cells: Selection, (row, col): (usize, usize)| UpdateRequest {
+            id: id((row, col)) as i64,
+            raw_value: format!("=SUM({})", cells.address()),
+            background: 0,
+            style: CellStyle::default(),
+            ttl_secs: None,
+            sheet,
+        };
+        let (top, left) = selection.top_left();
+        let (bottom, right) = (*selection.rows.end(), *selection.cols.end());
+        if top == bottom && left != right {
+            if right + 1 == num_cols {
+                return vec![];
+            }
+            return vec![sum(selection.clone(), (top, right + 1))];
+        }
+        if !selection.is_single_cell() {
+            if id((bottom + 1, left)) >= self.max_cells as u64 {
+                return vec![];
+            }
+            return selection
+                .cols
+                .clone()
+                .map(|col| {
+                    let column = Selection::spanning((top, col), (bottom, col));
+                    sum(column, (bottom + 1, col))
+                })
+                .collect();
+        }
+
+        let mut is_number = |cell| self.get(id(cell)).value_type == ValueType::Number;
+        let above = (0..top)
+            .rev()
+            .take(MAX_SUM_CELLS)
+            .take_while(|row| is_number((*row, left)))
+            .last();
+        if let Some(first) = above {
+            let column = Selection::spanning((first, left), (top - 1, left));
+            return vec![sum(column, (top, left))];
+        }
+        let before = (0..left)
+            .rev()
+            .take_while(|col| is_number((top, *col)))
+            .last();
+        if let Some(first) = before {
+            let row = Selection::spanning((top, first), (top, left - 1));
+            return vec![sum(row, (top, left))];
+        }
+        vec![]
+    }
+
+    /// Statistics of the loaded cells of a column in a sheet with `num_cols` columns.
+    pub(crate) fn loaded_statistics(&self, col: usize, num_cols: usize) -> LoadedStatistics {
+        let mut statistics = LoadedStatistics::default();
+        for (id, cell) in self.cells.lock().iter() {
+            if *id as usize % num_cols != col || cell.write_buffer.read().is_empty() {
+                continue;
+            }
+            statistics.filled += 1;
+            if cell.value_type == ValueType::Number {
+                if let Ok(number) = cell.content.read().parse::<f64>() {
+                    statistics.numbers += 1;
+                    statistics.sum += number;
+                }
+            }
+        }
+        statistics
+    }
+
+    /// The values of the loaded cells of a column, e.g. to fit the column to them.
+    pub(crate) fn loaded_values(&self, col: usize, num_cols: usize) -> Vec<String> {
+        let cells = self.cells.lock();
+        cells
+            .iter()
+            .chain(self.pinned_cells.iter())
+            .filter(|(id, _)| **id as usize % num_cols == col)
+            .map(|(_, cell)| cell.content.read().clone())
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
+
+    /// The ids of the loaded cells whose raw or computed value contains `text`, ignoring case,
+    /// like the search of the server.
+    pub(crate) fn search(&self, text: &str) -> BTreeSet<u64> {
+        let text = text.to_lowercase();
+        let matches = |cell: &CellContent| {
+            cell.write_buffer.read().to_lowercase().contains(&text)
+                || cell.content.read().to_lowercase().contains(&text)
+        };
+        let cells = self.cells.lock();
+        cells
+            .iter()
+            .chain(self.pinned_cells.iter())
+            .filter(|(_, cell)| matches(cell))
+            .map(|(id, _)| *id)
+            .collect()
     }
 
     pub fn get(&mut self, id: u64) -> Rc<CellContent> {
         let mut cells = self.cells.lock();
 
-        if let Some(c) = cells.get(&id) {
-            c.clone()
+        if let Some(c) = cells.get(&id).cloned() {
+            // Changes that arrived while the cell was edited are shown once the edit is done.
+            let remote = match c.is_editing() {
+                true => None,
+                false => c.remote.lock().take(),
+            };
+            match remote {
+                Some(remote) => {
+                    let c = Rc::new(CellContent::from(remote));
+                    cells.push(id, c.clone());
+                    if self.is_pinned(id) {
+                        self.pinned_cells.insert(id, c.clone());
+                    }
+                    c
+                }
+                None => c,
+            }
+        } else if self.is_pinned(id) {
+            // Pinned cells are up to date, the ones without content are empty.
+            let c = match self.pinned_cells.get(&id) {
+                Some(c) => c.clone(),
+                None => Rc::new(CellContent::empty(id).on_sheet(self.sheet)),
+            };
+            cells.push(id, c.clone());
+            c
         } else {
-            let c = Rc::new(CellContent::empty(id));
+            let c = Rc::new(CellContent::empty(id).on_sheet(self.sheet));
             cells.push(id, c.clone());
 
             if let Some(current_range) = &self.current_range {
@@ -271,13 +1171,14 @@ impl CellCache {
             self.current_range = Some(current_range.clone());
             trace!("fetching range: {:?}", current_range);
             let fetcher = self.fetcher.clone();
+            let sheet = self.sheet;
 
             let debouncer_clone = self.debouncer.clone();
             debouncer_clone
                 .borrow_mut()
-                .debounce(Duration::from_millis(100), move || {
+                .debounce(data_usage::fetch_debounce(), move || {
                     let mut max_retry = 10;
-                    while !fetcher.fetch(current_range.clone()) && max_retry > 0 {
+                    while !fetcher.fetch(current_range.clone(), sheet) && max_retry > 0 {
                         max_retry -= 1;
                     }
                 });
@@ -286,3 +1187,6 @@ impl CellCache {
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
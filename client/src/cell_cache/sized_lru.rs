@@ -0,0 +1,66 @@
+//! An LRU of cells bounded by the memory they take rather than by their number, cells with long
+//! values take as much as hundreds of empty ones.
+
+use std::rc::Rc;
+
+use lru::LruCache;
+
+use super::CellContent;
+
+pub(crate) struct SizedLru {
+    /// The cells with the bytes they took when they were pushed.
+    cells: LruCache<u64, (Rc<CellContent>, usize)>,
+    bytes: usize,
+    budget: usize,
+    /// Cells that are kept even over the budget, so the cells on screen aren't evicted.
+    min_len: usize,
+}
+
+impl SizedLru {
+    pub(crate) fn new(budget: usize, min_len: usize) -> Self {
+        Self {
+            cells: LruCache::unbounded(),
+            bytes: 0,
+            budget,
+            min_len,
+        }
+    }
+
+    /// The cell with `id`, marked as the most recently used.
+    pub(crate) fn get(&mut self, id: &u64) -> Option<&Rc<CellContent>> {
+        self.cells.get(id).map(|(cell, _)| cell)
+    }
+
+    /// The cell with `id`, without marking it as used.
+    pub(crate) fn peek(&self, id: &u64) -> Option<&Rc<CellContent>> {
+        self.cells.peek(id).map(|(cell, _)| cell)
+    }
+
+    /// Adds or replaces a cell and evicts the least recently used ones while over the budget.
+    pub(crate) fn push(&mut self, id: u64, cell: Rc<CellContent>) {
+        let size = cell.size();
+        self.bytes += size;
+        if let Some((_, (_, replaced))) = self.cells.push(id, (cell, size)) {
+            self.bytes -= replaced;
+        }
+        while self.bytes > self.budget && self.cells.len() > self.min_len.max(1) {
+            if let Some((_, (_, evicted))) = self.cells.pop_lru() {
+                self.bytes -= evicted;
+            }
+        }
+    }
+
+    /// The cells from the most to the least recently used.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&u64, &Rc<CellContent>)> {
+        self.cells.iter().map(|(id, (cell, _))| (id, cell))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// The bytes the cells take, as estimated by [`CellContent::size`].
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
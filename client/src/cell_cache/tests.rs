@@ -0,0 +1,939 @@
+//! Protocol conformance tests: drives the [`Loader`] and [`CellCache`] against a scripted fake
+//! server that speaks the same websocket protocol as `server/src/spreadsheet.rs`.
+//!
+//! Run with `cargo test -p spreadsheet-techdemo --target wasm32-unknown-unknown`
+//! (requires `wasm-bindgen-test-runner` and node).
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use gloo_timers::future::TimeoutFuture;
+use serde_json::{json, Value};
+use wasm_bindgen_test::wasm_bindgen_test;
+use xls_protocol::{address, PresenterMessage};
+
+use super::*;
+use crate::data_usage;
+use crate::find;
+use crate::sheets::{SheetView, Sheets};
+use crate::walkthrough::Walkthrough;
+
+const WIDTH: usize = 26;
+const HEIGHT: usize = 1000;
+/// How long to wait for the fetch debouncer to fire.
+const DEBOUNCE_MS: u32 = 150;
+
+/// A fake server: records region requests and answers them from its cells.
+#[derive(Clone, Default)]
+struct FakeServer {
+    requests: Rc<RefCell<Vec<Value>>>,
+    cells: Rc<RefCell<BTreeMap<i64, Value>>>,
+}
+
+impl MessageSender for FakeServer {
+    fn send(&mut self, msg: WsMessage) {
+        let WsMessage::Text(text) = msg else {
+            panic!("unexpected message: {msg:?}");
+        };
+        self.requests
+            .borrow_mut()
+            .push(serde_json::from_str(&text).expect("region request is not JSON"));
+    }
+}
+
+impl FakeServer {
+    fn set_cell(&self, id: i64, raw_value: &str, computed_value: &str) -> Value {
+        let cell = json!({
+            "id": id,
+            "raw_value": raw_value,
+            "computed_value": computed_value,
+            "background": 0,
+        });
+        self.cells.borrow_mut().insert(id, cell.clone());
+        cell
+    }
+
+    /// Region requests received so far, as `(from, to)`.
+    fn take_requests(&self) -> Vec<(u64, u64)> {
+        self.requests
+            .borrow_mut()
+            .drain(..)
+            .map(|region| {
+                (
+                    region["from"].as_u64().expect("from"),
+                    region["to"].as_u64().expect("to"),
+                )
+            })
+            .collect()
+    }
+
+    /// The frames the server sends for a region snapshot: an array of the cells followed by an
+    /// empty line.
+    fn snapshot(&self, from: u64, to: u64) -> Vec<WsEvent> {
+        let cells: Vec<Value> = self
+            .cells
+            .borrow()
+            .range(from as i64..to as i64)
+            .map(|(_, cell)| cell.clone())
+            .collect();
+        let mut frames = vec![];
+        if !cells.is_empty() {
+            frames.push(Value::Array(cells).to_string());
+        }
+        frames.push(String::new());
+        frames
+            .into_iter()
+            .map(|frame| WsEvent::Message(WsMessage::Text(frame)))
+            .collect()
+    }
+}
+
+fn cache(server: &FakeServer) -> CellCache {
+    let loader = Rc::new(Loader::new(server.clone()));
+    CellCache::new(loader, FIRST_SHEET, WIDTH, HEIGHT)
+}
+
+fn text(cell: &Value) -> WsEvent {
+    WsEvent::Message(WsMessage::Text(cell.to_string()))
+}
+
+#[wasm_bindgen_test]
+fn no_requests_before_open() {
+    let server = FakeServer::default();
+    let loader = Loader::new(server.clone());
+    assert!(!loader.fetch(0..10, FIRST_SHEET));
+    assert!(server.take_requests().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn requests_first_rows_on_open() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    assert_eq!(server.take_requests(), vec![(0, 2600)]);
+}
+
+#[wasm_bindgen_test]
+fn snapshot_fills_cache() {
+    let server = FakeServer::default();
+    server.set_cell(1, "=1+1", "2");
+    server.set_cell(27, "hello", "hello");
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+
+    for (from, to) in server.take_requests() {
+        for event in server.snapshot(from, to) {
+            cache.handle_event(event);
+        }
+    }
+    cache.swap_staged();
+
+    let cell = cache.get(1);
+    assert_eq!(cell.to_string(), "2");
+    assert_eq!(*cell.write_buffer.read(), "=1+1");
+    assert_eq!(cache.get(27).to_string(), "hello");
+    assert_eq!(cache.get(2).to_string(), "");
+}
+
+#[wasm_bindgen_test]
+fn invalid_messages_are_ignored() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    cache.handle_event(WsEvent::Message(WsMessage::Text(String::from("not json"))));
+    cache.handle_event(text(&json!({"id": 1, "raw_value": "missing fields"})));
+    cache.handle_event(WsEvent::Message(WsMessage::Binary(vec![1, 2, 3])));
+    assert_eq!(cache.get(1).to_string(), "");
+
+    // Ids outside of the spreadsheet are dropped.
+    cache.handle_event(text(&server.set_cell(-1, "negative", "negative")));
+    assert!(cache
+        .cells
+        .lock()
+        .iter()
+        .all(|(_, cell)| cell.to_string().is_empty()));
+}
+
+#[wasm_bindgen_test]
+fn sheets_have_their_own_cells() {
+    let server = FakeServer::default();
+    let loader = Rc::new(Loader::new(server.clone()));
+    let mut first = CellCache::new(loader.clone(), FIRST_SHEET, WIDTH, HEIGHT);
+    let mut second = CellCache::new(loader, 1, WIDTH, HEIGHT);
+    second.handle_event(WsEvent::Opened);
+    let requests = server.requests.borrow_mut().drain(..).collect::<Vec<_>>();
+    assert_eq!(requests, vec![json!({"from": 0, "to": 2600, "sheet": 1})]);
+
+    // Cells of the first sheet still on their way when the tab was switched.
+    second.handle_event(text(&server.set_cell(5, "first", "first")));
+    let mut cell = server.set_cell(6, "second", "second");
+    cell["sheet"] = json!(1);
+    second.handle_event(text(&cell));
+    second.swap_staged();
+    assert_eq!(second.get(5).to_string(), "");
+    assert_eq!(second.get(6).to_string(), "second");
+    assert_eq!(UpdateRequest::from(&*second.get(7)).sheet, 1);
+
+    first.handle_event(text(&server.set_cell(5, "first", "first")));
+    first.swap_staged();
+    assert_eq!(first.get(5).to_string(), "first");
+    assert_eq!(UpdateRequest::from(&*first.get(5)).sheet, FIRST_SHEET);
+}
+
+#[wasm_bindgen_test]
+fn switching_sheets_keeps_their_cells_and_view() {
+    let server = FakeServer::default();
+    let loader = Rc::new(Loader::new(server.clone()));
+    let mut sheets = Sheets::default();
+    let mut active = CellCache::new(loader.clone(), FIRST_SHEET, WIDTH, HEIGHT);
+    active.handle_event(WsEvent::Opened);
+    active.handle_event(text(&server.set_cell(5, "first", "first")));
+    active.swap_staged();
+    server.take_requests();
+
+    let second = sheets.add(loader, WIDTH, HEIGHT).unwrap();
+    let first_view = SheetView {
+        scroll_offset: 120.0,
+        focused: (4, 2),
+    };
+    assert_eq!(
+        sheets.switch(second, &mut active, first_view),
+        Some(SheetView::default())
+    );
+    assert_eq!(active.sheet(), 1);
+    assert_eq!(sheets.active(), second);
+    // The cells of the new sheet are requested over the same connection.
+    assert_eq!(server.take_requests(), vec![(0, 2600)]);
+    assert_eq!(active.get(5).to_string(), "");
+    assert_eq!(
+        sheets.switch(second, &mut active, SheetView::default()),
+        None
+    );
+
+    assert_eq!(
+        sheets.switch(0, &mut active, SheetView::default()),
+        Some(first_view)
+    );
+    assert_eq!(active.sheet(), FIRST_SHEET);
+    assert_eq!(active.get(5).to_string(), "first");
+    assert_eq!(sheets.names().collect::<Vec<_>>(), vec!["Sheet1", "Sheet2"]);
+}
+
+#[wasm_bindgen_test]
+fn updates_replace_cells() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    cache.handle_event(text(&server.set_cell(5, "old", "old")));
+    cache.swap_staged();
+    assert_eq!(cache.get(5).to_string(), "old");
+
+    cache.handle_event(text(&server.set_cell(5, "=2*3", "6")));
+    cache.swap_staged();
+    let cell = cache.get(5);
+    assert_eq!(cell.to_string(), "6");
+    assert_eq!(*cell.write_buffer.read(), "=2*3");
+}
+
+#[wasm_bindgen_test]
+fn updates_are_shown_at_the_next_frame() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    cache.handle_event(text(&server.set_cell(5, "old", "old")));
+    cache.swap_staged();
+    let shown = cache.get(5);
+
+    // A frame being drawn keeps the values it started with.
+    let mut changed = server.set_cell(5, "=2*3", "6");
+    changed["background"] = json!(7);
+    cache.handle_event(text(&server.set_cell(5, "=1+1", "2")));
+    cache.handle_event(text(&changed));
+    assert!(Rc::ptr_eq(&cache.get(5), &shown));
+    assert_eq!(shown.to_string(), "old");
+
+    // The next one shows the latest change as a whole.
+    cache.swap_staged();
+    let cell = cache.get(5);
+    assert_eq!(cell.to_string(), "6");
+    assert_eq!(*cell.write_buffer.read(), "=2*3");
+    assert_eq!(cell.background.load(Ordering::Relaxed), 7);
+}
+
+#[wasm_bindgen_test]
+async fn scrolling_requests_region_around_cell() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    server.take_requests();
+
+    // The prefetch window is 100 rows before and after the requested cell.
+    let id = 500 * WIDTH as u64;
+    cache.get(id);
+    TimeoutFuture::new(DEBOUNCE_MS).await;
+    let prefetch = 100 * WIDTH as u64;
+    assert_eq!(server.take_requests(), vec![(id - prefetch, id + prefetch)]);
+
+    // Cells inside the requested region don't trigger another request.
+    cache.get(id + 1);
+    cache.get(id - 1);
+    TimeoutFuture::new(DEBOUNCE_MS).await;
+    assert!(server.take_requests().is_empty());
+}
+
+#[wasm_bindgen_test]
+async fn reduced_updates_fetch_less_eagerly() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    server.take_requests();
+
+    data_usage::set_reduced(true);
+    cache.get(500 * WIDTH as u64);
+    TimeoutFuture::new(DEBOUNCE_MS).await;
+    assert!(server.take_requests().is_empty());
+    TimeoutFuture::new(500).await;
+    assert_eq!(server.take_requests().len(), 1);
+    data_usage::set_reduced(false);
+
+    assert_eq!(data_usage::format_bytes(999), "999 B");
+    assert_eq!(data_usage::format_bytes(1_450_000), "1.4 MB");
+}
+
+#[wasm_bindgen_test]
+async fn region_requests_are_debounced_and_clamped() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    server.take_requests();
+
+    // Scrolling quickly to the end only requests the last region.
+    cache.get(300 * WIDTH as u64);
+    cache.get(600 * WIDTH as u64);
+    let last = (WIDTH * HEIGHT) as u64 - 1;
+    cache.get(last);
+    TimeoutFuture::new(DEBOUNCE_MS).await;
+    assert_eq!(
+        server.take_requests(),
+        vec![(last - 100 * WIDTH as u64, (WIDTH * HEIGHT) as u64)]
+    );
+}
+
+#[wasm_bindgen_test]
+async fn no_requests_after_close() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    cache.handle_event(WsEvent::Closed);
+    server.take_requests();
+
+    cache.get(500 * WIDTH as u64);
+    TimeoutFuture::new(DEBOUNCE_MS).await;
+    assert!(server.take_requests().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn update_request_matches_server() {
+    let cell = CellContent::from(Cell {
+        id: 42,
+        raw_value: String::from("=1+1"),
+        computed_value: String::from("2"),
+        background: 7,
+        style: CellStyle::default(),
+        value_type: ValueType::Number,
+        cycle: None,
+        sheet: FIRST_SHEET,
+    });
+    let request = serde_json::to_value(UpdateRequest::from(&cell)).unwrap();
+    assert_eq!(
+        request,
+        json!({"id": 42, "raw_value": "=1+1", "background": 7})
+    );
+}
+
+#[wasm_bindgen_test]
+fn styles_are_kept_with_the_cell() {
+    let red = Color32::from_rgb(255, 0, 0);
+    let style = CellStyle {
+        bold: true,
+        italic: false,
+        color: Some(i32::from_le_bytes(red.to_array())),
+    };
+    let cell: Cell = serde_json::from_value(json!({
+        "id": 3,
+        "raw_value": "x",
+        "computed_value": "x",
+        "background": 0,
+        "style": i64::from(style),
+    }))
+    .unwrap();
+    let cell = CellContent::from(cell);
+    assert_eq!(cell.style(), style);
+    assert_eq!(cell.text_color(), Some(red));
+    let request = UpdateRequest::from(&cell);
+    assert_eq!(request.style, style);
+
+    let plain = CellContent::empty(4);
+    assert!(plain.style().is_plain());
+    assert_eq!(plain.text_color(), None);
+}
+
+#[wasm_bindgen_test]
+fn cells_are_evicted_by_size() {
+    let long = |id: u64| {
+        let value = "x".repeat(1000);
+        Rc::new(CellContent::from(Cell {
+            id: id as i64,
+            raw_value: value.clone(),
+            computed_value: value,
+            background: 0,
+            style: CellStyle::default(),
+            value_type: ValueType::String,
+            cycle: None,
+            sheet: FIRST_SHEET,
+        }))
+    };
+    let empty = CellContent::empty(0).size();
+    let long_size = long(0).size();
+    assert!(long_size > empty + 2000);
+
+    let budget = 100 * empty + 2 * long_size;
+    let mut cells = SizedLru::new(budget, 2);
+    for id in 0..100 {
+        cells.push(id, Rc::new(CellContent::empty(id)));
+    }
+    assert_eq!(cells.len(), 100);
+    assert_eq!(cells.bytes(), 100 * empty);
+    // Long cells push out the least recently used ones.
+    assert!(cells.get(&0).is_some());
+    cells.push(100, long(100));
+    cells.push(101, long(101));
+    cells.push(102, long(102));
+    assert!(cells.bytes() <= budget);
+    assert!(cells.peek(&0).is_some());
+    assert!(cells.peek(&1).is_none());
+    assert!(cells.peek(&102).is_some());
+    // Replacing a cell replaces its size.
+    let bytes = cells.bytes();
+    cells.push(102, Rc::new(CellContent::empty(102)));
+    assert_eq!(cells.bytes(), bytes - long_size + empty);
+
+    // The cells of a screen are kept over the budget.
+    let mut cells = SizedLru::new(1000, 2);
+    cells.push(0, long(0));
+    cells.push(1, long(1));
+    cells.push(2, long(2));
+    assert_eq!(cells.len(), 2);
+    assert!(cells.peek(&0).is_none());
+}
+
+#[wasm_bindgen_test]
+fn cells_in_a_cycle_are_marked() {
+    let cell: Cell = serde_json::from_value(json!({
+        "id": 27,
+        "raw_value": "=A1",
+        "computed_value": "#CYCLE!",
+        "background": 0,
+        "cycle": [26, 27],
+    }))
+    .unwrap();
+    let cell = CellContent::from(cell);
+    assert!(cell.in_cycle());
+    assert_eq!(cell.cycle, vec![26, 27]);
+    assert_eq!(cell_name(27), "B1");
+    assert!(!CellContent::empty(26).in_cycle());
+}
+
+#[wasm_bindgen_test]
+fn pending_edits_are_evaluated_locally() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    cache.handle_event(text(&server.set_cell(0, "20", "20")));
+    cache.handle_event(text(&server.set_cell(1, "old", "old")));
+    cache.handle_event(text(&server.set_cell(2, "old", "old")));
+    cache.swap_staged();
+
+    let url = String::from("http://localhost:3000/api/spreadsheet");
+    for (id, raw_value) in [(1, "=A0*2+1"), (2, "=VLOOKUP(1, A0:B0, 2)")] {
+        let edit = UpdateRequest {
+            id,
+            raw_value: String::from(raw_value),
+            background: 0,
+            style: CellStyle::default(),
+            ttl_secs: None,
+            sheet: FIRST_SHEET,
+        };
+        offline::enqueue(url.clone(), edit);
+    }
+    cache.show_pending_edits();
+    assert_eq!(cache.get(1).to_string(), "41");
+    assert!(cache.get(1).unsynced.load(Ordering::Relaxed));
+    // Formulas that need the pipeline are shown as written.
+    assert_eq!(cache.get(2).to_string(), "=VLOOKUP(1, A0:B0, 2)");
+
+    // The value of the server replaces the local one.
+    cache.handle_event(text(&server.set_cell(1, "=A0*2+1", "41")));
+    cache.swap_staged();
+    assert!(!cache.get(1).unsynced.load(Ordering::Relaxed));
+    offline::forget(1);
+    offline::forget(2);
+}
+
+#[wasm_bindgen_test]
+fn formulas_follow_changes_until_the_server_sends_them() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    cache.handle_event(text(&server.set_cell(0, "1", "1")));
+    cache.handle_event(text(&server.set_cell(1, "=A0+1", "2")));
+    cache.handle_event(text(&server.set_cell(2, "=B0*10", "20")));
+    cache.handle_event(text(&server.set_cell(3, "=A01", "")));
+    cache.swap_staged();
+
+    cache.handle_event(text(&server.set_cell(0, "5", "5")));
+    cache.swap_staged();
+    assert_eq!(cache.get(1).to_string(), "6");
+    assert_eq!(cache.get(2).to_string(), "60");
+    assert!(cache.get(2).provisional.load(Ordering::Relaxed));
+    // `A01` is another cell.
+    assert!(!cache.get(3).provisional.load(Ordering::Relaxed));
+
+    // The value of the server replaces the local one.
+    cache.handle_event(text(&server.set_cell(2, "=B0*10", "60")));
+    cache.swap_staged();
+    assert!(!cache.get(2).provisional.load(Ordering::Relaxed));
+}
+
+#[wasm_bindgen_test]
+fn find_goes_through_the_loaded_matches() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    cache.handle_event(text(&server.set_cell(3, "Hello", "Hello")));
+    cache.handle_event(text(&server.set_cell(7, "=UPPER(C0)", "HELLO")));
+    cache.handle_event(text(&server.set_cell(9, "bye", "bye")));
+    cache.swap_staged();
+
+    let matches = cache.search("hello");
+    assert_eq!(matches, BTreeSet::from([3, 7]));
+    assert_eq!(find::next_match(&matches, 0, true), Some(3));
+    assert_eq!(find::next_match(&matches, 3, true), Some(7));
+    // After the last match it starts over.
+    assert_eq!(find::next_match(&matches, 7, true), Some(3));
+    assert_eq!(find::next_match(&matches, 3, false), Some(7));
+    assert_eq!(find::next_match(&BTreeSet::new(), 3, true), None);
+}
+
+#[wasm_bindgen_test]
+fn value_types_are_kept() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    let mut number = server.set_cell(1, "42", "42");
+    number["value_type"] = json!("number");
+    cache.handle_event(text(&number));
+    cache.handle_event(text(&server.set_cell(2, "text", "text")));
+    cache.swap_staged();
+
+    assert_eq!(cache.get(1).value_type, ValueType::Number);
+    // Cells without a type are strings.
+    assert_eq!(cache.get(2).value_type, ValueType::String);
+}
+
+#[wasm_bindgen_test]
+fn changes_while_editing_are_kept_aside() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    cache.handle_event(text(&server.set_cell(5, "old", "old")));
+    cache.swap_staged();
+    let cell = cache.get(5);
+    cell.edit();
+    cell.write_buffer.write().push_str(" and mine");
+
+    cache.handle_event(text(&server.set_cell(5, "=2*3", "6")));
+    cache.swap_staged();
+    let editing = cache.get(5);
+    assert!(Rc::ptr_eq(&cell, &editing));
+    assert_eq!(*editing.write_buffer.read(), "old and mine");
+    assert_eq!(editing.remote.lock().as_ref().unwrap().computed_value, "6");
+
+    // Reverting takes their change.
+    editing.disable_edit(true);
+    assert_eq!(*editing.write_buffer.read(), "=2*3");
+    assert_eq!(cache.get(5).to_string(), "6");
+    assert!(cache.get(5).remote.lock().is_none());
+}
+
+#[wasm_bindgen_test]
+fn moving_cells_clears_the_source() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    cache.handle_event(text(&server.set_cell(0, "a", "a")));
+    cache.handle_event(text(&server.set_cell(1, "=A0", "a")));
+    cache.swap_staged();
+
+    // A0:B0 moved one column to the right overlaps itself in B0.
+    let from = Selection::spanning((0, 0), (0, 1));
+    let to = from.moved((0, 0), (0, 1), HEIGHT, WIDTH);
+    assert_eq!(to, Selection::spanning((0, 1), (0, 2)));
+    let updates = cache.move_updates(&from, &to, WIDTH);
+    let updates: Vec<(i64, &str)> = updates
+        .iter()
+        .map(|update| (update.id, update.raw_value.as_str()))
+        .collect();
+    assert_eq!(updates, vec![(0, ""), (1, "a"), (2, "=A0")]);
+
+    // Selections are kept within the sheet.
+    let to = from.moved((0, 1), (0, 0), HEIGHT, WIDTH);
+    assert_eq!(to, from);
+    let to = from.moved((0, 0), (3, WIDTH - 1), HEIGHT, WIDTH);
+    assert_eq!(to, Selection::spanning((3, WIDTH - 2), (3, WIDTH - 1)));
+}
+
+#[wasm_bindgen_test]
+fn selections_are_cleared_by_region() {
+    let regions = |selection: Selection| -> Vec<(i64, i64)> {
+        selection
+            .regions(WIDTH)
+            .iter()
+            .map(|region| (region.from, region.to))
+            .collect()
+    };
+    assert_eq!(
+        regions(Selection::spanning((1, 1), (2, 2))),
+        vec![(27, 29), (53, 55)]
+    );
+    // Whole rows are one region.
+    assert_eq!(
+        regions(Selection::spanning((1, 0), (3, WIDTH - 1))),
+        vec![(26, 104)]
+    );
+}
+
+#[wasm_bindgen_test]
+fn typed_values_are_cut_to_the_longest_cell() {
+    let cell = CellContent::empty(1);
+    *cell.write_buffer.write() = "é".repeat(70);
+    cell.truncate(64);
+    assert_eq!(*cell.write_buffer.read(), "é".repeat(64));
+    cell.truncate(64);
+    assert_eq!(cell.write_buffer.read().chars().count(), 64);
+}
+
+#[wasm_bindgen_test]
+fn fill_copies_the_first_cells() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    cache.handle_event(text(&server.set_cell(0, "a", "a")));
+    cache.handle_event(text(&server.set_cell(1, "b", "b")));
+    cache.swap_staged();
+
+    let selection = Selection::spanning((0, 0), (1, 1));
+    let raw_values = |updates: Vec<UpdateRequest>| {
+        updates
+            .into_iter()
+            .map(|update| (update.id, update.raw_value))
+            .collect::<Vec<_>>()
+    };
+    let down = cache.fill_updates(&selection, Fill::Down, WIDTH);
+    assert_eq!(
+        raw_values(down),
+        vec![(26, String::from("a")), (27, String::from("b"))]
+    );
+    let right = cache.fill_updates(&selection, Fill::Right, WIDTH);
+    assert_eq!(
+        raw_values(right),
+        vec![(1, String::from("a")), (27, String::new())]
+    );
+}
+
+#[wasm_bindgen_test]
+fn auto_sum_picks_the_numbers() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    for (id, value) in [(0, "text"), (26, "1"), (52, "2")] {
+        let mut cell = server.set_cell(id, value, value);
+        if id != 0 {
+            cell["value_type"] = json!("number");
+        }
+        cache.handle_event(text(&cell));
+    }
+    cache.swap_staged();
+    let raw_values = |updates: Vec<UpdateRequest>| {
+        updates
+            .into_iter()
+            .map(|update| (update.id, update.raw_value))
+            .collect::<Vec<_>>()
+    };
+
+    // The numbers above the focused cell, up to the text.
+    let below = Selection::spanning((3, 0), (3, 0));
+    let updates = cache.auto_sum_updates(&below, WIDTH);
+    assert_eq!(raw_values(updates), vec![(78, String::from("=SUM(A1:A2)"))]);
+    // Below each column of the selection.
+    let columns = Selection::spanning((1, 0), (2, 1));
+    let updates = cache.auto_sum_updates(&columns, WIDTH);
+    assert_eq!(
+        raw_values(updates),
+        vec![
+            (78, String::from("=SUM(A1:A2)")),
+            (79, String::from("=SUM(B1:B2)"))
+        ]
+    );
+    // Right of a row.
+    let row = Selection::spanning((1, 0), (1, 2));
+    let updates = cache.auto_sum_updates(&row, WIDTH);
+    assert_eq!(raw_values(updates), vec![(29, String::from("=SUM(A1:C1)"))]);
+    // Nothing to sum up.
+    let alone = Selection::spanning((0, 5), (0, 5));
+    assert!(cache.auto_sum_updates(&alone, WIDTH).is_empty());
+}
+
+#[wasm_bindgen_test]
+fn statistics_of_loaded_cells() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    for (id, value) in [(0, "1.5"), (26, "2"), (52, "text"), (1, "3")] {
+        let mut cell = server.set_cell(id, value, value);
+        if value != "text" {
+            cell["value_type"] = json!("number");
+        }
+        cache.handle_event(text(&cell));
+    }
+    cache.swap_staged();
+
+    let statistics = cache.loaded_statistics(0, WIDTH);
+    assert_eq!(
+        statistics,
+        LoadedStatistics {
+            filled: 3,
+            numbers: 2,
+            sum: 3.5
+        }
+    );
+    assert_eq!(
+        statistics.describe(),
+        "3 filled, 2 numbers, sum 3.5, average 1.75"
+    );
+}
+
+#[wasm_bindgen_test]
+fn addresses_are_parsed_back_into_selections() {
+    let region = Selection::spanning((1, 0), (20, 5));
+    assert_eq!(Selection::parse("A1:F20"), Some(region.clone()));
+    assert_eq!(Selection::parse(&region.address()), Some(region));
+    assert_eq!(Selection::parse("f20:a1"), Selection::parse("A1:F20"));
+    assert_eq!(
+        Selection::parse("B2"),
+        Some(Selection::spanning((2, 1), (2, 1)))
+    );
+    assert_eq!(
+        Selection::parse("AA0"),
+        Some(Selection::spanning((0, 26), (0, 26)))
+    );
+    for invalid in ["", "A", "12", "A1:", "A-1", "1A"] {
+        assert_eq!(Selection::parse(invalid), None, "{invalid}");
+    }
+}
+
+#[wasm_bindgen_test]
+fn cell_names_are_parsed_back_into_ids() {
+    for id in [0, 25, 26, 27, 1_039_999_999] {
+        assert_eq!(address::parse_cell_name(&cell_name(id)), Some(id));
+    }
+    assert_eq!(cell_name(1_039_999_999), "Z39999999");
+    for invalid in ["AA1", "A40000000", "b2", "B", "B2x"] {
+        assert_eq!(address::parse_cell_name(invalid), None, "{invalid}");
+    }
+    // Wider than the sheet, e.g. for column labels.
+    assert_eq!(address::column_letters(701), "ZZ");
+    assert_eq!(address::column_letters(702), "AAA");
+    assert_eq!(address::parse_column("AAA"), Some(702));
+}
+
+#[wasm_bindgen_test]
+fn presenter_messages_are_sent_once_connected() {
+    let server = FakeServer::default();
+    let loader = Loader::new(server.clone());
+    assert!(!loader.send(PresenterMessage::Follow(true)));
+    loader.is_open.store(true, Ordering::Relaxed);
+    assert!(loader.send(PresenterMessage::Follow(true)));
+    assert_eq!(
+        server.requests.borrow().as_slice(),
+        [json!({"follow": true})]
+    );
+
+    // Viewports are ignored unless the user follows the presenter.
+    let present = json!({"present": {"viewport": {"top_row": 10, "focused": 260}}});
+    assert_eq!(Walkthrough::default().followed(&present.to_string()), None);
+}
+
+#[wasm_bindgen_test]
+fn pinned_cells_are_kept_wherever_the_user_scrolls() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    let scoreboard = 500 * WIDTH as u64;
+    let rows = scoreboard..scoreboard + WIDTH as u64;
+    cache.pin(vec![rows]);
+    // Pinned regions are sent once the connection is open, and again after reconnecting.
+    assert!(server.requests.borrow().is_empty());
+    cache.handle_event(WsEvent::Opened);
+    let pinned = json!({"pinned": [{"from": scoreboard, "to": scoreboard + WIDTH as u64}]});
+    assert_eq!(server.requests.borrow()[1], pinned);
+
+    cache.handle_event(text(&server.set_cell(scoreboard as i64, "=1+1", "2")));
+    cache.swap_staged();
+    // Scrolling through more rows than the cache holds evicts all other cells.
+    for id in 0..300 * WIDTH as u64 {
+        cache.get(id);
+    }
+    assert_eq!(cache.get(scoreboard).to_string(), "2");
+    assert_eq!(cache.get(scoreboard + 1).to_string(), "");
+
+    cache.pin(vec![]);
+    assert_eq!(
+        server.requests.borrow().last(),
+        Some(&json!({"pinned": []}))
+    );
+}
+
+#[wasm_bindgen_test]
+fn the_user_is_told_why_the_connection_was_closed() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    // Errors that keep the connection open aren't shown.
+    cache.handle_event(text(
+        &json!({"error": "Invalid cell range", "code": "validation"}),
+    ));
+    assert_eq!(cache.connection_error(), None);
+
+    let error = json!({"error": "Bye", "code": "server_shutdown", "close": "server_shutdown"});
+    cache.handle_event(text(&error));
+    cache.handle_event(WsEvent::Closed);
+    assert!(cache.connection_error().unwrap().contains("restarting"));
+    cache.handle_event(WsEvent::Opened);
+    assert_eq!(cache.connection_error(), None);
+}
+
+#[wasm_bindgen_test]
+async fn the_client_reconnects_to_a_drained_server() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    let id = 500 * WIDTH as u64;
+    cache.get(id);
+    TimeoutFuture::new(DEBOUNCE_MS).await;
+    server.take_requests();
+
+    cache.handle_event(text(&json!({"reconnect_within_secs": 30})));
+    assert!(cache.reconnects());
+    let error = json!({"error": "Bye", "code": "server_shutdown", "close": "server_shutdown"});
+    cache.handle_event(text(&error));
+    cache.handle_event(WsEvent::Closed);
+    assert_eq!(cache.connection_error(), None);
+
+    // The new connection requests the cells the user looks at.
+    cache.handle_event(WsEvent::Opened);
+    assert!(!cache.reconnects());
+    let prefetch = 100 * WIDTH as u64;
+    assert_eq!(server.take_requests(), vec![(id - prefetch, id + prefetch)]);
+}
+
+#[wasm_bindgen_test]
+fn heartbeats_refetch_missed_cells() {
+    let server = FakeServer::default();
+    server.set_cell(1, "one", "one");
+    server.set_cell(2, "two", "two");
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    for (from, to) in server.take_requests() {
+        for event in server.snapshot(from, to) {
+            cache.handle_event(event);
+        }
+    }
+
+    let heartbeat = |seq: u64| {
+        let region = json!({"from": 0, "to": 2600});
+        text(&json!({"server_time_ms": 0, "region": region, "seq": seq, "interval_secs": 5}))
+    };
+    cache.handle_event(heartbeat(2));
+    assert!(server.take_requests().is_empty());
+
+    // A change didn't arrive.
+    cache.handle_event(heartbeat(3));
+    assert_eq!(server.take_requests(), vec![(0, 2600)]);
+    cache.handle_event(heartbeat(3));
+    assert!(server.take_requests().is_empty());
+}
+
+#[wasm_bindgen_test]
+async fn regions_are_requested_again_when_the_server_asks_to_wait() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    assert_eq!(server.take_requests(), vec![(0, 2600)]);
+
+    let busy = json!({"error": "Too many queries", "code": "busy", "retry_after_secs": 0});
+    cache.handle_event(text(&busy));
+    assert!(server.take_requests().is_empty());
+    TimeoutFuture::new(DEBOUNCE_MS).await;
+    assert_eq!(server.take_requests(), vec![(0, 2600)]);
+
+    // Neither are regions that changed too often.
+    let throttled =
+        json!({"error": "API limit exceeded", "code": "rate_limited", "retry_after_secs": 0});
+    cache.handle_event(text(&throttled));
+    TimeoutFuture::new(DEBOUNCE_MS).await;
+    assert_eq!(server.take_requests(), vec![(0, 2600)]);
+
+    // Other errors aren't retried.
+    let invalid = json!({"error": "Invalid region", "code": "validation"});
+    cache.handle_event(text(&invalid));
+    TimeoutFuture::new(DEBOUNCE_MS).await;
+    assert!(server.take_requests().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn connections_without_heartbeats_are_stale() {
+    let server = FakeServer::default();
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    assert!(!cache.connection_stale(100.0));
+
+    let region = json!({"from": 0, "to": 2600});
+    cache.handle_event(text(
+        &json!({"server_time_ms": 0, "region": region, "seq": 0, "interval_secs": 5}),
+    ));
+    assert!(!cache.connection_stale(100.0));
+    assert!(!cache.connection_stale(110.0));
+    assert!(cache.connection_stale(116.0));
+    // Not again until the new connection sent a heartbeat.
+    assert!(!cache.connection_stale(130.0));
+}
+
+#[wasm_bindgen_test]
+fn columns_are_fitted_to_the_loaded_cells() {
+    let server = FakeServer::default();
+    server.set_cell(0, "short", "short");
+    server.set_cell(WIDTH as i64, "=A1", "a much longer value");
+    server.set_cell(1, "other column", "other column");
+    let mut cache = cache(&server);
+    cache.handle_event(WsEvent::Opened);
+    for (from, to) in server.take_requests() {
+        for event in server.snapshot(from, to) {
+            cache.handle_event(event);
+        }
+    }
+    cache.swap_staged();
+    let mut values = cache.loaded_values(0, WIDTH);
+    values.sort();
+    assert_eq!(values, vec!["a much longer value", "short"]);
+}
@@ -0,0 +1,213 @@
+//! Cells on the system clipboard are tab-separated values, one line per row, like desktop
+//! spreadsheets copy and paste them. Values containing tabs, line breaks or quotes are quoted.
+//!
+//! Formulas copied within the sheet are pasted with their references moved along, see
+//! [`repoint`].
+
+use xls_protocol::address::{self, column_letters, parse_column};
+use xls_protocol::CELL_IDS;
+
+/// The rows of values as tab-separated values.
+pub(crate) fn to_tsv(rows: &[Vec<String>]) -> String {
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .map(|value| quote(value))
+                .collect::<Vec<_>>()
+                .join("\t")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn quote(value: &str) -> String {
+    if value.contains(['\t', '\n', '\r', '"']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The rows of values of tab-separated values, e.g. copied from Excel or Google Sheets.
+pub(crate) fn parse_tsv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                value.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if value.is_empty() => quoted = true,
+            '\t' if !quoted => row.push(std::mem::take(&mut value)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut value));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => value.push(c),
+        }
+    }
+    // The last line usually has no line break.
+    if !value.is_empty() || !row.is_empty() {
+        row.push(value);
+        rows.push(row);
+    }
+    rows
+}
+
+/// A cell reference like `B10`, whose column or row is anchored by a `$` like in `$B$10`.
+#[derive(Copy, Clone)]
+pub(crate) struct Reference {
+    row: i64,
+    column: i64,
+    row_anchored: bool,
+    column_anchored: bool,
+}
+
+impl Reference {
+    /// The reference at the start of `chars` with its length in chars, if there is one.
+    pub(crate) fn parse(chars: &[char]) -> Option<(Reference, usize)> {
+        let mut end = 0;
+        let mut take = |accept: fn(&char) -> bool| {
+            let start = end;
+            while chars.get(end).is_some_and(accept) {
+                end += 1;
+            }
+            chars[start..end].iter().collect::<String>()
+        };
+        let column_anchored = !take(|c| *c == '$').is_empty();
+        let letters = take(char::is_ascii_uppercase);
+        let row_anchored = !take(|c| *c == '$').is_empty();
+        let digits = take(char::is_ascii_digit);
+        // Names going on, e.g. the function `LOG10(`, aren't references.
+        if chars
+            .get(end)
+            .is_some_and(|c| is_name_char(*c) || *c == '(')
+        {
+            return None;
+        }
+        let reference = Reference {
+            row: digits.parse().ok()?,
+            column: parse_column(&letters).filter(|column| *column < address::COLUMNS)?,
+            row_anchored,
+            column_anchored,
+        };
+        Some((reference, end))
+    }
+
+    pub(crate) fn in_sheet(&self) -> bool {
+        (0..address::COLUMNS).contains(&self.column)
+            && (0..CELL_IDS.end / address::COLUMNS).contains(&self.row)
+    }
+
+    /// The reference `rows` and `cols` away, `#REF!` if that is outside of the sheet.
+    fn moved(&self, rows: i64, cols: i64) -> String {
+        let moved = Reference {
+            row: if self.row_anchored {
+                self.row
+            } else {
+                self.row + rows
+            },
+            column: match self.column_anchored {
+                true => self.column,
+                false => self.column + cols,
+            },
+            ..*self
+        };
+        if !moved.in_sheet() {
+            return String::from("#REF!");
+        }
+        let anchor = |anchored: bool| if anchored { "$" } else { "" };
+        format!(
+            "{}{}{}{}",
+            anchor(self.column_anchored),
+            column_letters(moved.column),
+            anchor(self.row_anchored),
+            moved.row
+        )
+    }
+}
+
+pub(crate) fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+/// A formula pasted `rows` rows and `cols` columns away from where it was copied: relative
+/// references like `A1` move along, the anchored parts of `$A$1`, `$A1` or `A$1` stay. References
+/// moved off the sheet become `#REF!`, like in other spreadsheets. Values that aren't formulas are
+/// pasted as they are.
+pub(crate) fn repoint(value: &str, rows: i64, cols: i64) -> String {
+    if !value.starts_with('=') {
+        return value.to_string();
+    }
+    let chars: Vec<char> = value.chars().collect();
+    let mut repointed = String::new();
+    let mut quote = None;
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        if let Some(q) = quote {
+            // Text in quotes is kept, e.g. `="A1"`.
+            if c == q {
+                quote = None;
+            }
+        } else if c == '"' || c == '\'' {
+            quote = Some(c);
+        } else if idx == 0 || !is_name_char(chars[idx - 1]) {
+            if let Some((reference, len)) = Reference::parse(&chars[idx..]) {
+                repointed.push_str(&reference.moved(rows, cols));
+                idx += len;
+                continue;
+            }
+        }
+        repointed.push(c);
+        idx += 1;
+    }
+    repointed
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn clipboard_cells_are_tab_separated() {
+        let rows = vec![
+            vec![String::from("Item"), String::from("Price")],
+            vec![String::from("say \"hi\""), String::from("1\t2")],
+        ];
+        let tsv = to_tsv(&rows);
+        assert_eq!(tsv, "Item\tPrice\n\"say \"\"hi\"\"\"\t\"1\t2\"");
+        assert_eq!(parse_tsv(&tsv), rows);
+
+        // Excel ends lines with `\r\n`, also the last one, and quotes line breaks.
+        let excel = "a\t\tc\r\n\"two\r\nlines\"\r\n";
+        assert_eq!(
+            parse_tsv(excel),
+            vec![vec!["a", "", "c"], vec!["two\r\nlines"]]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn pasted_formulas_are_repointed() {
+        let repoint = repoint;
+        assert_eq!(repoint("=A1+B2", 1, 1), "=B2+C3");
+        assert_eq!(repoint("=SUM(A1:A10)", 2, 0), "=SUM(A3:A12)");
+        // Anchored columns and rows stay.
+        assert_eq!(repoint("=$A$1*A1", 3, 2), "=$A$1*C4");
+        assert_eq!(repoint("=$A1+A$1", 3, 2), "=$A4+C$1");
+        // Strings and function names are kept.
+        assert_eq!(repoint("=CONCAT(\"A1\", B1)", 1, 0), "=CONCAT(\"A1\", B2)");
+        assert_eq!(repoint("=LOG10(A1)", 0, 1), "=LOG10(B1)");
+        // References moved off the sheet break, values aren't formulas.
+        assert_eq!(repoint("=A1+B5", -2, -1), "=#REF!+A3");
+        assert_eq!(repoint("A1", 1, 1), "A1");
+    }
+}
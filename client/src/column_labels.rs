@@ -0,0 +1,106 @@
+//! Labels shown in the column headers instead of the letters, e.g. `Price` instead of `A`.
+//!
+//! Personal labels are stored in the browser and take precedence over the labels shared with
+//! everyone through the server.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use egui::mutex::RwLock;
+use ehttp::Request;
+use log::{debug, warn};
+use xls_protocol::ColumnLabel;
+
+use crate::storage;
+
+/// Key of the personal labels in the local storage of the browser.
+const STORAGE_KEY: &str = "column_labels";
+
+pub(crate) struct ColumnLabels {
+    server: String,
+    personal: BTreeMap<u32, String>,
+    shared: Arc<RwLock<BTreeMap<u32, String>>>,
+}
+
+impl ColumnLabels {
+    /// Loads the personal labels and fetches the shared ones from `server`.
+    pub(crate) fn load(server: &str, egui_ctx: egui::Context) -> Self {
+        let shared = Arc::new(RwLock::new(BTreeMap::new()));
+        {
+            let shared = shared.clone();
+            let request = Request::get(format!("{server}/api/column-labels"));
+            ehttp::fetch(request, move |response| match response {
+                Ok(response) if response.ok => {
+                    match serde_json::from_slice::<Vec<ColumnLabel>>(&response.bytes) {
+                        Ok(labels) => {
+                            *shared.write() = labels
+                                .into_iter()
+                                .map(|label| (label.column, label.label))
+                                .collect();
+                            egui_ctx.request_repaint();
+                        }
+                        Err(e) => warn!("Invalid column labels: {e}"),
+                    }
+                }
+                Ok(response) => warn!("Failed to fetch column labels: {:?}", response.text()),
+                Err(e) => debug!("Failed to fetch column labels: {e}"),
+            });
+        }
+        Self {
+            server: server.to_string(),
+            personal: storage::load(STORAGE_KEY),
+            shared,
+        }
+    }
+
+    /// The label of a column, if it has one.
+    pub(crate) fn get(&self, column: u32) -> Option<String> {
+        self.personal
+            .get(&column)
+            .or(self.shared.read().get(&column))
+            .cloned()
+    }
+
+    /// The personal labels of all columns.
+    pub(crate) fn personal(&self) -> &BTreeMap<u32, String> {
+        &self.personal
+    }
+
+    /// Replaces all personal labels.
+    pub(crate) fn set_personal(&mut self, personal: BTreeMap<u32, String>) {
+        self.personal = personal;
+        storage::store(STORAGE_KEY, &self.personal);
+    }
+
+    /// Sets the personal label of a column, an empty label removes it.
+    pub(crate) fn set(&mut self, column: u32, label: &str) {
+        let label = label.trim();
+        if label.is_empty() {
+            self.personal.remove(&column);
+        } else {
+            self.personal.insert(column, label.to_string());
+        }
+        storage::store(STORAGE_KEY, &self.personal);
+    }
+
+    /// Shares the label of a column with everyone, an empty label removes it.
+    pub(crate) fn share(&self, column: u32, label: &str) {
+        let label = ColumnLabel {
+            column,
+            label: label.trim().to_string(),
+        };
+        if label.label.is_empty() {
+            self.shared.write().remove(&column);
+        } else {
+            self.shared.write().insert(column, label.label.clone());
+        }
+        let request = Request::json(format!("{}/api/column-labels", self.server), &label).unwrap();
+        ehttp::fetch(request, move |response| match response {
+            Ok(response) if !response.ok => {
+                warn!("Failed to share column label: {:?}", response.text())
+            }
+            Ok(_) => {}
+            Err(e) => debug!("Failed to share column label: {e}"),
+        });
+    }
+}
@@ -0,0 +1,84 @@
+//! Statistics of columns shown when hovering their headers: of the cells that are loaded, and of
+//! all cells once the user asked the server to compute them.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use egui::mutex::RwLock;
+use ehttp::Request;
+use log::{debug, warn};
+use xls_protocol::ColumnStatistics;
+
+/// Statistics of the loaded cells of a column.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct LoadedStatistics {
+    pub(crate) filled: usize,
+    pub(crate) numbers: usize,
+    pub(crate) sum: f64,
+}
+
+impl LoadedStatistics {
+    pub(crate) fn describe(&self) -> String {
+        let average = (self.numbers > 0).then(|| self.sum / self.numbers as f64);
+        describe(self.filled as u64, self.numbers as u64, self.sum, average)
+    }
+}
+
+/// The statistics of entire columns, computed by the server.
+pub(crate) struct EntireColumns {
+    server: String,
+    statistics: Arc<RwLock<BTreeMap<u32, ColumnStatistics>>>,
+}
+
+impl EntireColumns {
+    pub(crate) fn new(server: &str) -> Self {
+        Self {
+            server: server.to_string(),
+            statistics: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+
+    /// Fetches the statistics of all columns from the server.
+    pub(crate) fn compute(&self, egui_ctx: egui::Context) {
+        let statistics = self.statistics.clone();
+        let request = Request::get(format!("{}/api/column-statistics", self.server));
+        ehttp::fetch(request, move |response| match response {
+            Ok(response) if response.ok => {
+                match serde_json::from_slice::<Vec<ColumnStatistics>>(&response.bytes) {
+                    Ok(columns) => {
+                        *statistics.write() = columns
+                            .into_iter()
+                            .map(|column| (column.column, column))
+                            .collect();
+                        egui_ctx.request_repaint();
+                    }
+                    Err(e) => warn!("Invalid column statistics: {e}"),
+                }
+            }
+            Ok(response) => warn!("Failed to fetch column statistics: {:?}", response.text()),
+            Err(e) => debug!("Failed to fetch column statistics: {e}"),
+        });
+    }
+
+    /// The statistics of a column, if they were computed.
+    pub(crate) fn describe(&self, column: u32) -> Option<String> {
+        let statistics = self.statistics.read();
+        if statistics.is_empty() {
+            return None;
+        }
+        Some(match statistics.get(&column) {
+            Some(s) => describe(s.filled, s.numbers, s.sum, s.average),
+            // Columns without content have no row.
+            None => describe(0, 0, 0.0, None),
+        })
+    }
+}
+
+fn describe(filled: u64, numbers: u64, sum: f64, average: Option<f64>) -> String {
+    match average {
+        Some(average) => {
+            format!("{filled} filled, {numbers} numbers, sum {sum}, average {average}")
+        }
+        None => format!("{filled} filled, no numbers"),
+    }
+}
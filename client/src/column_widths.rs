@@ -0,0 +1,94 @@
+//! Widths of the columns: the widths the user dragged the columns to or fitted them to their
+//! content are stored in the browser.
+
+use std::collections::BTreeMap;
+
+use crate::storage;
+
+/// Key of the widths in the local storage of the browser.
+const STORAGE_KEY: &str = "column_widths";
+
+pub(crate) const DEFAULT_WIDTH: f32 = 100.0;
+pub(crate) const MIN_WIDTH: f32 = 25.0;
+/// Widest a column is fitted to its content, so one long text doesn't push the other columns
+/// out of view.
+const MAX_FITTED_WIDTH: f32 = 600.0;
+
+#[derive(Default)]
+pub(crate) struct ColumnWidths {
+    widths: BTreeMap<u32, f32>,
+    /// Set when a column was fitted, the table has to forget the widths it shows then.
+    reset: bool,
+    /// Whether widths changed since they were stored.
+    changed: bool,
+}
+
+impl ColumnWidths {
+    pub(crate) fn load() -> Self {
+        ColumnWidths {
+            widths: storage::load(STORAGE_KEY),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn get(&self, column: u32) -> f32 {
+        self.widths.get(&column).copied().unwrap_or(DEFAULT_WIDTH)
+    }
+
+    /// Records the width a column is shown with, e.g. while the user drags its border.
+    pub(crate) fn shown(&mut self, column: u32, width: f32) {
+        if (self.get(column) - width).abs() > 0.5 {
+            self.widths.insert(column, width);
+            self.changed = true;
+        }
+    }
+
+    /// Fits a column to the widest of `content_widths`.
+    pub(crate) fn fit(&mut self, column: u32, content_widths: impl IntoIterator<Item = f32>) {
+        let widest = content_widths
+            .into_iter()
+            .fold(MIN_WIDTH, f32::max)
+            .min(MAX_FITTED_WIDTH);
+        self.widths.insert(column, widest.ceil());
+        self.changed = true;
+        self.reset = true;
+    }
+
+    /// Whether the table has to forget the widths it shows to show the stored ones.
+    pub(crate) fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.reset)
+    }
+
+    /// Stores the widths if they changed, but not while the user still drags a border.
+    pub(crate) fn save(&mut self, dragging: bool) {
+        if self.changed && !dragging {
+            storage::store(STORAGE_KEY, &self.widths);
+            self.changed = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn columns_keep_a_usable_width() {
+        let mut widths = ColumnWidths::default();
+        assert_eq!(widths.get(0), DEFAULT_WIDTH);
+        widths.fit(0, [40.5, 80.0]);
+        assert_eq!(widths.get(0), 80.0);
+        assert!(widths.take_reset());
+        assert!(!widths.take_reset());
+        // Empty columns keep a width that can be grabbed, long texts don't hide the other columns.
+        widths.fit(1, []);
+        assert_eq!(widths.get(1), MIN_WIDTH);
+        widths.fit(2, [5000.0]);
+        assert!(widths.get(2) < 1000.0);
+        // Dragging a border changes the width.
+        widths.shown(0, 120.0);
+        assert_eq!(widths.get(0), 120.0);
+    }
+}
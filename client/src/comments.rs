@@ -0,0 +1,123 @@
+//! Short notes attached to cells, shared with everyone through the server. Cells with a note
+//! have a marker in their corner and show the note when hovered.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use egui::mutex::RwLock;
+use ehttp::Request;
+use log::{debug, warn};
+use xls_protocol::CellComment;
+
+/// Cells before and after a cell whose comments are fetched with it, like the cell cache.
+const PREFETCH: u64 = 1300;
+
+/// How often the comments of the visible cells are fetched again, in seconds.
+const REFRESH_SECS: f64 = 30.0;
+
+/// Longest comment, the server truncates longer ones.
+pub(crate) const MAX_COMMENT_CHARS: usize = 280;
+
+pub(crate) struct Comments {
+    server: String,
+    /// The ids whose comments were fetched last, with when.
+    fetched: Option<(Range<u64>, f64)>,
+    max_cells: u64,
+    shared: Arc<RwLock<BTreeMap<u64, String>>>,
+}
+
+impl Comments {
+    pub(crate) fn new(server: &str, max_cells: u64) -> Self {
+        Self {
+            server: server.to_string(),
+            fetched: None,
+            max_cells,
+            shared: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+
+    /// The comment of cell `id`, fetching the comments around it when they aren't known or are
+    /// outdated.
+    pub(crate) fn get(&mut self, ctx: &egui::Context, id: u64) -> Option<String> {
+        let now = ctx.input(|i| i.time);
+        let outdated = match &self.fetched {
+            Some((range, at)) => !range.contains(&id) || now - at > REFRESH_SECS,
+            None => true,
+        };
+        if outdated {
+            let range = id.saturating_sub(PREFETCH)..(id + PREFETCH).min(self.max_cells);
+            self.fetch(ctx, range.clone());
+            self.fetched = Some((range, now));
+        }
+        self.shared.read().get(&id).cloned()
+    }
+
+    fn fetch(&self, ctx: &egui::Context, range: Range<u64>) {
+        let url = format!(
+            "{}/api/comments/{}..{}",
+            self.server, range.start, range.end
+        );
+        let shared = self.shared.clone();
+        let ctx = ctx.clone();
+        ehttp::fetch(Request::get(url), move |response| match response {
+            Ok(response) if response.ok => match response.json::<Vec<CellComment>>() {
+                Ok(comments) => {
+                    let mut shared = shared.write();
+                    shared.retain(|id, _| !range.contains(id));
+                    shared.extend(
+                        comments
+                            .into_iter()
+                            .map(|comment| (comment.id as u64, comment.comment)),
+                    );
+                    ctx.request_repaint();
+                }
+                Err(e) => warn!("Invalid comments: {e}"),
+            },
+            Ok(response) => warn!("Failed to fetch comments: {:?}", response.text()),
+            Err(e) => debug!("Failed to fetch comments: {e}"),
+        });
+    }
+
+    /// Attaches a comment to cell `id` for everyone, an empty comment removes it.
+    pub(crate) fn comment(&self, id: u64, comment: &str) {
+        let comment = CellComment {
+            id: id as i64,
+            comment: normalized(comment),
+        };
+        // Shown right away, the server confirms it at the next refresh.
+        if comment.comment.is_empty() {
+            self.shared.write().remove(&id);
+        } else {
+            self.shared.write().insert(id, comment.comment.clone());
+        }
+        let request = Request::json(format!("{}/api/comments", self.server), &comment).unwrap();
+        ehttp::fetch(request, move |response| match response {
+            Ok(response) if !response.ok => {
+                warn!("Failed to save comment: {:?}", response.text())
+            }
+            Ok(_) => {}
+            Err(e) => debug!("Failed to save comment: {e}"),
+        });
+    }
+}
+
+/// `comment` as the server stores it: trimmed and at most [`MAX_COMMENT_CHARS`] long.
+pub(crate) fn normalized(comment: &str) -> String {
+    comment.trim().chars().take(MAX_COMMENT_CHARS).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn comments_are_trimmed_like_the_server_does() {
+        assert_eq!(normalized("  Source: 2023 report\n"), "Source: 2023 report");
+        assert_eq!(normalized(" "), "");
+        let long = "é".repeat(2 * MAX_COMMENT_CHARS);
+        assert_eq!(normalized(&long).chars().count(), MAX_COMMENT_CHARS);
+    }
+}
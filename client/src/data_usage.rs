@@ -0,0 +1,74 @@
+//! The data received from the server, so users on metered connections see what the live updates
+//! cost them. In the reduced update mode the statistics aren't streamed and scrolling fetches
+//! cells less eagerly.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::storage;
+
+/// Key of the reduced update mode in the local storage of the browser.
+const STORAGE_KEY: &str = "reduced_updates";
+
+/// How long scrolling has to pause before the cells are fetched.
+const FETCH_DEBOUNCE: Duration = Duration::from_millis(100);
+const REDUCED_FETCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+static WEBSOCKET_BYTES: AtomicU64 = AtomicU64::new(0);
+static STATS_BYTES: AtomicU64 = AtomicU64::new(0);
+static REDUCED: AtomicBool = AtomicBool::new(false);
+
+/// Restores the update mode the user picked last.
+pub(crate) fn load() {
+    REDUCED.store(storage::load(STORAGE_KEY), Ordering::Relaxed);
+}
+
+/// Counts a message received over the websocket.
+pub(crate) fn record_websocket(bytes: usize) {
+    WEBSOCKET_BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Counts a chunk of the statistics stream.
+pub(crate) fn record_stats(bytes: usize) {
+    STATS_BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Bytes received over the websocket and the statistics stream since the page was opened.
+pub(crate) fn received() -> (u64, u64) {
+    (
+        WEBSOCKET_BYTES.load(Ordering::Relaxed),
+        STATS_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+pub(crate) fn reduced() -> bool {
+    REDUCED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_reduced(reduced: bool) {
+    REDUCED.store(reduced, Ordering::Relaxed);
+    storage::store(STORAGE_KEY, &reduced);
+}
+
+/// How long scrolling has to pause before the cells are fetched, in the current mode.
+pub(crate) fn fetch_debounce() -> Duration {
+    match reduced() {
+        true => REDUCED_FETCH_DEBOUNCE,
+        false => FETCH_DEBOUNCE,
+    }
+}
+
+/// `bytes` for humans, e.g. `1.4 MB`.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
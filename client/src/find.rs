@@ -0,0 +1,183 @@
+//! Finds cells containing a text: the loaded cells are searched right away, the cells that
+//! aren't loaded can be found with the search of the server.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use egui::mutex::RwLock;
+use egui::{Key, TextEdit, Ui};
+use ehttp::Request;
+use log::debug;
+use xls_protocol::{Cell, ErrorResponse};
+
+use crate::cell_cache::CellCache;
+use crate::debouncer::Debouncer;
+
+/// Shortest text the server searches for.
+const MIN_SERVER_QUERY_CHARS: usize = 3;
+
+/// The match after (or before) the focused cell, starting over at the other end of the sheet.
+pub(crate) fn next_match(matches: &BTreeSet<u64>, focused: u64, forward: bool) -> Option<u64> {
+    if forward {
+        matches
+            .range(focused + 1..)
+            .next()
+            .or(matches.first())
+            .copied()
+    } else {
+        matches
+            .range(..focused)
+            .next_back()
+            .or(matches.last())
+            .copied()
+    }
+}
+
+/// The cells the server found for a text, or why it couldn't search.
+type ServerMatches = (String, Result<BTreeSet<u64>, String>);
+
+#[derive(Default)]
+pub(crate) struct Find {
+    query: String,
+    /// Whether the server is asked for cells that aren't loaded.
+    entire_sheet: bool,
+    /// The loaded cells containing `query`.
+    loaded: BTreeSet<u64>,
+    server: Arc<RwLock<Option<ServerMatches>>>,
+    /// Waits until the user stopped typing before asking the server.
+    debouncer: Option<Debouncer>,
+    focus: bool,
+}
+
+impl Find {
+    /// The loaded cells and the cells the server found for the current text.
+    fn matches(&self) -> BTreeSet<u64> {
+        let mut matches = self.loaded.clone();
+        if let Some((query, Ok(found))) = self.server.read().as_ref() {
+            if *query == self.query {
+                matches.extend(found);
+            }
+        }
+        matches
+    }
+
+    /// Asks the server for the cells containing the current text.
+    fn search_server(&mut self, ctx: egui::Context) {
+        if !self.entire_sheet || self.query.chars().count() < MIN_SERVER_QUERY_CHARS {
+            return;
+        }
+        let query = self.query.clone();
+        let server = self.server.clone();
+        let debouncer = self.debouncer.get_or_insert_with(Debouncer::new);
+        debouncer.debounce(Duration::from_millis(300), move || {
+            let host = CellCache::API_HOST.unwrap_or("http://localhost:3000");
+            let url = format!("{host}/api/search?q={}", url_encoded(&query));
+            ehttp::fetch(Request::get(url), move |response| {
+                let found = match response {
+                    Ok(response) if response.ok => {
+                        serde_json::from_slice::<Vec<Cell>>(&response.bytes)
+                            .map(|cells| cells.iter().map(|cell| cell.id as u64).collect())
+                            .map_err(|e| format!("Invalid search results: {e}"))
+                    }
+                    Ok(response) => Err(serde_json::from_slice::<ErrorResponse>(&response.bytes)
+                        .map_or_else(
+                            |_| format!("Search failed ({})", response.status),
+                            |e| e.error,
+                        )),
+                    Err(e) => {
+                        debug!("Failed to search: {e}");
+                        Err(String::from("The server is unreachable"))
+                    }
+                };
+                *server.write() = Some((query, found));
+                ctx.request_repaint();
+            });
+        });
+    }
+
+    /// Shows the search, returns the cell to go to when the user asks for the next or previous
+    /// match of the `focused` cell.
+    pub(crate) fn ui(&mut self, ui: &mut Ui, cache: &CellCache, focused: u64) -> Option<u64> {
+        let mut search = false;
+        let response = ui.add(
+            TextEdit::singleline(&mut self.query)
+                .hint_text("Find in the sheet")
+                .desired_width(200.0),
+        );
+        if std::mem::take(&mut self.focus) {
+            response.request_focus();
+        }
+        if response.changed() {
+            search = true;
+        }
+        if ui
+            .checkbox(&mut self.entire_sheet, "Search cells that aren't loaded")
+            .on_hover_text(format!(
+                "Asks the server, for texts of at least {MIN_SERVER_QUERY_CHARS} characters"
+            ))
+            .changed()
+        {
+            search = true;
+        }
+        if search {
+            self.loaded = match self.query.is_empty() {
+                true => BTreeSet::new(),
+                false => cache.search(&self.query),
+            };
+            self.search_server(ui.ctx().clone());
+        }
+
+        let mut forward = None;
+        // Enter goes to the next match and Shift+Enter to the previous one, like in browsers.
+        if response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+            forward = Some(!ui.input(|i| i.modifiers.shift));
+            response.request_focus();
+        }
+        let matches = self.matches();
+        ui.horizontal(|ui| {
+            if ui
+                .button("⏶")
+                .on_hover_text("Previous (Shift+Enter)")
+                .clicked()
+            {
+                forward = Some(false);
+            }
+            if ui.button("⏷").on_hover_text("Next (Enter)").clicked() {
+                forward = Some(true);
+            }
+            match matches.iter().position(|id| *id == focused) {
+                Some(idx) => ui.label(format!("{} of {}", idx + 1, matches.len())),
+                None if self.query.is_empty() => ui.label(""),
+                None => ui.label(format!("{} matches", matches.len())),
+            };
+        });
+        if let Some((query, Err(error))) = self.server.read().as_ref() {
+            if *query == self.query && self.entire_sheet {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+        }
+        // Cells loaded since the search are found when going to the next match.
+        if forward.is_some() && !self.query.is_empty() {
+            self.loaded = cache.search(&self.query);
+        }
+        forward.and_then(|forward| next_match(&self.matches(), focused, forward))
+    }
+
+    /// Focuses the text field the next time the search is shown.
+    pub(crate) fn focus(&mut self) {
+        self.focus = true;
+    }
+}
+
+/// `text` encoded for the query of a URL.
+fn url_encoded(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                char::from(byte).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,253 @@
+//! Explains the errors formulas evaluate to, like `#DIV/0!`, and points to the part of the
+//! formula that likely caused them.
+
+use std::ops::Range;
+
+use egui::text::{LayoutJob, TextFormat};
+use egui::{Stroke, TextStyle, Ui};
+use xls_protocol::functions::FUNCTIONS;
+
+use crate::clipboard::Reference;
+use crate::highlight::{self, Kind};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Explanation {
+    pub(crate) message: &'static str,
+    /// The bytes of the formula that likely caused the error, if they can be told.
+    pub(crate) culprit: Option<Range<usize>>,
+}
+
+/// Why `raw_value` evaluated to `error`, `None` for values that aren't errors of the formula
+/// engine and for `#CYCLE!`, which is explained with the cells of the cycle.
+pub(crate) fn explain(error: &str, raw_value: &str) -> Option<Explanation> {
+    let parts = parts(raw_value);
+    let (message, culprit) = match error {
+        "#DIV/0!" => ("Division by zero", divisor(raw_value, &parts)),
+        "#REF!" => (
+            "A reference is outside of the sheet, or a lookup reaches outside of its range",
+            outside_reference(raw_value, &parts),
+        ),
+        "#PARSE!" => (
+            "The formula can't be read, e.g. a parenthesis or a quote isn't closed",
+            unclosed(raw_value, &parts),
+        ),
+        "#ARG!" => (
+            "A function got the wrong number of arguments",
+            wrong_call(raw_value, &parts),
+        ),
+        "#CAST!" => (
+            "A value has the wrong type, e.g. text where a number is expected",
+            first(&parts, Kind::String),
+        ),
+        "#VALUE!" => (
+            "A referenced cell is empty, or a value is invalid like a date that doesn't exist",
+            single_reference(raw_value, &parts),
+        ),
+        "#DEPTH!" => (
+            "The formula depends on a chain of more than 32 cells referencing each other",
+            first(&parts, Kind::Reference),
+        ),
+        _ => return None,
+    };
+    Some(Explanation { message, culprit })
+}
+
+/// The tooltip of a cell whose formula `raw_value` evaluated to `error`.
+pub(crate) fn ui(ui: &mut Ui, error: &str, raw_value: &str, explanation: &Explanation) {
+    ui.strong(format!("{error} {}", explanation.message));
+    let Some(culprit) = &explanation.culprit else {
+        return;
+    };
+    let font = TextStyle::Monospace.resolve(ui.style());
+    let normal = TextFormat::simple(font.clone(), ui.visuals().text_color());
+    let error_color = ui.visuals().error_fg_color;
+    let highlighted = TextFormat {
+        underline: Stroke::new(1.0, error_color),
+        ..TextFormat::simple(font, error_color)
+    };
+    let mut job = LayoutJob::default();
+    job.append(&raw_value[..culprit.start], 0.0, normal.clone());
+    job.append(&raw_value[culprit.clone()], 0.0, highlighted);
+    job.append(&raw_value[culprit.end..], 0.0, normal);
+    ui.label(job);
+}
+
+/// The parts of a formula with their bytes.
+fn parts(raw_value: &str) -> Vec<(Kind, Range<usize>)> {
+    let mut start = 0;
+    highlight::spans(raw_value)
+        .into_iter()
+        .map(|(kind, text)| {
+            let range = start..start + text.len();
+            start = range.end;
+            (kind, range)
+        })
+        .collect()
+}
+
+fn first(parts: &[(Kind, Range<usize>)], kind: Kind) -> Option<Range<usize>> {
+    parts
+        .iter()
+        .find(|(other, _)| *other == kind)
+        .map(|(_, range)| range.clone())
+}
+
+/// What follows a `/`, preferably a literal `0`.
+fn divisor(raw_value: &str, parts: &[(Kind, Range<usize>)]) -> Option<Range<usize>> {
+    let divisors: Vec<&Range<usize>> = parts
+        .windows(2)
+        .filter(|pair| {
+            pair[0].0 == Kind::Other && raw_value[pair[0].1.clone()].trim_end().ends_with('/')
+        })
+        .map(|pair| &pair[1].1)
+        .collect();
+    divisors
+        .iter()
+        .find(|range| raw_value[(**range).clone()].parse::<f64>() == Ok(0.0))
+        .or(divisors.first())
+        .map(|range| (*range).clone())
+}
+
+/// The first reference to a row beyond the sheet.
+fn outside_reference(raw_value: &str, parts: &[(Kind, Range<usize>)]) -> Option<Range<usize>> {
+    parts
+        .iter()
+        .filter(|(kind, _)| *kind == Kind::Reference)
+        .find(|(_, range)| {
+            let chars: Vec<char> = raw_value[range.clone()].chars().collect();
+            Reference::parse(&chars).is_some_and(|(reference, _)| !reference.in_sheet())
+        })
+        .map(|(_, range)| range.clone())
+}
+
+/// A string that isn't closed, a `)` without a `(` or the last `(` without a `)`.
+fn unclosed(raw_value: &str, parts: &[(Kind, Range<usize>)]) -> Option<Range<usize>> {
+    let unclosed_string = parts.iter().find(|(kind, range)| {
+        let text = &raw_value[range.clone()];
+        *kind == Kind::String && (text.len() < 2 || !text.ends_with(&text[..1]))
+    });
+    if let Some((_, range)) = unclosed_string {
+        return Some(range.clone());
+    }
+    let mut open = vec![];
+    for (_, range) in parts.iter().filter(|(kind, _)| *kind != Kind::String) {
+        for (idx, c) in raw_value[range.clone()].char_indices() {
+            let idx = range.start + idx;
+            match c {
+                '(' => open.push(idx),
+                ')' if open.pop().is_none() => return Some(idx..idx + 1),
+                _ => {}
+            }
+        }
+    }
+    open.pop().map(|idx| idx..idx + 1)
+}
+
+/// The first call of a function with fewer or more arguments than it takes.
+fn wrong_call(raw_value: &str, parts: &[(Kind, Range<usize>)]) -> Option<Range<usize>> {
+    parts
+        .iter()
+        .filter(|(kind, _)| *kind == Kind::Function)
+        .find_map(|(_, name)| {
+            let function = FUNCTIONS
+                .iter()
+                .find(|function| function.name.eq_ignore_ascii_case(&raw_value[name.clone()]))?;
+            let (count, end) = arguments(&raw_value[name.end..]);
+            let (min, max) = arity(function.arguments);
+            let wrong = count < min || max.is_some_and(|max| count > max);
+            wrong.then(|| name.start..name.end + end)
+        })
+}
+
+/// The number of arguments of the call starting with the `(` at the start of `call`, and where
+/// the call ends.
+fn arguments(call: &str) -> (usize, usize) {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut commas = 0;
+    let mut empty = true;
+    for (idx, c) in call.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 1 => {
+                return (if empty { 0 } else { commas + 1 }, idx + 1);
+            }
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 1 => commas += 1,
+            _ => {}
+        }
+        if depth > 0 && c != '(' && !c.is_whitespace() {
+            empty = false;
+        }
+    }
+    (if empty { 0 } else { commas + 1 }, call.len())
+}
+
+/// The fewest and most arguments of a function with `arguments` like `number, [digits]`, no
+/// most if they go on like `value, ...`.
+fn arity(arguments: &str) -> (usize, Option<usize>) {
+    let names: Vec<&str> = arguments
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    let required = names
+        .iter()
+        .filter(|name| !name.starts_with('[') && **name != "...")
+        .count();
+    match names.contains(&"...") {
+        true => (required, None),
+        false => (required, Some(names.len())),
+    }
+}
+
+/// The first reference that isn't part of a range like `A1:B2`, ranges skip empty cells.
+fn single_reference(raw_value: &str, parts: &[(Kind, Range<usize>)]) -> Option<Range<usize>> {
+    let around = |idx: usize| parts.get(idx).map(|(_, range)| &raw_value[range.clone()]);
+    parts
+        .iter()
+        .enumerate()
+        .filter(|(_, (kind, _))| *kind == Kind::Reference)
+        .find(|(idx, _)| {
+            let before = idx.checked_sub(1).and_then(around);
+            !before.is_some_and(|text| text.ends_with(':'))
+                && !around(idx + 1).is_some_and(|text| text.starts_with(':'))
+        })
+        .map(|(_, (_, range))| range.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn formula_errors_point_to_their_cause() {
+        let culprit = |error: &str, raw_value: &'static str| {
+            let explanation = explain(error, raw_value).unwrap();
+            explanation.culprit.map(|range| &raw_value[range])
+        };
+        assert_eq!(culprit("#DIV/0!", "=A1/B1+A1/ 0"), Some("0"));
+        assert_eq!(culprit("#DIV/0!", "=A1/B1"), Some("B1"));
+        assert_eq!(culprit("#REF!", "=A1+B99999999"), Some("B99999999"));
+        assert_eq!(culprit("#REF!", "=VLOOKUP(1, A1:B2, 3)"), None);
+        assert_eq!(culprit("#PARSE!", "=SUM(A1, \"x)"), Some("\"x)"));
+        assert_eq!(culprit("#PARSE!", "=SUM(A1, ABS(2)"), Some("("));
+        assert_eq!(culprit("#PARSE!", "=1+2)"), Some(")"));
+        assert_eq!(
+            culprit("#ARG!", "=ABS(1) + ROUND(1, 2, (3))"),
+            Some("ROUND(1, 2, (3))")
+        );
+        assert_eq!(culprit("#ARG!", "=NOW(1)"), Some("NOW(1)"));
+        assert_eq!(culprit("#ARG!", "=SUM()"), Some("SUM()"));
+        assert_eq!(culprit("#CAST!", "=MIN(1, \"a\")"), Some("\"a\""));
+        assert_eq!(culprit("#VALUE!", "=SUM(A1:A3) + B2"), Some("B2"));
+        assert_eq!(culprit("#DEPTH!", "=1 + A1:A3"), Some("A1"));
+        // Cycles are explained with their cells.
+        assert_eq!(explain("#CYCLE!", "=A1"), None);
+    }
+}
@@ -0,0 +1,131 @@
+//! Colors the parts of a formula while it is edited: function names, cell references, strings and
+//! numbers.
+
+use egui::text::{LayoutJob, TextFormat};
+use egui::{Color32, FontId, Visuals};
+
+use crate::clipboard::{is_name_char, Reference};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Function,
+    Reference,
+    String,
+    Number,
+    /// Operators, separators and everything else.
+    Other,
+}
+
+impl Kind {
+    fn color(self, visuals: &Visuals) -> Color32 {
+        match (self, visuals.dark_mode) {
+            (Kind::Function, true) => Color32::from_rgb(97, 175, 239),
+            (Kind::Function, false) => Color32::from_rgb(0, 92, 197),
+            (Kind::Reference, true) => Color32::from_rgb(229, 192, 123),
+            (Kind::Reference, false) => Color32::from_rgb(176, 96, 0),
+            (Kind::String, true) => Color32::from_rgb(152, 195, 121),
+            (Kind::String, false) => Color32::from_rgb(34, 134, 58),
+            (Kind::Number, true) => Color32::from_rgb(209, 154, 102),
+            (Kind::Number, false) => Color32::from_rgb(153, 51, 153),
+            (Kind::Other, _) => visuals.text_color(),
+        }
+    }
+}
+
+/// `text` split into its parts, in order. Values that aren't formulas are one part.
+pub(crate) fn spans(text: &str) -> Vec<(Kind, String)> {
+    if !text.starts_with('=') {
+        return vec![(Kind::Other, text.to_string())];
+    }
+    let chars: Vec<char> = text.chars().collect();
+    let mut spans: Vec<(Kind, String)> = vec![];
+    let mut push = |kind: Kind, part: &[char]| match spans.last_mut() {
+        Some((last, text)) if *last == kind && kind == Kind::Other => text.extend(part),
+        _ => spans.push((kind, part.iter().collect())),
+    };
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        let starts_name = idx == 0 || !is_name_char(chars[idx - 1]);
+        let len_while =
+            |accept: fn(&char) -> bool| chars[idx..].iter().take_while(|c| accept(c)).count();
+        let (kind, len) = if c == '"' || c == '\'' {
+            // Up to the closing quote, or the end while it is typed.
+            let len = chars[idx + 1..]
+                .iter()
+                .position(|other| *other == c)
+                .map_or(chars.len() - idx, |end| end + 2);
+            (Kind::String, len)
+        } else if !starts_name {
+            (Kind::Other, 1)
+        } else if let Some((_, len)) = Reference::parse(&chars[idx..]) {
+            (Kind::Reference, len)
+        } else if c.is_ascii_digit() {
+            (Kind::Number, len_while(|c| c.is_ascii_digit() || *c == '.'))
+        } else if c.is_ascii_alphabetic() {
+            let len = len_while(|c| is_name_char(*c));
+            match chars.get(idx + len) {
+                Some('(') => (Kind::Function, len),
+                _ => (Kind::Other, len),
+            }
+        } else {
+            (Kind::Other, 1)
+        };
+        push(kind, &chars[idx..idx + len]);
+        idx += len;
+    }
+    spans
+}
+
+/// The layout of `text` in `font` with its parts colored for `visuals`.
+pub(crate) fn layout_job(text: &str, font: FontId, visuals: &Visuals) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    for (kind, part) in spans(text) {
+        let format = TextFormat::simple(font.clone(), kind.color(visuals));
+        job.append(&part, 0.0, format);
+    }
+    job
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn formulas_are_highlighted_by_part() {
+        use super::Kind::{self, Function, Number, Other, Reference};
+        let part = |kind, text: &str| (kind, String::from(text));
+        assert_eq!(
+            spans("=SUM(A1:$B$2)*1.5"),
+            vec![
+                part(Other, "="),
+                part(Function, "SUM"),
+                part(Other, "("),
+                part(Reference, "A1"),
+                part(Other, ":"),
+                part(Reference, "$B$2"),
+                part(Other, ")*"),
+                part(Number, "1.5"),
+            ]
+        );
+        assert_eq!(
+            spans("=CONCAT(\"A1\", LOG10(2), TRUE"),
+            vec![
+                part(Other, "="),
+                part(Function, "CONCAT"),
+                part(Other, "("),
+                part(Kind::String, "\"A1\""),
+                part(Other, ", "),
+                part(Function, "LOG10"),
+                part(Other, "("),
+                part(Number, "2"),
+                part(Other, "), TRUE"),
+            ]
+        );
+        // Strings being typed go to the end, values aren't formulas.
+        assert_eq!(spans("=\"abc")[1], part(Kind::String, "\"abc"));
+        assert_eq!(spans("A1 + 2"), vec![part(Other, "A1 + 2")]);
+    }
+}
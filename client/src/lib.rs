@@ -1,8 +1,34 @@
 #![warn(clippy::all, rust_2018_idioms)]
 mod app;
+mod appearance;
+mod autocomplete;
 mod cell_cache;
+mod clipboard;
+mod column_labels;
+mod column_statistics;
+mod column_widths;
+mod comments;
+mod data_usage;
 mod debouncer;
+mod find;
+mod formula_errors;
+mod highlight;
 mod http;
+mod meta;
+mod moderation;
+mod my_edits;
+mod offline;
+mod pinned;
 mod reference;
+mod renderer;
+mod retry;
+mod scratchpad;
+mod selection;
+mod settings;
+mod sheets;
+mod snippets;
+mod storage;
+mod walkthrough;
 
 pub use app::SpreadsheetApp;
+pub use renderer::{CellRenderer, RenderedCell};
@@ -0,0 +1,64 @@
+//! What the deployment allows, fetched from the server at startup so the same build of the app
+//! serves differently configured deployments. Until it arrives the public deployment is assumed.
+
+use std::sync::Arc;
+
+use egui::mutex::RwLock;
+use ehttp::Request;
+use log::{debug, warn};
+use xls_protocol::Meta;
+
+pub(crate) struct Deployment {
+    meta: Arc<RwLock<Meta>>,
+}
+
+impl Deployment {
+    /// Fetches the meta of the deployment at `server`.
+    pub(crate) fn load(server: &str, egui_ctx: egui::Context) -> Self {
+        let meta = Arc::new(RwLock::new(Meta::default()));
+        {
+            let meta = meta.clone();
+            let request = Request::get(format!("{server}/api/meta"));
+            ehttp::fetch(request, move |response| match response {
+                Ok(response) if response.ok => {
+                    match serde_json::from_slice::<Meta>(&response.bytes) {
+                        Ok(fetched) => {
+                            *meta.write() = fetched;
+                            egui_ctx.request_repaint();
+                        }
+                        Err(e) => warn!("Invalid meta: {e}"),
+                    }
+                }
+                // Older servers don't serve it.
+                Ok(response) => debug!("Failed to fetch meta: {:?}", response.text()),
+                Err(e) => debug!("Failed to fetch meta: {e}"),
+            });
+        }
+        Self { meta }
+    }
+
+    pub(crate) fn writes_allowed(&self) -> bool {
+        self.meta.read().writes_allowed
+    }
+
+    pub(crate) fn max_cell_chars(&self) -> usize {
+        self.meta.read().max_cell_chars as usize
+    }
+
+    pub(crate) fn rows(&self) -> u64 {
+        self.meta.read().rows
+    }
+
+    pub(crate) fn auth_required(&self) -> bool {
+        self.meta.read().auth_required
+    }
+
+    /// Whether the deployment enabled the experimental `feature`.
+    pub(crate) fn experimental(&self, feature: &str) -> bool {
+        self.meta
+            .read()
+            .experimental_features
+            .iter()
+            .any(|enabled| enabled == feature)
+    }
+}
@@ -0,0 +1,231 @@
+//! The moderator mode: with the admin token, every visible cell shows who wrote it last, and
+//! moderators clear cells and ban editors through the admin API.
+//!
+//! The token is only kept in memory, it isn't stored in the browser.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use egui::mutex::RwLock;
+use egui::{TextEdit, Ui};
+use ehttp::Request;
+use log::debug;
+use xls_protocol::address::cell_name;
+use xls_protocol::{BanRequest, CellAttribution, ClearRequest, ClearResponse, Region};
+
+/// Cells before and after a cell whose attribution is fetched with it, like the cell cache.
+const PREFETCH: u64 = 1300;
+
+/// How often the attribution of the visible cells is fetched again, in seconds.
+const REFRESH_SECS: f64 = 10.0;
+
+#[derive(Default)]
+struct Shared {
+    attribution: BTreeMap<u64, CellAttribution>,
+    /// The outcome of the last action, or why it failed.
+    message: Option<String>,
+    /// The server rejected the token, moderation is disabled.
+    unauthorized: bool,
+}
+
+pub(crate) struct Moderation {
+    server: String,
+    token: String,
+    enabled: bool,
+    /// The ids whose attribution was fetched last, with when.
+    fetched: Option<(Range<u64>, f64)>,
+    max_cells: u64,
+    shared: Arc<RwLock<Shared>>,
+}
+
+impl Moderation {
+    pub(crate) fn new(server: &str, max_cells: u64) -> Self {
+        Self {
+            server: server.to_string(),
+            token: String::new(),
+            enabled: false,
+            fetched: None,
+            max_cells,
+            shared: Arc::new(RwLock::new(Shared::default())),
+        }
+    }
+
+    fn disable(&mut self) {
+        self.enabled = false;
+        self.fetched = None;
+        self.shared.write().attribution.clear();
+    }
+
+    fn request(&self, mut request: Request) -> Request {
+        request
+            .headers
+            .insert("Authorization", format!("Bearer {}", self.token));
+        request
+    }
+
+    /// Who wrote cell `id` last, fetching the attribution around it when it isn't known or is
+    /// outdated.
+    pub(crate) fn get(&mut self, ctx: &egui::Context, id: u64) -> Option<CellAttribution> {
+        if std::mem::take(&mut self.shared.write().unauthorized) {
+            self.disable();
+        }
+        if !self.enabled {
+            return None;
+        }
+        let now = ctx.input(|i| i.time);
+        let outdated = match &self.fetched {
+            Some((range, at)) => !range.contains(&id) || now - at > REFRESH_SECS,
+            None => true,
+        };
+        if outdated {
+            let range = id.saturating_sub(PREFETCH)..(id + PREFETCH).min(self.max_cells);
+            self.fetch(ctx, range.clone());
+            self.fetched = Some((range, now));
+        }
+        self.shared.read().attribution.get(&id).cloned()
+    }
+
+    fn fetch(&self, ctx: &egui::Context, range: Range<u64>) {
+        let url = format!(
+            "{}/api/admin/attribution/{}..{}",
+            self.server, range.start, range.end
+        );
+        let shared = self.shared.clone();
+        let ctx = ctx.clone();
+        ehttp::fetch(self.request(Request::get(url)), move |response| {
+            let mut shared = shared.write();
+            match response {
+                Ok(response) if response.status == 401 => {
+                    shared.unauthorized = true;
+                    shared.message = Some(String::from("The admin token was rejected"));
+                }
+                Ok(response) if response.ok => match response.json::<Vec<CellAttribution>>() {
+                    Ok(attribution) => {
+                        shared.attribution.retain(|id, _| !range.contains(id));
+                        shared
+                            .attribution
+                            .extend(attribution.into_iter().map(|cell| (cell.id as u64, cell)));
+                    }
+                    Err(e) => shared.message = Some(format!("Invalid attribution: {e}")),
+                },
+                Ok(response) => {
+                    shared.message = Some(format!(
+                        "Failed to fetch the attribution: {}",
+                        response.text().unwrap_or_default()
+                    ))
+                }
+                Err(e) => debug!("Failed to fetch the attribution: {e}"),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Empties the cells of `regions`.
+    fn clear(&mut self, ctx: &egui::Context, regions: Vec<Region>) {
+        let url = format!("{}/api/admin/clear", self.server);
+        let request = Request::json(url, &ClearRequest { regions }).unwrap();
+        let shared = self.shared.clone();
+        let ctx = ctx.clone();
+        ehttp::fetch(self.request(request), move |response| {
+            shared.write().message = Some(match response {
+                Ok(response) if response.ok => match response.json::<ClearResponse>() {
+                    Ok(cleared) => format!("Cleared {} cells", cleared.cleared),
+                    Err(e) => format!("Invalid response: {e}"),
+                },
+                Ok(response) => format!(
+                    "Failed to clear the cells: {}",
+                    response.text().unwrap_or_default()
+                ),
+                Err(e) => format!("Failed to clear the cells: {e}"),
+            });
+            ctx.request_repaint();
+        });
+        // The cleared cells are attributed to the moderator.
+        self.fetched = None;
+    }
+
+    /// Rejects the updates of the editor with `ip` from now on.
+    fn ban(&self, ctx: &egui::Context, ip: String) {
+        let url = format!("{}/api/admin/bans", self.server);
+        let request = Request::json(url, &BanRequest { ip: ip.clone() }).unwrap();
+        let shared = self.shared.clone();
+        let ctx = ctx.clone();
+        ehttp::fetch(self.request(request), move |response| {
+            shared.write().message = Some(match response {
+                Ok(response) if response.ok => format!("Banned {ip}"),
+                Ok(response) => format!(
+                    "Failed to ban {ip}: {}",
+                    response.text().unwrap_or_default()
+                ),
+                Err(e) => format!("Failed to ban {ip}: {e}"),
+            });
+            ctx.request_repaint();
+        });
+    }
+
+    /// The contents of the moderation window, acting on the cells of `regions` and the editor
+    /// of the focused cell `focused`.
+    pub(crate) fn ui(&mut self, ui: &mut Ui, focused: u64, regions: Vec<Region>) {
+        if !self.enabled {
+            ui.label("Shows who wrote each cell, and clears cells and bans editors.");
+            let response = ui.add(
+                TextEdit::singleline(&mut self.token)
+                    .password(true)
+                    .hint_text("Admin token"),
+            );
+            let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if (ui.button("Enable").clicked() || submitted) && !self.token.is_empty() {
+                self.enabled = true;
+                self.shared.write().message = None;
+            }
+        } else {
+            let address = cell_name(focused as i64);
+            match self.get(ui.ctx(), focused) {
+                Some(attribution) => {
+                    ui.label(format!(
+                        "{address} was written by {} at {}",
+                        attribution.ip, attribution.ts
+                    ));
+                    if ui.button(format!("Ban {}", attribution.ip)).clicked() {
+                        self.ban(ui.ctx(), attribution.ip);
+                    }
+                }
+                None => {
+                    ui.label(format!("{address} was never written"));
+                }
+            }
+            let cells: i64 = regions.iter().map(|region| region.to - region.from).sum();
+            if ui.button(format!("Clear {cells} selected cells")).clicked() {
+                self.clear(ui.ctx(), regions);
+            }
+            if ui.button("Disable").clicked() {
+                self.disable();
+            }
+        }
+        if let Some(message) = &self.shared.read().message {
+            ui.label(message);
+        }
+    }
+}
+
+/// The start of an IP, to fit into a cell.
+pub(crate) fn short_ip(ip: &str) -> &str {
+    match ip.char_indices().nth(8) {
+        Some((end, _)) => &ip[..end],
+        None => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn ips_are_shortened() {
+        assert_eq!(short_ip("127.0.0.1"), "127.0.0.");
+        assert_eq!(short_ip("::1"), "::1");
+    }
+}
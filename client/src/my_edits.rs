@@ -0,0 +1,55 @@
+//! The cells edited in this session, so users can find their contributions to the shared sheet
+//! again after scrolling around.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Most edited cells that are remembered, the least recently edited ones are forgotten first.
+const MAX_EDITS: usize = 1000;
+
+/// The edited cells with when they were edited last, larger is more recent.
+static EDITS: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+
+/// Remembers that the user edited a cell.
+pub(crate) fn record(id: u64) {
+    let mut edits = EDITS.lock().unwrap();
+    let latest = edits.values().max().map_or(0, |latest| latest + 1);
+    edits.insert(id, latest);
+    if edits.len() > MAX_EDITS {
+        if let Some((&oldest, _)) = edits.iter().min_by_key(|(_, edited)| **edited) {
+            edits.remove(&oldest);
+        }
+    }
+}
+
+/// Whether the user edited a cell.
+pub(crate) fn contains(id: u64) -> bool {
+    EDITS.lock().unwrap().contains_key(&id)
+}
+
+/// The edited cells, the most recently edited first.
+pub(crate) fn recent() -> Vec<u64> {
+    let edits = EDITS.lock().unwrap();
+    let mut recent: Vec<(u64, u64)> = edits.iter().map(|(id, edited)| (*id, *edited)).collect();
+    recent.sort_by_key(|(_, edited)| std::cmp::Reverse(*edited));
+    recent.into_iter().map(|(id, _)| id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn my_edits_are_listed_most_recent_first() {
+        for id in [900_001, 900_002, 900_001] {
+            record(id);
+        }
+        assert!(contains(900_002));
+        assert!(!contains(900_003));
+        let recent = recent();
+        let position = |id| recent.iter().position(|edited| *edited == id).unwrap();
+        assert!(position(900_001) < position(900_002));
+    }
+}
@@ -0,0 +1,90 @@
+//! Keeps the edits that could not be sent because the server is unreachable and sends them again
+//! once it is back. Until then the cells show what their formula evaluates to locally.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use xlformula_engine::types::{Boolean, Error, Value};
+use xlformula_engine::{calculate, parse_formula, NoCustomFunction};
+use xls_protocol::address;
+use xls_protocol::UpdateRequest;
+
+use crate::cell_cache::update_cell;
+use crate::debouncer::Debouncer;
+
+/// How long to wait before sending the pending edits again.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Edits that could not be sent with the URL to send them to, only the latest edit of a cell is
+/// kept.
+static PENDING: Mutex<BTreeMap<i64, (String, UpdateRequest)>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    static RETRY: RefCell<Debouncer> = RefCell::new(Debouncer::new());
+}
+
+/// Keeps an edit the server didn't receive and schedules sending it again.
+pub(crate) fn enqueue(url: String, request: UpdateRequest) {
+    PENDING.lock().unwrap().insert(request.id, (url, request));
+    RETRY.with(|retry| retry.borrow_mut().debounce(RETRY_DELAY, retry_pending));
+}
+
+/// Forgets the pending edit of a cell, it is replaced by a newer one.
+pub(crate) fn forget(id: i64) {
+    PENDING.lock().unwrap().remove(&id);
+}
+
+/// The edits that didn't reach the server yet.
+pub(crate) fn pending() -> Vec<UpdateRequest> {
+    PENDING
+        .lock()
+        .unwrap()
+        .values()
+        .map(|(_, request)| request.clone())
+        .collect()
+}
+
+/// Sends the pending edits again, the ones that fail again are enqueued again.
+pub(crate) fn retry_pending() {
+    let pending = std::mem::take(&mut *PENDING.lock().unwrap());
+    for (url, request) in pending.into_values() {
+        update_cell(url, request);
+    }
+}
+
+/// Evaluates a formula without the server, looking up the values of referenced cells with
+/// `lookup`. Returns `None` for formulas that can't be evaluated locally, e.g. because they use
+/// functions of the pipeline or reference cells that aren't loaded.
+pub(crate) fn evaluate(raw_value: &str, lookup: impl Fn(u64) -> Option<String>) -> Option<String> {
+    if !raw_value.starts_with('=') {
+        return Some(raw_value.to_string());
+    }
+    let data_function = |name: String| {
+        address::parse_cell_name(&name)
+            .and_then(|id| lookup(id as u64))
+            .filter(|value| !value.is_empty())
+            .map_or(Value::Error(Error::Value), |value| parse_value(&value))
+    };
+    let formula = parse_formula::parse_string_to_formula(raw_value, None::<NoCustomFunction<'_>>);
+    match calculate::calculate_formula(formula, Some(&data_function)) {
+        Value::Error(_) => None,
+        result => Some(calculate::result_to_string(result)),
+    }
+}
+
+/// Interprets a computed value like the pipeline does.
+fn parse_value(value: &str) -> Value {
+    if let Ok(number) = value.parse::<f32>() {
+        return Value::Number(number);
+    }
+    if let Ok(boolean) = value.parse::<bool>() {
+        return Value::Boolean(if boolean {
+            Boolean::True
+        } else {
+            Boolean::False
+        });
+    }
+    Value::Text(value.to_string())
+}
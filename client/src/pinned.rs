@@ -0,0 +1,69 @@
+//! Rows the user pinned, e.g. a scoreboard, whose cells are kept up to date wherever the user
+//! scrolls. The pinned rows are stored in the browser.
+
+use std::ops::{Range, RangeInclusive};
+
+use crate::storage;
+
+/// Key of the pinned rows in the local storage of the browser.
+const STORAGE_KEY: &str = "pinned_rows";
+
+/// Most pinned regions, the server doesn't accept more by default.
+pub(crate) const MAX_PINNED: usize = 4;
+
+/// Most rows of a pinned region, the server truncates larger regions by default.
+const MAX_ROWS: usize = 400;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct PinnedRows {
+    rows: Vec<RangeInclusive<usize>>,
+}
+
+impl PinnedRows {
+    pub(crate) fn load() -> Self {
+        Self::from(storage::load::<Vec<RangeInclusive<usize>>>(STORAGE_KEY))
+    }
+
+    pub(crate) fn rows(&self) -> &[RangeInclusive<usize>] {
+        &self.rows
+    }
+
+    /// Pins rows, at most `MAX_ROWS` of them. Returns false if `MAX_PINNED` regions are pinned
+    /// already.
+    pub(crate) fn pin(&mut self, rows: RangeInclusive<usize>) -> bool {
+        if self.rows.len() >= MAX_PINNED {
+            return false;
+        }
+        let end = (*rows.end()).min(rows.start() + MAX_ROWS - 1);
+        self.rows.push(*rows.start()..=end);
+        storage::store(STORAGE_KEY, &self.rows);
+        true
+    }
+
+    pub(crate) fn unpin(&mut self, index: usize) {
+        self.rows.remove(index);
+        storage::store(STORAGE_KEY, &self.rows);
+    }
+
+    /// Replaces all pinned rows, e.g. with imported ones.
+    pub(crate) fn replace(&mut self, pinned: PinnedRows) {
+        *self = pinned;
+        storage::store(STORAGE_KEY, &self.rows);
+    }
+
+    /// The cell ids of the pinned rows in a sheet with `num_cols` columns.
+    pub(crate) fn ids(&self, num_cols: usize) -> Vec<Range<u64>> {
+        let num_cols = num_cols as u64;
+        self.rows
+            .iter()
+            .map(|rows| *rows.start() as u64 * num_cols..(*rows.end() as u64 + 1) * num_cols)
+            .collect()
+    }
+}
+
+impl From<Vec<RangeInclusive<usize>>> for PinnedRows {
+    fn from(mut rows: Vec<RangeInclusive<usize>>) -> Self {
+        rows.truncate(MAX_PINNED);
+        Self { rows }
+    }
+}
@@ -1,6 +1,15 @@
-use egui::{CollapsingHeader, Ui};
+use egui::{CollapsingHeader, Label, RichText, Sense, TextEdit, Ui};
+use xls_protocol::functions::{Category, Function, FUNCTIONS};
 
-pub struct ReferenceWindow {}
+#[derive(Default)]
+pub struct ReferenceWindow {
+    /// Only lines containing it are shown, unless it is part of the title of their section.
+    query: String,
+    /// Whether the title of the section being shown contains the query.
+    title_matches: bool,
+    /// An example the user clicked to insert it into the focused cell.
+    pub(crate) inserted: Option<String>,
+}
 
 impl ReferenceWindow {
     pub(crate) fn ui(&mut self, ui: &mut Ui) {
@@ -8,90 +17,197 @@ impl ReferenceWindow {
 
         // Title
         ui.heading("Formula Help");
+        ui.add(TextEdit::singleline(&mut self.query).hint_text("Search functions and topics"));
 
         // Features section
-        CollapsingHeader::new("Features")
-            .default_open(true)
-            .show(ui, |ui| {
-                ui.label("The formula engine support:");
-                ui.label("• Any numbers, negative and positive, as float or integer.");
-                ui.label("• Arithmetic operations: +, -, /, *, ^");
-                ui.label("• Logical operations: AND(), OR(), NOT(), XOR().");
-                ui.label("• Comparison operations: =, >, >=, <, <=, <>.");
-                ui.label("• String operation: & (concatenation).");
-                ui.label("• Built-in variables: TRUE, FALSE.");
-                ui.label("• Excel functions: ABS(), SUM(), PRODUCT(), AVERAGE(), RIGHT(), LEFT(), IF(), ISBLANK().");
-                ui.label("• Operations on lists of values (one-dimensional range).");
-                ui.label("• Add or subtract dates and Excel function DAYS().");
-                ui.label("• Custom functions with number arguments.");
-            });
-
-        CollapsingHeader::new("Examples")
-            .default_open(false)
-            .show(ui, |ui| {
-                self.add_examples(ui);
-            });
+        self.section(ui, "Features", true, |rw, ui| {
+            rw.text(ui, "The formula engine support:");
+            rw.text(
+                ui,
+                "• Any numbers, negative and positive, as float or integer.",
+            );
+            rw.text(ui, "• Arithmetic operations: +, -, /, *, ^");
+            rw.text(ui, "• Comparison operations: =, >, >=, <, <=, <>.");
+            rw.text(ui, "• String operation: & (concatenation).");
+            rw.text(ui, "• Built-in variables: TRUE, FALSE.");
+            for category in Category::ALL {
+                let names: Vec<String> = FUNCTIONS
+                    .iter()
+                    .filter(|function| function.category == category)
+                    .map(|function| format!("{}()", function.name))
+                    .collect();
+                rw.text(
+                    ui,
+                    &format!("• {}: {}.", category.title(), names.join(", ")),
+                );
+            }
+            rw.text(
+                ui,
+                "• Operations on lists of values and ranges like A1:B10.",
+            );
+            rw.text(ui, "• Add or subtract dates and Excel function DAYS().");
+            rw.text(ui, "• Custom functions with number arguments.");
+        });
+
+        self.section(ui, "Functions", false, |rw, ui| {
+            for category in Category::ALL {
+                rw.section(ui, category.title(), true, |rw, ui| {
+                    for function in FUNCTIONS.iter().filter(|f| f.category == category) {
+                        rw.function(ui, function);
+                    }
+                });
+            }
+            rw.text(
+                ui,
+                "• Press Tab while typing a function name to complete it.",
+            );
+        });
+
+        self.section(ui, "Examples", false, |rw, ui| {
+            rw.add_examples(ui);
+        });
 
         // Logical Expressions section
-        CollapsingHeader::new("Logical Expressions")
-            .default_open(false)
-            .show(ui, |ui| {
-                ui.label("Supports logical expressions like AND(), OR(), and more:");
-                ui.monospace("=2>=1");
-                ui.monospace("=OR(1>1,1<>1)");
-                ui.monospace("=AND(\"test\",\"True\", 1, true)");
-            });
+        self.section(ui, "Logical Expressions", false, |rw, ui| {
+            rw.text(
+                ui,
+                "Supports logical expressions like AND(), OR(), and more:",
+            );
+            rw.example(ui, "=2>=1");
+            rw.example(ui, "=OR(1>1,1<>1)");
+            rw.example(ui, "=AND(\"test\",\"True\", 1, true)");
+        });
 
         // Date Handling section
-        CollapsingHeader::new("Handling Dates")
-            .default_open(false)
-            .show(ui, |ui| {
-                ui.label("Supports adding, subtracting, and calculating days between dates:");
-                ui.label("• Dates must be written in the RFC 3339: e.g., 2019-03-01T02:00:00.000Z");
-                ui.monospace("=DAYS(A12, A32)");
-            });
-
-        CollapsingHeader::new("References")
-            .default_open(false)
-            .show(ui, |ui| {
-                ui.label("Supports referencing other cells:");
-                ui.monospace("=A12");
-                ui.label("• The demo limits the number of allowed references per cell to 1000.");
-            });
+        self.section(ui, "Handling Dates", false, |rw, ui| {
+            rw.text(ui, "Supports adding, subtracting, and calculating days between dates:");
+            rw.text(ui, "• Dates must be written in the RFC 3339: e.g., 2019-03-01T02:00:00.000Z");
+            rw.example(ui, "=DAYS(A12, A32)");
+            rw.text(ui, "• Create dates with DATE(year, month, day) and take them apart with YEAR(), MONTH() and DAY():");
+            rw.example(ui, "=DAYS(DATE(2025, 12, 24), A12)");
+            rw.example(ui, "=YEAR(A12)");
+            rw.text(ui, "• Format dates and numbers with TEXT(value, format) and Excel format codes:");
+            rw.example(ui, r#"=TEXT(A12, "dd.mm.yyyy hh:mm")"#);
+            rw.example(ui, r#"=TEXT(DATE(2025, 1, 5), "dddd, d mmmm")"#);
+            rw.example(ui, r#"=TEXT(1234.5, "$#,##0.00")"#);
+            rw.example(ui, r#"=TEXT(0.25, "0%")"#);
+            rw.text(ui, "• NOW() and TODAY() return the current time and date, they are updated every minute:");
+            rw.example(ui, r#"=TEXT(NOW(), "hh:mm")"#);
+            rw.example(ui, "=DAYS(DATE(2025, 12, 24), TODAY())");
+        });
+
+        self.section(ui, "Random Numbers", false, |rw, ui| {
+            rw.text(ui, "RAND() returns a number between 0 and 1, RANDBETWEEN(low, high) a whole number between low and high:");
+            rw.example(ui, "=RAND()");
+            rw.example(ui, "=RANDBETWEEN(1, 6)");
+            rw.text(ui, "• Random numbers are drawn once when the cell is written and stay the same until it is edited again.");
+            rw.text(ui, "• Combined with NOW() or TODAY() they are drawn again whenever the cell is updated.");
+        });
+
+        self.section(ui, "Lookups", false, |rw, ui| {
+            rw.text(ui, "Look up a value in the first column (row) of a range and return the cell in another column (row):");
+            rw.example(ui, "=VLOOKUP(\"pear\", A1:C10, 3, FALSE)");
+            rw.example(ui, "=HLOOKUP(2019, B1:F3, 2)");
+            rw.text(ui, "• The first column (row) is searched, the index counts from 1.");
+            rw.text(ui, "• With FALSE only exact matches are found, otherwise the first column (row) must be sorted and the largest value not above the searched one matches.");
+            rw.text(ui, "• The range counts towards the limit of 1000 cells per formula.");
+        });
+
+        self.section(ui, "References", false, |rw, ui| {
+            rw.text(ui, "Supports referencing other cells:");
+            rw.example(ui, "=A12");
+            rw.text(ui, "• The demo limits the number of allowed references per cell to 1000.");
+            rw.text(ui, "Ranges reference every cell of a rectangle:");
+            rw.example(ui, "=SUM(A1:B10)");
+            rw.example(ui, "=MAX(A1:A5, C1:C5)");
+            rw.text(ui, "• The ranges of a formula can cover at most 1000 cells together.");
+            rw.text(ui, "• Empty cells in a range are skipped.");
+            rw.text(ui, "• Cells that reference themselves, directly or through other cells, show #CYCLE! and are outlined in red, hover them to see the cells of the cycle.");
+            rw.text(ui, "• Formulas depending on a chain of more than 32 cells referencing each other show #DEPTH!.");
+        });
+
+        self.section(ui, "Selecting Cells", false, |rw, ui| {
+            rw.text(ui, "• Shift-click a cell or press Shift and an arrow key to select a range.");
+            rw.text(ui, "• Drag the outline of the selection to move its cells.");
+            rw.text(ui, "• Ctrl+D copies the top cells of the selection down, Ctrl+R the left cells to the right.");
+        });
+    }
+
+    fn add_examples(&mut self, ui: &mut Ui) {
+        self.section(ui, "Parsing and Evaluating Formulas", true, |rw, ui| {
+            rw.example(ui, "=1+2");
+            rw.example(ui, "=(1*(2+3))*2");
+            rw.example(ui, "=1+3/0");
+        });
+
+        self.section(ui, "Concatenating Strings", true, |rw, ui| {
+            rw.example(ui, r#"="Hello " & " World!""#);
+            rw.text(
+                ui,
+                "• Concatenating number and string results in a #CAST! error.",
+            );
+        });
+
+        self.section(ui, "Working with Lists", true, |rw, ui| {
+            rw.example(ui, "={1,2,3}+{1,2,3}");
+        });
+    }
+
+    /// A collapsible section, open while searching.
+    fn section(
+        &mut self,
+        ui: &mut Ui,
+        title: &str,
+        default_open: bool,
+        add_contents: impl FnOnce(&mut Self, &mut Ui),
+    ) {
+        let searching = !self.query.is_empty();
+        let outer = self.title_matches;
+        self.title_matches = outer || self.matches(title);
+        CollapsingHeader::new(title)
+            .default_open(default_open)
+            .open(searching.then_some(true))
+            .show(ui, |ui| add_contents(self, ui));
+        self.title_matches = outer;
+    }
+
+    fn text(&mut self, ui: &mut Ui, text: &str) {
+        if self.title_matches || self.matches(text) {
+            ui.label(text);
+        }
+    }
+
+    /// A function of the catalog, shown if its name or description contains the query.
+    fn function(&mut self, ui: &mut Ui, function: &Function) {
+        if !self.title_matches
+            && !self.matches(function.name)
+            && !self.matches(function.description)
+        {
+            return;
+        }
+        ui.label(RichText::new(function.signature()).monospace().strong());
+        ui.label(function.description);
+        self.insertable(ui, function.example);
+    }
+
+    fn example(&mut self, ui: &mut Ui, formula: &str) {
+        if self.title_matches || self.matches(formula) {
+            self.insertable(ui, formula);
+        }
+    }
+
+    /// A formula that is inserted into the focused cell when clicked.
+    fn insertable(&mut self, ui: &mut Ui, formula: &str) {
+        let example = ui
+            .add(Label::new(RichText::new(formula).monospace()).sense(Sense::click()))
+            .on_hover_text("Click to insert into the focused cell");
+        if example.clicked() {
+            self.inserted = Some(formula.to_string());
+        }
     }
 
-    fn add_examples(&self, ui: &mut Ui) {
-        CollapsingHeader::new("Parsing and Evaluating Formulas")
-            .default_open(true)
-            .show(ui, |ui| {
-                ui.monospace("=1+2");
-                ui.monospace("=(1*(2+3))*2");
-                ui.monospace("=1+3/0");
-            });
-
-        CollapsingHeader::new("Concatenating Strings")
-            .default_open(true)
-            .show(ui, |ui| {
-                ui.monospace(r#"="Hello " & " World!""#);
-                ui.label("• Concatenating number and string results in a #CAST! error.");
-            });
-
-        CollapsingHeader::new("Excel Functions")
-            .default_open(true)
-            .show(ui, |ui| {
-                ui.monospace("=ABS(-1)");
-                ui.monospace(r#"=SUM(1,2,"3")"#);
-                ui.monospace("=PRODUCT(ABS(1),2*1, 3,4*1)");
-                ui.monospace("=RIGHT(\"apple\", 3)");
-                ui.monospace("=LEFT(\"apple\", 3)");
-                ui.monospace("=LEFT(\"apple\")");
-                ui.monospace("=IF(TRUE,1,0)");
-            });
-
-        CollapsingHeader::new("Working with Lists")
-            .default_open(true)
-            .show(ui, |ui| {
-                ui.monospace("={1,2,3}+{1,2,3}");
-            });
+    fn matches(&self, text: &str) -> bool {
+        text.to_lowercase()
+            .contains(&self.query.trim().to_lowercase())
     }
 }
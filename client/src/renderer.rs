@@ -0,0 +1,108 @@
+//! Hooks for embedders to draw and edit cells their own way, e.g. to show `=QR("...")` as a QR
+//! code, without changing [`crate::cell_cache::CellContent`].
+
+use egui::{Response, Ui};
+use xls_protocol::ValueType;
+
+/// A cell handed to a [`CellRenderer`].
+#[derive(Debug, Clone, Copy)]
+pub struct RenderedCell<'a> {
+    pub id: u64,
+    /// What the user typed, e.g. `=QR("hello")`.
+    pub raw_value: &'a str,
+    /// What the formula evaluated to.
+    pub computed_value: &'a str,
+    pub value_type: ValueType,
+}
+
+/// Draws the cells it matches instead of the default label.
+///
+/// ```ignore
+/// struct Qr;
+///
+/// impl CellRenderer for Qr {
+///     fn matches(&self, cell: &RenderedCell<'_>) -> bool {
+///         cell.raw_value.starts_with("=QR(")
+///     }
+///
+///     fn ui(&self, ui: &mut Ui, cell: &RenderedCell<'_>) -> Response {
+///         ui.add(qr_code(cell.computed_value))
+///     }
+/// }
+///
+/// app.register_renderer(Qr);
+/// ```
+pub trait CellRenderer {
+    /// Whether the renderer is responsible for a cell.
+    fn matches(&self, cell: &RenderedCell<'_>) -> bool;
+
+    /// Draws a cell that isn't edited. The response should sense clicks, they focus the cell.
+    fn ui(&self, ui: &mut Ui, cell: &RenderedCell<'_>) -> Response;
+
+    /// Edits the raw value of a cell, `None` for the default text field. Enter saves the value
+    /// and Escape discards it like for other cells.
+    fn editor(
+        &self,
+        _ui: &mut Ui,
+        _cell: &RenderedCell<'_>,
+        _raw_value: &mut String,
+    ) -> Option<Response> {
+        None
+    }
+}
+
+/// The registered renderers, the first one matching a cell draws it.
+#[derive(Default)]
+pub(crate) struct Renderers(Vec<Box<dyn CellRenderer>>);
+
+impl Renderers {
+    pub(crate) fn register(&mut self, renderer: impl CellRenderer + 'static) {
+        self.0.push(Box::new(renderer));
+    }
+
+    pub(crate) fn find(&self, cell: &RenderedCell<'_>) -> Option<&dyn CellRenderer> {
+        self.0
+            .iter()
+            .find(|renderer| renderer.matches(cell))
+            .map(|renderer| renderer.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn first_matching_renderer_draws_the_cell() {
+        struct Prefix(&'static str);
+
+        impl CellRenderer for Prefix {
+            fn matches(&self, cell: &RenderedCell<'_>) -> bool {
+                cell.raw_value.starts_with(self.0)
+            }
+
+            fn ui(&self, ui: &mut Ui, _cell: &RenderedCell<'_>) -> Response {
+                ui.label(self.0)
+            }
+        }
+
+        let mut renderers = Renderers::default();
+        renderers.register(Prefix("=QR("));
+        renderers.register(Prefix("="));
+        let cell = |raw_value| RenderedCell {
+            id: 0,
+            raw_value,
+            computed_value: "",
+            value_type: ValueType::String,
+        };
+        // Only the catch-all renderer matches other formulas.
+        let other_formula = cell("=1");
+        let qr = renderers.find(&cell("=QR(\"hello\")")).unwrap();
+        assert!(!qr.matches(&other_formula));
+        let formula = renderers.find(&cell("=1+1")).unwrap();
+        assert!(formula.matches(&other_formula));
+        assert!(renderers.find(&cell("hello")).is_none());
+    }
+}
@@ -0,0 +1,176 @@
+//! Sends cell updates again when the server is briefly unavailable or rate limits them, waiting
+//! longer after each attempt. Only one request per cell is in flight, a newer update of the cell
+//! is sent once it completed instead of racing it.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use ehttp::{Request, Response};
+use gloo_timers::callback::Timeout;
+use log::{debug, warn};
+use xls_protocol::UpdateRequest;
+
+use crate::offline;
+
+/// How often and how long to wait before sending a request again.
+pub(crate) struct RetryPolicy {
+    /// Attempts including the first one.
+    pub(crate) max_attempts: u32,
+    /// Wait after the first failed attempt, doubled after each attempt.
+    pub(crate) base_delay: Duration,
+    /// Longest wait between attempts, unless the server asks for a longer one.
+    pub(crate) max_delay: Duration,
+}
+
+/// The policy of cell saves and background changes, after it gave up the update is kept until
+/// the server is reachable again.
+pub(crate) const POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 4,
+    base_delay: Duration::from_millis(500),
+    max_delay: Duration::from_secs(8),
+};
+
+impl RetryPolicy {
+    /// How long to wait after the failed `attempt` (starting at 0), `None` if it was the last.
+    /// `jitter` in `0.0..1.0` spreads the retries of many clients over the second half of the
+    /// delay, a `Retry-After` of the server is waited at least.
+    pub(crate) fn delay(
+        &self,
+        attempt: u32,
+        retry_after: Option<Duration>,
+        jitter: f64,
+    ) -> Option<Duration> {
+        if attempt + 1 >= self.max_attempts {
+            return None;
+        }
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay);
+        let delay = delay.mul_f64(0.5 + jitter / 2.0);
+        Some(retry_after.map_or(delay, |retry_after| retry_after.max(delay)))
+    }
+}
+
+/// The `Retry-After` of a response, in seconds.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let secs = response.headers.get("retry-after")?.trim().parse().ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Cells with a request in flight, with the latest update of the cell made meanwhile.
+static IN_FLIGHT: Mutex<BTreeMap<i64, Option<(String, UpdateRequest)>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Sends an update of a cell, after the request in flight for the cell if there is one.
+pub(crate) fn send(url: String, update: UpdateRequest) {
+    let mut in_flight = IN_FLIGHT.lock().unwrap();
+    if let Some(next) = in_flight.get_mut(&update.id) {
+        *next = Some((url, update));
+        return;
+    }
+    in_flight.insert(update.id, None);
+    drop(in_flight);
+    attempt(url, update, 0);
+}
+
+fn attempt(url: String, update: UpdateRequest, attempt_nr: u32) {
+    let request = Request::json(&url, &update).unwrap();
+    ehttp::fetch(request, move |response| {
+        let (failure, wait) = match &response {
+            Ok(response) if response.status == 429 || response.status >= 500 => {
+                (format!("status {}", response.status), retry_after(response))
+            }
+            Ok(response) => {
+                if !response.ok {
+                    warn!("POST request failed: {:?}", response.text());
+                } else {
+                    // The server is reachable (again).
+                    offline::retry_pending();
+                }
+                return completed(update.id);
+            }
+            Err(e) => (e.clone(), None),
+        };
+        // A newer update of the cell replaces this one.
+        if has_next(update.id) {
+            return completed(update.id);
+        }
+        match POLICY.delay(attempt_nr, wait, js_sys::Math::random()) {
+            Some(delay) => {
+                debug!(
+                    "Sending the update of cell {} failed ({failure}), retrying in {delay:?}",
+                    update.id
+                );
+                Timeout::new(delay.as_millis() as u32, move || {
+                    if has_next(update.id) {
+                        completed(update.id);
+                    } else {
+                        attempt(url, update, attempt_nr + 1);
+                    }
+                })
+                .forget();
+            }
+            None => {
+                debug!(
+                    "Sending the update of cell {} failed ({failure}), keeping it",
+                    update.id
+                );
+                let id = update.id;
+                offline::enqueue(url, update);
+                completed(id);
+            }
+        }
+    });
+}
+
+fn has_next(id: i64) -> bool {
+    matches!(IN_FLIGHT.lock().unwrap().get(&id), Some(Some(_)))
+}
+
+/// The request of a cell completed, sends the update made meanwhile.
+fn completed(id: i64) {
+    let mut in_flight = IN_FLIGHT.lock().unwrap();
+    match in_flight.get_mut(&id).and_then(Option::take) {
+        Some((url, update)) => {
+            drop(in_flight);
+            attempt(url, update, 0);
+        }
+        None => {
+            in_flight.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn failed_updates_are_retried_with_growing_delays() {
+        let delays: Vec<Option<Duration>> = (0..POLICY.max_attempts)
+            .map(|attempt| POLICY.delay(attempt, None, 1.0))
+            .collect();
+        assert_eq!(
+            delays,
+            [
+                Some(Duration::from_millis(500)),
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(2)),
+                None
+            ]
+        );
+        // Jitter shortens the delay by up to half.
+        assert_eq!(POLICY.delay(1, None, 0.0), Some(Duration::from_millis(500)));
+        // The server can ask for a longer delay, not a shorter one.
+        let retry_after = Some(Duration::from_secs(30));
+        assert_eq!(POLICY.delay(0, retry_after, 0.5), retry_after);
+        assert_eq!(
+            POLICY.delay(2, Some(Duration::ZERO), 1.0),
+            Some(Duration::from_secs(2))
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! A private sheet to try out formulas without touching the shared sheet. It is only kept in
+//! memory for this session, its formulas are evaluated locally and only see the cells of the
+//! scratchpad. Nothing is sent to the server until the user publishes the cells, which copies
+//! them to the shared sheet as they were typed.
+
+use std::cell::RefCell;
+
+use egui::{Button, Grid, RichText, TextEdit, Ui};
+use xls_protocol::address::{self, cell_name, column_letters};
+use xls_protocol::{CellStyle, UpdateRequest};
+
+use crate::offline;
+
+const ROWS: usize = 10;
+const COLS: usize = 6;
+
+/// Width of a cell of the grid.
+const CELL_WIDTH: f32 = 70.0;
+
+pub(crate) struct Scratchpad {
+    /// What the user typed, row by row.
+    raw_values: Vec<String>,
+    /// What the cells evaluate to, `None` if they can't be evaluated locally.
+    computed: Vec<Option<String>>,
+}
+
+impl Default for Scratchpad {
+    fn default() -> Self {
+        Scratchpad {
+            raw_values: vec![String::new(); ROWS * COLS],
+            computed: vec![Some(String::new()); ROWS * COLS],
+        }
+    }
+}
+
+impl Scratchpad {
+    /// Sets the raw value of a cell and evaluates the scratchpad again.
+    pub(crate) fn set(&mut self, row: usize, col: usize, raw_value: &str) {
+        self.raw_values[row * COLS + col] = raw_value.to_string();
+        self.evaluate();
+    }
+
+    /// What a cell evaluates to, `None` if it can't be evaluated locally.
+    pub(crate) fn computed(&self, row: usize, col: usize) -> Option<&str> {
+        self.computed[row * COLS + col].as_deref()
+    }
+
+    fn evaluate(&mut self) {
+        let computed = RefCell::new(vec![None; ROWS * COLS]);
+        let visiting = RefCell::new(vec![false; ROWS * COLS]);
+        for idx in 0..ROWS * COLS {
+            self.value(idx, &computed, &visiting);
+        }
+        self.computed = computed
+            .into_inner()
+            .into_iter()
+            .map(Option::flatten)
+            .collect();
+    }
+
+    /// Evaluates a cell and the cells it references, cells in a cycle can't be evaluated.
+    fn value(
+        &self,
+        idx: usize,
+        computed: &RefCell<Vec<Option<Option<String>>>>,
+        visiting: &RefCell<Vec<bool>>,
+    ) -> Option<String> {
+        if let Some(value) = &computed.borrow()[idx] {
+            return value.clone();
+        }
+        if visiting.borrow()[idx] {
+            return None;
+        }
+        visiting.borrow_mut()[idx] = true;
+        let value = offline::evaluate(&self.raw_values[idx], |id| {
+            let (row, col) = address::position(id as i64);
+            let (row, col) = (row as usize, col as usize);
+            if row >= ROWS || col >= COLS {
+                return None;
+            }
+            self.value(row * COLS + col, computed, visiting)
+        });
+        visiting.borrow_mut()[idx] = false;
+        computed.borrow_mut()[idx] = Some(value.clone());
+        value
+    }
+
+    /// The updates copying the cells with content to the shared `sheet`, with the top left cell
+    /// of the scratchpad at `top_left`. Cells that would end up outside of the sheet are left
+    /// out.
+    pub(crate) fn publish_updates(
+        &self,
+        top_left: (usize, usize),
+        num_rows: usize,
+        num_cols: usize,
+        sheet: u32,
+    ) -> Vec<UpdateRequest> {
+        let mut updates = vec![];
+        for (idx, raw_value) in self.raw_values.iter().enumerate() {
+            let (row, col) = (top_left.0 + idx / COLS, top_left.1 + idx % COLS);
+            if raw_value.is_empty() || row >= num_rows || col >= num_cols {
+                continue;
+            }
+            updates.push(UpdateRequest {
+                id: address::id(row as i64, col as i64),
+                raw_value: raw_value.clone(),
+                background: 0,
+                style: CellStyle::default(),
+                ttl_secs: None,
+                sheet,
+            });
+        }
+        updates
+    }
+
+    /// Shows the scratchpad, returns `true` if the user wants to publish it to the shared sheet
+    /// at `target`.
+    pub(crate) fn ui(&mut self, ui: &mut Ui, target: (usize, usize)) -> bool {
+        ui.label("Try out formulas here, only you see them until you publish them.");
+        let mut edited = None;
+        Grid::new("scratchpad").striped(true).show(ui, |ui| {
+            ui.label("");
+            for col in 0..COLS {
+                ui.strong(column_letters(col as i64));
+            }
+            ui.end_row();
+            for row in 0..ROWS {
+                ui.strong(row.to_string());
+                for col in 0..COLS {
+                    let idx = row * COLS + col;
+                    let id = ui.make_persistent_id(("scratchpad", idx));
+                    // The raw value is edited, the other cells show what they evaluate to.
+                    if ui.memory(|memory| memory.has_focus(id)) {
+                        let mut raw_value = self.raw_values[idx].clone();
+                        let edit = TextEdit::singleline(&mut raw_value)
+                            .id(id)
+                            .desired_width(CELL_WIDTH);
+                        if ui.add(edit).changed() {
+                            edited = Some((row, col, raw_value));
+                        }
+                    } else {
+                        let mut shown = self.computed(row, col).unwrap_or("⚠").to_string();
+                        let edit = TextEdit::singleline(&mut shown)
+                            .id(id)
+                            .desired_width(CELL_WIDTH);
+                        let response = ui.add(edit);
+                        if self.computed(row, col).is_none() {
+                            response.on_hover_text("Can't be evaluated before it is published");
+                        }
+                    }
+                }
+                ui.end_row();
+            }
+        });
+        if let Some((row, col, raw_value)) = edited {
+            self.set(row, col, &raw_value);
+        }
+
+        ui.separator();
+        let cells = self.raw_values.iter().filter(|raw| !raw.is_empty()).count();
+        let mut publish = false;
+        ui.horizontal(|ui| {
+            let target = cell_name(address::id(target.0 as i64, target.1 as i64));
+            let button = ui.add_enabled(cells > 0, Button::new(format!("Publish to {target}")));
+            if button
+                .on_hover_text("Copies the cells to the shared sheet, starting at the focused cell")
+                .clicked()
+            {
+                publish = true;
+            }
+            if ui.add_enabled(cells > 0, Button::new("Clear")).clicked() {
+                *self = Scratchpad::default();
+            }
+        });
+        ui.label(
+            RichText::new("Formulas are copied as typed.")
+                .small()
+                .weak(),
+        );
+        publish
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+    use xls_protocol::FIRST_SHEET;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn scratchpad_is_evaluated_locally_and_published_as_typed() {
+        let mut scratchpad = Scratchpad::default();
+        scratchpad.set(0, 0, "2");
+        scratchpad.set(0, 1, "=A0*3");
+        scratchpad.set(1, 1, "=B0+1");
+        scratchpad.set(0, 2, "cut off");
+        assert_eq!(scratchpad.computed(0, 1), Some("6"));
+        assert_eq!(scratchpad.computed(1, 1), Some("7"));
+        // Cycles and functions of the pipeline can't be evaluated locally.
+        scratchpad.set(2, 0, "=A3");
+        scratchpad.set(3, 0, "=A2");
+        assert_eq!(scratchpad.computed(2, 0), None);
+        scratchpad.set(2, 0, "=VLOOKUP(1, A0:B0, 2)");
+        assert_eq!(scratchpad.computed(2, 0), None);
+        scratchpad.set(2, 0, "");
+        scratchpad.set(3, 0, "");
+
+        // Published at the focused cell, cells beyond the last column are left out.
+        let updates = scratchpad.publish_updates((10, 24), 1000, 26, FIRST_SHEET);
+        let published: Vec<(i64, &str)> = updates
+            .iter()
+            .map(|update| (update.id, update.raw_value.as_str()))
+            .collect();
+        assert_eq!(published, vec![(284, "2"), (285, "=A0*3"), (311, "=B0+1")]);
+    }
+}
@@ -0,0 +1,120 @@
+//! A rectangle of selected cells, spanned by the focused cell and an anchor, e.g. the cell that
+//! was focused when the user started to shift-click.
+
+use std::ops::RangeInclusive;
+
+use xls_protocol::address::{column_letters, parse_column};
+use xls_protocol::Region;
+
+/// Which way the first cells of a selection are copied into the rest of it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Fill {
+    /// The top cell of each column.
+    Down,
+    /// The left cell of each row.
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Selection {
+    pub(crate) rows: RangeInclusive<usize>,
+    pub(crate) cols: RangeInclusive<usize>,
+}
+
+impl Selection {
+    /// The cells between two corners given as (row, column).
+    pub(crate) fn spanning(a: (usize, usize), b: (usize, usize)) -> Self {
+        Self {
+            rows: a.0.min(b.0)..=a.0.max(b.0),
+            cols: a.1.min(b.1)..=a.1.max(b.1),
+        }
+    }
+
+    pub(crate) fn contains(&self, row: usize, col: usize) -> bool {
+        self.rows.contains(&row) && self.cols.contains(&col)
+    }
+
+    /// The (row, column) of the top left cell.
+    pub(crate) fn top_left(&self) -> (usize, usize) {
+        (*self.rows.start(), *self.cols.start())
+    }
+
+    pub(crate) fn is_single_cell(&self) -> bool {
+        self.rows.start() == self.rows.end() && self.cols.start() == self.cols.end()
+    }
+
+    /// The ids of the cells in a sheet with `num_cols` columns, row by row.
+    pub(crate) fn ids(&self, num_cols: usize) -> impl Iterator<Item = u64> + '_ {
+        self.rows.clone().flat_map(move |row| {
+            self.cols
+                .clone()
+                .map(move |col| row as u64 * num_cols as u64 + col as u64)
+        })
+    }
+
+    /// The ranges of cell ids covered in a sheet with `num_cols` columns, one per row unless
+    /// the selection spans whole rows.
+    pub(crate) fn regions(&self, num_cols: usize) -> Vec<Region> {
+        let id = |row: usize, col: usize| (row * num_cols + col) as i64;
+        if *self.cols.start() == 0 && *self.cols.end() == num_cols - 1 {
+            return vec![Region::new(
+                id(*self.rows.start(), 0),
+                id(*self.rows.end() + 1, 0),
+            )];
+        }
+        self.rows
+            .clone()
+            .map(|row| Region::new(id(row, *self.cols.start()), id(row, *self.cols.end()) + 1))
+            .collect()
+    }
+
+    /// The selection moved by the distance between `from` and `to`, kept within a sheet of
+    /// `num_rows` times `num_cols` cells.
+    pub(crate) fn moved(
+        &self,
+        from: (usize, usize),
+        to: (usize, usize),
+        num_rows: usize,
+        num_cols: usize,
+    ) -> Self {
+        let shift = |range: &RangeInclusive<usize>, from: usize, to: usize, max: usize| {
+            let start = (*range.start() + to)
+                .saturating_sub(from)
+                .min(max - 1 - (range.end() - range.start()));
+            start..=start + (range.end() - range.start())
+        };
+        Self {
+            rows: shift(&self.rows, from.0, to.0, num_rows),
+            cols: shift(&self.cols, from.1, to.1, num_cols),
+        }
+    }
+
+    /// The selection of a range like `B2:C4`, or of a single cell like `B2`.
+    pub(crate) fn parse(address: &str) -> Option<Self> {
+        let cell = |cell: &str| {
+            let digits = cell.find(|c: char| c.is_ascii_digit())?;
+            let (letters, row) = cell.split_at(digits);
+            let col = parse_column(&letters.to_ascii_uppercase())?;
+            Some((row.parse().ok()?, col as usize))
+        };
+        match address.trim().split_once(':') {
+            Some((a, b)) => Some(Self::spanning(cell(a)?, cell(b)?)),
+            None => cell(address.trim()).map(|cell| Self::spanning(cell, cell)),
+        }
+    }
+
+    /// The selection as a range like `B2:C4`, or a single cell like `B2`.
+    pub(crate) fn address(&self) -> String {
+        let cell = |row: usize, col: usize| format!("{}{row}", column_letters(col as i64));
+        let (top, left) = self.top_left();
+        if self.is_single_cell() {
+            cell(top, left)
+        } else {
+            format!(
+                "{}:{}",
+                cell(top, left),
+                cell(*self.rows.end(), *self.cols.end())
+            )
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! The personal settings as one JSON document, so users can take them from one browser to another.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+
+use crate::appearance::Appearance;
+use crate::snippets::Snippet;
+
+/// Everything that is stored in the browser instead of on the server.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    pub(crate) appearance: Appearance,
+    /// The personal labels of columns.
+    pub(crate) column_labels: BTreeMap<u32, String>,
+    pub(crate) pinned_rows: Vec<RangeInclusive<usize>>,
+    /// Missing from settings exported before there were snippets, which keeps the snippets.
+    pub(crate) snippets: Option<Vec<Snippet>>,
+}
+
+impl Settings {
+    pub(crate) fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// The settings in an exported document, settings missing from it are the defaults.
+    pub(crate) fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid settings: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn settings_survive_export_and_import() {
+        let settings = Settings {
+            appearance: Appearance {
+                grid_lines: true,
+                font_size: Some(18.0),
+                ..Appearance::default()
+            },
+            column_labels: BTreeMap::from([(0, "Price".to_string())]),
+            pinned_rows: vec![500..=520],
+            snippets: Some(vec![Snippet {
+                name: "Total".to_string(),
+                text: "=SUM({range})".to_string(),
+            }]),
+        };
+        assert_eq!(Settings::from_json(&settings.to_json()).unwrap(), settings);
+
+        let labels_only = Settings::from_json(r#"{"column_labels": {"2": "Total"}}"#).unwrap();
+        assert_eq!(labels_only.appearance, Appearance::default());
+        assert_eq!(labels_only.column_labels[&2], "Total");
+        assert_eq!(labels_only.snippets, None);
+        assert!(Settings::from_json("not json").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! The tabs at the bottom of the app. Each sheet has its own cells and remembers where it was
+//! scrolled to, the cells of the active sheet are in the cache of the app.
+
+use std::rc::Rc;
+
+use xls_protocol::FIRST_SHEET;
+
+use crate::cell_cache::{CellCache, Loader};
+
+/// Most sheets a user can add.
+pub(crate) const MAX_SHEETS: usize = 8;
+
+/// Where a sheet was looked at when the user switched to another one.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct SheetView {
+    pub(crate) scroll_offset: f32,
+    pub(crate) focused: (usize, usize),
+}
+
+struct Sheet {
+    name: String,
+    /// `None` while the sheet is active.
+    cache: Option<CellCache>,
+    view: SheetView,
+}
+
+pub(crate) struct Sheets {
+    sheets: Vec<Sheet>,
+    active: usize,
+}
+
+impl Default for Sheets {
+    fn default() -> Self {
+        Self {
+            sheets: vec![Sheet {
+                name: name(FIRST_SHEET),
+                cache: None,
+                view: SheetView::default(),
+            }],
+            active: 0,
+        }
+    }
+}
+
+/// The name of the tab of `sheet`, counted from 1 like in other spreadsheets.
+fn name(sheet: u32) -> String {
+    format!("Sheet{}", sheet + 1)
+}
+
+impl Sheets {
+    pub(crate) fn active(&self) -> usize {
+        self.active
+    }
+
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+        self.sheets.iter().map(|sheet| sheet.name.as_str())
+    }
+
+    /// Adds an empty sheet after the others, returns its index or `None` if there are
+    /// `MAX_SHEETS` already.
+    pub(crate) fn add(&mut self, loader: Rc<Loader>, width: usize, height: usize) -> Option<usize> {
+        if self.sheets.len() >= MAX_SHEETS {
+            return None;
+        }
+        let sheet = self.sheets.len() as u32;
+        self.sheets.push(Sheet {
+            name: name(sheet),
+            cache: Some(CellCache::new(loader, sheet, width, height)),
+            view: SheetView::default(),
+        });
+        Some(sheet as usize)
+    }
+
+    /// Makes sheet `index` the active one: keeps `cache` and `view` of the active sheet and
+    /// puts the cells of sheet `index` into `cache`. Returns where sheet `index` was left, `None`
+    /// if it is active already or doesn't exist.
+    pub(crate) fn switch(
+        &mut self,
+        index: usize,
+        cache: &mut CellCache,
+        view: SheetView,
+    ) -> Option<SheetView> {
+        if index == self.active {
+            return None;
+        }
+        let mut next = self.sheets.get_mut(index)?.cache.take()?;
+        next.take_over(cache);
+        let previous = &mut self.sheets[self.active];
+        previous.cache = Some(std::mem::replace(cache, next));
+        previous.view = view;
+        self.active = index;
+        Some(self.sheets[index].view)
+    }
+}
@@ -0,0 +1,187 @@
+//! Frequently used formulas and values the user can insert into the focused cell with one click.
+//! Snippets can have holes, `{}` or with a hint like `{range}`, which Tab selects one after the
+//! other while the cell is edited. The snippets are stored in the browser.
+
+use std::ops::Range;
+
+use egui::{Button, Grid, TextEdit, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::storage;
+
+/// Key of the snippets in the local storage of the browser.
+const STORAGE_KEY: &str = "snippets";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Snippet {
+    pub(crate) name: String,
+    pub(crate) text: String,
+}
+
+impl Snippet {
+    fn new(name: &str, text: &str) -> Self {
+        Snippet {
+            name: name.to_string(),
+            text: text.to_string(),
+        }
+    }
+}
+
+/// The snippets of users that didn't change them.
+fn defaults() -> Vec<Snippet> {
+    vec![
+        Snippet::new("Sum", "=SUM({range})"),
+        Snippet::new("Average", "=AVERAGE({range})"),
+        Snippet::new("If", "=IF({condition}, {then}, {else})"),
+        Snippet::new("Percentage", "=ROUND({part}/{total}*100, 1)"),
+        Snippet::new("Date", "{date}T00:00:00.000Z"),
+        Snippet::new("Today", "=TODAY()"),
+    ]
+}
+
+/// The char ranges of the holes in `text`, including their braces.
+pub(crate) fn holes(text: &str) -> Vec<Range<usize>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut holes = vec![];
+    let mut start = None;
+    for (idx, c) in chars.iter().enumerate() {
+        match c {
+            '{' => start = Some(idx),
+            '}' => {
+                if let Some(start) = start.take() {
+                    holes.push(start..idx + 1);
+                }
+            }
+            // Braces around anything else are e.g. an array of a formula.
+            c if !(c.is_ascii_lowercase() || *c == '_' || *c == ' ') => start = None,
+            _ => {}
+        }
+    }
+    holes
+}
+
+/// The first hole after the cursor, or the first hole of `text` if there is none after it.
+pub(crate) fn next_hole(text: &str, cursor: usize) -> Option<Range<usize>> {
+    let holes = holes(text);
+    holes
+        .iter()
+        .find(|hole| hole.start >= cursor)
+        .or(holes.first())
+        .cloned()
+}
+
+pub(crate) struct Snippets {
+    snippets: Vec<Snippet>,
+    /// A snippet that is inserted into the focused cell when clicked.
+    pub(crate) inserted: Option<String>,
+    /// The snippet being added.
+    new: Snippet,
+}
+
+impl Snippets {
+    pub(crate) fn load() -> Self {
+        Snippets {
+            snippets: storage::load::<Option<Vec<Snippet>>>(STORAGE_KEY).unwrap_or_else(defaults),
+            inserted: None,
+            new: Snippet::new("", ""),
+        }
+    }
+
+    pub(crate) fn snippets(&self) -> &[Snippet] {
+        &self.snippets
+    }
+
+    /// Replaces all snippets, e.g. with imported ones.
+    pub(crate) fn replace(&mut self, snippets: Vec<Snippet>) {
+        self.snippets = snippets;
+        storage::store(STORAGE_KEY, &self.snippets);
+    }
+
+    /// Lists the snippets to insert one, returns `true` if the user wants to change them.
+    pub(crate) fn menu(&mut self, ui: &mut Ui) -> bool {
+        if self.snippets.is_empty() {
+            ui.weak("No snippets");
+        }
+        for snippet in &self.snippets {
+            if ui
+                .button(&snippet.name)
+                .on_hover_text(&snippet.text)
+                .clicked()
+            {
+                self.inserted = Some(snippet.text.clone());
+                ui.close_menu();
+            }
+        }
+        ui.separator();
+        let edit = ui.button("Edit Snippets…").clicked();
+        if edit {
+            ui.close_menu();
+        }
+        edit
+    }
+
+    /// Lets the user change, remove and add snippets.
+    pub(crate) fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Mark holes with {} or a hint like {range}, Tab jumps between them.");
+        let mut changed = false;
+        let mut removed = None;
+        Grid::new("snippets").striped(true).show(ui, |ui| {
+            for (idx, snippet) in self.snippets.iter_mut().enumerate() {
+                let name = TextEdit::singleline(&mut snippet.name).desired_width(100.0);
+                changed |= ui.add(name).changed();
+                let text = TextEdit::singleline(&mut snippet.text).code_editor();
+                changed |= ui.add(text).changed();
+                if ui.button("🗑").on_hover_text("Remove").clicked() {
+                    removed = Some(idx);
+                }
+                ui.end_row();
+            }
+            let name = TextEdit::singleline(&mut self.new.name)
+                .hint_text("Name")
+                .desired_width(100.0);
+            ui.add(name);
+            let text = TextEdit::singleline(&mut self.new.text)
+                .hint_text("=SUM({range})")
+                .code_editor();
+            ui.add(text);
+            let complete = !self.new.name.trim().is_empty() && !self.new.text.is_empty();
+            if ui.add_enabled(complete, Button::new("➕")).clicked() {
+                self.snippets
+                    .push(std::mem::replace(&mut self.new, Snippet::new("", "")));
+                changed = true;
+            }
+            ui.end_row();
+        });
+        if let Some(idx) = removed {
+            self.snippets.remove(idx);
+            changed = true;
+        }
+        if ui.button("Reset to the defaults").clicked() {
+            self.snippets = defaults();
+            changed = true;
+        }
+        if changed {
+            storage::store(STORAGE_KEY, &self.snippets);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn tab_jumps_between_the_holes_of_snippets() {
+        let text = "=IF({condition}, {}, {1, 2})";
+        assert_eq!(holes(text), vec![4..15, 17..19]);
+        assert_eq!(next_hole(text, 0), Some(4..15));
+        assert_eq!(next_hole(text, 15), Some(17..19));
+        // After the last hole it starts over.
+        assert_eq!(next_hole(text, 19), Some(4..15));
+        // Holes are counted in chars, like the cursor.
+        assert_eq!(holes("€ {amount}"), vec![2..10]);
+        assert_eq!(next_hole("=SUM(A1:A9)", 0), None);
+    }
+}
@@ -0,0 +1,36 @@
+//! Personal settings kept in the local storage of the browser.
+
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// The value stored under `key`, the default if there is none or it can't be read.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn load<T: DeserializeOwned + Default>(key: &str) -> T {
+    local_storage()
+        .and_then(|storage| storage.get_item(key).ok()?)
+        .and_then(|value| serde_json::from_str(&value).ok())
+        .unwrap_or_default()
+}
+
+/// Stores `value` under `key`.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn store<T: Serialize>(key: &str, value: &T) {
+    let value = serde_json::to_string(value).unwrap();
+    if let Some(Err(e)) = local_storage().map(|storage| storage.set_item(key, &value)) {
+        warn!("Failed to store {key}: {e:?}");
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn load<T: DeserializeOwned + Default>(_key: &str) -> T {
+    T::default()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn store<T: Serialize>(_key: &str, _value: &T) {}
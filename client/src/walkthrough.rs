@@ -0,0 +1,79 @@
+//! Live walkthroughs: the presenter sends its viewport over the websocket and the server sends it
+//! to the clients following the presenter, which scroll along.
+
+use std::time::Duration;
+
+use egui::{TextEdit, Ui};
+use xls_protocol::{PresenterMessage, Viewport};
+
+use crate::cell_cache::Loader;
+
+/// Least time between two viewports sent by the presenter, in seconds, scrolling faster would
+/// hit the rate limit of the server.
+const SEND_INTERVAL: f64 = 0.1;
+
+#[derive(Default)]
+pub(crate) struct Walkthrough {
+    following: bool,
+    presenting: bool,
+    /// The `PRESENTER_KEY` of the server.
+    key: String,
+    /// The viewport sent last, with when it was sent.
+    sent: Option<(Viewport, f64)>,
+}
+
+impl Walkthrough {
+    pub(crate) fn ui(&mut self, ui: &mut Ui, loader: &Loader) {
+        if ui
+            .checkbox(&mut self.following, "Follow the presenter")
+            .changed()
+        {
+            loader.send(PresenterMessage::Follow(self.following));
+        }
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Presenter key");
+            ui.add(TextEdit::singleline(&mut self.key).password(true));
+        });
+        ui.add_enabled_ui(!self.key.is_empty(), |ui| {
+            if ui.checkbox(&mut self.presenting, "Present").changed() {
+                self.sent = None;
+            }
+        });
+        ui.weak("Everyone following the presenter scrolls along with you.");
+    }
+
+    /// Sends the viewport of the presenter when it changed.
+    pub(crate) fn present(&mut self, ctx: &egui::Context, loader: &Loader, viewport: Viewport) {
+        if !self.presenting || self.sent.is_some_and(|(sent, _)| sent == viewport) {
+            return;
+        }
+        let now = ctx.input(|i| i.time);
+        match self.sent {
+            Some((_, sent_at)) if now - sent_at < SEND_INTERVAL => {
+                ctx.request_repaint_after(Duration::from_secs_f64(SEND_INTERVAL));
+            }
+            _ => {
+                let key = self.key.clone();
+                if loader.send(PresenterMessage::Present { viewport, key }) {
+                    self.sent = Some((viewport, now));
+                }
+            }
+        }
+    }
+
+    /// The viewport of the presenter if `message` is one and the user follows the presenter.
+    pub(crate) fn followed(&self, message: &str) -> Option<Viewport> {
+        match serde_json::from_str(message) {
+            Ok(PresenterMessage::Present { viewport, .. }) if self.following => Some(viewport),
+            _ => None,
+        }
+    }
+
+    /// Follows the presenter again after the websocket reconnected.
+    pub(crate) fn reconnected(&self, loader: &Loader) {
+        if self.following {
+            loader.send(PresenterMessage::Follow(true));
+        }
+    }
+}
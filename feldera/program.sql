@@ -1,6 +1,11 @@
 -- Given a cell value as a formula (e.g., =A0+B0), and a context with cell values
--- referenced in the formula, returns the computed value of the cell
-create function cell_value(cell varchar(64), mentions_ids bigint array, mentions_values varchar(64) array) returns varchar(64);
+-- referenced in the formula, returns the computed value of the cell. `now` is what NOW()
+-- returns and `id` seeds RAND(), passing the time of the `clock` or the time the cell was
+-- written and its id keeps the function deterministic.
+create function cell_value(cell varchar(64), mentions_ids bigint array, mentions_values varchar(64) array, now timestamp, id bigint) returns varchar(64);
+
+-- Like cell_value, but returns the type of the value: number, string, bool, date or error
+create function cell_value_type(cell varchar(64), mentions_ids bigint array, mentions_values varchar(64) array, now timestamp, id bigint) returns varchar(8);
 
 -- Given a cell value e.g., =A0+B0, returns an array of cell ids that were mentioned in the formula
 create function mentions(cell varchar(64)) returns bigint array;
@@ -9,8 +14,24 @@ create function mentions(cell varchar(64)) returns bigint array;
 declare recursive view spreadsheet_view (
                                         id bigint not null,
                                         background integer not null,
+                                        -- Bold, italic and text color of the cell, see CellStyle in the protocol crate
+                                        style bigint,
                                         raw_value varchar(64) not null,
-                                        computed_value varchar(64)
+                                        computed_value varchar(64),
+                                        -- The type of computed_value: number, string, bool, date or error
+                                        value_type varchar(8),
+                                        -- When the cell was last updated
+                                        ts timestamp not null,
+                                        -- The cells in the reference cycle this cell is part of
+                                        cycle bigint array
+    );
+
+-- Forward declaration of the cells every cell depends on, directly or through up to 31 other cells
+declare recursive view cell_dependencies (
+                                        id bigint not null,
+                                        dependency_id bigint,
+                                        -- How many references lead from the cell to the dependency
+                                        depth integer not null
     );
 
 -- Raw spreadsheet cell data coming from backend/user, updates
@@ -20,7 +41,11 @@ create table spreadsheet_data (
                                   ip varchar(45) not null,
                                   ts timestamp not null,
                                   raw_value varchar(64) not null,
-                                  background integer not null
+                                  background integer not null,
+                                  -- Bold, italic and text color of the cell, NULL for unstyled cells
+                                  style bigint,
+                                  -- The cell is cleared at this time, unless it is overwritten before
+                                  expires_at timestamp
 ) with (
       'materialized' = 'true',
       'connectors' = '[{
@@ -34,7 +59,9 @@ create table spreadsheet_data (
                         "id": { "values": [1039999974, 0, 1, 2, 12, 14, 40, 66, 92, 118, 170, 196, 222, 13, 65, 91, 117, 15, 41, 67, 93, 119, 39, 144] },
                         "ip": { "values": ["0"] },
                         "raw_value": { "values": ["42", "=A39999999", "=A0", "=A0+B0", "Reference", "Functions", "=ABS(-1)", "=AVERAGE(1,2,3,1,2,3)", "={1,2,3}+{1,2,3}", "=SUM(1,2,3)", "=PRODUCT(ABS(1),2*1, 3,4*1)", "=RIGHT(\"apple\", 3)", "=LEFT(\"apple\", 3)", "Logic", "=2>=1", "=OR(1>1,1<>1)", "=AND(\"test\",\"True\", 1, true)", "Datetime", "2019-03-01T02:00:00.000Z", "2019-08-30T02:00:00.000Z", "=DAYS(P1, P2)", "=P1+5", "=XOR(0,1)", "=IF(TRUE,1,0)"] },
-                        "background": { "strategy": "uniform", "range": [0, 1] }
+                        "background": { "strategy": "uniform", "range": [0, 1] },
+                        "style": { "null_percentage": 100 },
+                        "expires_at": { "null_percentage": 100 }
                     }
                 }]
             }
@@ -42,8 +69,74 @@ create table spreadsheet_data (
     }]'
       );
 
+-- Labels users gave to the column headers, e.g. Price instead of A
+create table column_labels (
+                                  col integer not null,
+                                  label varchar(32) not null,
+                                  ip varchar(45) not null,
+                                  ts timestamp not null
+) with ('materialized' = 'true');
+
+-- Notes users attached to cells, the latest note of a cell wins and an empty note removes it
+create table cell_comments (
+                                  id bigint not null,
+                                  comment varchar(280) not null,
+                                  ip varchar(45) not null,
+                                  ts timestamp not null
+) with ('materialized' = 'true');
+
+-- Cell updates flagged or blocked by the moderation heuristics of the server, for review
+create table moderation_events (
+                                  id bigint not null,
+                                  ip varchar(45) not null,
+                                  raw_value varchar(64) not null,
+                                  -- blocked_url, repeated or high_entropy
+                                  reason varchar(16) not null,
+                                  -- Whether the update was rejected or only flagged
+                                  blocked boolean not null,
+                                  ts timestamp not null
+) with ('materialized' = 'true');
+
+-- Editors banned by moderators, their updates are rejected like the ones of IPs that reached the API limit
+create table banned_editors (
+                                  ip varchar(45) not null,
+                                  ts timestamp not null
+) with ('materialized' = 'true');
+
+-- The current time, the server overwrites the row with id 0 every VOLATILE_REFRESH_SECS so cells using
+-- NOW() or TODAY() are evaluated again
+create table clock (
+                                  id integer not null primary key,
+                                  ts timestamp not null
+);
+
+-- The latest label of every column, empty labels reset the column to its letter
+create materialized view latest_column_labels as
+select
+    c.col,
+    c.label
+from
+    column_labels c
+        join
+    (select col, max(ts) as max_ts from column_labels group by col) l on c.col = l.col and c.ts = l.max_ts
+where
+    c.label <> '';
+
+-- The latest note of every cell that has one
+create materialized view latest_cell_comments as
+select
+    c.id,
+    c.comment
+from
+    cell_comments c
+        join
+    (select id, max(ts) as max_ts from cell_comments group by id) l on c.id = l.id and c.ts = l.max_ts
+where
+    c.comment <> '';
+
 -- Get the latest cell value for the spreadsheet.
--- (By finding the one with the highest `ts` for a given `id`)
+-- (By finding the one with the highest `ts` for a given `id`, cells whose latest value
+-- expired are empty)
 create view latest_cells as with
                                 max_ts_per_cell as (
                                     select
@@ -58,13 +151,17 @@ create view latest_cells as with
                                 s.id,
                                 s.raw_value,
                                 s.background,
+                                s.style,
+                                s.ts,
                                 -- The append with null is silly but crucial to ensure that the
                                 -- cross join in `latest_cells_with_mention` returns all cells
                                 -- not just those that reference another cell
                                 ARRAY_APPEND(mentions(s.raw_value), null) as mentioned_cell_ids
                             from
                                 spreadsheet_data s
-                                    join max_ts_per_cell mt on s.id = mt.id and s.ts = mt.max_ts;
+                                    join max_ts_per_cell mt on s.id = mt.id and s.ts = mt.max_ts
+                            where
+                                s.expires_at is null or s.expires_at > NOW();
 
 -- List all mentioned ids per latest cell
 create view latest_cells_with_mentions as
@@ -72,16 +169,76 @@ select
     s.id,
     s.raw_value,
     s.background,
+    s.style,
+    s.ts,
     m.mentioned_id
 from
     latest_cells s, unnest(s.mentioned_cell_ids) as m(mentioned_id);
 
+-- The transitive closure of the mentions up to 32 references deep. The bound keeps the size of
+-- the closure linear in the number of cells (a chain of cells would otherwise have a quadratic
+-- closure) and ends the recursion when there is a reference cycle
+create view cell_dependencies as
+select
+    id,
+    mentioned_id as dependency_id,
+    1 as depth
+from
+    latest_cells_with_mentions
+where
+    mentioned_id is not null
+union
+select
+    d.id,
+    m.mentioned_id as dependency_id,
+    d.depth + 1 as depth
+from
+    cell_dependencies d
+        join
+    latest_cells_with_mentions m on d.dependency_id = m.id
+where
+    m.mentioned_id is not null
+    and d.depth < 32;
+
+-- Every dependency of a cell once, no matter how many references lead to it
+create local view distinct_dependencies as
+select distinct
+    id,
+    dependency_id
+from
+    cell_dependencies;
+
+-- Cells that depend on themselves, with all cells of their cycle (the cells they depend on
+-- that depend on them in turn, including themselves)
+create local view cell_cycles as
+select
+    d.id,
+    ARRAY_AGG(d.dependency_id) as cycle
+from
+    distinct_dependencies d
+        join
+    distinct_dependencies back on d.dependency_id = back.id and back.dependency_id = d.id
+group by
+    d.id;
+
+-- Cells with references 32 deep, they might be part of a longer cycle than cell_dependencies
+-- can see
+create local view too_deep_cells as
+select distinct
+    id
+from
+    cell_dependencies
+where
+    depth = 32;
+
 -- Like latest_cells_with_mentions, but enrich it with values of mentioned cells
 create local view mentions_with_values as
 select
     m.id,
     m.raw_value,
     m.background,
+    m.style,
+    m.ts,
     m.mentioned_id,
     sv.computed_value as mentioned_value
 from
@@ -95,6 +252,8 @@ select
     id,
     raw_value,
     background,
+    style,
+    ts,
     ARRAY_AGG(mentioned_id) as mentions_ids,
     ARRAY_AGG(mentioned_value) as mentions_values
 from
@@ -102,19 +261,87 @@ from
 group by
     id,
     raw_value,
-    background;
+    background,
+    style,
+    ts;
+
+-- Cells using NOW() or TODAY() with the time of the clock, which they are evaluated at instead of
+-- the time they were written
+create local view volatile_cells as
+select
+    s.id,
+    c.ts as now
+from
+    latest_cells s
+        cross join
+    clock c
+where
+    s.raw_value like '%NOW()%' or s.raw_value like '%TODAY()%';
 
 -- Calculate the final spreadsheet by executing the UDF for the formula
+-- (Cells in a cycle don't have a value, evaluating them would never reach a fixed point, and
+-- neither do cells with too deep references)
 create materialized view spreadsheet_view as
 select
-    id,
-    background,
-    raw_value,
-    cell_value(raw_value, mentions_ids, mentions_values) AS computed_value
+    m.id,
+    m.background,
+    m.style,
+    m.raw_value,
+    case
+        when c.cycle is not null then '#CYCLE!'
+        when t.id is not null then '#DEPTH!'
+        else cell_value(m.raw_value, m.mentions_ids, m.mentions_values, coalesce(v.now, m.ts), m.id)
+    end AS computed_value,
+    case
+        when c.cycle is not null or t.id is not null then 'error'
+        else cell_value_type(m.raw_value, m.mentions_ids, m.mentions_values, coalesce(v.now, m.ts), m.id)
+    end AS value_type,
+    m.ts,
+    c.cycle
 from
-    mentions_aggregated;
+    mentions_aggregated m
+        left join
+    cell_cycles c on m.id = c.id
+        left join
+    too_deep_cells t on m.id = t.id
+        left join
+    volatile_cells v on m.id = v.id;
+
+-- Per column: how many cells have content and the sum and average of the numbers
+create materialized view column_statistics as
+select
+    id % 26 as col,
+    count(*) as filled,
+    count(number) as numbers,
+    coalesce(sum(number), 0) as total,
+    avg(number) as average
+from (
+    select
+        id,
+        case
+            when value_type = 'number' then cast(computed_value as double)
+        end as number
+    from
+        spreadsheet_view
+    where
+        raw_value != ''
+) as cells
+group by
+    id % 26;
 
--- Figure out which IPs currently reached their API limit
+-- Writes per region of 1000 rows (26000 cells) in the last minute, shows where the action is
+create materialized view write_heatmap as
+select
+    id / 26000 as region,
+    count(*) as writes
+from
+    spreadsheet_data
+where
+    ts >= NOW() - INTERVAL 1 MINUTE
+group by
+    id / 26000;
+
+-- Figure out which IPs currently reached their API limit or were banned
 create materialized view api_limit_reached as
 select
     ip
@@ -125,7 +352,12 @@ where
 group by
     ip
 having
-    count(*) > 100;
+    count(*) > 100
+union
+select
+    ip
+from
+    banned_editors;
 
 -- Compute statistics
 create materialized view spreadsheet_statistics as
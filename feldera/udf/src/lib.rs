@@ -1,12 +1,14 @@
 use feldera_sqllib::*;
 
+use std::cell::RefCell;
 use std::sync::Arc;
-use std::collections::{BTreeMap, VecDeque};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use xlformula_engine::calculate;
 use xlformula_engine::parse_formula;
 use xlformula_engine::NoCustomFunction;
 use xlformula_engine::types::{Formula, Value, Error, Boolean};
-use chrono::DateTime;
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate};
 
 fn parse_as_value(input: SqlString) -> Value {
     if let Ok(number) = input.str().parse::<f32>() {
@@ -15,15 +17,49 @@ fn parse_as_value(input: SqlString) -> Value {
     if let Ok(boolean) = input.str().parse::<bool>() {
         return Value::Boolean(if boolean { Boolean::True } else { Boolean::False });
     }
-    if let Ok(date) = DateTime::parse_from_rfc3339(input.str()) {
+    if let Some(date) = parse_date(input.str()) {
         return Value::Date(date);
     }
     Value::Text(String::from(input.str()))
 }
 
-pub fn cell_value(raw_content: Option<SqlString>, mentions_ids: Option<Arc<Vec<Option<i64>>>>, mentions_values: Option<Arc<Vec<Option<SqlString>>>>) -> Result<Option<SqlString>, Box<dyn std::error::Error>> {
+/// Parses dates in RFC 3339 and in the format dates are displayed in (`2019-03-01 02:00:00 +00:00`).
+fn parse_date(input: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(input)
+        .or_else(|_| DateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S %:z"))
+        .ok()
+}
+
+/// Computes the value of cell `id`, `now` is the time of the clock for cells using `NOW()` and the
+/// time the cell was written otherwise.
+pub fn cell_value(raw_content: Option<SqlString>, mentions_ids: Option<Arc<Vec<Option<i64>>>>, mentions_values: Option<Arc<Vec<Option<SqlString>>>>, now: Option<Timestamp>, id: Option<i64>) -> Result<Option<SqlString>, Box<dyn std::error::Error>> {
+    let result = compute(raw_content, mentions_ids, mentions_values, now, id);
+    Ok(Some(SqlString::from(calculate::result_to_string(result))))
+}
+
+/// The type of the value `cell_value` computes with the same arguments: `number`, `string`, `bool`,
+/// `date` or `error`. Values that aren't formulas are typed like when they are referenced.
+pub fn cell_value_type(raw_content: Option<SqlString>, mentions_ids: Option<Arc<Vec<Option<i64>>>>, mentions_values: Option<Arc<Vec<Option<SqlString>>>>, now: Option<Timestamp>, id: Option<i64>) -> Result<Option<SqlString>, Box<dyn std::error::Error>> {
+    let value = match raw_content {
+        Some(raw_content) if !raw_content.str().starts_with('=') => parse_as_value(raw_content),
+        raw_content => compute(raw_content, mentions_ids, mentions_values, now, id),
+    };
+    let value_type = match value {
+        Value::Number(_) => "number",
+        Value::Boolean(_) => "bool",
+        Value::Date(_) => "date",
+        Value::Error(_) => "error",
+        Value::Text(_) | Value::Iterator(_) | Value::Blank => "string",
+    };
+    Ok(Some(SqlString::from(value_type)))
+}
+
+fn compute(raw_content: Option<SqlString>, mentions_ids: Option<Arc<Vec<Option<i64>>>>, mentions_values: Option<Arc<Vec<Option<SqlString>>>>, now: Option<Timestamp>, id: Option<i64>) -> Value {
     let cell_content = raw_content.unwrap_or_else(|| SqlString::new());
-    let formula = parse_formula::parse_string_to_formula(cell_content.str(), None::<NoCustomFunction>);
+    let environment = Environment {
+        now: now.and_then(|now| DateTime::from_timestamp_millis(now.milliseconds())).map(|now| now.fixed_offset()),
+        seed: splitmix64(id.unwrap_or_default() as u64) ^ now.map_or(0, |now| now.milliseconds() as u64),
+    };
 
     let mentions_ids = mentions_ids.map(Arc::unwrap_or_clone).unwrap_or_else(|| vec![]);
     let mentions_values = mentions_values.map(Arc::unwrap_or_clone).unwrap_or_else(|| vec![]);
@@ -34,11 +70,537 @@ pub fn cell_value(raw_content: Option<SqlString>, mentions_ids: Option<Arc<Vec<O
             context.insert(id_to_cell_reference(id), parse_as_value(value));
         }
     }
-    let data_function = |s: String| context.get(&s).cloned().unwrap_or_else(|| Value::Error(Error::Value));
 
-    let result = calculate::calculate_formula(formula, Some(&data_function));
-    let result_str = calculate::result_to_string(result);
-    Ok(Some(SqlString::from(result_str)))
+    if cell_content.str().starts_with('=') {
+        match expand_ranges(cell_content.str()) {
+            Ok((_, range_cells)) => {
+                // Like in Excel, empty cells are blank in ranges but an error when referenced on their own.
+                let data_function = |s: String| {
+                    context.get(&s).cloned().unwrap_or_else(|| match range_cells.contains(&s) {
+                        true => Value::Blank,
+                        false => Value::Error(Error::Value),
+                    })
+                };
+                evaluate(cell_content.str(), &data_function, &environment)
+            }
+            Err(e) => Value::Error(e),
+        }
+    } else {
+        let data_function = |_: String| Value::Error(Error::Value);
+        evaluate(cell_content.str(), &data_function, &environment)
+    }
+}
+
+/// What the volatile functions of a cell see. It is derived from the cell, so evaluating a cell
+/// twice gives the same result (the pipeline relies on it to retract old values).
+struct Environment {
+    /// What `NOW()` returns: the time of the clock or the time the cell was written.
+    now: Option<DateTime<FixedOffset>>,
+    /// Seed of `RAND()` and `RANDBETWEEN()`, from the cell id and the time it was written.
+    seed: u64,
+}
+
+/// Evaluates `formula`, looking up the values of references with `data_function`.
+fn evaluate(formula: &str, data_function: &dyn Fn(String) -> Value, environment: &Environment) -> Value {
+    // Results of calls that can't be written as a literal (dates), referenced as `_R<index>`.
+    let results = RefCell::new(Vec::new());
+    let data_function = |name: String| match name.strip_prefix("_R").and_then(|idx| idx.parse::<usize>().ok()) {
+        Some(idx) => results.borrow().get(idx).cloned().unwrap_or(Value::Error(Error::Reference)),
+        None => data_function(name),
+    };
+    let calculate = |formula: &str| {
+        let formula = match formula.starts_with('=') {
+            true => match expand_ranges(formula) {
+                Ok((formula, _)) => formula,
+                Err(e) => return Value::Error(e),
+            },
+            false => formula.to_string(),
+        };
+        let formula = parse_formula::parse_string_to_formula(&formula, None::<NoCustomFunction>);
+        calculate::calculate_formula(formula, Some(&data_function))
+    };
+    if !formula.starts_with('=') {
+        return calculate(formula);
+    }
+    // Ranges are expanded after the calls are evaluated, so lookups still know the shape of their table.
+    let mut calls = 0;
+    let expanded = rewrite_calls(formula, &mut |function, args| {
+        // Every call of a random function gets its own number.
+        calls += 1;
+        let random = splitmix64(environment.seed.wrapping_add(calls));
+        let result = match function {
+            "VLOOKUP" | "HLOOKUP" => lookup(function, &args, &calculate),
+            _ => {
+                let args = args.iter().map(|arg| calculate(&format!("={arg}"))).collect();
+                call_function(function, args, environment.now, random)
+            }
+        };
+        result.map(|value| match value {
+            Value::Date(_) => {
+                results.borrow_mut().push(value);
+                format!("_R{}", results.borrow().len() - 1)
+            }
+            value => value_to_literal(value),
+        })
+    });
+    match expanded {
+        Ok(formula) => calculate(&formula),
+        Err(e) => Value::Error(e),
+    }
+}
+
+/// Most cells the ranges of a formula can cover together.
+const MAX_RANGE_CELLS: u64 = 1000;
+
+/// Replaces ranges like `A1:B10` outside of string literals with the list of their cells
+/// (`{A1,B1,...,B10}`). Returns the new formula and the cells of all ranges.
+fn expand_ranges(formula: &str) -> Result<(String, BTreeSet<String>), Error> {
+    let mut result = String::new();
+    let mut cells = BTreeSet::new();
+    let mut range_cells = 0;
+    let mut quote = None;
+    let mut idx = 0;
+    while let Some(c) = formula[idx..].chars().next() {
+        let in_name = result.ends_with(|p: char| p.is_ascii_alphanumeric() || p == '_' || p == '.');
+        if quote.is_none() && !in_name {
+            if let Some((from, to, len)) = parse_range(&formula[idx..]) {
+                let (rows, cols) = (from.0.min(to.0)..=from.0.max(to.0), from.1.min(to.1)..=from.1.max(to.1));
+                range_cells += (rows.end() - rows.start() + 1) as u64 * (cols.end() - cols.start() + 1) as u64;
+                if range_cells > MAX_RANGE_CELLS {
+                    return Err(Error::Reference);
+                }
+                let range = rows
+                    .flat_map(|row| cols.clone().map(move |col| id_to_cell_reference(row * 26 + col)))
+                    .collect::<Vec<_>>();
+                result.push_str(&format!("{{{}}}", range.join(",")));
+                cells.extend(range);
+                idx += len;
+                continue;
+            }
+        }
+        match quote {
+            Some(q) if c == q => quote = None,
+            None if c == '"' || c == '\'' => quote = Some(c),
+            _ => {}
+        }
+        result.push(c);
+        idx += c.len_utf8();
+    }
+    Ok((result, cells))
+}
+
+/// Parses a range like `A1:B10` at the start of `s` into the (row, column) of its corners and
+/// its length.
+fn parse_range(s: &str) -> Option<((i64, i64), (i64, i64), usize)> {
+    let (from, from_len) = parse_cell_reference(s)?;
+    let rest = s[from_len..].strip_prefix(':')?;
+    let (to, to_len) = parse_cell_reference(rest)?;
+    let len = from_len + 1 + to_len;
+    match s[len..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '(') {
+        true => None,
+        false => Some((from, to, len)),
+    }
+}
+
+/// Parses a single column cell reference like `B10` at the start of `s` into its row, column and
+/// length.
+fn parse_cell_reference(s: &str) -> Option<((i64, i64), usize)> {
+    let col = s.chars().next().filter(char::is_ascii_uppercase)?;
+    let digits = s[1..].len() - s[1..].trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let row = s[1..1 + digits].parse().ok()?;
+    Some(((row, col as i64 - 'A' as i64), 1 + digits))
+}
+
+/// Functions the formula engine does not know about. Calls to them are evaluated by us and
+/// replaced with their result before the formula is handed to the engine.
+const FUNCTIONS: [&str; 18] = [
+    "COUNT", "MIN", "MAX", "ROUND", "TRIM", "UPPER", "LOWER", "VLOOKUP", "HLOOKUP", "TEXT", "DATE", "YEAR", "MONTH",
+    "DAY", "NOW", "TODAY", "RAND", "RANDBETWEEN",
+];
+
+/// Replaces every call of one of [`FUNCTIONS`] in `formula` with what `call` returns for it,
+/// the arguments passed to `call` are already rewritten.
+fn rewrite_calls(formula: &str, call: &mut dyn FnMut(&str, Vec<String>) -> Result<String, Error>) -> Result<String, Error> {
+    let mut result = String::new();
+    let mut rest = formula;
+    while let Some((start, function, args_start)) = next_call(rest) {
+        let (args, end) = split_args(&rest[args_start..]).ok_or(Error::Parse)?;
+        let args = args
+            .into_iter()
+            .map(|arg| rewrite_calls(arg, call))
+            .collect::<Result<Vec<_>, _>>()?;
+        result.push_str(&rest[..start]);
+        result.push_str(&call(function, args)?);
+        rest = &rest[args_start + end..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Finds the next call of one of [`FUNCTIONS`] outside of string literals and returns where its
+/// name and its arguments start.
+fn next_call(formula: &str) -> Option<(usize, &'static str, usize)> {
+    let mut quote = None;
+    let mut identifier_start = None;
+    for (idx, c) in formula.char_indices() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            identifier_start.get_or_insert(idx);
+            continue;
+        }
+        if let Some(start) = identifier_start.take() {
+            let function = FUNCTIONS.iter().find(|f| **f == &formula[start..idx]);
+            if let Some(function) = function {
+                let args = &formula[idx..];
+                if let Some(args) = args.trim_start_matches(' ').strip_prefix('(') {
+                    return Some((start, function, formula.len() - args.len()));
+                }
+            }
+        }
+        if c == '"' || c == '\'' {
+            quote = Some(c);
+        }
+    }
+    None
+}
+
+/// Splits the arguments of a call at the top-level commas, `args` starts after the opening
+/// parenthesis. Returns the arguments and the index after the closing parenthesis.
+fn split_args(args: &str) -> Option<(Vec<&str>, usize)> {
+    let mut quote = None;
+    let mut depth = 0;
+    let mut split = vec![];
+    let mut arg_start = 0;
+    for (idx, c) in args.char_indices() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '(' | '{' => depth += 1,
+            ',' if depth == 0 => {
+                split.push(&args[arg_start..idx]);
+                arg_start = idx + 1;
+            }
+            ')' if depth == 0 => {
+                let last = &args[arg_start..idx];
+                if !split.is_empty() || !last.trim().is_empty() {
+                    split.push(last);
+                }
+                return Some((split, idx + 1));
+            }
+            ')' | '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Evaluates one of [`FUNCTIONS`].
+fn call_function(function: &str, args: Vec<Value>, now: Option<DateTime<FixedOffset>>, random: u64) -> Result<Value, Error> {
+    match function {
+        "NOW" | "TODAY" | "RAND" if !args.is_empty() => Err(Error::Argument),
+        "RAND" => Ok(Value::Number((random >> 40) as f32 / (1u64 << 24) as f32)),
+        "RANDBETWEEN" => {
+            let (low, high) = match numbers(args)?.as_slice() {
+                [low, high] => (low.ceil(), high.floor()),
+                _ => return Err(Error::Argument),
+            };
+            // Text like "NaN" or "inf" parses as a number too.
+            if !low.is_finite() || !high.is_finite() || low > high {
+                return Err(Error::Value);
+            }
+            let offset = random.checked_rem((high - low + 1.0) as u64).ok_or(Error::Value)?;
+            Ok(Value::Number(low + offset as f32))
+        }
+        "NOW" => now.map(Value::Date).ok_or(Error::Value),
+        "TODAY" => {
+            let today = now.and_then(|now| now.date_naive().and_hms_opt(0, 0, 0)).ok_or(Error::Value)?;
+            Ok(Value::Date(today.and_utc().fixed_offset()))
+        }
+        "COUNT" => Ok(Value::Number(
+            flatten(args)
+                .into_iter()
+                .filter(|arg| matches!(arg, Value::Number(_)))
+                .count() as f32,
+        )),
+        "MIN" => Ok(Value::Number(numbers(args)?.into_iter().reduce(f32::min).unwrap_or(0.0))),
+        "MAX" => Ok(Value::Number(numbers(args)?.into_iter().reduce(f32::max).unwrap_or(0.0))),
+        "ROUND" => {
+            let (number, digits) = match numbers(args)?.as_slice() {
+                [number] => (*number, 0.0),
+                [number, digits] => (*number, digits.trunc()),
+                _ => return Err(Error::Argument),
+            };
+            let factor = 10f32.powf(digits);
+            let rounded = (number * factor).round() / factor;
+            Ok(Value::Number(if rounded.is_finite() { rounded } else { number }))
+        }
+        "TRIM" | "UPPER" | "LOWER" => {
+            let [arg] = <[Value; 1]>::try_from(args).map_err(|_| Error::Argument)?;
+            let text = match arg {
+                Value::Error(e) => return Err(e),
+                Value::Iterator(_) => return Err(Error::Cast),
+                value => calculate::result_to_string(value),
+            };
+            Ok(Value::Text(match function {
+                "TRIM" => text.split_whitespace().collect::<Vec<_>>().join(" "),
+                "UPPER" => text.to_uppercase(),
+                _ => text.to_lowercase(),
+            }))
+        }
+        "DATE" => {
+            let date = match numbers(args)?.as_slice() {
+                [year, month, day] => NaiveDate::from_ymd_opt(*year as i32, *month as u32, *day as u32),
+                _ => return Err(Error::Argument),
+            };
+            let date = date.and_then(|date| date.and_hms_opt(0, 0, 0)).ok_or(Error::Value)?;
+            Ok(Value::Date(date.and_utc().fixed_offset()))
+        }
+        "YEAR" | "MONTH" | "DAY" => {
+            let [arg] = <[Value; 1]>::try_from(args).map_err(|_| Error::Argument)?;
+            let date = to_date(arg)?;
+            Ok(Value::Number(match function {
+                "YEAR" => date.year() as f32,
+                "MONTH" => date.month() as f32,
+                _ => date.day() as f32,
+            }))
+        }
+        "TEXT" => {
+            let [value, format] = <[Value; 2]>::try_from(args).map_err(|_| Error::Argument)?;
+            let Value::Text(format) = format else {
+                return Err(Error::Cast);
+            };
+            Ok(Value::Text(match value {
+                Value::Number(number) => format_number(number, &format),
+                Value::Error(e) => return Err(e),
+                Value::Iterator(_) => return Err(Error::Cast),
+                value => match to_date(value.clone()) {
+                    Ok(date) => format_date(date, &format),
+                    Err(_) => calculate::result_to_string(value),
+                },
+            }))
+        }
+        _ => unreachable!("not in FUNCTIONS"),
+    }
+}
+
+fn to_date(value: Value) -> Result<DateTime<FixedOffset>, Error> {
+    match value {
+        Value::Date(date) => Ok(date),
+        Value::Text(text) => parse_date(&text).ok_or(Error::Cast),
+        Value::Error(e) => Err(e),
+        _ => Err(Error::Cast),
+    }
+}
+
+/// Formats a date with an Excel format like `dd.mm.yyyy` or `d mmmm yyyy hh:mm`.
+fn format_date(date: DateTime<FixedOffset>, format: &str) -> String {
+    // Runs of the same letter, everything else is copied.
+    let mut tokens: Vec<(char, usize)> = vec![];
+    for c in format.chars() {
+        let letter = c.to_ascii_lowercase();
+        match tokens.last_mut() {
+            Some((last, count)) if *last == letter && "ymdhs".contains(letter) => *count += 1,
+            _ => tokens.push((if "ymdhs".contains(letter) { letter } else { c }, 1)),
+        }
+    }
+    let mut pattern = String::new();
+    for (idx, (token, count)) in tokens.iter().enumerate() {
+        // `m` is minutes after hours or before seconds, months otherwise.
+        let minutes = *token == 'm'
+            && (tokens[..idx].iter().rev().find(|(t, _)| t.is_ascii_alphabetic()).is_some_and(|(t, _)| *t == 'h')
+                || tokens[idx + 1..].iter().find(|(t, _)| t.is_ascii_alphabetic()).is_some_and(|(t, _)| *t == 's'));
+        pattern.push_str(match (token, count) {
+            ('y', 1..=2) => "%y",
+            ('y', _) => "%Y",
+            ('m', _) if minutes => "%M",
+            ('m', 1) => "%-m",
+            ('m', 2) => "%m",
+            ('m', 3) => "%b",
+            ('m', _) => "%B",
+            ('d', 1) => "%-d",
+            ('d', 2) => "%d",
+            ('d', 3) => "%a",
+            ('d', _) => "%A",
+            ('h', 1) => "%-H",
+            ('h', _) => "%H",
+            ('s', _) => "%S",
+            ('%', _) => "%%",
+            (c, _) => {
+                pattern.push(*c);
+                continue;
+            }
+        });
+    }
+    date.format(&pattern).to_string()
+}
+
+/// Formats a number with an Excel format like `0.00`, `#,##0`, `0%` or `$#,##0.00`.
+fn format_number(number: f32, format: &str) -> String {
+    let is_digit = |c: char| matches!(c, '0' | '#' | ',' | '.');
+    let (Some(start), Some(end)) = (format.find(is_digit), format.rfind(is_digit)) else {
+        return format.to_string();
+    };
+    let (prefix, digits, suffix) = (&format[..start], &format[start..=end], &format[end + 1..]);
+    let (int_digits, frac_digits) = digits.split_once('.').unwrap_or((digits, ""));
+    let number = number as f64 * if format.contains('%') { 100.0 } else { 1.0 };
+
+    let decimals = frac_digits.chars().filter(|c| matches!(c, '0' | '#')).count();
+    let required_decimals = frac_digits.chars().filter(|c| *c == '0').count();
+    let formatted = format!("{:.*}", decimals, number.abs());
+    let (int, frac) = formatted.split_once('.').unwrap_or((&formatted, ""));
+    // Optional (`#`) decimals are dropped if they are zero.
+    let mut frac = frac.to_string();
+    while frac.len() > required_decimals && frac.ends_with('0') {
+        frac.pop();
+    }
+
+    let min_int = int_digits.chars().filter(|c| *c == '0').count();
+    let int = format!("{int:0>min_int$}");
+    let int = match int_digits.contains(',') {
+        true => {
+            let mut grouped = String::new();
+            for (idx, c) in int.chars().enumerate() {
+                if idx > 0 && (int.len() - idx) % 3 == 0 {
+                    grouped.push(',');
+                }
+                grouped.push(c);
+            }
+            grouped
+        }
+        false => int,
+    };
+    let int = if int == "0" && min_int == 0 && !frac.is_empty() { String::new() } else { int };
+    let sign = if number < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') { "-" } else { "" };
+    match frac.is_empty() {
+        true => format!("{sign}{prefix}{int}{suffix}"),
+        false => format!("{sign}{prefix}{int}.{frac}{suffix}"),
+    }
+}
+
+/// Evaluates `VLOOKUP(value, range, index, [approximate])` and `HLOOKUP(...)`: finds `value` in
+/// the first column (row) of `range` and returns the cell in column (row) `index` of the range.
+/// Approximate lookups (the default) expect the first column (row) to be sorted and match the
+/// largest value that is not larger than `value`.
+fn lookup(function: &str, args: &[String], calculate: &dyn Fn(&str) -> Value) -> Result<Value, Error> {
+    let (value, range, index, approximate) = match args {
+        [value, range, index] => (value, range, index, None),
+        [value, range, index, approximate] => (value, range, index, Some(approximate)),
+        _ => return Err(Error::Argument),
+    };
+    let range = range.trim();
+    let (from, to) = match parse_range(range) {
+        Some((from, to, len)) if len == range.len() => (from, to),
+        _ => return Err(Error::Reference),
+    };
+    let (rows, cols) = (from.0.min(to.0)..=from.0.max(to.0), from.1.min(to.1)..=from.1.max(to.1));
+
+    let value = match calculate(&format!("={value}")) {
+        Value::Error(e) => return Err(e),
+        value => value,
+    };
+    // Larger indexes are outside the range anyway, clamping them keeps the offsets from overflowing.
+    let size = rows.end() - rows.start() + 1 + cols.end() - cols.start();
+    let index = match numbers(vec![calculate(&format!("={index}"))])?.as_slice() {
+        [index] if *index >= 1.0 => index.min(size as f32) as i64 - 1,
+        _ => return Err(Error::Value),
+    };
+    let approximate = match approximate.map(|approximate| calculate(&format!("={approximate}"))) {
+        None | Some(Value::Boolean(Boolean::True)) => true,
+        Some(Value::Boolean(Boolean::False)) => false,
+        Some(Value::Number(number)) => number != 0.0,
+        Some(Value::Error(e)) => return Err(e),
+        Some(_) => return Err(Error::Cast),
+    };
+
+    // The keys to search and the cells to return from, as (row, column).
+    let (keys, result_offset) = match function {
+        "VLOOKUP" => (rows.clone().map(|row| (row, *cols.start())).collect::<Vec<_>>(), (0, index)),
+        _ => (cols.clone().map(|col| (*rows.start(), col)).collect::<Vec<_>>(), (index, 0)),
+    };
+    let cell = |(row, col): (i64, i64)| calculate(&format!("={}", id_to_cell_reference(row * 26 + col)));
+    let mut found = None;
+    for key in keys {
+        match compare(&cell(key), &value) {
+            Some(Ordering::Equal) => {
+                found = Some(key);
+                break;
+            }
+            Some(Ordering::Less) if approximate => found = Some(key),
+            Some(Ordering::Greater) if approximate => break,
+            _ => {}
+        }
+    }
+    let (row, col) = found.ok_or(Error::Value)?;
+    let (row, col) = (row + result_offset.0, col + result_offset.1);
+    if !rows.contains(&row) || !cols.contains(&col) {
+        return Err(Error::Reference);
+    }
+    Ok(cell((row, col)))
+}
+
+/// Compares numbers with numbers and text with text (ignoring case), other values don't match.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+        (Value::Text(a), Value::Text(b)) => Some(a.to_lowercase().cmp(&b.to_lowercase())),
+        (Value::Boolean(a), Value::Boolean(b)) => Some((*a == Boolean::True).cmp(&(*b == Boolean::True))),
+        _ => None,
+    }
+}
+
+/// A well mixed 64-bit hash (SplitMix64), stable across builds unlike the hashers of `std`.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+fn flatten(values: Vec<Value>) -> Vec<Value> {
+    values
+        .into_iter()
+        .flat_map(|value| match value {
+            Value::Iterator(values) => flatten(values),
+            value => vec![value],
+        })
+        .collect()
+}
+
+/// The numbers among `values`, blanks and booleans are skipped.
+fn numbers(values: Vec<Value>) -> Result<Vec<f32>, Error> {
+    let mut numbers = vec![];
+    for value in flatten(values) {
+        match value {
+            Value::Number(number) => numbers.push(number),
+            Value::Text(text) => numbers.push(text.trim().parse().map_err(|_| Error::Cast)?),
+            Value::Error(e) => return Err(e),
+            Value::Date(_) => return Err(Error::Cast),
+            Value::Blank | Value::Boolean(_) | Value::Iterator(_) => {}
+        }
+    }
+    Ok(numbers)
+}
+
+/// Turns the result of a function back into something the formula engine can parse.
+fn value_to_literal(value: Value) -> String {
+    match value {
+        Value::Number(number) if number < 0.0 => format!("({number})"),
+        Value::Number(number) => number.to_string(),
+        Value::Text(text) => format!("\"{}\"", text.replace('"', "\"\"")),
+        Value::Boolean(Boolean::True) => String::from("TRUE"),
+        Value::Boolean(Boolean::False) => String::from("FALSE"),
+        value => format!("\"{}\"", calculate::result_to_string(value)),
+    }
 }
 
 fn cell_references_to_ids(crf: &str) -> Option<i64> {
@@ -70,7 +632,20 @@ fn id_to_cell_reference(id: i64) -> String {
 
 pub fn mentions(raw_content: Option<SqlString>) -> Result<Option<Arc<Vec<Option<i64>>>>, Box<dyn std::error::Error>> {
     let cell_content = raw_content.unwrap_or_else(|| SqlString::new());
-    let formula = parse_formula::parse_string_to_formula(cell_content.str(), None::<NoCustomFunction>);
+    // Ranges and calls of our own functions become lists, so the engine finds the references in
+    // them. Formulas with too large ranges evaluate to an error and don't mention anything.
+    let cell_content = match cell_content.str().starts_with('=') {
+        true => match expand_ranges(cell_content.str()) {
+            Ok((formula, _)) => rewrite_calls(&formula, &mut |_, args| match args.is_empty() {
+                true => Ok(String::from("0")),
+                false => Ok(format!("{{{}}}", args.join(","))),
+            })
+            .unwrap_or(formula),
+            Err(_) => return Ok(Some(Arc::new(vec![]))),
+        },
+        false => cell_content.str().to_string(),
+    };
+    let formula = parse_formula::parse_string_to_formula(&cell_content, None::<NoCustomFunction>);
 
     let mut formulas = VecDeque::from(vec![formula]);
     let mut references = vec![];
@@ -156,19 +731,183 @@ mod tests {
 
     #[test]
     fn empty() {
-        let result = cell_value(Some("".to_string()), None, None).unwrap().unwrap();
+        let result = cell_value(Some("".to_string()), None, None, None, None).unwrap().unwrap();
         assert_eq!(result, String::new());
     }
 
     #[test]
     fn non_formula() {
-        let result = cell_value(Some("just a text".to_string()), None, None).unwrap().unwrap();
+        let result = cell_value(Some("just a text".to_string()), None, None, None, None).unwrap().unwrap();
         assert_eq!(result, "just a text".to_string());
     }
 
     #[test]
     fn math() {
-        let result = cell_value(Some("=(1*(2+3))*2".to_string()), None, None).unwrap().unwrap();
+        let result = cell_value(Some("=(1*(2+3))*2".to_string()), None, None, None, None).unwrap().unwrap();
         assert_eq!(result, "10");
     }
+
+    #[test]
+    fn value_types() {
+        let value_type = |formula: &str| cell_value_type(Some(formula.to_string()), None, None, None, None).unwrap().unwrap();
+        assert_eq!(value_type("42"), "number");
+        assert_eq!(value_type("=\"4\" & \"2\""), "string");
+        assert_eq!(value_type("=1>2"), "bool");
+        assert_eq!(value_type("=DATE(2025, 1, 5)"), "date");
+        assert_eq!(value_type("=1/0"), "error");
+        assert_eq!(value_type("true"), "bool");
+        assert_eq!(value_type("2019-03-01T02:00:00.000Z"), "date");
+        assert_eq!(value_type("just a text"), "string");
+        assert_eq!(value_type(""), "string");
+    }
+
+    #[test]
+    fn mentions_in_functions() {
+        let result = mentions(Some("=MAX(A1, ROUND(A2), \"MIN(A3)\")".to_string())).unwrap().unwrap();
+        assert_eq!(*result, vec![Some(26), Some(52)]);
+    }
+
+    #[test]
+    fn functions() {
+        let eval = |formula: &str| cell_value(Some(formula.to_string()), None, None, None, None).unwrap().unwrap();
+        assert_eq!(eval("=COUNT(1, \"a\", 2, TRUE)"), "2");
+        assert_eq!(eval("=MIN(3, -1, 2)"), "-1");
+        assert_eq!(eval("=MAX({1,5}, 2)+1"), "6");
+        assert_eq!(eval("=MAX()"), "0");
+        assert_eq!(eval("=ROUND(2.567, 2)"), "2.57");
+        assert_eq!(eval("=ROUND(MAX(1.5, 0))"), "2");
+        assert_eq!(eval("=TRIM(\"  a   b \")"), "a b");
+        assert_eq!(eval("=UPPER(\"say \"\"hi\"\"\")"), "SAY \"HI\"");
+        assert_eq!(eval("=LOWER(\"ABC\") & \"d\""), "abcd");
+        assert_eq!(eval("=\"MIN(1)\""), "MIN(1)");
+        assert_eq!(eval("=ROUND(1, 2, 3)"), "#ARG!");
+        assert_eq!(eval("=MIN(1, \"a\")"), "#CAST!");
+        assert_eq!(eval("=MIN(1"), "#PARSE!");
+    }
+
+    #[test]
+    fn ranges() {
+        let result = mentions(Some("=SUM(B1:A2, \"C1:C2\") + COUNT(A0:A0)".to_string())).unwrap().unwrap();
+        assert_eq!(*result, vec![Some(0), Some(26), Some(27), Some(52), Some(53)]);
+        let result = mentions(Some("=SUM(A0:Z100)".to_string())).unwrap().unwrap();
+        assert_eq!(*result, vec![]);
+
+        let eval = |formula: &str| {
+            cell_value(
+                Some(formula.to_string()),
+                Some(Arc::new(vec![Some(26), Some(27), Some(52), Some(53)])),
+                Some(Arc::new(vec![Some("1".to_string()), Some("2".to_string()), Some("3".to_string()), None])),
+                None,
+                None,
+            )
+            .unwrap()
+            .unwrap()
+        };
+        assert_eq!(eval("=SUM(A1:B2)"), "6");
+        assert_eq!(eval("=COUNT(A1:B2) + MAX(A1:A2)"), "6");
+        assert_eq!(eval("=B2"), "#VALUE!");
+        assert_eq!(eval("=SUM(A0:Z100)"), "#REF!");
+        assert_eq!(eval("A1:B2"), "A1:B2");
+    }
+
+    #[test]
+    fn lookups() {
+        // A column of "a", "b", "c" next to a column of 1, 2 and an empty cell
+        let cells = [(0, "a"), (1, "1"), (26, "b"), (27, "2"), (52, "c")];
+        let eval = |formula: &str| {
+            cell_value(
+                Some(formula.to_string()),
+                Some(Arc::new(cells.iter().map(|(id, _)| Some(*id)).collect())),
+                Some(Arc::new(cells.iter().map(|(_, value)| Some(value.to_string())).collect())),
+                None,
+                None,
+            )
+            .unwrap()
+            .unwrap()
+        };
+        assert_eq!(eval("=VLOOKUP(\"B\", A0:B2, 2, FALSE)"), "2");
+        assert_eq!(eval("=VLOOKUP(\"bb\", A0:B2, 2)"), "2");
+        assert_eq!(eval("=VLOOKUP(\"bb\", A0:B2, 2, FALSE)"), "#VALUE!");
+        assert_eq!(eval("=VLOOKUP(\"a\", A0:B2, 3)"), "#REF!");
+        assert_eq!(eval("=VLOOKUP(\"a\", A0:B2, 99999999999999999999)"), "#REF!");
+        assert_eq!(eval("=HLOOKUP(\"a\", A0:B1, \"inf\")"), "#REF!");
+        assert_eq!(eval("=VLOOKUP(\"a\", {1,2}, 1)"), "#REF!");
+        assert_eq!(eval("=HLOOKUP(\"a\", A0:B1, 2, FALSE) & HLOOKUP(\"b\", A1:B1, 1)"), "bb");
+        assert_eq!(eval("=HLOOKUP(1, B0:B1, 2) + 1"), "3");
+    }
+
+    #[test]
+    fn dates_and_formatting() {
+        let eval = |formula: &str| cell_value(Some(formula.to_string()), None, None, None, None).unwrap().unwrap();
+        assert_eq!(eval("=DATE(2024, 2, 29)"), "2024-02-29 00:00:00 +00:00");
+        assert_eq!(eval("=DATE(2023, 2, 29)"), "#VALUE!");
+        assert_eq!(eval("=YEAR(DATE(2024, 2, 29)) + MONTH(\"2019-03-01T02:00:00.000Z\")"), "2027");
+        assert_eq!(eval("=DAY(\"2024-02-29 00:00:00 +00:00\")"), "29");
+        assert_eq!(eval("=DAYS(DATE(2024, 3, 1), DATE(2024, 2, 1))"), "29");
+        assert_eq!(eval("=TEXT(DATE(2024, 3, 1), \"dd.mm.yyyy\")"), "01.03.2024");
+        assert_eq!(eval("=TEXT(DATE(2024, 3, 1), \"dddd, d mmmm yy hh:mm\")"), "Friday, 1 March 24 00:00");
+        assert_eq!(eval("=TEXT(1234.567, \"$#,##0.00\")"), "$1,234.57");
+        assert_eq!(eval("=TEXT(-0.5, \"0.0#\")"), "-0.5");
+        assert_eq!(eval("=TEXT(0.256, \"0%\")"), "26%");
+        assert_eq!(eval("=TEXT(7, \"000\")"), "007");
+        assert_eq!(eval("=TEXT(\"a\", \"0.00\")"), "a");
+
+        let result = cell_value(
+            Some("=TEXT(A0, \"yyyy-mm-dd\")".to_string()),
+            Some(Arc::new(vec![Some(0)])),
+            Some(Arc::new(vec![Some("2024-02-29 00:00:00 +00:00".to_string())])),
+            None,
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(result, "2024-02-29");
+    }
+
+    #[test]
+    fn volatile_functions() {
+        let now = Timestamp::new(DateTime::parse_from_rfc3339("2024-02-29T13:14:15Z").unwrap().timestamp_millis());
+        let eval = |formula: &str| cell_value(Some(formula.to_string()), None, None, Some(now), Some(1)).unwrap().unwrap();
+        assert_eq!(eval("=TEXT(NOW(), \"yyyy-mm-dd hh:mm:ss\")"), "2024-02-29 13:14:15");
+        assert_eq!(eval("=TODAY()"), "2024-02-29 00:00:00 +00:00");
+        assert_eq!(eval("=DAYS(DATE(2024, 3, 10), TODAY())"), "10");
+        assert_eq!(eval("=NOW(1)"), "#ARG!");
+        let result = mentions(Some("=DAYS(NOW(), A1)".to_string())).unwrap().unwrap();
+        assert_eq!(*result, vec![Some(26)]);
+    }
+
+    #[test]
+    fn random_functions() {
+        let eval = |formula: &str, now: i64, id: i64| {
+            cell_value(Some(formula.to_string()), None, None, Some(Timestamp::new(now)), Some(id)).unwrap().unwrap()
+        };
+        let rand = eval("=RAND()", 1000, 1).parse::<f32>().unwrap();
+        assert!((0.0..1.0).contains(&rand));
+        assert_eq!(eval("=RAND()", 1000, 1), eval("=RAND()", 1000, 1));
+        assert_ne!(eval("=RAND()", 1000, 1), eval("=RAND()", 1000, 2));
+        assert_ne!(eval("=RAND()", 1000, 1), eval("=RAND()", 1001, 1));
+        assert_eq!(eval("=RAND() = RAND()", 1000, 1), "FALSE");
+        for now in 0..20 {
+            let number = eval("=RANDBETWEEN(1.5, 4)", now, 1).parse::<f32>().unwrap();
+            assert!([2.0, 3.0, 4.0].contains(&number), "{number}");
+        }
+        assert_eq!(eval("=RANDBETWEEN(2, 1)", 1000, 1), "#VALUE!");
+        for bounds in ["\"NaN\", 1", "1, \"nan\"", "\"-inf\", 1", "1, \"inf\""] {
+            assert_eq!(eval(&format!("=RANDBETWEEN({bounds})"), 1000, 1), "#VALUE!", "{bounds}");
+        }
+    }
+
+    #[test]
+    fn functions_with_references() {
+        let result = cell_value(
+            Some("=COUNT(A0, A1, A2) + MAX(A0, A1)".to_string()),
+            Some(Arc::new(vec![Some(0), Some(26), Some(52)])),
+            Some(Arc::new(vec![Some("3".to_string()), Some("7".to_string()), Some("x".to_string())])),
+            None,
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(result, "9");
+    }
 }
@@ -0,0 +1,64 @@
+//! Conversions between cell ids, their row and column, and names like `B10`.
+//!
+//! Rows are numbered from `0` like the ids, columns are lettered `A` to `Z`, then `AA`, `AB`
+//! and so on like in other spreadsheets.
+
+use crate::CELL_IDS;
+
+/// Columns of the sheet.
+pub const COLUMNS: i64 = 26;
+
+/// The id of the cell in `row` and `column`.
+pub const fn id(row: i64, column: i64) -> i64 {
+    row * COLUMNS + column
+}
+
+/// The row and column of a cell.
+pub const fn position(id: i64) -> (i64, i64) {
+    (id / COLUMNS, id % COLUMNS)
+}
+
+/// The letters of a column, `0` is `A` and `26` is `AA`.
+pub fn column_letters(column: i64) -> String {
+    let mut letters = Vec::new();
+    let mut rest = column + 1;
+    while rest > 0 {
+        letters.push(b'A' + ((rest - 1) % 26) as u8);
+        rest = (rest - 1) / 26;
+    }
+    letters
+        .iter()
+        .rev()
+        .map(|&letter| char::from(letter))
+        .collect()
+}
+
+/// The column of uppercase letters like `AA`.
+pub fn parse_column(letters: &str) -> Option<i64> {
+    if letters.is_empty() || !letters.bytes().all(|letter| letter.is_ascii_uppercase()) {
+        return None;
+    }
+    letters.bytes().try_fold(-1, |column: i64, letter| {
+        (column + 1)
+            .checked_mul(26)?
+            .checked_add((letter - b'A') as i64)
+    })
+}
+
+/// The name of a cell like `B10`.
+pub fn cell_name(id: i64) -> String {
+    let (row, column) = position(id);
+    format!("{}{row}", column_letters(column))
+}
+
+/// The id of a cell name like `B10`, if the cell is in the sheet.
+pub fn parse_cell_name(name: &str) -> Option<i64> {
+    let digits = name.find(|c: char| c.is_ascii_digit())?;
+    let (letters, row) = name.split_at(digits);
+    let column = parse_column(letters).filter(|column| *column < COLUMNS)?;
+    if !row.bytes().all(|digit| digit.is_ascii_digit()) {
+        return None;
+    }
+    let id = row.parse::<i64>().ok()?.checked_mul(COLUMNS)? + column;
+    CELL_IDS.contains(&id).then_some(id)
+}
@@ -0,0 +1,280 @@
+//! The functions formulas can call: the ones of the formula engine and the ones implemented in
+//! `feldera/udf`. The server rejects formulas calling other functions, the client suggests them
+//! while typing and lists them in its formula reference.
+
+/// What a function works with, functions are grouped by it in the formula reference.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Category {
+    Math,
+    Logic,
+    Text,
+    Date,
+    Lookup,
+    Random,
+}
+
+impl Category {
+    pub const ALL: [Category; 6] = [
+        Category::Math,
+        Category::Logic,
+        Category::Text,
+        Category::Date,
+        Category::Lookup,
+        Category::Random,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Category::Math => "Math",
+            Category::Logic => "Logic",
+            Category::Text => "Text",
+            Category::Date => "Dates",
+            Category::Lookup => "Lookups",
+            Category::Random => "Random Numbers",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Function {
+    pub name: &'static str,
+    /// The arguments, optional ones in brackets, e.g. `number, [digits]`.
+    pub arguments: &'static str,
+    pub description: &'static str,
+    /// A formula calling the function.
+    pub example: &'static str,
+    pub category: Category,
+}
+
+impl Function {
+    /// The name with the arguments, e.g. `ROUND(number, [digits])`.
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.arguments)
+    }
+}
+
+/// The function called `name`, names are upper case.
+pub fn function(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
+
+/// All functions, sorted by name.
+pub const FUNCTIONS: [Function; 31] = [
+    Function {
+        name: "ABS",
+        arguments: "number",
+        description: "The absolute value of a number.",
+        example: "=ABS(-1)",
+        category: Category::Math,
+    },
+    Function {
+        name: "AND",
+        arguments: "value, ...",
+        description: "TRUE if all values are true.",
+        example: "=AND(\"test\",\"True\", 1, true)",
+        category: Category::Logic,
+    },
+    Function {
+        name: "AVERAGE",
+        arguments: "number, ...",
+        description: "The average of numbers and ranges.",
+        example: "=AVERAGE(A1:A10)",
+        category: Category::Math,
+    },
+    Function {
+        name: "COUNT",
+        arguments: "value, ...",
+        description: "How many of the values are numbers.",
+        example: "=COUNT(A0, A1, \"text\")",
+        category: Category::Math,
+    },
+    Function {
+        name: "DATE",
+        arguments: "year, month, day",
+        description: "The date of a day.",
+        example: "=DATE(2025, 12, 24)",
+        category: Category::Date,
+    },
+    Function {
+        name: "DAY",
+        arguments: "date",
+        description: "The day of the month of a date.",
+        example: "=DAY(A12)",
+        category: Category::Date,
+    },
+    Function {
+        name: "DAYS",
+        arguments: "end_date, start_date",
+        description: "The number of days between two dates.",
+        example: "=DAYS(DATE(2025, 12, 24), A12)",
+        category: Category::Date,
+    },
+    Function {
+        name: "HLOOKUP",
+        arguments: "value, range, row, [approximate]",
+        description: "Searches the first row of a range and returns the cell in another row.",
+        example: "=HLOOKUP(2019, B1:F3, 2)",
+        category: Category::Lookup,
+    },
+    Function {
+        name: "IF",
+        arguments: "condition, then, else",
+        description: "One of two values depending on a condition.",
+        example: "=IF(TRUE,1,0)",
+        category: Category::Logic,
+    },
+    Function {
+        name: "ISBLANK",
+        arguments: "value",
+        description: "TRUE if the value is empty.",
+        example: "=ISBLANK(A1)",
+        category: Category::Logic,
+    },
+    Function {
+        name: "LEFT",
+        arguments: "text, [count]",
+        description: "The first characters of a text, one by default.",
+        example: "=LEFT(\"apple\", 3)",
+        category: Category::Text,
+    },
+    Function {
+        name: "LOWER",
+        arguments: "text",
+        description: "A text in lower case.",
+        example: "=LOWER(\"Apple\")",
+        category: Category::Text,
+    },
+    Function {
+        name: "MAX",
+        arguments: "number, ...",
+        description: "The largest of numbers and ranges.",
+        example: "=MAX(A0, A1) - MIN(A0, A1)",
+        category: Category::Math,
+    },
+    Function {
+        name: "MIN",
+        arguments: "number, ...",
+        description: "The smallest of numbers and ranges.",
+        example: "=MIN(A1:A5, C1:C5)",
+        category: Category::Math,
+    },
+    Function {
+        name: "MONTH",
+        arguments: "date",
+        description: "The month of a date, from 1 to 12.",
+        example: "=MONTH(A12)",
+        category: Category::Date,
+    },
+    Function {
+        name: "NOT",
+        arguments: "value",
+        description: "TRUE if the value is false.",
+        example: "=NOT(1>2)",
+        category: Category::Logic,
+    },
+    Function {
+        name: "NOW",
+        arguments: "",
+        description: "The current time, updated every minute.",
+        example: "=TEXT(NOW(), \"hh:mm\")",
+        category: Category::Date,
+    },
+    Function {
+        name: "OR",
+        arguments: "value, ...",
+        description: "TRUE if any value is true.",
+        example: "=OR(1>1,1<>1)",
+        category: Category::Logic,
+    },
+    Function {
+        name: "PRODUCT",
+        arguments: "number, ...",
+        description: "The product of numbers and ranges.",
+        example: "=PRODUCT(ABS(1),2*1, 3,4*1)",
+        category: Category::Math,
+    },
+    Function {
+        name: "RAND",
+        arguments: "",
+        description: "A random number between 0 and 1.",
+        example: "=RAND()",
+        category: Category::Random,
+    },
+    Function {
+        name: "RANDBETWEEN",
+        arguments: "low, high",
+        description: "A random whole number between low and high.",
+        example: "=RANDBETWEEN(1, 6)",
+        category: Category::Random,
+    },
+    Function {
+        name: "RIGHT",
+        arguments: "text, [count]",
+        description: "The last characters of a text, one by default.",
+        example: "=RIGHT(\"apple\", 3)",
+        category: Category::Text,
+    },
+    Function {
+        name: "ROUND",
+        arguments: "number, [digits]",
+        description: "A number rounded to a number of digits, 0 by default.",
+        example: "=ROUND(2.567, 2)",
+        category: Category::Math,
+    },
+    Function {
+        name: "SUM",
+        arguments: "number, ...",
+        description: "The sum of numbers and ranges.",
+        example: "=SUM(1,2,\"3\")",
+        category: Category::Math,
+    },
+    Function {
+        name: "TEXT",
+        arguments: "value, format",
+        description: "A date or number formatted with an Excel format code.",
+        example: "=TEXT(1234.5, \"$#,##0.00\")",
+        category: Category::Text,
+    },
+    Function {
+        name: "TODAY",
+        arguments: "",
+        description: "The current date, updated every minute.",
+        example: "=DAYS(DATE(2025, 12, 24), TODAY())",
+        category: Category::Date,
+    },
+    Function {
+        name: "TRIM",
+        arguments: "text",
+        description: "A text without leading and trailing spaces.",
+        example: "=UPPER(TRIM(\"  apple \"))",
+        category: Category::Text,
+    },
+    Function {
+        name: "UPPER",
+        arguments: "text",
+        description: "A text in upper case.",
+        example: "=UPPER(\"apple\")",
+        category: Category::Text,
+    },
+    Function {
+        name: "VLOOKUP",
+        arguments: "value, range, column, [approximate]",
+        description: "Searches the first column of a range and returns the cell in another column.",
+        example: "=VLOOKUP(\"pear\", A1:C10, 3, FALSE)",
+        category: Category::Lookup,
+    },
+    Function {
+        name: "XOR",
+        arguments: "value, ...",
+        description: "TRUE if an odd number of values are true.",
+        example: "=XOR(TRUE, FALSE)",
+        category: Category::Logic,
+    },
+    Function {
+        name: "YEAR",
+        arguments: "date",
+        description: "The year of a date.",
+        example: "=YEAR(A12)",
+        category: Category::Date,
+    },
+];
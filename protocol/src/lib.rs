@@ -0,0 +1,609 @@
+//! Types exchanged between the server and the client.
+//!
+//! Enable the `openapi` feature to derive `utoipa::ToSchema` and the `graphql` feature to derive
+//! `async_graphql::SimpleObject` for them.
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+pub mod address;
+pub mod functions;
+
+/// Valid cell ids: 26 columns times 40 million rows.
+pub const CELL_IDS: Range<i64> = 0..1_040_000_000;
+
+/// The sheet of regions and updates that don't name one.
+pub const FIRST_SHEET: u32 = 0;
+
+fn is_first_sheet(sheet: &u32) -> bool {
+    *sheet == FIRST_SHEET
+}
+
+/// A cell as it is stored in `spreadsheet_view` and sent to clients.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct Cell {
+    /// Row-major index of the cell (`row * 26 + column`).
+    pub id: i64,
+    /// Background color as little-endian premultiplied RGBA.
+    pub background: i32,
+    /// How the text of the cell is drawn.
+    #[serde(default, skip_serializing_if = "CellStyle::is_plain")]
+    #[cfg_attr(feature = "openapi", schema(value_type = i64))]
+    pub style: CellStyle,
+    /// What the user typed, e.g. a formula.
+    pub raw_value: String,
+    /// The result of evaluating `raw_value`.
+    pub computed_value: String,
+    /// What `computed_value` is.
+    #[serde(default)]
+    pub value_type: ValueType,
+    /// The cells of the reference cycle `raw_value` is part of, `computed_value` is `#CYCLE!`
+    /// then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cycle: Option<Vec<i64>>,
+    /// The tab of the client the cell is on.
+    #[serde(default, skip_serializing_if = "is_first_sheet")]
+    pub sheet: u32,
+}
+
+/// The text style of a cell. It is stored in the `style` column as one integer: bit 0 is bold,
+/// bit 1 italic, bit 2 whether the cell has a text color and the upper 32 bits are that color.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+#[serde(from = "Option<i64>", into = "i64")]
+pub struct CellStyle {
+    pub bold: bool,
+    pub italic: bool,
+    /// Text color as little-endian premultiplied RGBA, the default text color if `None`.
+    pub color: Option<i32>,
+}
+
+impl CellStyle {
+    const BOLD: i64 = 1;
+    const ITALIC: i64 = 1 << 1;
+    const COLORED: i64 = 1 << 2;
+
+    /// Whether the text is drawn like in a cell without style.
+    pub fn is_plain(&self) -> bool {
+        *self == CellStyle::default()
+    }
+}
+
+/// Rows written before cells had a style have `NULL` in the `style` column.
+impl From<Option<i64>> for CellStyle {
+    fn from(style: Option<i64>) -> Self {
+        CellStyle::from(style.unwrap_or_default())
+    }
+}
+
+impl From<i64> for CellStyle {
+    fn from(style: i64) -> Self {
+        CellStyle {
+            bold: style & CellStyle::BOLD != 0,
+            italic: style & CellStyle::ITALIC != 0,
+            color: (style & CellStyle::COLORED != 0).then_some((style >> 32) as i32),
+        }
+    }
+}
+
+impl From<CellStyle> for i64 {
+    fn from(style: CellStyle) -> Self {
+        let mut encoded = 0;
+        if style.bold {
+            encoded |= CellStyle::BOLD;
+        }
+        if style.italic {
+            encoded |= CellStyle::ITALIC;
+        }
+        if let Some(color) = style.color {
+            encoded |= CellStyle::COLORED | ((color as u32 as i64) << 32);
+        }
+        encoded
+    }
+}
+
+/// The type of a computed value.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    Number,
+    #[default]
+    String,
+    Bool,
+    /// A date and time, formatted like `2019-03-01 02:00:00 +00:00`.
+    Date,
+    /// An error like `#VALUE!` or `#CYCLE!`.
+    Error,
+}
+
+/// The range of cells `[from, to)` a client is looking at, sent over the websocket.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Region {
+    pub from: i64,
+    pub to: i64,
+    /// The tab of the client the cells are on.
+    #[serde(default, skip_serializing_if = "is_first_sheet")]
+    pub sheet: u32,
+}
+
+impl Region {
+    /// The cells `[from, to)` of the first sheet.
+    pub fn new(from: i64, to: i64) -> Self {
+        Region {
+            from,
+            to,
+            sheet: FIRST_SHEET,
+        }
+    }
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Region::new(0, 2500)
+    }
+}
+
+/// Regions a websocket client keeps receiving the changes of besides the region it is looking at,
+/// e.g. a scoreboard the user pinned. Replaces the regions pinned before.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PinnedRegions {
+    pub pinned: Vec<Region>,
+}
+
+/// The part of the sheet a presenter is looking at, the clients following the presenter show
+/// the same part.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Viewport {
+    /// The first visible row.
+    pub top_row: u64,
+    /// The id of the focused cell.
+    pub focused: u64,
+}
+
+/// Messages of a live walkthrough, sent over the websocket besides regions and cells.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenterMessage {
+    /// Sent by the presenter whenever the viewport changes, with the `PRESENTER_KEY` of the
+    /// server. The server sends it to the followers without the key.
+    Present {
+        viewport: Viewport,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        key: String,
+    },
+    /// Starts or stops following the presenter.
+    Follow(bool),
+}
+
+/// What a websocket client sends, told apart by its fields.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ClientMessage {
+    /// Replaces the region the client is looking at, the server answers with a snapshot of it.
+    Region(Region),
+    Pinned(PinnedRegions),
+    Presenter(PresenterMessage),
+}
+
+impl From<Region> for ClientMessage {
+    fn from(region: Region) -> Self {
+        ClientMessage::Region(region)
+    }
+}
+
+impl From<PinnedRegions> for ClientMessage {
+    fn from(pinned: PinnedRegions) -> Self {
+        ClientMessage::Pinned(pinned)
+    }
+}
+
+impl From<PresenterMessage> for ClientMessage {
+    fn from(message: PresenterMessage) -> Self {
+        ClientMessage::Presenter(message)
+    }
+}
+
+/// What the server sends over the websocket, told apart by its fields.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ServerMessage {
+    /// A snapshot of a region, in chunks for large regions.
+    Snapshot(Vec<Cell>),
+    /// A cell that changed.
+    Cell(Cell),
+    Error(WsError),
+    ReconnectSoon(ReconnectSoon),
+    Heartbeat(Heartbeat),
+    /// The viewport of the presenter, sent to the clients following it.
+    Presenter(PresenterMessage),
+}
+
+/// Body of `POST /api/spreadsheet`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UpdateRequest {
+    pub id: i64,
+    pub raw_value: String,
+    pub background: i32,
+    #[serde(default, skip_serializing_if = "CellStyle::is_plain")]
+    #[cfg_attr(feature = "openapi", schema(value_type = i64))]
+    pub style: CellStyle,
+    /// Clears the cell after this many seconds, unless it is overwritten before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// The tab of the client the cell is on.
+    #[serde(default, skip_serializing_if = "is_first_sheet")]
+    pub sheet: u32,
+}
+
+/// A label shown in the header of a column instead of its letter, body of
+/// `POST /api/column-labels`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ColumnLabel {
+    /// Index of the column, `0` is `A`.
+    pub column: u32,
+    /// An empty label resets the column to its letter.
+    pub label: String,
+}
+
+/// A note attached to a cell, body of `POST /api/comments`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CellComment {
+    pub id: i64,
+    /// An empty comment removes the note of the cell.
+    pub comment: String,
+}
+
+/// How `GET /api/column-values` picks the cells of a large range of rows.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Sampling {
+    /// The cell of every `step`th row.
+    #[default]
+    Every,
+    /// The smallest and the largest number of every bucket of `step` rows, so peaks show up in
+    /// charts.
+    MinMax,
+}
+
+/// The computed value of a cell, without the rest of the cell.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ColumnValue {
+    pub id: i64,
+    pub computed_value: String,
+}
+
+/// The values of a column over a range of rows, body of `GET /api/column-values`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ColumnValues {
+    /// Index of the column, `0` is `A`.
+    pub column: u32,
+    pub sampling: Sampling,
+    /// Rows per sampled row or bucket, `1` if every cell is returned.
+    pub step: u64,
+    /// The cells with content, ordered by id.
+    pub values: Vec<ColumnValue>,
+}
+
+/// Statistics of all cells of a column, a row of `column_statistics`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ColumnStatistics {
+    /// Index of the column, `0` is `A`.
+    pub column: u32,
+    /// Cells with content.
+    pub filled: u64,
+    /// Cells whose value is a number.
+    pub numbers: u64,
+    /// The sum of the numbers.
+    pub sum: f64,
+    /// The average of the numbers, `None` without numbers.
+    pub average: Option<f64>,
+}
+
+/// Rows of a region of the write heatmap, regions start at multiples of it.
+pub const HEATMAP_REGION_ROWS: i64 = 1000;
+
+/// How many cells of a region were written in the last minute, a row of `write_heatmap`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RegionActivity {
+    /// The cells of the region, `HEATMAP_REGION_ROWS` rows.
+    pub region: Region,
+    /// Writes in the last minute.
+    pub writes: u64,
+}
+
+/// Who wrote the current value of a cell, listed by `GET /api/admin/attribution/{range}` for
+/// moderators.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CellAttribution {
+    pub id: i64,
+    /// The IP of the editor as stored in `spreadsheet_data`, a hash if the server anonymizes
+    /// IPs.
+    pub ip: String,
+    /// When the value was written (UTC).
+    pub ts: String,
+}
+
+/// Body of `POST /api/admin/clear`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ClearRequest {
+    /// The regions whose cells are cleared, e.g. one per row of a selection.
+    pub regions: Vec<Region>,
+}
+
+/// Response of `POST /api/admin/clear`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ClearResponse {
+    /// Cells that had content.
+    pub cleared: usize,
+}
+
+/// Body of `POST /api/admin/bans`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BanRequest {
+    /// The IP of the editor, as in [`CellAttribution::ip`].
+    pub ip: String,
+}
+
+/// A row of `spreadsheet_statistics`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct Stats {
+    pub filled_total: u64,
+    pub filled_this_hour: u64,
+    pub filled_today: u64,
+    pub filled_this_week: u64,
+    pub currently_active_users: u64,
+}
+
+/// A change of [`Stats`], streamed by `GET /api/stats`: the first message sets every field, the
+/// following ones only the fields that changed.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StatsUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filled_total: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filled_this_hour: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filled_today: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filled_this_week: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currently_active_users: Option<u64>,
+}
+
+impl StatsUpdate {
+    /// The names of the fields, e.g. for `GET /api/stats?fields=filled_total`.
+    pub const FIELDS: [&'static str; 5] = [
+        "filled_total",
+        "filled_this_hour",
+        "filled_today",
+        "filled_this_week",
+        "currently_active_users",
+    ];
+
+    /// The update with only the fields named in `fields`.
+    pub fn select(self, fields: &[&str]) -> StatsUpdate {
+        let keep = |name: &str, value: Option<u64>| value.filter(|_| fields.contains(&name));
+        StatsUpdate {
+            filled_total: keep("filled_total", self.filled_total),
+            filled_this_hour: keep("filled_this_hour", self.filled_this_hour),
+            filled_today: keep("filled_today", self.filled_today),
+            filled_this_week: keep("filled_this_week", self.filled_this_week),
+            currently_active_users: keep("currently_active_users", self.currently_active_users),
+        }
+    }
+}
+
+impl From<&Stats> for StatsUpdate {
+    /// An update that sets every field.
+    fn from(stats: &Stats) -> Self {
+        StatsUpdate {
+            filled_total: Some(stats.filled_total),
+            filled_this_hour: Some(stats.filled_this_hour),
+            filled_today: Some(stats.filled_today),
+            filled_this_week: Some(stats.filled_this_week),
+            currently_active_users: Some(stats.currently_active_users),
+        }
+    }
+}
+
+impl Stats {
+    /// The fields that changed from `self` to `new`, `None` if nothing changed.
+    pub fn diff(&self, new: &Stats) -> Option<StatsUpdate> {
+        let changed = |old: u64, new: u64| (old != new).then_some(new);
+        let update = StatsUpdate {
+            filled_total: changed(self.filled_total, new.filled_total),
+            filled_this_hour: changed(self.filled_this_hour, new.filled_this_hour),
+            filled_today: changed(self.filled_today, new.filled_today),
+            filled_this_week: changed(self.filled_this_week, new.filled_this_week),
+            currently_active_users: changed(
+                self.currently_active_users,
+                new.currently_active_users,
+            ),
+        };
+        (update != StatsUpdate::default()).then_some(update)
+    }
+
+    /// Merges an update into the statistics.
+    pub fn apply(&mut self, update: &StatsUpdate) {
+        let fields = [
+            (&mut self.filled_total, update.filled_total),
+            (&mut self.filled_this_hour, update.filled_this_hour),
+            (&mut self.filled_today, update.filled_today),
+            (&mut self.filled_this_week, update.filled_this_week),
+            (
+                &mut self.currently_active_users,
+                update.currently_active_users,
+            ),
+        ];
+        for (field, value) in fields {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+    }
+}
+
+/// How a deployment is configured, body of `GET /api/meta`. Clients fetch it at startup and hide
+/// what the deployment doesn't allow, so one build of the client serves all deployments.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Meta {
+    /// Whether cells, comments and column labels can be edited.
+    pub writes_allowed: bool,
+    /// Longest raw value of a cell, longer values are truncated.
+    pub max_cell_chars: u32,
+    pub columns: u64,
+    pub rows: u64,
+    /// Whether the websocket requires a token.
+    pub auth_required: bool,
+    /// Features that are still tried out, the client ignores the ones it doesn't know.
+    #[serde(default)]
+    pub experimental_features: Vec<String>,
+}
+
+/// The configuration of the public deployment, assumed until the meta is fetched.
+impl Default for Meta {
+    fn default() -> Self {
+        Meta {
+            writes_allowed: true,
+            max_cell_chars: 64,
+            columns: address::COLUMNS as u64,
+            rows: CELL_IDS.end as u64 / address::COLUMNS as u64,
+            auth_required: false,
+            experimental_features: Vec::new(),
+        }
+    }
+}
+
+/// Body of every error response of the REST API.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErrorResponse {
+    /// Human-readable description of the error.
+    pub error: String,
+    /// Machine-readable error kind, e.g. `rate_limited` or `validation`.
+    pub code: String,
+    /// The field of the request body that is invalid, e.g. `raw_value`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Seconds after which the request may succeed if it is sent again, also sent as the
+    /// `Retry-After` header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+}
+
+/// Why the server closes a websocket, the code of its close frame. Browsers don't tell
+/// applications the code, so the server sends it as a [`WsError`] right before closing as well.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// The client IP has too many connections open.
+    RateLimited,
+    /// The client sent a message that is neither a region nor another known message.
+    InvalidRegion,
+    /// The server shuts down, e.g. for a deployment.
+    ServerShutdown,
+    /// The server requires a token to connect and the client sent none or an invalid one.
+    AuthRequired,
+}
+
+impl CloseReason {
+    pub const ALL: [CloseReason; 4] = [
+        CloseReason::RateLimited,
+        CloseReason::InvalidRegion,
+        CloseReason::ServerShutdown,
+        CloseReason::AuthRequired,
+    ];
+
+    /// The code of the close frame, `4000` plus the matching HTTP status.
+    pub fn code(&self) -> u16 {
+        match self {
+            CloseReason::RateLimited => 4429,
+            CloseReason::InvalidRegion => 4400,
+            CloseReason::ServerShutdown => 4503,
+            CloseReason::AuthRequired => 4401,
+        }
+    }
+
+    /// The name of the reason, e.g. `rate_limited`, the `code` of its [`WsError`].
+    pub fn name(&self) -> &'static str {
+        match self {
+            CloseReason::RateLimited => "rate_limited",
+            CloseReason::InvalidRegion => "invalid_region",
+            CloseReason::ServerShutdown => "server_shutdown",
+            CloseReason::AuthRequired => "auth_required",
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<CloseReason> {
+        CloseReason::ALL
+            .into_iter()
+            .find(|reason| reason.code() == code)
+    }
+}
+
+/// Sent over the websocket when the server is drained, e.g. before a deployment replaces it.
+///
+/// The server closes the connection with [`CloseReason::ServerShutdown`] at a random time within
+/// `reconnect_within_secs`, so its clients don't all reconnect to the other instances at once.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReconnectSoon {
+    pub reconnect_within_secs: u64,
+}
+
+/// Sent over the websocket every `interval_secs`, so clients notice when the connection is stale
+/// and whether it missed cells.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Heartbeat {
+    /// Time of the server in milliseconds since the Unix epoch.
+    pub server_time_ms: i64,
+    /// The region the connection is subscribed to. It isn't the last region the client asked
+    /// for if the server ignored that request, e.g. because the client scrolled too fast.
+    pub region: Region,
+    /// The number of cells sent over the connection before the heartbeat.
+    pub seq: u64,
+    /// Time until the next heartbeat.
+    pub interval_secs: u64,
+}
+
+/// An error sent over the websocket instead of a cell, e.g. for an invalid region.
+///
+/// Without `close` it has the fields of an [`ErrorResponse`] and the connection stays open.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct WsError {
+    /// Human-readable description of the error.
+    pub error: String,
+    /// Machine-readable error kind, e.g. `validation`, the name of `close` if it is set.
+    pub code: String,
+    /// Set if the server closes the connection after the error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub close: Option<CloseReason>,
+    /// Seconds after which the request may succeed if it is sent again, e.g. for `busy`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+}
@@ -0,0 +1,216 @@
+//! Round-trips the messages of the protocol through JSON, the untagged envelopes must come back
+//! as the variant they were sent as.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use xls_protocol::{
+    Cell, CellStyle, ClientMessage, CloseReason, Heartbeat, Meta, PinnedRegions, PresenterMessage,
+    ReconnectSoon, Region, ServerMessage, UpdateRequest, ValueType, Viewport, WsError, CELL_IDS,
+    FIRST_SHEET,
+};
+
+fn round_trip<T: Serialize + DeserializeOwned>(message: &T) -> T {
+    let json = serde_json::to_string(message).unwrap();
+    serde_json::from_str(&json).unwrap_or_else(|e| panic!("{json} doesn't parse: {e}"))
+}
+
+fn cell(id: i64) -> Cell {
+    Cell {
+        id,
+        background: 0x00ff00ff,
+        style: CellStyle::default(),
+        raw_value: String::from("=A1*2"),
+        computed_value: String::from("4"),
+        value_type: ValueType::Number,
+        cycle: None,
+        sheet: FIRST_SHEET,
+    }
+}
+
+#[test]
+fn server_messages_round_trip() {
+    let viewport = Viewport {
+        top_row: 10,
+        focused: 261,
+    };
+    let messages = [
+        ServerMessage::Snapshot(vec![cell(1), cell(2)]),
+        ServerMessage::Snapshot(vec![]),
+        ServerMessage::Cell(cell(3)),
+        ServerMessage::Cell(Cell {
+            computed_value: String::from("#CYCLE!"),
+            value_type: ValueType::Error,
+            cycle: Some(vec![3, 4]),
+            ..cell(3)
+        }),
+        ServerMessage::Cell(Cell {
+            style: CellStyle {
+                bold: true,
+                italic: false,
+                color: Some(-16_776_961),
+            },
+            ..cell(4)
+        }),
+        ServerMessage::Error(WsError {
+            error: String::from("Invalid region"),
+            code: String::from("validation"),
+            close: None,
+            retry_after_secs: None,
+        }),
+        ServerMessage::Error(WsError {
+            error: String::from("Too many queries are running"),
+            code: String::from("busy"),
+            close: None,
+            retry_after_secs: Some(1),
+        }),
+        ServerMessage::Error(WsError {
+            error: String::from("Too many connections"),
+            code: String::from("rate_limited"),
+            close: Some(CloseReason::RateLimited),
+            retry_after_secs: None,
+        }),
+        ServerMessage::ReconnectSoon(ReconnectSoon {
+            reconnect_within_secs: 30,
+        }),
+        ServerMessage::Heartbeat(Heartbeat {
+            server_time_ms: 1_700_000_000_000,
+            region: Region::new(0, 2600),
+            seq: 42,
+            interval_secs: 15,
+        }),
+        ServerMessage::Presenter(PresenterMessage::Present {
+            viewport,
+            key: String::new(),
+        }),
+    ];
+    for message in messages {
+        assert_eq!(round_trip(&message), message);
+    }
+}
+
+#[test]
+fn client_messages_round_trip() {
+    let messages = [
+        ClientMessage::Region(Region::new(0, 2600)),
+        ClientMessage::Pinned(PinnedRegions {
+            pinned: vec![Region::new(26, 52)],
+        }),
+        ClientMessage::Pinned(PinnedRegions { pinned: vec![] }),
+        ClientMessage::Presenter(PresenterMessage::Follow(true)),
+        ClientMessage::Presenter(PresenterMessage::Present {
+            viewport: Viewport::default(),
+            key: String::from("secret"),
+        }),
+    ];
+    for message in messages {
+        assert_eq!(round_trip(&message), message);
+    }
+}
+
+#[test]
+fn messages_keep_their_wire_format() {
+    let region = ClientMessage::from(Region::new(0, 26));
+    assert_eq!(
+        serde_json::to_value(region).unwrap(),
+        json!({"from": 0, "to": 26})
+    );
+    let second_sheet = Region {
+        sheet: 1,
+        ..Region::new(0, 26)
+    };
+    assert_eq!(
+        serde_json::to_value(ClientMessage::from(second_sheet)).unwrap(),
+        json!({"from": 0, "to": 26, "sheet": 1})
+    );
+    assert_eq!(
+        round_trip(&ClientMessage::from(second_sheet)),
+        second_sheet.into()
+    );
+    let follow = ClientMessage::from(PresenterMessage::Follow(false));
+    assert_eq!(
+        serde_json::to_value(follow).unwrap(),
+        json!({"follow": false})
+    );
+    // A cell sent by an older server, without a value type.
+    let cell: ServerMessage = serde_json::from_value(json!({
+        "id": 5,
+        "background": 0,
+        "raw_value": "hi",
+        "computed_value": "hi",
+    }))
+    .unwrap();
+    assert!(matches!(
+        cell,
+        ServerMessage::Cell(Cell {
+            id: 5,
+            value_type: ValueType::String,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn update_requests_round_trip() {
+    let update = UpdateRequest {
+        id: 1_039_999_999,
+        raw_value: String::from("hello"),
+        background: -1,
+        style: CellStyle::default(),
+        ttl_secs: None,
+        sheet: FIRST_SHEET,
+    };
+    assert_eq!(round_trip(&update), update);
+    assert!(!serde_json::to_string(&update).unwrap().contains("ttl_secs"));
+    let expiring = UpdateRequest {
+        ttl_secs: Some(60),
+        ..update.clone()
+    };
+    assert_eq!(round_trip(&expiring), expiring);
+    let styled = UpdateRequest {
+        style: CellStyle {
+            bold: false,
+            italic: true,
+            color: Some(0),
+        },
+        ..update
+    };
+    assert_eq!(round_trip(&styled), styled);
+}
+
+#[test]
+fn styles_are_stored_as_one_integer() {
+    let style = CellStyle {
+        bold: true,
+        italic: true,
+        color: Some(i32::from_le_bytes([255, 0, 0, 255])),
+    };
+    let encoded = serde_json::to_value(style).unwrap();
+    assert_eq!(encoded, json!(0xff0000ff_i64 << 32 | 0b111));
+    assert_eq!(serde_json::from_value::<CellStyle>(encoded).unwrap(), style);
+    // Rows written before cells had a style.
+    assert_eq!(
+        serde_json::from_value::<CellStyle>(json!(null)).unwrap(),
+        CellStyle::default()
+    );
+    let plain = serde_json::to_value(cell(1)).unwrap();
+    assert!(plain.get("style").is_none());
+}
+
+#[test]
+fn meta_defaults_to_the_public_deployment() {
+    let meta = Meta::default();
+    assert!(meta.writes_allowed);
+    assert_eq!(meta.columns * meta.rows, CELL_IDS.end as u64);
+    assert_eq!(round_trip(&meta), meta);
+    // Servers that don't try out any features may leave them out.
+    let parsed: Meta = serde_json::from_value(json!({
+        "writes_allowed": false,
+        "max_cell_chars": 64,
+        "columns": 26,
+        "rows": 40_000_000,
+        "auth_required": true,
+    }))
+    .unwrap();
+    assert!(parsed.experimental_features.is_empty());
+}
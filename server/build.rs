@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protox compiles the proto file without requiring protoc to be installed.
+    let file_descriptors = protox::compile(["proto/spreadsheet.proto"], ["proto"])?;
+    tonic_build::configure().compile_fds(file_descriptors)?;
+    println!("cargo:rerun-if-changed=proto/spreadsheet.proto");
+    Ok(())
+}
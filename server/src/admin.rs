@@ -0,0 +1,188 @@
+//! Endpoints for operators under `/api/admin`.
+//!
+//! They require `Authorization: Bearer <ADMIN_TOKEN>` and are disabled if `ADMIN_TOKEN` is not
+//! set. The dashboard at `/admin` asks for the token and polls [`overview`].
+
+use std::sync::LazyLock;
+
+use axum::extract::{Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use log::info;
+use serde::Serialize;
+use xls_protocol::ErrorResponse;
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::error_log::{self, LoggedError};
+use crate::pipeline::{self, PipelineStatus};
+use crate::subscribers::RegionSubscribers;
+use crate::{
+    cached_ranges, connectors, embed_tokens, integrations, latency, moderation, moderator_actions,
+    retention, scratch, seed, AppState,
+};
+
+static ADMIN_TOKEN: LazyLock<String> = LazyLock::new(|| env_or("ADMIN_TOKEN", String::new()));
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/connectors", get(connectors::list))
+        .route(
+            "/connectors/:name",
+            put(connectors::put).delete(connectors::delete),
+        )
+        .route("/cache", get(cached_ranges::list))
+        .route(
+            "/cache/:range",
+            put(cached_ranges::add).delete(cached_ranges::remove),
+        )
+        .route("/attribution/:range", get(moderator_actions::attribution))
+        .route("/bans", post(moderator_actions::ban))
+        .route("/cache/:range/refresh", post(cached_ranges::refresh))
+        .route("/clear", post(moderator_actions::clear))
+        .route("/cleanup", post(scratch::trigger))
+        .route(
+            "/embed-tokens",
+            get(embed_tokens::list).post(embed_tokens::issue),
+        )
+        .route("/embed-tokens/:name", delete(embed_tokens::revoke))
+        .route(
+            "/integrations",
+            get(integrations::list).post(integrations::add),
+        )
+        .route("/integrations/:name", delete(integrations::remove))
+        .route("/latency", get(latency::summary))
+        .route("/moderation", get(moderation::events))
+        .route("/retention", get(retention::policy))
+        .route("/seed/:range", post(seed::seed))
+        .route("/overview", get(overview))
+        .route("/drain", post(drain))
+        .route("/shutdown", post(shutdown))
+        .route_layer(middleware::from_fn(require_admin))
+}
+
+async fn require_admin(request: Request, next: Next) -> Response {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) if !ADMIN_TOKEN.is_empty() && constant_time_eq(token, &ADMIN_TOKEN) => {
+            next.run(request).await
+        }
+        _ => XlsError::Unauthorized.into_response(),
+    }
+}
+
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Number of subscribed regions listed in the overview.
+const MAX_REGIONS: usize = 20;
+
+/// Everything shown on the admin dashboard.
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct Overview {
+    /// Open websocket connections.
+    connections: usize,
+    /// The most subscribed regions.
+    regions: Vec<RegionSubscribers>,
+    /// IPs in the `api_limit_reached` view.
+    api_limited_ips: Vec<String>,
+    /// IPs that ran out of tokens for cell updates.
+    throttled_update_ips: Vec<String>,
+    /// IPs that ran out of tokens for region changes.
+    throttled_region_ips: Vec<String>,
+    /// `null` if Feldera is unavailable, see `pipeline_error`.
+    pipeline: Option<PipelineStatus>,
+    pipeline_error: Option<String>,
+    /// The most recent errors logged by the server, newest first.
+    recent_errors: Vec<LoggedError>,
+}
+
+/// Connections, rate limits, pipeline status and recent errors.
+#[utoipa::path(
+    get,
+    path = "/api/admin/overview",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Overview),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn overview(State(state): State<AppState>) -> Json<Overview> {
+    let mut regions = state.subscribers.by_region();
+    regions.truncate(MAX_REGIONS);
+    let mut api_limited_ips: Vec<String> = state.api_limits.iter().map(|ip| ip.clone()).collect();
+    api_limited_ips.sort();
+    let (pipeline, pipeline_error) = match pipeline::status_handler(State(state.clone())).await {
+        Ok(Json(status)) => (Some(status), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    Json(Overview {
+        connections: state.subscribers.connections(),
+        regions,
+        api_limited_ips,
+        throttled_update_ips: state.update_limiter.throttled(),
+        throttled_region_ips: state.region_limiter.throttled(),
+        pipeline,
+        pipeline_error,
+        recent_errors: error_log::recent(),
+    })
+}
+
+/// Stops accepting websockets and closes the open ones gradually within `WS_DRAIN_SECS`, e.g.
+/// before a rolling deployment replaces the server. Clients are told to reconnect soon and
+/// reconnect to other instances, not all at once.
+#[utoipa::path(
+    post,
+    path = "/api/admin/drain",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 202, description = "The server is drained"),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn drain(State(state): State<AppState>) -> StatusCode {
+    info!(
+        "Draining {} websocket connections",
+        state.subscribers.connections()
+    );
+    state.drain.cancel();
+    StatusCode::ACCEPTED
+}
+
+/// Closes all websockets with the `server_shutdown` close reason and stops the server, e.g.
+/// before a deployment replaces it. Clients reconnect to the new server.
+#[utoipa::path(
+    post,
+    path = "/api/admin/shutdown",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 202, description = "The server shuts down"),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn shutdown(State(state): State<AppState>) -> StatusCode {
+    info!("Shutting down");
+    state.shutdown.cancel();
+    StatusCode::ACCEPTED
+}
+
+/// The admin dashboard, it fetches its data from `/api/admin/overview`.
+pub(crate) async fn dashboard() -> Html<&'static str> {
+    Html(include_str!("../static/admin.html"))
+}
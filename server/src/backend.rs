@@ -0,0 +1,162 @@
+//! Where the cells are stored: reading regions, writing updates and following the changes of the
+//! cells go through a [`SpreadsheetBackend`].
+//!
+//! `SPREADSHEET_BACKEND` selects it: `feldera` (the default) uses the pipeline, `memory` keeps
+//! the cells in the server for local development and offline demos. The memory backend doesn't
+//! evaluate formulas, a cell shows what was typed into it, and features that query other views
+//! of the pipeline (statistics, search, diffs, column labels, ...) still need Feldera.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::{LazyLock, Mutex};
+
+use axum::async_trait;
+use log::info;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::broadcast::Sender;
+use xls_protocol::{Cell, CellStyle, ValueType, FIRST_SHEET};
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::fanout;
+use crate::feldera::{adhoc_query, insert_batch};
+
+static SPREADSHEET_BACKEND: LazyLock<String> =
+    LazyLock::new(|| env_or("SPREADSHEET_BACKEND", String::from("feldera")));
+
+#[async_trait]
+pub(crate) trait SpreadsheetBackend: Send + Sync {
+    /// The cells with an id in `range` as rows of `spreadsheet_view`, one JSON object per line.
+    async fn query_range(&self, range: Range<i64>) -> Result<String, XlsError>;
+
+    /// Inserts rows of `spreadsheet_data`, i.e. [`UpdatePayload`]s.
+    ///
+    /// [`UpdatePayload`]: crate::spreadsheet::UpdatePayload
+    async fn insert_cells(&self, rows: &[Value]) -> Result<(), XlsError>;
+
+    /// The changed cells, one JSON object per message, and
+    /// [`XlsError::ChangesMissed`] when changes were lost.
+    fn subscribe_changes(&self, capacity: usize) -> Sender<Result<String, XlsError>>;
+}
+
+/// The backend selected by `SPREADSHEET_BACKEND`.
+pub(crate) fn from_env(client: Client) -> Box<dyn SpreadsheetBackend> {
+    match SPREADSHEET_BACKEND.as_str() {
+        "memory" => {
+            info!("Storing the cells in memory, they are lost when the server stops");
+            Box::new(MemoryBackend::default())
+        }
+        "feldera" => Box::new(FelderaBackend { client }),
+        other => panic!("Unknown SPREADSHEET_BACKEND {other:?}, use feldera or memory"),
+    }
+}
+
+/// The cells are rows of `spreadsheet_view` of the Feldera pipeline.
+pub(crate) struct FelderaBackend {
+    client: Client,
+}
+
+#[async_trait]
+impl SpreadsheetBackend for FelderaBackend {
+    async fn query_range(&self, range: Range<i64>) -> Result<String, XlsError> {
+        let sql = format!(
+            "SELECT * FROM spreadsheet_view WHERE id >= {} and id < {}",
+            range.start, range.end
+        );
+        adhoc_query(self.client.clone(), sql.as_str()).await
+    }
+
+    async fn insert_cells(&self, rows: &[Value]) -> Result<(), XlsError> {
+        insert_batch(self.client.clone(), "spreadsheet_data", rows).await
+    }
+
+    fn subscribe_changes(&self, capacity: usize) -> Sender<Result<String, XlsError>> {
+        fanout::subscribe_change_stream(self.client.clone(), "spreadsheet_view", capacity)
+    }
+}
+
+/// The fields of a `spreadsheet_data` row the memory backend keeps.
+#[derive(Deserialize)]
+struct Row {
+    id: i64,
+    raw_value: String,
+    background: i32,
+    #[serde(default)]
+    style: CellStyle,
+}
+
+/// The cells are kept in a map, empty cells are removed.
+#[derive(Default)]
+pub(crate) struct MemoryBackend {
+    cells: Mutex<BTreeMap<i64, Cell>>,
+    /// Set by the first `subscribe_changes`.
+    changes: Mutex<Option<Sender<Result<String, XlsError>>>>,
+}
+
+impl MemoryBackend {
+    fn cell(row: Row) -> Cell {
+        let value_type = match row.raw_value.parse::<f64>() {
+            Ok(_) => ValueType::Number,
+            Err(_) => ValueType::String,
+        };
+        Cell {
+            id: row.id,
+            background: row.background,
+            style: row.style,
+            computed_value: row.raw_value.clone(),
+            raw_value: row.raw_value,
+            value_type,
+            cycle: None,
+            sheet: FIRST_SHEET,
+        }
+    }
+}
+
+#[async_trait]
+impl SpreadsheetBackend for MemoryBackend {
+    async fn query_range(&self, range: Range<i64>) -> Result<String, XlsError> {
+        let mut snapshot = String::new();
+        for cell in self
+            .cells
+            .lock()
+            .unwrap()
+            .range(range)
+            .map(|(_, cell)| cell)
+        {
+            snapshot.push_str(&serde_json::to_string(cell).unwrap());
+            snapshot.push('\n');
+        }
+        Ok(snapshot)
+    }
+
+    async fn insert_cells(&self, rows: &[Value]) -> Result<(), XlsError> {
+        let cells = rows
+            .iter()
+            .map(|row| Row::deserialize(row).map(Self::cell))
+            .collect::<Result<Vec<Cell>, _>>()
+            .map_err(|e| XlsError::ParseError(e.to_string()))?;
+        let changes = self.changes.lock().unwrap().clone();
+        let mut stored = self.cells.lock().unwrap();
+        for cell in cells {
+            if cell.raw_value.is_empty() && cell.background == 0 {
+                stored.remove(&cell.id);
+            } else {
+                stored.insert(cell.id, cell.clone());
+            }
+            if let Some(changes) = &changes {
+                let _ = changes.send(Ok(format!("{}\n", serde_json::to_string(&cell).unwrap())));
+            }
+        }
+        Ok(())
+    }
+
+    fn subscribe_changes(&self, capacity: usize) -> Sender<Result<String, XlsError>> {
+        self.changes
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| tokio::sync::broadcast::channel(capacity).0)
+            .clone()
+    }
+}
@@ -0,0 +1,328 @@
+//! Synthetic load generator for the spreadsheet server.
+//!
+//! Simulates viewers that connect to the websocket and scroll to random regions, and writers
+//! that post cell edits at a fixed rate. At the end it prints latency percentiles for region
+//! snapshots, POST requests and the time it takes for an edit to be broadcast back to viewers.
+//!
+//! ```bash
+//! cargo run --release --bin loadgen -- --url http://localhost:3000 --viewers 100 --writers 5 --rate 2 --duration 60
+//! ```
+//!
+//! Keep in mind that the pipeline limits the number of edits per IP per hour
+//! (see `api_limit_reached` in `program.sql`).
+//!
+//! With `--seed-region from..to` and the `--admin-token` of the server it first fills the region
+//! with sample content, e.g. for screenshots of a fresh deployment:
+//!
+//! ```bash
+//! cargo run --release --bin loadgen -- --admin-token secret --seed-region 0..520 --duration 0
+//! ```
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{SinkExt, StreamExt};
+use rand::Rng;
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Number of cells in the spreadsheet.
+const MAX_ID: i64 = 1_040_000_000;
+/// Size of a region requested by a viewer (same as the client prefetches: 100 rows around the view).
+const REGION_SIZE: i64 = 200 * 26;
+/// Writers only edit cells in this range so the observer can see all of them.
+const WRITE_RANGE: std::ops::Range<i64> = 0..2600;
+
+struct Options {
+    url: String,
+    viewers: usize,
+    writers: usize,
+    rate: f64,
+    scroll_interval: Duration,
+    duration: Duration,
+    seed_region: Option<String>,
+    admin_token: String,
+}
+
+impl Options {
+    fn parse() -> Self {
+        let mut options = Options {
+            url: String::from("http://localhost:3000"),
+            viewers: 10,
+            writers: 1,
+            rate: 1.0,
+            scroll_interval: Duration::from_secs(2),
+            duration: Duration::from_secs(30),
+            seed_region: None,
+            admin_token: String::new(),
+        };
+
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        for arg in args.chunks(2) {
+            let (flag, value) = match arg {
+                [flag, value] => (flag.as_str(), value.as_str()),
+                _ => Self::usage(),
+            };
+            match flag {
+                "--url" => options.url = value.trim_end_matches('/').to_string(),
+                "--viewers" => options.viewers = value.parse().unwrap_or_else(|_| Self::usage()),
+                "--writers" => options.writers = value.parse().unwrap_or_else(|_| Self::usage()),
+                "--rate" => options.rate = value.parse().unwrap_or_else(|_| Self::usage()),
+                "--scroll-interval" => {
+                    options.scroll_interval =
+                        Duration::from_secs_f64(value.parse().unwrap_or_else(|_| Self::usage()))
+                }
+                "--duration" => {
+                    options.duration =
+                        Duration::from_secs(value.parse().unwrap_or_else(|_| Self::usage()))
+                }
+                "--seed-region" => options.seed_region = Some(value.to_string()),
+                "--admin-token" => options.admin_token = value.to_string(),
+                _ => Self::usage(),
+            }
+        }
+        options
+    }
+
+    fn usage() -> ! {
+        eprintln!(
+            "usage: loadgen [--url http://localhost:3000] [--viewers 10] [--writers 1] \
+             [--rate <edits/s per writer>] [--scroll-interval <secs>] [--duration <secs>] \
+             [--seed-region <from..to> --admin-token <token>]"
+        );
+        std::process::exit(1)
+    }
+
+    fn ws_url(&self) -> String {
+        format!("{}/api/spreadsheet", self.url.replacen("http", "ws", 1))
+    }
+}
+
+#[derive(Default)]
+struct Latencies {
+    snapshot: Mutex<Vec<Duration>>,
+    post: Mutex<Vec<Duration>>,
+    broadcast: Mutex<Vec<Duration>>,
+    /// Values that were posted but not seen by the observer yet.
+    pending: Mutex<HashMap<String, Instant>>,
+    errors: AtomicU64,
+}
+
+fn print_percentiles(name: &str, latencies: &Mutex<Vec<Duration>>) {
+    let mut latencies = latencies.lock().unwrap();
+    if latencies.is_empty() {
+        println!("{name:<20} no samples");
+        return;
+    }
+    latencies.sort();
+    let percentile = |p: f64| {
+        let idx = ((latencies.len() - 1) as f64 * p).round() as usize;
+        latencies[idx].as_secs_f64() * 1000.0
+    };
+    println!(
+        "{name:<20} n={:<8} p50={:>8.1}ms p90={:>8.1}ms p99={:>8.1}ms max={:>8.1}ms",
+        latencies.len(),
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        percentile(1.0),
+    );
+}
+
+/// Connects to the websocket and jumps to a random region every `scroll_interval`.
+async fn viewer(options: Arc<Options>, latencies: Arc<Latencies>, deadline: Instant) {
+    let (socket, _) = match tokio_tungstenite::connect_async(options.ws_url()).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("viewer failed to connect: {e}");
+            latencies.errors.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    let (mut sender, mut receiver) = socket.split();
+
+    while Instant::now() < deadline {
+        let from = rand::thread_rng().gen_range(0..(MAX_ID - REGION_SIZE) / 26) * 26;
+        let region = from..from + REGION_SIZE;
+        let requested = Instant::now();
+        let msg = json!({"from": region.start, "to": region.end}).to_string();
+        if sender.send(Message::Text(msg)).await.is_err() {
+            latencies.errors.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        // Read until it's time to scroll again, the first cell of the new region counts as the
+        // snapshot latency (empty regions don't send anything).
+        let mut first_seen = false;
+        let scroll_at = requested + options.scroll_interval;
+        while let Ok(Some(msg)) = tokio::time::timeout_at(scroll_at.into(), receiver.next()).await {
+            let Ok(Message::Text(text)) = msg else {
+                continue;
+            };
+            let mut ids = cells(&text).filter_map(|cell| cell["id"].as_i64());
+            if !first_seen && ids.any(|id| region.contains(&id)) {
+                first_seen = true;
+                latencies.snapshot.lock().unwrap().push(requested.elapsed());
+            }
+        }
+    }
+}
+
+/// Watches the cells writers edit and measures how long edits take to come back.
+async fn observer(options: Arc<Options>, latencies: Arc<Latencies>, deadline: Instant) {
+    let (mut socket, _) = match tokio_tungstenite::connect_async(options.ws_url()).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("observer failed to connect: {e}");
+            latencies.errors.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    let msg = json!({"from": WRITE_RANGE.start, "to": WRITE_RANGE.end}).to_string();
+    if socket.send(Message::Text(msg)).await.is_err() {
+        latencies.errors.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    while let Ok(Some(msg)) = tokio::time::timeout_at(deadline.into(), socket.next()).await {
+        let Ok(Message::Text(text)) = msg else {
+            continue;
+        };
+        for cell in cells(&text) {
+            let Some(raw_value) = cell["raw_value"].as_str() else {
+                continue;
+            };
+            if let Some(posted) = latencies.pending.lock().unwrap().remove(raw_value) {
+                latencies.broadcast.lock().unwrap().push(posted.elapsed());
+            }
+        }
+    }
+}
+
+/// The cells of a websocket message, snapshots send arrays of cells and changes single cells.
+fn cells(text: &str) -> impl Iterator<Item = Value> {
+    let cells = match serde_json::from_str::<Value>(text) {
+        Ok(Value::Array(cells)) => cells,
+        Ok(cell) => vec![cell],
+        Err(_) => vec![],
+    };
+    cells.into_iter()
+}
+
+/// Fills `region` with sample content with `POST /api/admin/seed`.
+async fn seed(options: &Options, region: &str) -> Result<(), String> {
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/admin/seed/{region}", options.url))
+        .bearer_auth(&options.admin_token)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body: Value = response.json().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!("HTTP {status}: {}", body["error"]));
+    }
+    println!(
+        "Seeded {} cells in {region} (seed {})",
+        body["seeded"], body["seed"]
+    );
+    Ok(())
+}
+
+/// Posts `rate` edits per second to random cells in `WRITE_RANGE`.
+async fn writer(idx: usize, options: Arc<Options>, latencies: Arc<Latencies>, deadline: Instant) {
+    let client = reqwest::Client::new();
+    let url = format!("{}/api/spreadsheet", options.url);
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / options.rate));
+    let mut seq = 0u64;
+
+    while Instant::now() < deadline {
+        interval.tick().await;
+        seq += 1;
+        let id = rand::thread_rng().gen_range(WRITE_RANGE);
+        let raw_value = format!("loadgen {idx} {seq}");
+        let posted = Instant::now();
+        latencies
+            .pending
+            .lock()
+            .unwrap()
+            .insert(raw_value.clone(), posted);
+
+        let response = client
+            .post(&url)
+            .json(&json!({"id": id, "raw_value": raw_value, "background": 0}))
+            .send()
+            .await;
+        match response {
+            Ok(resp) if resp.status().is_success() => {
+                latencies.post.lock().unwrap().push(posted.elapsed());
+            }
+            Ok(resp) => {
+                eprintln!("POST failed: HTTP {}", resp.status());
+                latencies.pending.lock().unwrap().remove(&raw_value);
+                latencies.errors.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                eprintln!("POST failed: {e}");
+                latencies.pending.lock().unwrap().remove(&raw_value);
+                latencies.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let options = Arc::new(Options::parse());
+    let latencies = Arc::new(Latencies::default());
+    if let Some(region) = &options.seed_region {
+        if let Err(e) = seed(&options, region).await {
+            eprintln!("seeding {region} failed: {e}");
+            std::process::exit(1);
+        }
+    }
+    let deadline = Instant::now() + options.duration;
+    println!(
+        "Running {} viewers and {} writers ({} edits/s each) against {} for {:?}",
+        options.viewers, options.writers, options.rate, options.url, options.duration
+    );
+
+    let mut tasks = Vec::new();
+    if options.writers > 0 {
+        tasks.push(tokio::spawn(observer(
+            options.clone(),
+            latencies.clone(),
+            deadline,
+        )));
+    }
+    for _ in 0..options.viewers {
+        tasks.push(tokio::spawn(viewer(
+            options.clone(),
+            latencies.clone(),
+            deadline,
+        )));
+    }
+    for idx in 0..options.writers {
+        tasks.push(tokio::spawn(writer(
+            idx,
+            options.clone(),
+            latencies.clone(),
+            deadline,
+        )));
+    }
+    for task in tasks {
+        let _ = task.await;
+    }
+
+    print_percentiles("region snapshot", &latencies.snapshot);
+    print_percentiles("cell update POST", &latencies.post);
+    print_percentiles("edit to broadcast", &latencies.broadcast);
+    println!(
+        "{:<20} {} edits never came back, {} errors",
+        "",
+        latencies.pending.lock().unwrap().len(),
+        latencies.errors.load(Ordering::Relaxed)
+    );
+}
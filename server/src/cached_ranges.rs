@@ -0,0 +1,147 @@
+//! Admin endpoints to manage the ranges of cell ids [`SpreadSheetView`] keeps in memory, so
+//! operators can cache a region that suddenly gets a lot of traffic, e.g. a deep link to the
+//! middle of the sheet, without redeploying. Ranges are addressed as `from..to`, changes are lost
+//! when the server restarts.
+//!
+//! [`SpreadSheetView`]: crate::spreadsheet::SpreadSheetView
+
+use std::ops::Range;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use log::info;
+use serde::Serialize;
+use xls_protocol::ErrorResponse;
+
+use crate::error::XlsError;
+use crate::scratch::parse_region;
+use crate::AppState;
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct CachedRange {
+    from: i64,
+    to: i64,
+    /// Cells in the range that have a value.
+    cells: usize,
+}
+
+pub(crate) fn parse(range: &str) -> Result<Range<i64>, XlsError> {
+    parse_region(range).ok_or_else(|| {
+        XlsError::InvalidField("range", format!("Invalid range of cell ids {range:?}"))
+    })
+}
+
+async fn cached_range(state: &AppState, range: Range<i64>) -> Json<CachedRange> {
+    let cells = state
+        .spreadsheet_view
+        .cached_ranges()
+        .await
+        .into_iter()
+        .find(|(cached, _)| *cached == range)
+        .map_or(0, |(_, cells)| cells);
+    Json(CachedRange {
+        from: range.start,
+        to: range.end,
+        cells,
+    })
+}
+
+/// Lists the cached ranges.
+#[utoipa::path(
+    get,
+    path = "/api/admin/cache",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = [CachedRange]),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn list(State(state): State<AppState>) -> Json<Vec<CachedRange>> {
+    Json(
+        state
+            .spreadsheet_view
+            .cached_ranges()
+            .await
+            .into_iter()
+            .map(|(range, cells)| CachedRange {
+                from: range.start,
+                to: range.end,
+                cells,
+            })
+            .collect(),
+    )
+}
+
+/// Loads the cells of a range into the cache, it must not overlap a cached range. All cached
+/// ranges together span at most `CACHE_MAX_IDS` cell ids.
+#[utoipa::path(
+    put,
+    path = "/api/admin/cache/{range}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("range" = String, Path, description = "Range of cell ids, e.g. `500000..600000`")),
+    responses(
+        (status = 200, body = CachedRange),
+        (status = 400, body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn add(
+    State(state): State<AppState>,
+    Path(range): Path<String>,
+) -> Result<Json<CachedRange>, XlsError> {
+    let range = parse(&range)?;
+    state.spreadsheet_view.cache_range(range.clone()).await?;
+    info!("Caching cells {}..{}", range.start, range.end);
+    Ok(cached_range(&state, range).await)
+}
+
+/// Removes a range and its cells from the cache, its regions are queried from Feldera again.
+#[utoipa::path(
+    delete,
+    path = "/api/admin/cache/{range}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("range" = String, Path, description = "Range of cell ids, e.g. `500000..600000`")),
+    responses(
+        (status = 204, description = "The range is no longer cached"),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn remove(
+    State(state): State<AppState>,
+    Path(range): Path<String>,
+) -> Result<StatusCode, XlsError> {
+    let range = parse(&range)?;
+    state.spreadsheet_view.uncache_range(range.clone()).await?;
+    info!("Stopped caching cells {}..{}", range.start, range.end);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Reloads the cells of a cached range from Feldera, e.g. if they look out of date.
+#[utoipa::path(
+    post,
+    path = "/api/admin/cache/{range}/refresh",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("range" = String, Path, description = "Range of cell ids, e.g. `0..100000`")),
+    responses(
+        (status = 200, body = CachedRange),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn refresh(
+    State(state): State<AppState>,
+    Path(range): Path<String>,
+) -> Result<Json<CachedRange>, XlsError> {
+    let range = parse(&range)?;
+    state.spreadsheet_view.refresh_range(range.clone()).await?;
+    info!("Refreshed cached cells {}..{}", range.start, range.end);
+    Ok(cached_range(&state, range).await)
+}
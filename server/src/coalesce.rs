@@ -0,0 +1,99 @@
+//! Coalesces rapid successive changes of the same cell before they are fanned out.
+//!
+//! Dragging the color slider updates a cell many times per second. Changes are held back for
+//! `CHANGE_COALESCE_WINDOW_MS` and only the latest value per cell is broadcast to subscribers.
+//! Changes skipped because the coalescing fell behind are reported as
+//! [`XlsError::ChangesMissed`], like the ones the change stream skipped.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use log::warn;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender;
+use tokio::time::Instant;
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::metrics::METRICS;
+
+static CHANGE_COALESCE_WINDOW: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_millis(env_or("CHANGE_COALESCE_WINDOW_MS", 50)));
+
+#[derive(Deserialize)]
+struct CellId {
+    id: i64,
+}
+
+/// Republishes the cell changes of `upstream`, keeping only the latest change per cell within
+/// the coalescing window. Returns `upstream` if coalescing is disabled.
+pub(crate) fn coalesce_cells(
+    upstream: Sender<Result<String, XlsError>>,
+    capacity: usize,
+) -> Sender<Result<String, XlsError>> {
+    if CHANGE_COALESCE_WINDOW.is_zero() {
+        return upstream;
+    }
+    let (tx, _) = tokio::sync::broadcast::channel(capacity);
+    let downstream = tx.clone();
+    let mut changes = upstream.subscribe();
+
+    tokio::spawn(async move {
+        // Keeps the upstream channel open.
+        let _upstream = upstream;
+        let mut pending: BTreeMap<i64, String> = BTreeMap::new();
+        let mut deadline = Instant::now();
+        loop {
+            let change = if pending.is_empty() {
+                changes.recv().await
+            } else {
+                tokio::select! {
+                    change = changes.recv() => change,
+                    _ = tokio::time::sleep_until(deadline) => {
+                        for (_, change) in std::mem::take(&mut pending) {
+                            let _ = tx.send(Ok(change));
+                        }
+                        continue;
+                    }
+                }
+            };
+            match change {
+                Ok(Ok(change)) => match serde_json::from_str::<CellId>(&change) {
+                    Ok(cell) => {
+                        if pending.is_empty() {
+                            deadline = Instant::now() + *CHANGE_COALESCE_WINDOW;
+                        }
+                        if pending.insert(cell.id, change).is_some() {
+                            METRICS
+                                .cell_changes_coalesced_total
+                                .fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    Err(_) => {
+                        let _ = tx.send(Ok(change));
+                    }
+                },
+                Ok(Err(e)) => {
+                    for (_, change) in std::mem::take(&mut pending) {
+                        let _ = tx.send(Ok(change));
+                    }
+                    let _ = tx.send(Err(e));
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    let missed = format!("Coalescing fell behind, skipped {skipped} changes");
+                    warn!("{missed}");
+                    for (_, change) in std::mem::take(&mut pending) {
+                        let _ = tx.send(Ok(change));
+                    }
+                    let _ = tx.send(Err(XlsError::ChangesMissed(missed)));
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    downstream
+}
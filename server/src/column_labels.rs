@@ -0,0 +1,118 @@
+//! Column header labels shared by all users, e.g. `Price` instead of `A`.
+//!
+//! Labels are stored in the `column_labels` table, the latest label of a column wins and an
+//! empty label resets the column to its letter.
+
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use chrono::Utc;
+use rustrict::Censor;
+use serde::{Deserialize, Serialize};
+use xls_protocol::{ColumnLabel, ErrorResponse};
+
+use crate::error::{JsonBody, XlsError};
+use crate::feldera::{adhoc_query, insert, parse_rows};
+use crate::meta;
+use crate::privacy;
+use crate::spreadsheet::{client_ip, format_timestamp, CLIENT_IP_HEADER};
+use crate::AppState;
+
+/// Number of columns of the sheet.
+const COLUMNS: u32 = 26;
+
+/// Longest label, longer ones are truncated.
+const MAX_LABEL_CHARS: usize = 32;
+
+/// A row of the `latest_column_labels` view.
+#[derive(Deserialize, Debug)]
+struct ColumnLabelRow {
+    col: u32,
+    label: String,
+}
+
+/// A row of the `column_labels` table.
+#[derive(Serialize, Debug)]
+struct ColumnLabelPayload {
+    col: u32,
+    label: String,
+    ip: String,
+    ts: String,
+}
+
+/// Lists the shared column labels.
+#[utoipa::path(
+    get,
+    path = "/api/column-labels",
+    responses(
+        (status = 200, description = "The shared labels of the columns that have one", body = [ColumnLabel]),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn list(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ColumnLabel>>, XlsError> {
+    let rows = adhoc_query(
+        state.http_client,
+        "SELECT col, label FROM latest_column_labels ORDER BY col",
+    )
+    .await?;
+    let labels = parse_rows::<ColumnLabelRow>(&rows)?
+        .into_iter()
+        .map(|row| ColumnLabel {
+            column: row.col,
+            label: row.label,
+        })
+        .collect();
+    Ok(Json(labels))
+}
+
+/// Shares the label of a column with everyone, an empty label removes it.
+#[utoipa::path(
+    post,
+    path = "/api/column-labels",
+    request_body = ColumnLabel,
+    responses(
+        (status = 200, description = "The label was sent to Feldera", body = Object),
+        (status = 400, description = "Invalid column", body = ErrorResponse),
+        (status = 403, description = "The client IP is not allowed", body = ErrorResponse),
+        (status = 429, description = "API limit exceeded", body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn share(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    JsonBody(column_label): JsonBody<ColumnLabel>,
+) -> Result<Json<serde_json::Value>, XlsError> {
+    let client_ip = privacy::anonymize(client_ip(
+        headers.get(CLIENT_IP_HEADER).map(|ip| ip.as_bytes()),
+        addr,
+    ));
+    meta::check_writable()?;
+    if state.api_limits.contains(&client_ip) {
+        return Err(XlsError::RateLimited(None));
+    }
+    if column_label.column >= COLUMNS {
+        return Err(XlsError::InvalidField(
+            "column",
+            String::from("Invalid column"),
+        ));
+    }
+    let label = column_label
+        .label
+        .trim()
+        .chars()
+        .take(MAX_LABEL_CHARS)
+        .collect::<String>();
+    let row = ColumnLabelPayload {
+        col: column_label.column,
+        label: Censor::new(label.chars()).censor(),
+        ip: client_ip,
+        ts: format_timestamp(Utc::now()),
+    };
+    insert(state.http_client, "column_labels", row).await
+}
@@ -0,0 +1,50 @@
+//! Statistics of whole columns, maintained by the `column_statistics` view.
+
+use axum::extract::State;
+use axum::Json;
+use serde::Deserialize;
+use xls_protocol::{ColumnStatistics, ErrorResponse};
+
+use crate::error::XlsError;
+use crate::feldera::{adhoc_query, parse_rows};
+use crate::AppState;
+
+/// A row of the `column_statistics` view.
+#[derive(Deserialize, Debug)]
+struct ColumnStatisticsRow {
+    col: u32,
+    filled: u64,
+    numbers: u64,
+    total: f64,
+    average: Option<f64>,
+}
+
+/// Lists the statistics of the columns that have content.
+#[utoipa::path(
+    get,
+    path = "/api/column-statistics",
+    responses(
+        (status = 200, description = "The statistics of the columns that have content", body = [ColumnStatistics]),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn list(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ColumnStatistics>>, XlsError> {
+    let rows = adhoc_query(
+        state.http_client,
+        "SELECT col, filled, numbers, total, average FROM column_statistics ORDER BY col",
+    )
+    .await?;
+    let statistics = parse_rows::<ColumnStatisticsRow>(&rows)?
+        .into_iter()
+        .map(|row| ColumnStatistics {
+            column: row.col,
+            filled: row.filled,
+            numbers: row.numbers,
+            sum: row.total,
+            average: row.average,
+        })
+        .collect();
+    Ok(Json(statistics))
+}
@@ -0,0 +1,173 @@
+//! The values of a column over many rows for charts, down-sampled by the server so clients don't
+//! load every cell of the range.
+//!
+//! With [`Sampling::Every`] Feldera only returns the cells of every `step`th row. With
+//! [`Sampling::MinMax`] it returns the numbers of the range and the server keeps the smallest and
+//! the largest of every bucket of `step` rows.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::Deserialize;
+use xls_protocol::address::COLUMNS;
+use xls_protocol::{ColumnValue, ColumnValues, ErrorResponse, Sampling, CELL_IDS};
+
+use crate::error::XlsError;
+use crate::feldera::{adhoc_query, parse_rows};
+use crate::privacy;
+use crate::spreadsheet::{client_ip, CLIENT_IP_HEADER};
+use crate::AppState;
+
+/// Most rows sampled at once.
+const MAX_ROWS: i64 = 1_000_000;
+
+/// Most rows sampled at once with [`Sampling::MinMax`], which loads every number of the range.
+const MAX_MIN_MAX_ROWS: i64 = 100_000;
+
+/// Values returned if the request doesn't say.
+const DEFAULT_POINTS: u64 = 500;
+
+/// Most values returned at once.
+const MAX_POINTS: u64 = 5000;
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ColumnValuesParams {
+    /// Index of the column, `0` is `A`.
+    column: u32,
+    from_row: i64,
+    /// The first row after the range.
+    to_row: i64,
+    /// Most values to return.
+    points: Option<u64>,
+    #[serde(default)]
+    sampling: Sampling,
+}
+
+/// Lists the computed values of a column over a range of at most 1000000 rows (100000 with
+/// `min_max`), sampled down to at most `points` values. Requests count like region changes on the
+/// websocket.
+#[utoipa::path(
+    get,
+    path = "/api/column-values",
+    params(
+        ("column" = u32, Query, description = "Index of the column, `0` is `A`"),
+        ("from_row" = i64, Query, description = "First row of the range"),
+        ("to_row" = i64, Query, description = "First row after the range"),
+        ("points" = Option<u64>, Query, description = "Most values to return, at most 5000 (default 500)"),
+        ("sampling" = Option<Sampling>, Query, description = "How the cells are picked (default `every`)"),
+    ),
+    responses(
+        (status = 200, description = "The sampled values of the column", body = ColumnValues),
+        (status = 400, description = "Invalid column or range", body = ErrorResponse),
+        (status = 429, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn list(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    Query(params): Query<ColumnValuesParams>,
+) -> Result<Json<ColumnValues>, XlsError> {
+    if params.column as i64 >= COLUMNS {
+        return Err(XlsError::InvalidField(
+            "column",
+            String::from("Invalid column"),
+        ));
+    }
+    let rows = CELL_IDS.end / COLUMNS;
+    if params.from_row < 0 || params.from_row >= params.to_row || params.to_row > rows {
+        return Err(XlsError::Validation(String::from("Invalid range of rows")));
+    }
+    let max_rows = match params.sampling {
+        Sampling::Every => MAX_ROWS,
+        Sampling::MinMax => MAX_MIN_MAX_ROWS,
+    };
+    if params.to_row - params.from_row > max_rows {
+        return Err(XlsError::InvalidField(
+            "to_row",
+            format!("At most {max_rows} rows can be sampled at once"),
+        ));
+    }
+    let points = params.points.unwrap_or(DEFAULT_POINTS);
+    if points == 0 || points > MAX_POINTS {
+        return Err(XlsError::InvalidField(
+            "points",
+            format!("points must be between 1 and {MAX_POINTS}"),
+        ));
+    }
+    let client_ip = privacy::anonymize(client_ip(
+        headers.get(CLIENT_IP_HEADER).map(|ip| ip.as_bytes()),
+        addr,
+    ));
+    if !state.region_limiter.check(&client_ip) {
+        let retry_after = state.region_limiter.retry_after(&client_ip);
+        return Err(XlsError::RateLimited(Some(retry_after.as_secs())));
+    }
+    // Min-max sampling returns up to two values per bucket.
+    let buckets = match params.sampling {
+        Sampling::Every => points,
+        Sampling::MinMax => points.div_ceil(2),
+    };
+    let step = ((params.to_row - params.from_row) as u64).div_ceil(buckets);
+    let mut sql = format!(
+        "SELECT id, computed_value FROM spreadsheet_view WHERE id >= {} and id < {} and id % {COLUMNS} = {} and raw_value != ''",
+        params.from_row * COLUMNS,
+        params.to_row * COLUMNS,
+        params.column,
+    );
+    match params.sampling {
+        Sampling::Every if step > 1 => sql.push_str(&format!(
+            " and (id / {COLUMNS} - {}) % {step} = 0",
+            params.from_row
+        )),
+        Sampling::Every => {}
+        Sampling::MinMax => sql.push_str(" and value_type = 'number'"),
+    }
+    let cells = state
+        .spreadsheet_view
+        .run_adhoc(adhoc_query(state.http_client.clone(), &sql))
+        .await?;
+    let cells = parse_rows::<ColumnValue>(&cells)?;
+    let mut values = match params.sampling {
+        Sampling::Every => cells,
+        Sampling::MinMax => min_max(cells, params.from_row, step),
+    };
+    values.sort_by_key(|value| value.id);
+    Ok(Json(ColumnValues {
+        column: params.column,
+        sampling: params.sampling,
+        step,
+        values,
+    }))
+}
+
+/// The smallest and the largest number of every bucket of `step` rows starting at `from_row`,
+/// once if they are the same cell.
+fn min_max(cells: Vec<ColumnValue>, from_row: i64, step: u64) -> Vec<ColumnValue> {
+    let mut buckets: BTreeMap<i64, [(f64, ColumnValue); 2]> = BTreeMap::new();
+    for cell in cells {
+        let Ok(number) = cell.computed_value.parse::<f64>() else {
+            continue;
+        };
+        let bucket = (cell.id / COLUMNS - from_row) / step as i64;
+        match buckets.get_mut(&bucket) {
+            Some([min, _]) if number < min.0 => *min = (number, cell),
+            Some([_, max]) if number > max.0 => *max = (number, cell),
+            Some(_) => {}
+            None => {
+                buckets.insert(bucket, [(number, cell.clone()), (number, cell)]);
+            }
+        }
+    }
+    buckets
+        .into_values()
+        .flat_map(|[(_, min), (_, max)]| match min.id == max.id {
+            true => vec![min],
+            false => vec![min, max],
+        })
+        .collect()
+}
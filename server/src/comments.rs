@@ -0,0 +1,120 @@
+//! Short notes users attach to cells, shown as a marker in the corner of the cell.
+//!
+//! Notes are stored in the `cell_comments` table, the latest note of a cell wins and an empty
+//! note removes it. The server follows the `latest_cell_comments` view, so listing the notes of
+//! a range doesn't query Feldera.
+
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Path, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use chrono::Utc;
+use rustrict::Censor;
+use serde::Serialize;
+use xls_protocol::{CellComment, ErrorResponse, CELL_IDS};
+
+use crate::cached_ranges;
+use crate::error::{JsonBody, XlsError};
+use crate::feldera::insert;
+use crate::meta;
+use crate::privacy;
+use crate::spreadsheet::{client_ip, format_timestamp, CLIENT_IP_HEADER};
+use crate::AppState;
+
+/// Largest range listed at once, 400 rows like the largest region of a websocket.
+const MAX_LISTED_CELLS: i64 = 400 * 26;
+
+/// Longest comment, longer ones are truncated.
+const MAX_COMMENT_CHARS: usize = 280;
+
+/// A row of the `cell_comments` table.
+#[derive(Serialize, Debug)]
+struct CellCommentPayload {
+    id: i64,
+    comment: String,
+    ip: String,
+    ts: String,
+}
+
+/// Lists the comments of the cells in a range of at most 10400 cells.
+#[utoipa::path(
+    get,
+    path = "/api/comments/{range}",
+    params(("range" = String, Path, description = "Range of cell ids, e.g. `0..2600`")),
+    responses(
+        (status = 200, description = "The comments of the cells that have one, ordered by cell id", body = [CellComment]),
+        (status = 400, description = "Invalid or too large range", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn list(
+    State(state): State<AppState>,
+    Path(range): Path<String>,
+) -> Result<Json<Vec<CellComment>>, XlsError> {
+    let range = cached_ranges::parse(&range)?;
+    if range.end - range.start > MAX_LISTED_CELLS {
+        return Err(XlsError::InvalidField(
+            "range",
+            format!("At most {MAX_LISTED_CELLS} cells can be listed at once"),
+        ));
+    }
+    let mut comments: Vec<CellComment> = state
+        .cell_comments
+        .iter()
+        .filter(|entry| range.contains(entry.key()))
+        .map(|entry| CellComment {
+            id: *entry.key(),
+            comment: entry.value().clone(),
+        })
+        .collect();
+    comments.sort_by_key(|comment| comment.id);
+    Ok(Json(comments))
+}
+
+/// Attaches a comment to a cell, an empty comment removes it.
+#[utoipa::path(
+    post,
+    path = "/api/comments",
+    request_body = CellComment,
+    responses(
+        (status = 200, description = "The comment was sent to Feldera", body = Object),
+        (status = 400, description = "Invalid cell id", body = ErrorResponse),
+        (status = 403, description = "The client IP is not allowed or the deployment is read-only", body = ErrorResponse),
+        (status = 429, description = "API limit exceeded", body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn comment(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    JsonBody(comment): JsonBody<CellComment>,
+) -> Result<Json<serde_json::Value>, XlsError> {
+    let client_ip = privacy::anonymize(client_ip(
+        headers.get(CLIENT_IP_HEADER).map(|ip| ip.as_bytes()),
+        addr,
+    ));
+    meta::check_writable()?;
+    if state.api_limits.contains(&client_ip) {
+        return Err(XlsError::RateLimited(None));
+    }
+    if !CELL_IDS.contains(&comment.id) {
+        return Err(XlsError::InvalidField(
+            "id",
+            String::from("Invalid cell id"),
+        ));
+    }
+    let text = comment
+        .comment
+        .trim()
+        .chars()
+        .take(MAX_COMMENT_CHARS)
+        .collect::<String>();
+    let row = CellCommentPayload {
+        id: comment.id,
+        comment: Censor::new(text.chars()).censor(),
+        ip: client_ip,
+        ts: format_timestamp(Utc::now()),
+    };
+    insert(state.http_client, "cell_comments", row).await
+}
@@ -0,0 +1,19 @@
+//! Helpers to read the server configuration from environment variables.
+
+use std::env::var;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use log::warn;
+
+/// Reads and parses the environment variable `name`, falling back to `default` if it is unset
+/// or can't be parsed.
+pub(crate) fn env_or<T: FromStr + Display>(name: &str, default: T) -> T {
+    match var(name) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            warn!("Invalid value {value:?} for {name}, using default {default}");
+            default
+        }),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,301 @@
+//! Output connectors configured through the admin API, e.g. to stream `spreadsheet_view`
+//! changes to a Kafka topic or S3.
+//!
+//! Feldera connectors are declared in the SQL program, so every connector is appended to the
+//! programs of the read and the write pipelines as a view over the source view:
+//!
+//! ```sql
+//! -- connector: <name>
+//! create view connector_<name> with ('connectors' = '[<config>]') as select * from <view>;
+//! ```
+//!
+//! Adding or removing a connector updates the programs and restarts the pipelines, which makes
+//! them unavailable until the new programs compiled. Without storage a pipeline loses its state
+//! when it restarts, i.e. the whole sheet, so connectors can only be changed if every pipeline
+//! has storage and the request confirms the restart with `?restart=true`.
+
+use std::sync::LazyLock;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use log::info;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use xls_protocol::ErrorResponse;
+
+use crate::error::{JsonBody, XlsError};
+use crate::feldera::{pipelines, READ_PIPELINE};
+use crate::pipeline;
+use crate::AppState;
+
+/// Views connectors can be attached to.
+const VIEWS: [&str; 3] = [
+    "spreadsheet_view",
+    "spreadsheet_statistics",
+    "api_limit_reached",
+];
+
+static CONNECTOR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?m)^-- connector: (\w+)\ncreate view connector_\w+ with \('connectors' = '\[(.*)\]'\) as select \* from (\w+);\n?",
+    )
+    .unwrap()
+});
+
+/// Serializes program updates, the program is read, modified and written back.
+static PROGRAM_UPDATE: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// An output connector.
+#[derive(Serialize, Debug, Clone, PartialEq, utoipa::ToSchema)]
+pub(crate) struct Connector {
+    /// Letters, digits and underscores.
+    name: String,
+    #[serde(flatten)]
+    definition: ConnectorDefinition,
+}
+
+/// Body of `PUT /api/admin/connectors/{name}`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, utoipa::ToSchema)]
+pub(crate) struct ConnectorDefinition {
+    /// One of `spreadsheet_view`, `spreadsheet_statistics` or `api_limit_reached`.
+    view: String,
+    /// A Feldera output connector, e.g.
+    /// `{"transport": {"name": "kafka_output", "config": {...}}, "format": {"name": "json"}}`.
+    #[schema(value_type = Object)]
+    config: Value,
+}
+
+/// Query of the requests that change connectors.
+#[derive(Deserialize, Debug)]
+pub(crate) struct ChangeParams {
+    /// Confirms that the pipelines restart.
+    #[serde(default)]
+    restart: bool,
+}
+
+impl Connector {
+    fn validate(&self) -> Result<(), XlsError> {
+        let valid_name = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(XlsError::Validation(String::from(
+                "Connector names may only contain letters, digits and underscores",
+            )));
+        }
+        if !VIEWS.contains(&self.definition.view.as_str()) {
+            return Err(XlsError::Validation(format!(
+                "Connectors can only be attached to {}",
+                VIEWS.join(", ")
+            )));
+        }
+        if !self.definition.config["transport"].is_object() {
+            return Err(XlsError::Validation(String::from(
+                "The connector config needs a transport",
+            )));
+        }
+        Ok(())
+    }
+
+    fn to_sql(&self) -> String {
+        let config = self.definition.config.to_string().replace('\'', "''");
+        format!(
+            "-- connector: {name}\ncreate view connector_{name} with ('connectors' = '[{config}]') as select * from {view};\n",
+            name = self.name,
+            view = self.definition.view,
+        )
+    }
+}
+
+/// The connectors in a program.
+fn parse(program: &str) -> Vec<Connector> {
+    CONNECTOR
+        .captures_iter(program)
+        .filter_map(|caps| {
+            let config = serde_json::from_str(&caps[2].replace("''", "'")).ok()?;
+            Some(Connector {
+                name: caps[1].to_string(),
+                definition: ConnectorDefinition {
+                    view: caps[3].to_string(),
+                    config,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Removes the connector `name` from a program.
+fn remove(program: &str, name: &str) -> String {
+    CONNECTOR
+        .replace_all(program, |caps: &regex::Captures<'_>| {
+            if &caps[1] == name {
+                String::new()
+            } else {
+                caps[0].to_string()
+            }
+        })
+        .to_string()
+}
+
+/// Adds or replaces a connector in a program.
+fn upsert(program: &str, connector: &Connector) -> String {
+    let mut program = remove(program, &connector.name);
+    if !program.ends_with('\n') {
+        program.push('\n');
+    }
+    program.push_str(&connector.to_sql());
+    program
+}
+
+async fn current_pipeline(state: &AppState, name: &str) -> Result<pipeline::Pipeline, XlsError> {
+    pipeline::status(&state.http_client, name)
+        .await?
+        .ok_or_else(|| XlsError::FelderaUnavailable(format!("Pipeline {name} does not exist")))
+}
+
+async fn current_program(state: &AppState) -> Result<String, XlsError> {
+    Ok(current_pipeline(state, &READ_PIPELINE).await?.program_code)
+}
+
+/// Fails unless the request confirms the restart and every pipeline keeps its state over it.
+async fn check_restart(state: &AppState, params: &ChangeParams) -> Result<(), XlsError> {
+    if !params.restart {
+        return Err(XlsError::InvalidField(
+            "restart",
+            String::from(
+                "Changing connectors restarts the pipelines, confirm it with ?restart=true",
+            ),
+        ));
+    }
+    for name in pipelines() {
+        if !current_pipeline(state, name).await?.has_storage() {
+            return Err(XlsError::Validation(format!(
+                "Pipeline {name} has no storage, restarting it would lose the sheet"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Updates the programs of the pipelines in the background, one after the other, errors show up
+/// in `/api/pipeline/status`.
+fn spawn_program_update(state: AppState, update: impl Fn(&str) -> String + Send + Sync + 'static) {
+    tokio::spawn(async move {
+        let _guard = PROGRAM_UPDATE.lock().await;
+        let result = async {
+            for name in pipelines() {
+                let program = current_pipeline(&state, name).await?.program_code;
+                let updated = update(&program);
+                if updated == program {
+                    continue;
+                }
+                pipeline::replace_program(
+                    &state.http_client,
+                    &state.pipeline_supervisor,
+                    name,
+                    &updated,
+                )
+                .await?;
+            }
+            Ok::<(), XlsError>(())
+        }
+        .await;
+        if let Err(e) = result {
+            state
+                .pipeline_supervisor
+                .record_error(format!("Failed to update connectors: {e}"));
+        }
+    });
+}
+
+/// Lists the output connectors of the read pipeline.
+#[utoipa::path(
+    get,
+    path = "/api/admin/connectors",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = [Connector]),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn list(State(state): State<AppState>) -> Result<Json<Vec<Connector>>, XlsError> {
+    Ok(Json(parse(&current_program(&state).await?)))
+}
+
+/// Adds or replaces an output connector.
+///
+/// The pipelines are restarted with the new connector in the background.
+#[utoipa::path(
+    put,
+    path = "/api/admin/connectors/{name}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(
+        ("name" = String, Path, description = "Name of the connector"),
+        ("restart" = bool, Query, description = "Must be `true`, the pipelines restart"),
+    ),
+    request_body = ConnectorDefinition,
+    responses(
+        (status = 202, description = "The pipelines are being updated", body = Connector),
+        (status = 400, description = "Invalid connector, unconfirmed restart or a pipeline without storage", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn put(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<ChangeParams>,
+    JsonBody(definition): JsonBody<ConnectorDefinition>,
+) -> Result<(StatusCode, Json<Connector>), XlsError> {
+    let connector = Connector { name, definition };
+    connector.validate()?;
+    check_restart(&state, &params).await?;
+    info!(
+        "Adding connector {} to {}",
+        connector.name, connector.definition.view
+    );
+    let added = connector.clone();
+    spawn_program_update(state, move |program| upsert(program, &added));
+    Ok((StatusCode::ACCEPTED, Json(connector)))
+}
+
+/// Removes an output connector.
+///
+/// The pipelines are restarted without the connector in the background.
+#[utoipa::path(
+    delete,
+    path = "/api/admin/connectors/{name}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(
+        ("name" = String, Path, description = "Name of the connector"),
+        ("restart" = bool, Query, description = "Must be `true`, the pipelines restart"),
+    ),
+    responses(
+        (status = 202, description = "The pipelines are being updated"),
+        (status = 400, description = "Unconfirmed restart or a pipeline without storage", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn delete(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<ChangeParams>,
+) -> Result<StatusCode, XlsError> {
+    let program = current_program(&state).await?;
+    if !parse(&program).iter().any(|c| c.name == name) {
+        return Err(XlsError::NotFound(format!(
+            "Connector {name} does not exist"
+        )));
+    }
+    check_restart(&state, &params).await?;
+    info!("Removing connector {name}");
+    spawn_program_update(state, move |program| remove(program, &name));
+    Ok(StatusCode::ACCEPTED)
+}
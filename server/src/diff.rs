@@ -0,0 +1,163 @@
+//! Lets clients that poll instead of keeping a websocket open, e.g. integrations or widgets,
+//! fetch only the cells of a range that changed since their last request.
+
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use xls_protocol::{Cell, CellStyle, ErrorResponse, Region, ValueType, FIRST_SHEET};
+
+use crate::cached_ranges;
+use crate::error::XlsError;
+use crate::feldera::{adhoc_query, parse_rows};
+use crate::latency::parse_timestamp;
+use crate::privacy;
+use crate::spreadsheet::{client_ip, subscription_region, CLIENT_IP_HEADER};
+use crate::AppState;
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct DiffParams {
+    range: String,
+    since: Option<String>,
+}
+
+/// A row of `spreadsheet_view` with the time the cell was written.
+#[derive(Deserialize)]
+struct ChangedCell {
+    #[serde(flatten)]
+    cell: Cell,
+    ts: Option<String>,
+}
+
+/// A cell whose latest row expired, `spreadsheet_view` doesn't have it anymore.
+#[derive(Deserialize)]
+struct ExpiredCell {
+    id: i64,
+    expires_at: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct DiffResponse {
+    /// The cells that changed, cleared cells have an empty `raw_value`.
+    cells: Vec<Cell>,
+    /// When the latest of the cells changed, pass it as `since` to get the next changes.
+    until: Option<String>,
+}
+
+/// The cells of a range that changed after `since`, all cells of the range without it. The
+/// range is at most `WS_MAX_REGION_CELLS` long, requests count like region changes on the
+/// websocket.
+///
+/// Cells that expired after `since` are returned cleared, until the cleanup deletes their rows
+/// (see `CELL_CLEANUP_INTERVAL_SECS`), clients polling less often miss them.
+#[utoipa::path(
+    get,
+    path = "/api/diff",
+    params(
+        ("range" = String, Query, description = "Range of cell ids, e.g. `0..520`"),
+        ("since" = Option<String>, Query, description = "The `until` of the previous response, e.g. `2024-01-01 12:00:00.000`"),
+    ),
+    responses(
+        (status = 200, body = DiffResponse),
+        (status = 400, description = "Invalid or too large range, or invalid timestamp", body = ErrorResponse),
+        (status = 429, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn diff(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    Query(params): Query<DiffParams>,
+) -> Result<Json<DiffResponse>, XlsError> {
+    let range = cached_ranges::parse(&params.range)?;
+    let region = Region::new(range.start, range.end);
+    let truncated = subscription_region(region)?;
+    if truncated != region {
+        return Err(XlsError::InvalidField(
+            "range",
+            format!(
+                "At most {} cells can be diffed at once",
+                truncated.to - truncated.from
+            ),
+        ));
+    }
+    let client_ip = privacy::anonymize(client_ip(
+        headers.get(CLIENT_IP_HEADER).map(|ip| ip.as_bytes()),
+        addr,
+    ));
+    if !state.region_limiter.check(&client_ip) {
+        let retry_after = state.region_limiter.retry_after(&client_ip);
+        return Err(XlsError::RateLimited(Some(retry_after.as_secs())));
+    }
+    let mut sql = format!(
+        "SELECT * FROM spreadsheet_view WHERE id >= {} and id < {}",
+        region.from, region.to
+    );
+    // Formatted again, the parameter isn't put into the query as is.
+    let since = match &params.since {
+        Some(since) => Some(
+            parse_timestamp(since)
+                .ok_or_else(|| {
+                    XlsError::InvalidField("since", format!("Invalid timestamp {since:?}"))
+                })?
+                .format("%Y-%m-%d %H:%M:%S%.3f")
+                .to_string(),
+        ),
+        None => None,
+    };
+    if let Some(since) = &since {
+        sql.push_str(&format!(" and ts > TIMESTAMP '{since}'"));
+    }
+    let view = &state.spreadsheet_view;
+    let rows = view
+        .run_adhoc(adhoc_query(state.http_client.clone(), &sql))
+        .await?;
+    let rows: Vec<ChangedCell> = parse_rows(&rows)?;
+    let expired: Vec<ExpiredCell> = match &since {
+        Some(since) => {
+            let sql = expired_cells(region, since);
+            let expired = view
+                .run_adhoc(adhoc_query(state.http_client.clone(), &sql))
+                .await?;
+            parse_rows(&expired)?
+        }
+        None => vec![],
+    };
+    let until = rows
+        .iter()
+        .filter_map(|row| row.ts.clone())
+        .chain(expired.iter().map(|cell| cell.expires_at.clone()))
+        .max_by_key(|ts| parse_timestamp(ts))
+        .or(params.since);
+    let mut cells: Vec<Cell> = rows
+        .into_iter()
+        .map(|row| row.cell)
+        .filter(|cell| !expired.iter().any(|expired| expired.id == cell.id))
+        .collect();
+    cells.extend(expired.iter().map(|expired| Cell {
+        id: expired.id,
+        background: 0,
+        style: CellStyle::default(),
+        raw_value: String::new(),
+        computed_value: String::new(),
+        value_type: ValueType::default(),
+        cycle: None,
+        sheet: FIRST_SHEET,
+    }));
+    cells.sort_by_key(|cell| cell.id);
+    Ok(Json(DiffResponse { cells, until }))
+}
+
+/// The cells of `region` whose latest row expired after `since`.
+fn expired_cells(region: Region, since: &str) -> String {
+    format!(
+        "SELECT d.id, d.expires_at FROM spreadsheet_data d \
+        JOIN (SELECT id, MAX(ts) AS ts FROM spreadsheet_data WHERE id >= {} AND id < {} GROUP BY id) l \
+        ON d.id = l.id AND d.ts = l.ts \
+        WHERE d.expires_at > TIMESTAMP '{since}' AND d.expires_at <= NOW()",
+        region.from, region.to
+    )
+}
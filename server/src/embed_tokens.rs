@@ -0,0 +1,324 @@
+//! Read-only tokens for embedding regions of the sheet in other sites, e.g. a scoreboard widget.
+//!
+//! Unlike the `WS_TOKENS` of bots, an embed token can only read the regions it was issued for,
+//! from any origin: over the websocket (passed as `?token=`) and with `GET /api/embed/cells`.
+//! Each token has its own rate limit shared by all its readers. Tokens are issued and revoked
+//! with the admin endpoints under `/api/admin/embed-tokens`, they are kept in memory and stored in
+//! `EMBED_TOKENS_PATH` if it is set. Revoking a token closes the websockets reading with it.
+
+use std::sync::{Arc, LazyLock, RwLock};
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use log::info;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use xls_protocol::{Cell, ErrorResponse, Region, CELL_IDS};
+
+use crate::admin::constant_time_eq;
+use crate::config::env_or;
+use crate::error::{JsonBody, XlsError};
+use crate::feldera::parse_rows;
+use crate::persist;
+use crate::rate_limit::RateLimiter;
+use crate::spreadsheet::subscription_region;
+use crate::AppState;
+
+static EMBED_TOKENS_PATH: LazyLock<String> =
+    LazyLock::new(|| env_or("EMBED_TOKENS_PATH", String::new()));
+
+/// Most regions a single token can read or integration can write.
+const MAX_TOKEN_REGIONS: usize = 16;
+
+fn default_requests_per_sec() -> f64 {
+    1.0
+}
+
+fn default_burst() -> f64 {
+    10.0
+}
+
+/// A token that can read some regions of the sheet.
+#[derive(Serialize, Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub(crate) struct EmbedToken {
+    /// Identifies the token, e.g. the site embedding the regions.
+    name: String,
+    /// Passed as `?token=` by the readers, generated when the token is issued.
+    #[serde(default)]
+    token: String,
+    /// The cells the token can read.
+    regions: Vec<Region>,
+    /// Region changes and requests per second, shared by all readers of the token.
+    #[serde(default = "default_requests_per_sec")]
+    requests_per_sec: f64,
+    /// Region changes and requests a token can make at once.
+    #[serde(default = "default_burst")]
+    burst: f64,
+}
+
+impl EmbedToken {
+    fn validate(&self) -> Result<(), XlsError> {
+        if self.name.trim().is_empty() {
+            return Err(XlsError::InvalidField(
+                "name",
+                String::from("The name must not be empty"),
+            ));
+        }
+        validate_regions(&self.regions)?;
+        if !(self.requests_per_sec > 0.0 && self.burst >= 1.0) {
+            return Err(XlsError::InvalidField(
+                "requests_per_sec",
+                String::from("The rate must be positive and the burst at least 1"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks the regions a token or an integration is restricted to.
+pub(crate) fn validate_regions(regions: &[Region]) -> Result<(), XlsError> {
+    if regions.is_empty() || regions.len() > MAX_TOKEN_REGIONS {
+        return Err(XlsError::InvalidField(
+            "regions",
+            format!("Between 1 and {MAX_TOKEN_REGIONS} regions are allowed"),
+        ));
+    }
+    let invalid = |region: &Region| {
+        region.from >= region.to || region.from < CELL_IDS.start || region.to > CELL_IDS.end
+    };
+    if let Some(region) = regions.iter().find(|region| invalid(region)) {
+        return Err(XlsError::InvalidField(
+            "regions",
+            format!("Invalid range of cell ids {}..{}", region.from, region.to),
+        ));
+    }
+    Ok(())
+}
+
+/// An issued token and the rate limit of its readers.
+pub(crate) struct Reader {
+    token: EmbedToken,
+    limiter: RateLimiter,
+    /// Cancelled when the token is revoked, which closes the websockets reading with it.
+    revoked: CancellationToken,
+}
+
+impl Reader {
+    fn new(token: EmbedToken) -> Self {
+        let limiter = RateLimiter::new(token.requests_per_sec, token.burst);
+        Reader {
+            token,
+            limiter,
+            revoked: CancellationToken::new(),
+        }
+    }
+
+    /// Completes once the token is revoked.
+    pub(crate) async fn revoked(&self) {
+        self.revoked.cancelled().await
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.token.name
+    }
+
+    /// Takes a request from the rate limit of the token.
+    pub(crate) fn check(&self) -> Result<(), XlsError> {
+        if self.limiter.check(&self.token.name) {
+            return Ok(());
+        }
+        let retry_after = self.limiter.retry_after(&self.token.name);
+        Err(XlsError::RateLimited(Some(retry_after.as_secs())))
+    }
+
+    /// The part of `region` the token can read: the overlap with the first of its regions that
+    /// overlaps.
+    pub(crate) fn restrict(&self, region: Region) -> Result<Region, XlsError> {
+        self.token
+            .regions
+            .iter()
+            .map(|allowed| Region {
+                from: region.from.max(allowed.from),
+                to: region.to.min(allowed.to),
+                ..region
+            })
+            .find(|overlap| overlap.from < overlap.to)
+            .ok_or_else(|| XlsError::Validation(String::from("The token can't read this region")))
+    }
+}
+
+/// The issued tokens.
+pub(crate) struct EmbedTokens {
+    readers: RwLock<Vec<Arc<Reader>>>,
+}
+
+impl EmbedTokens {
+    /// Loads the tokens stored in `EMBED_TOKENS_PATH`.
+    pub(crate) fn load() -> Arc<Self> {
+        let tokens: Vec<EmbedToken> = persist::load(&EMBED_TOKENS_PATH);
+        if !tokens.is_empty() {
+            info!("Loaded {} embed tokens", tokens.len());
+        }
+        Arc::new(EmbedTokens {
+            readers: RwLock::new(tokens.into_iter().map(Reader::new).map(Arc::new).collect()),
+        })
+    }
+
+    /// The reader of a token, `None` if it wasn't issued or was revoked.
+    pub(crate) fn find(&self, token: &str) -> Option<Arc<Reader>> {
+        self.readers
+            .read()
+            .unwrap()
+            .iter()
+            .find(|reader| constant_time_eq(token, &reader.token.token))
+            .cloned()
+    }
+
+    fn tokens(&self) -> Vec<EmbedToken> {
+        let readers = self.readers.read().unwrap();
+        readers.iter().map(|reader| reader.token.clone()).collect()
+    }
+
+    fn issue(&self, token: EmbedToken) -> Result<(), XlsError> {
+        let mut readers = self.readers.write().unwrap();
+        if readers.iter().any(|reader| reader.token.name == token.name) {
+            return Err(XlsError::InvalidField(
+                "name",
+                format!("Token {} already exists", token.name),
+            ));
+        }
+        readers.push(Arc::new(Reader::new(token)));
+        persist::store(
+            &EMBED_TOKENS_PATH,
+            readers.iter().map(|reader| &reader.token),
+        );
+        Ok(())
+    }
+
+    /// Revokes a token and closes the websockets using it, returns `false` if it doesn't
+    /// exist.
+    fn revoke(&self, name: &str) -> bool {
+        let mut readers = self.readers.write().unwrap();
+        let Some(index) = readers.iter().position(|reader| reader.token.name == name) else {
+            return false;
+        };
+        readers.remove(index).revoked.cancel();
+        persist::store(
+            &EMBED_TOKENS_PATH,
+            readers.iter().map(|reader| &reader.token),
+        );
+        true
+    }
+}
+
+/// Lists the issued embed tokens.
+#[utoipa::path(
+    get,
+    path = "/api/admin/embed-tokens",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = [EmbedToken]),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn list(State(state): State<AppState>) -> Json<Vec<EmbedToken>> {
+    Json(state.embed_tokens.tokens())
+}
+
+/// Issues a token that can read the regions from any origin, the token is generated.
+#[utoipa::path(
+    post,
+    path = "/api/admin/embed-tokens",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body = EmbedToken,
+    responses(
+        (status = 201, description = "The token was issued", body = EmbedToken),
+        (status = 400, body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn issue(
+    State(state): State<AppState>,
+    JsonBody(mut token): JsonBody<EmbedToken>,
+) -> Result<(StatusCode, Json<EmbedToken>), XlsError> {
+    token.validate()?;
+    token.name = token.name.trim().to_string();
+    token.token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    state.embed_tokens.issue(token.clone())?;
+    info!(
+        "Issued embed token {} for {} regions",
+        token.name,
+        token.regions.len()
+    );
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+/// Revokes an embed token, websockets already open with it stay open.
+#[utoipa::path(
+    delete,
+    path = "/api/admin/embed-tokens/{name}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("name" = String, Path, description = "Name of the token")),
+    responses(
+        (status = 204, description = "The token was revoked"),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn revoke(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, XlsError> {
+    if !state.embed_tokens.revoke(&name) {
+        return Err(XlsError::NotFound(format!(
+            "Embed token {name} does not exist"
+        )));
+    }
+    info!("Revoked embed token {name}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct CellsParams {
+    token: String,
+    from: i64,
+    to: i64,
+}
+
+/// The cells of a region an embed token can read, from any origin. The region is truncated to
+/// the part the token can read and to `WS_MAX_REGION_CELLS`.
+#[utoipa::path(
+    get,
+    path = "/api/embed/cells",
+    params(
+        ("token" = String, Query, description = "Embed token"),
+        ("from" = i64, Query, description = "First cell id of the region"),
+        ("to" = i64, Query, description = "Cell id after the region"),
+    ),
+    responses(
+        (status = 200, description = "The cells of the region that have a value", body = [Cell]),
+        (status = 400, description = "The token can't read the region", body = ErrorResponse),
+        (status = 401, description = "The token is invalid or revoked", body = ErrorResponse),
+        (status = 429, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn cells(
+    State(state): State<AppState>,
+    Query(params): Query<CellsParams>,
+) -> Result<Json<Vec<Cell>>, XlsError> {
+    let reader = state
+        .embed_tokens
+        .find(&params.token)
+        .ok_or(XlsError::Unauthorized)?;
+    reader.check()?;
+    let region = Region::new(params.from, params.to);
+    let region = reader.restrict(subscription_region(region)?)?;
+    let snapshot = state.spreadsheet_view.query(region).await?;
+    Ok(Json(parse_rows(&snapshot)?))
+}
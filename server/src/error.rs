@@ -0,0 +1,203 @@
+//! Error type shared by the request handlers and the Feldera helpers.
+
+use std::fmt::Display;
+use std::io;
+
+use axum::async_trait;
+use axum::extract::{FromRequest, Request};
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::de::{DeserializeOwned, StdError};
+use tokio_util::codec::LinesCodecError;
+use xls_protocol::{ErrorResponse, WsError};
+
+#[derive(Clone, Debug)]
+pub(crate) enum XlsError {
+    /// Feldera could not be reached or answered with an unexpected status.
+    FelderaUnavailable(String),
+    /// A request to Feldera did not complete in time.
+    QueryTimeout(String),
+    /// Data we received could not be parsed.
+    ParseError(String),
+    /// The client exceeded its API limit, it may retry after the seconds if they are known.
+    RateLimited(Option<u64>),
+    /// The client sent a request we refuse to process.
+    Validation(String),
+    /// A field of the request body is invalid, as `(field, message)`.
+    InvalidField(&'static str, String),
+    /// The request lacks valid credentials for an admin endpoint or the websocket.
+    Unauthorized,
+    /// The client IP is not allowed to use the spreadsheet.
+    Forbidden,
+    /// The page opening the websocket is not on an allowed origin.
+    OriginNotAllowed,
+    /// The requested resource does not exist.
+    NotFound(String),
+    /// A change stream skipped changes or reconnected, so changes may have been missed.
+    ChangesMissed(String),
+    /// The server is drained before it stops and doesn't accept new websockets.
+    Draining,
+    /// Too many queries wait for Feldera, the client may retry after the seconds.
+    Busy(u64),
+    /// The deployment doesn't accept edits, see `READ_ONLY`.
+    ReadOnly,
+}
+
+impl XlsError {
+    /// Machine-readable identifier of the error kind, sent to clients as `code`.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            XlsError::FelderaUnavailable(_) => "feldera_unavailable",
+            XlsError::QueryTimeout(_) => "query_timeout",
+            XlsError::ParseError(_) => "parse_error",
+            XlsError::RateLimited(_) => "rate_limited",
+            XlsError::Validation(_) | XlsError::InvalidField(_, _) => "validation",
+            XlsError::Unauthorized => "unauthorized",
+            XlsError::Forbidden => "forbidden",
+            XlsError::OriginNotAllowed => "origin_not_allowed",
+            XlsError::NotFound(_) => "not_found",
+            XlsError::ChangesMissed(_) => "changes_missed",
+            XlsError::Draining => "draining",
+            XlsError::Busy(_) => "busy",
+            XlsError::ReadOnly => "read_only",
+        }
+    }
+
+    /// Seconds after which the request may succeed if it is sent again, if they are known.
+    pub(crate) fn retry_after_secs(&self) -> Option<u64> {
+        match self {
+            XlsError::RateLimited(retry_after_secs) => *retry_after_secs,
+            XlsError::Busy(retry_after_secs) => Some(*retry_after_secs),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            XlsError::FelderaUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            XlsError::QueryTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            XlsError::ParseError(_) => StatusCode::BAD_GATEWAY,
+            XlsError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            XlsError::Validation(_) | XlsError::InvalidField(_, _) => StatusCode::BAD_REQUEST,
+            XlsError::Unauthorized => StatusCode::UNAUTHORIZED,
+            XlsError::Forbidden | XlsError::OriginNotAllowed | XlsError::ReadOnly => {
+                StatusCode::FORBIDDEN
+            }
+            XlsError::NotFound(_) => StatusCode::NOT_FOUND,
+            XlsError::ChangesMissed(_) | XlsError::Draining | XlsError::Busy(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        }
+    }
+}
+
+impl Display for XlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XlsError::FelderaUnavailable(message)
+            | XlsError::QueryTimeout(message)
+            | XlsError::ParseError(message)
+            | XlsError::Validation(message)
+            | XlsError::InvalidField(_, message)
+            | XlsError::NotFound(message)
+            | XlsError::ChangesMissed(message) => write!(f, "{}", message.trim()),
+            XlsError::RateLimited(_) => write!(f, "API limit exceeded"),
+            XlsError::Unauthorized => write!(f, "Unauthorized"),
+            XlsError::Forbidden => write!(f, "Access from this IP is not allowed"),
+            XlsError::OriginNotAllowed => write!(f, "Websockets from this origin are not allowed"),
+            XlsError::Draining => write!(f, "The server is restarting, connect again"),
+            XlsError::Busy(_) => write!(f, "Too many queries are running, retry shortly"),
+            XlsError::ReadOnly => write!(f, "This spreadsheet is read-only"),
+        }
+    }
+}
+
+impl From<io::Error> for XlsError {
+    fn from(e: io::Error) -> Self {
+        XlsError::FelderaUnavailable(e.to_string())
+    }
+}
+
+impl From<LinesCodecError> for XlsError {
+    fn from(e: LinesCodecError) -> Self {
+        match e {
+            LinesCodecError::MaxLineLengthExceeded => XlsError::ParseError(e.to_string()),
+            LinesCodecError::Io(e) => XlsError::from(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for XlsError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            XlsError::QueryTimeout(e.to_string())
+        } else if e.is_decode() {
+            XlsError::ParseError(e.to_string())
+        } else {
+            XlsError::FelderaUnavailable(e.to_string())
+        }
+    }
+}
+
+impl StdError for XlsError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        None
+    }
+}
+
+impl From<&XlsError> for ErrorResponse {
+    fn from(e: &XlsError) -> Self {
+        ErrorResponse {
+            error: e.to_string(),
+            code: String::from(e.code()),
+            field: match e {
+                XlsError::InvalidField(field, _) => Some(String::from(*field)),
+                _ => None,
+            },
+            retry_after_secs: e.retry_after_secs(),
+        }
+    }
+}
+
+/// Errors sent over the websocket, the connection stays open.
+impl From<&XlsError> for WsError {
+    fn from(e: &XlsError) -> Self {
+        WsError {
+            error: e.to_string(),
+            code: String::from(e.code()),
+            close: None,
+            retry_after_secs: e.retry_after_secs(),
+        }
+    }
+}
+
+/// Errors are returned to clients as `{"error": "<message>", "code": "<code>"}`, with the
+/// invalid `field` and `retry_after_secs` if they are known.
+impl IntoResponse for XlsError {
+    fn into_response(self) -> Response {
+        let body = ErrorResponse::from(&self);
+        let mut response = (self.status(), Json(&body)).into_response();
+        if let Some(secs) = body.retry_after_secs {
+            response.headers_mut().insert(RETRY_AFTER, secs.into());
+        }
+        response
+    }
+}
+
+/// A JSON request body like [`Json`], but malformed bodies are rejected with an [`XlsError`] so
+/// they get the same error body as other errors.
+pub(crate) struct JsonBody<T>(pub(crate) T);
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for JsonBody<T> {
+    type Rejection = XlsError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, XlsError> {
+        match Json::<T>::from_request(request, state).await {
+            Ok(Json(body)) => Ok(JsonBody(body)),
+            Err(rejection) => Err(XlsError::Validation(rejection.body_text())),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Keeps the most recent errors that were logged, for the admin dashboard.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use log::{Level, Log, Metadata, Record};
+use serde::Serialize;
+
+/// Number of errors that are kept.
+const CAPACITY: usize = 50;
+
+static RECENT_ERRORS: Mutex<VecDeque<LoggedError>> = Mutex::new(VecDeque::new());
+
+#[derive(Serialize, Clone, Debug, utoipa::ToSchema)]
+pub(crate) struct LoggedError {
+    /// RFC 3339 timestamp.
+    time: String,
+    /// The module that logged the error.
+    target: String,
+    message: String,
+}
+
+/// Wraps `env_logger` and records every error in addition to logging it.
+struct ErrorLog {
+    inner: env_logger::Logger,
+}
+
+impl Log for ErrorLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() == Level::Error || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() == Level::Error {
+            let mut errors = RECENT_ERRORS.lock().unwrap();
+            if errors.len() == CAPACITY {
+                errors.pop_front();
+            }
+            errors.push_back(LoggedError {
+                time: Utc::now().to_rfc3339(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            });
+        }
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the logger, configured through `RUST_LOG` like `env_logger`.
+pub(crate) fn init() {
+    let inner = env_logger::Builder::from_default_env().build();
+    let max_level = inner.filter().max(log::LevelFilter::Error);
+    if log::set_boxed_logger(Box::new(ErrorLog { inner })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// The most recent errors, newest first.
+pub(crate) fn recent() -> Vec<LoggedError> {
+    RECENT_ERRORS
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,57 @@
+//! Removes expired ephemeral cells from `spreadsheet_data`.
+//!
+//! `spreadsheet_view` already hides a cell once its latest value expired, but the rows stay in
+//! the table. This job deletes them together with the older values of the cell, which would
+//! otherwise become visible again once the expired row is gone.
+//!
+//! It only runs on the publisher, see [`fanout::is_publisher`].
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use log::{error, info};
+use reqwest::Client;
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::fanout;
+use crate::feldera::delete_rows;
+
+/// How often expired cells are removed, `0` disables the job.
+static CELL_CLEANUP_INTERVAL: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("CELL_CLEANUP_INTERVAL_SECS", 300)));
+
+/// Rows deleted with a single request.
+const CLEANUP_BATCH_SIZE: usize = 1000;
+
+/// Expired rows and the rows they replaced, oldest first so a partial cleanup never uncovers an
+/// older value.
+const EXPIRED_ROWS: &str = "SELECT d.* FROM spreadsheet_data d \
+    JOIN (SELECT id, MAX(ts) AS ts FROM spreadsheet_data WHERE expires_at <= NOW() GROUP BY id) e \
+    ON d.id = e.id AND d.ts <= e.ts \
+    ORDER BY d.ts";
+
+pub(crate) fn spawn_cleanup(client: Client) {
+    if CELL_CLEANUP_INTERVAL.is_zero() {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(*CELL_CLEANUP_INTERVAL).await;
+            if !fanout::is_publisher("spreadsheet_view") {
+                continue;
+            }
+            match cleanup(&client).await {
+                Ok(0) => {}
+                Ok(deleted) => info!("Deleted {deleted} rows of expired cells"),
+                Err(e) => error!("Failed to delete expired cells: {e}"),
+            }
+        }
+    });
+}
+
+/// Deletes expired rows, returns how many.
+async fn cleanup(client: &Client) -> Result<usize, XlsError> {
+    let sql = format!("{EXPIRED_ROWS} LIMIT {CLEANUP_BATCH_SIZE}");
+    delete_rows(client, "spreadsheet_data", &sql).await
+}
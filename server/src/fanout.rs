@@ -0,0 +1,270 @@
+//! Fans change streams out to all server instances through Redis pub/sub.
+//!
+//! Without `FANOUT_REDIS_URL` every instance subscribes to the Feldera change streams directly.
+//! With it, the instance holding a lease in Redis reads the change stream from Feldera and
+//! publishes every line to a Redis channel, and all instances (including the publisher) feed
+//! their local broadcast channel from that Redis channel. This keeps the number of egress
+//! connections to Feldera constant no matter how many instances run behind the load balancer.
+//!
+//! Changes the publisher missed are published as a line starting with [`MISSED_MARKER`], and
+//! every instance reports them, and changes it might have missed while reconnecting to Redis, as
+//! [`XlsError::ChangesMissed`].
+//!
+//! Jobs that write to the pipeline, e.g. cleanups, only run on the instance publishing
+//! `spreadsheet_view`, see [`is_publisher`].
+
+use std::collections::HashSet;
+use std::env::var;
+use std::sync::atomic::Ordering;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use futures::StreamExt;
+use log::{error, info, warn};
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use reqwest::Client;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender;
+
+use crate::error::XlsError;
+use crate::feldera;
+use crate::metrics::METRICS;
+
+static FANOUT_REDIS_URL: LazyLock<Option<String>> =
+    LazyLock::new(|| var("FANOUT_REDIS_URL").ok().filter(|url| !url.is_empty()));
+
+/// Identifies this instance when acquiring the publisher lease.
+static INSTANCE_ID: LazyLock<String> = LazyLock::new(|| {
+    var("FLY_MACHINE_ID").unwrap_or_else(|_| {
+        format!(
+            "{}-{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        )
+    })
+});
+
+/// Starts the lines telling the instances that changes were missed, followed by the reason. Changes
+/// are JSON objects, so they never start with it.
+const MISSED_MARKER: &str = "!missed ";
+
+/// The views whose publisher lease this instance holds.
+static HELD_LEASES: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Mutex::default);
+
+/// How long the publisher lease is valid without being renewed.
+const LEASE_TTL: Duration = Duration::from_secs(15);
+/// How often the lease is acquired/renewed.
+const LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Renews the lease only if it is still held by us.
+const RENEW_LEASE_SCRIPT: &str = r"
+if redis.call('get', KEYS[1]) == ARGV[1] then
+    return redis.call('pexpire', KEYS[1], ARGV[2])
+else
+    return 0
+end";
+
+/// Whether this instance publishes the changes of `view_name`. Without `FANOUT_REDIS_URL` every
+/// instance is on its own and publishes to itself.
+///
+/// Jobs that write to the pipeline only run on the publisher of `spreadsheet_view`, so a single
+/// instance runs them: if several instances deleted the same row, the row would be deleted twice,
+/// which corrupts the table.
+pub(crate) fn is_publisher(view_name: &str) -> bool {
+    FANOUT_REDIS_URL.is_none() || HELD_LEASES.lock().unwrap().contains(view_name)
+}
+
+/// Subscribes to a view's change stream, through Redis if `FANOUT_REDIS_URL` is set.
+pub(crate) fn subscribe_change_stream(
+    client: Client,
+    view_name: &str,
+    capacity: usize,
+) -> Sender<Result<String, XlsError>> {
+    let Some(url) = FANOUT_REDIS_URL.as_ref() else {
+        return feldera::subscribe_change_stream(client, view_name, capacity);
+    };
+    let redis = match redis::Client::open(url.as_str()) {
+        Ok(redis) => redis,
+        Err(e) => {
+            error!("Invalid FANOUT_REDIS_URL, subscribing to feldera directly: {e}");
+            HELD_LEASES.lock().unwrap().insert(String::from(view_name));
+            return feldera::subscribe_change_stream(client, view_name, capacity);
+        }
+    };
+
+    let (tx, _) = tokio::sync::broadcast::channel(capacity);
+    let channel = format!("xls:changes:{view_name}");
+    let lease = format!("xls:publisher:{view_name}");
+    tokio::spawn(forward_from_redis(
+        redis.clone(),
+        channel.clone(),
+        tx.clone(),
+    ));
+    tokio::spawn(publish_to_redis(
+        redis,
+        client,
+        String::from(view_name),
+        capacity,
+        channel,
+        lease,
+    ));
+
+    tx
+}
+
+/// Feeds the local broadcast channel from the Redis channel.
+async fn forward_from_redis(
+    redis: redis::Client,
+    channel: String,
+    tx: Sender<Result<String, XlsError>>,
+) {
+    let mut reconnected = false;
+    loop {
+        match redis.get_async_pubsub().await {
+            Ok(mut pubsub) => match pubsub.subscribe(&channel).await {
+                Ok(()) => {
+                    if reconnected {
+                        let missed =
+                            format!("Resubscribed to {channel}, changes may have been missed");
+                        let _ = tx.send(Err(XlsError::ChangesMissed(missed)));
+                    }
+                    reconnected = true;
+                    let mut messages = pubsub.on_message();
+                    while let Some(msg) = messages.next().await {
+                        match msg.get_payload::<String>() {
+                            Ok(line) => {
+                                METRICS
+                                    .fanout_received_total
+                                    .fetch_add(1, Ordering::Relaxed);
+                                // Sending fails only if there are no receivers at the moment.
+                                let change = match line.strip_prefix(MISSED_MARKER) {
+                                    Some(missed) => {
+                                        Err(XlsError::ChangesMissed(String::from(missed)))
+                                    }
+                                    None => Ok(line),
+                                };
+                                let _ = tx.send(change);
+                            }
+                            Err(e) => warn!("Invalid message on {channel}: {e}"),
+                        }
+                    }
+                }
+                Err(e) => error!("Failed to subscribe to {channel}: {e}"),
+            },
+            Err(e) => error!("Failed to connect to redis: {e}"),
+        }
+
+        warn!("Lost connection to {channel}, wait 5 seconds before reconnecting");
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Publishes the Feldera change stream to Redis while this instance holds the lease.
+///
+/// The Feldera subscription is opened whenever this instance becomes the publisher and closed when
+/// it loses the lease. Every new publisher tells the instances that changes may have been missed
+/// while the lease was free.
+async fn publish_to_redis(
+    redis: redis::Client,
+    client: Client,
+    view_name: String,
+    capacity: usize,
+    channel: String,
+    lease: String,
+) {
+    let renew_lease = redis::Script::new(RENEW_LEASE_SCRIPT);
+
+    loop {
+        let mut conn = match redis.get_multiplexed_async_connection().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to connect to redis: {e}");
+                tokio::time::sleep(LEASE_RENEW_INTERVAL).await;
+                continue;
+            }
+        };
+
+        if !acquire_lease(&mut conn, &lease).await {
+            tokio::time::sleep(LEASE_RENEW_INTERVAL).await;
+            continue;
+        }
+        info!("Publishing {view_name} changes to {channel}");
+        HELD_LEASES.lock().unwrap().insert(view_name.clone());
+        // Only the publisher is connected to Feldera, the stream is closed again when the lease is lost.
+        let (tx, mut changes) = tokio::sync::broadcast::channel(capacity);
+        let stream = feldera::spawn_change_stream(client.clone(), &view_name, tx);
+        // Nobody published while the lease was free.
+        let missed = format!("{MISSED_MARKER}Publisher of {view_name} changed");
+        let published: Result<(), _> = conn.publish(&channel, missed).await;
+        if let Err(e) = published {
+            error!("Failed to publish to {channel}: {e}");
+        }
+
+        let mut renew = tokio::time::interval(LEASE_RENEW_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = renew.tick() => {
+                    let renewed: Result<i64, _> = renew_lease
+                        .key(&lease)
+                        .arg(&*INSTANCE_ID)
+                        .arg(LEASE_TTL.as_millis() as u64)
+                        .invoke_async(&mut conn)
+                        .await;
+                    if !matches!(renewed, Ok(1)) {
+                        warn!("Lost publisher lease for {view_name}: {renewed:?}");
+                        break;
+                    }
+                }
+                change = changes.recv() => {
+                    let line = match change {
+                        Ok(Ok(line)) => line,
+                        Ok(Err(XlsError::ChangesMissed(missed))) => format!("{MISSED_MARKER}{missed}"),
+                        Ok(Err(e)) => {
+                            warn!("Error receiving change: {e}");
+                            continue;
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            format!("{MISSED_MARKER}Publishing {view_name} skipped {skipped} changes")
+                        }
+                        Err(RecvError::Closed) => {
+                            error!("The change stream of {view_name} closed");
+                            break;
+                        }
+                    };
+                    let published: Result<(), _> = conn.publish(&channel, line).await;
+                    if let Err(e) = published {
+                        error!("Failed to publish to {channel}: {e}");
+                        break;
+                    }
+                    METRICS.fanout_published_total.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        stream.abort();
+        HELD_LEASES.lock().unwrap().remove(&view_name);
+    }
+}
+
+async fn acquire_lease(conn: &mut MultiplexedConnection, lease: &str) -> bool {
+    let acquired: Result<Option<String>, _> = redis::cmd("SET")
+        .arg(lease)
+        .arg(&*INSTANCE_ID)
+        .arg("NX")
+        .arg("PX")
+        .arg(LEASE_TTL.as_millis() as u64)
+        .query_async(conn)
+        .await;
+    match acquired {
+        Ok(Some(_)) => true,
+        // We might still hold the lease from before a reconnect.
+        Ok(None) => conn
+            .get::<_, Option<String>>(lease)
+            .await
+            .is_ok_and(|holder| holder.as_ref() == Some(&*INSTANCE_ID)),
+        Err(e) => {
+            error!("Failed to acquire publisher lease {lease}: {e}");
+            false
+        }
+    }
+}
@@ -1,39 +1,127 @@
 //! Helper functions for the Feldera API
 
 use std::env::var;
+use std::fmt::Debug;
 use std::io;
+use std::ops::Range;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, LazyLock};
 use std::time::Duration;
 
-use crate::stats::XlsError;
-use axum::http::StatusCode;
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::metrics::{GaugeGuard, METRICS};
 use axum::Json;
-use dashmap::DashSet;
+use dashmap::{DashMap, DashSet};
 use futures::{StreamExt, TryStreamExt};
 use log::{error, warn};
 use reqwest::Client;
+use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serde_json::Value;
 use tokio::sync::broadcast::Sender;
+use tokio::task::JoinHandle;
 
-const PIPELINE_NAME: &str = "xls";
-const FELDERA_HOST: LazyLock<String> =
+/// Default pipeline for reads and writes.
+static FELDERA_PIPELINE: LazyLock<String> =
+    LazyLock::new(|| var("FELDERA_PIPELINE").unwrap_or_else(|_| String::from("xls")));
+/// Pipeline that serves adhoc queries and change streams.
+pub(crate) static READ_PIPELINE: LazyLock<String> =
+    LazyLock::new(|| var("FELDERA_READ_PIPELINE").unwrap_or_else(|_| FELDERA_PIPELINE.clone()));
+/// Pipelines that receive cell updates, the first one is the primary.
+///
+/// For a blue/green upgrade, writes go to both the old and the new pipeline until the new one
+/// has caught up, then `FELDERA_READ_PIPELINE` is switched over to the new pipeline.
+pub(crate) static WRITE_PIPELINES: LazyLock<Vec<String>> = LazyLock::new(|| {
+    let pipelines: Vec<String> = var("FELDERA_WRITE_PIPELINES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if pipelines.is_empty() {
+        vec![FELDERA_PIPELINE.clone()]
+    } else {
+        pipelines
+    }
+});
+pub(crate) static FELDERA_HOST: LazyLock<String> =
     LazyLock::new(|| var("FELDERA_HOST").unwrap_or_else(|_| String::from("http://localhost:8080")));
-static FELDERA_API_KEY: LazyLock<String> =
+pub(crate) static FELDERA_API_KEY: LazyLock<String> =
     LazyLock::new(|| var("FELDERA_API_KEY").unwrap_or_else(|_| String::new()));
+/// Timeout for request/response calls to Feldera (doesn't apply to the change streams).
+static FELDERA_REQUEST_TIMEOUT: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("FELDERA_REQUEST_TIMEOUT_SECS", 30)));
+/// How long to wait before reconnecting to a change stream.
+static FELDERA_RECONNECT_DELAY: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("FELDERA_RECONNECT_DELAY_SECS", 10)));
 
-pub(crate) async fn adhoc_query(client: Client, sql: &str) -> Result<String, XlsError> {
-    let url = format!("{}/v0/pipelines/{PIPELINE_NAME}/query", &*FELDERA_HOST);
-    let response = client
-        .get(url)
-        .bearer_auth(&*FELDERA_API_KEY)
-        .query(&[("sql", sql), ("format", "json")])
+/// All pipelines the server uses (read and write), without duplicates.
+pub(crate) fn pipelines() -> Vec<&'static str> {
+    let mut pipelines = vec![READ_PIPELINE.as_str()];
+    for name in WRITE_PIPELINES.iter() {
+        if !pipelines.contains(&name.as_str()) {
+            pipelines.push(name);
+        }
+    }
+    pipelines
+}
+
+/// Creates the HTTP client that is shared by all requests to Feldera.
+///
+/// The pool keeps a bounded number of idle connections around so bursts of adhoc queries
+/// and ingress requests don't open a new connection each time.
+pub(crate) fn http_client() -> Client {
+    Client::builder()
+        .pool_max_idle_per_host(env_or("FELDERA_POOL_MAX_IDLE", 32))
+        .pool_idle_timeout(Duration::from_secs(env_or(
+            "FELDERA_POOL_IDLE_TIMEOUT_SECS",
+            90,
+        )))
+        .connect_timeout(Duration::from_secs(env_or(
+            "FELDERA_CONNECT_TIMEOUT_SECS",
+            5,
+        )))
+        .tcp_keepalive(Duration::from_secs(60))
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+/// Sends a request/response style call to Feldera and keeps the request metrics up to date.
+pub(crate) async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, XlsError> {
+    let _in_flight = GaugeGuard::new(&METRICS.feldera_requests_in_flight);
+    METRICS
+        .feldera_requests_total
+        .fetch_add(1, Ordering::Relaxed);
+    let response = request
+        .timeout(*FELDERA_REQUEST_TIMEOUT)
         .send()
         .await
-        .map_err(XlsError::from)?;
+        .inspect_err(|_| {
+            METRICS
+                .feldera_request_errors_total
+                .fetch_add(1, Ordering::Relaxed);
+        })?;
+    if !response.status().is_success() {
+        METRICS
+            .feldera_request_errors_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(response)
+}
+
+pub(crate) async fn adhoc_query(client: Client, sql: &str) -> Result<String, XlsError> {
+    let url = format!("{}/v0/pipelines/{}/query", &*FELDERA_HOST, &*READ_PIPELINE);
+    let response = send(
+        client
+            .get(url)
+            .bearer_auth(&*FELDERA_API_KEY)
+            .query(&[("sql", sql), ("format", "json")]),
+    )
+    .await?;
 
     if !response.status().is_success() {
-        return Err(XlsError::from(format!(
+        return Err(XlsError::FelderaUnavailable(format!(
             "Failed to fetch data: HTTP {}: {:?}",
             response.status(),
             response.text().await.unwrap_or_else(|e| e.to_string())
@@ -57,28 +145,77 @@ enum Change {
     Delete(Value),
 }
 
+/// Cells that were deleted from `spreadsheet_view` without being replaced, i.e., cells whose
+/// value expired, as empty cells so clients clear them.
+fn cleared_cells(changes: &[Change]) -> Vec<Value> {
+    let inserted: Vec<&Value> = changes
+        .iter()
+        .filter_map(|change| match change {
+            Change::Insert(value) => Some(&value["id"]),
+            Change::Delete(_) => None,
+        })
+        .collect();
+    changes
+        .iter()
+        .filter_map(|change| match change {
+            Change::Delete(value) if !inserted.contains(&&value["id"]) => Some(serde_json::json!({
+                "id": value["id"],
+                "background": 0,
+                "raw_value": "",
+                "computed_value": "",
+            })),
+            _ => None,
+        })
+        .collect()
+}
+
 /// Parses a record from the feldera change stream.
 #[derive(serde::Deserialize)]
-#[allow(dead_code)]
 struct Record {
     sequence_number: i64,
     json_data: Option<Vec<Change>>,
 }
 
+/// The sequence numbers of the records of a change stream connection. Feldera skips the
+/// numbers of records it dropped because they weren't read fast enough.
+#[derive(Default)]
+struct SequenceNumbers {
+    next: Option<i64>,
+}
+
+impl SequenceNumbers {
+    /// The sequence numbers skipped before `sequence_number`, if any.
+    fn skipped(&mut self, sequence_number: i64) -> Option<Range<i64>> {
+        let next = self.next.replace(sequence_number + 1)?;
+        (sequence_number > next).then_some(next..sequence_number)
+    }
+}
+
 pub(crate) fn subscribe_change_stream(
     client: Client,
     view_name: &str,
     capacity: usize,
 ) -> Sender<Result<String, XlsError>> {
     let (tx, _) = tokio::sync::broadcast::channel(capacity);
-    let subscribe = tx.clone();
+    spawn_change_stream(client, view_name, tx.clone());
+    tx
+}
+
+/// Feeds `tx` from a view's change stream until the returned task is aborted, which closes the
+/// connection to Feldera.
+pub(crate) fn spawn_change_stream(
+    client: Client,
+    view_name: &str,
+    tx: Sender<Result<String, XlsError>>,
+) -> JoinHandle<()> {
     let url = format!(
-        "{}/v0/pipelines/{PIPELINE_NAME}/egress/{view_name}",
-        &*FELDERA_HOST
+        "{}/v0/pipelines/{}/egress/{view_name}",
+        &*FELDERA_HOST, &*READ_PIPELINE
     );
     let view = String::from(view_name);
 
     tokio::spawn(async move {
+        let mut reconnected = false;
         loop {
             let response = client
                 .post(url.clone())
@@ -94,6 +231,7 @@ pub(crate) fn subscribe_change_stream(
 
             match response {
                 Ok(resp) if resp.status().is_success() => {
+                    let _open = GaugeGuard::new(&METRICS.feldera_streams_open);
                     let stream = resp
                         .bytes_stream()
                         .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
@@ -102,6 +240,14 @@ pub(crate) fn subscribe_change_stream(
                         reader,
                         tokio_util::codec::LinesCodec::new(),
                     );
+                    // Only the cache of `spreadsheet_view` recovers from missed changes.
+                    let track_gaps = view == "spreadsheet_view";
+                    if track_gaps && reconnected {
+                        let missed = format!("Reconnected to {view}, changes may have been missed");
+                        let _ = tx.send(Err(XlsError::ChangesMissed(missed)));
+                    }
+                    reconnected = true;
+                    let mut sequence = SequenceNumbers::default();
 
                     while let Some(line) = decoder.next().await {
                         match line {
@@ -109,20 +255,36 @@ pub(crate) fn subscribe_change_stream(
                                 //log::debug!("Received change: {line}");
                                 match serde_json::from_str::<Record>(&line) {
                                     Ok(record) => {
-                                        // walk record.json_data in reverse and return first `insert`
-                                        'inner: for change in
-                                            record.json_data.unwrap_or_else(|| vec![]).iter().rev()
+                                        let skipped = sequence.skipped(record.sequence_number);
+                                        if let Some(skipped) = skipped.filter(|_| track_gaps) {
+                                            let missed = format!("{view} skipped {skipped:?}");
+                                            warn!("{missed}");
+                                            let _ = tx.send(Err(XlsError::ChangesMissed(missed)));
+                                        }
+                                        let changes = record.json_data.unwrap_or_else(|| vec![]);
+                                        let cleared = if view == "spreadsheet_view" {
+                                            cleared_cells(&changes)
+                                        } else {
+                                            vec![]
+                                        };
+                                        // walk record.json_data in reverse and return all `insert`s, then the cleared cells
+                                        'inner: for value in changes
+                                            .iter()
+                                            .rev()
+                                            .filter_map(|change| match change {
+                                                Change::Insert(value) => Some(value),
+                                                Change::Delete(_) => None,
+                                            })
+                                            .chain(cleared.iter())
                                         {
-                                            if let Change::Insert(value) = change {
-                                                let mut value_str = value.to_string();
-                                                value_str.push('\n');
-                                                //log::debug!("broadcasting change: {value_str}");
-                                                if tx.send(Ok(value_str)).is_err() {
-                                                    // A send operation can only fail if there are no active receivers,
-                                                    // implying that the message could never be received.
-                                                    // The error contains the message being sent as a payload so it can be recovered.
-                                                    break 'inner;
-                                                }
+                                            let mut value_str = value.to_string();
+                                            value_str.push('\n');
+                                            //log::debug!("broadcasting change: {value_str}");
+                                            if tx.send(Ok(value_str)).is_err() {
+                                                // A send operation can only fail if there are no active receivers,
+                                                // implying that the message could never be received.
+                                                // The error contains the message being sent as a payload so it can be recovered.
+                                                break 'inner;
                                             }
                                         }
                                     }
@@ -142,53 +304,146 @@ pub(crate) fn subscribe_change_stream(
                 }
                 _ => {
                     error!("Failed to fetch change stream at {url}: {:?}", response);
-                    let _ = tx.send(Err(XlsError::from("Failed to fetch change stream")));
+                    let _ = tx.send(Err(XlsError::FelderaUnavailable(String::from(
+                        "Failed to fetch change stream",
+                    ))));
                 }
             }
 
-            warn!("Lost connection to change stream at {url}, wait 10 seconds before retrying to get changes again");
-            tokio::time::sleep(Duration::from_secs(10)).await;
+            warn!(
+                "Lost connection to change stream at {url}, wait {:?} before retrying to get changes again",
+                *FELDERA_RECONNECT_DELAY
+            );
+            tokio::time::sleep(*FELDERA_RECONNECT_DELAY).await;
         }
-    });
+    })
+}
 
-    subscribe
+/// Parses the newline-delimited JSON rows returned by [`adhoc_query`].
+pub(crate) fn parse_rows<T: DeserializeOwned>(rows: &str) -> Result<Vec<T>, XlsError> {
+    rows.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| XlsError::ParseError(e.to_string())))
+        .collect()
 }
 
+/// Inserts `data` into `table_name` of every write pipeline.
+///
+/// Only failures of the primary pipeline are reported to the caller.
 pub(crate) async fn insert<T: Serialize>(
     client: Client,
     table_name: &str,
     data: T,
-) -> (StatusCode, Json<Value>) {
+) -> Result<Json<Value>, XlsError> {
+    insert_all(client, table_name, data, false, "raw").await?;
+    Ok(Json(serde_json::json!({"success": true})))
+}
+
+/// Inserts multiple rows into a table with a single request.
+pub(crate) async fn insert_batch<T: Serialize>(
+    client: Client,
+    table_name: &str,
+    rows: &[T],
+) -> Result<(), XlsError> {
+    insert_all(client, table_name, rows, true, "raw").await
+}
+
+/// Deletes multiple rows from a table with a single request, the rows must match exactly.
+pub(crate) async fn delete_batch<T: Serialize>(
+    client: Client,
+    table_name: &str,
+    rows: &[T],
+) -> Result<(), XlsError> {
+    let deletes: Vec<Value> = rows
+        .iter()
+        .map(|row| serde_json::json!({ "delete": row }))
+        .collect();
+    insert_all(client, table_name, &deletes, true, "insert_delete").await
+}
+
+/// Replaces rows of a table by `new` with a single request, the `old` rows must match exactly.
+pub(crate) async fn replace_batch<T: Serialize>(
+    client: Client,
+    table_name: &str,
+    old: &[T],
+    new: &[T],
+) -> Result<(), XlsError> {
+    let updates: Vec<Value> = old
+        .iter()
+        .map(|row| serde_json::json!({ "delete": row }))
+        .chain(new.iter().map(|row| serde_json::json!({ "insert": row })))
+        .collect();
+    insert_all(client, table_name, &updates, true, "insert_delete").await
+}
+
+/// Deletes the rows of `table_name` returned by the adhoc query `sql`, returns how many.
+pub(crate) async fn delete_rows(
+    client: &Client,
+    table_name: &str,
+    sql: &str,
+) -> Result<usize, XlsError> {
+    let rows: Vec<Value> = parse_rows(&adhoc_query(client.clone(), sql).await?)?;
+    if !rows.is_empty() {
+        delete_batch(client.clone(), table_name, &rows).await?;
+    }
+    Ok(rows.len())
+}
+
+/// Inserts `data` into all write pipelines, `array` is set if `data` is a list of rows.
+async fn insert_all<T: Serialize>(
+    client: Client,
+    table_name: &str,
+    data: T,
+    array: bool,
+    update_format: &str,
+) -> Result<(), XlsError> {
+    for (idx, pipeline) in WRITE_PIPELINES.iter().enumerate() {
+        let result = insert_into(&client, pipeline, table_name, &data, array, update_format).await;
+        match result {
+            Err(e) if idx == 0 => return Err(e),
+            Err(e) => warn!("Failed to insert into secondary pipeline {pipeline}: {e}"),
+            Ok(()) => {}
+        }
+    }
+
+    Ok(())
+}
+
+async fn insert_into<T: Serialize>(
+    client: &Client,
+    pipeline: &str,
+    table_name: &str,
+    data: &T,
+    array: bool,
+    update_format: &str,
+) -> Result<(), XlsError> {
     let url = format!(
-        "{}/v0/pipelines/{PIPELINE_NAME}/ingress/{table_name}",
+        "{}/v0/pipelines/{pipeline}/ingress/{table_name}",
         &*FELDERA_HOST
     );
 
-    let response = client
-        .post(url.clone())
-        .bearer_auth(&*FELDERA_API_KEY)
-        .header("Content-Type", "application/json")
-        .query(&[("format", "json"), ("update_format", "raw")])
-        .json(&data)
-        .send()
-        .await;
+    let response = send(
+        client
+            .post(url)
+            .bearer_auth(&*FELDERA_API_KEY)
+            .header("Content-Type", "application/json")
+            .query(&[
+                ("format", "json"),
+                ("update_format", update_format),
+                ("array", if array { "true" } else { "false" }),
+            ])
+            .json(data),
+    )
+    .await?;
 
-    match response {
-        Ok(resp) if resp.status().is_success() => {
-            (StatusCode::OK, Json(serde_json::json!({"success": true})))
-        }
-        Ok(resp) => {
-            let body = resp.text().await.unwrap_or_else(|e| e.to_string());
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": body})),
-            )
-        }
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({"error": format!("Failed to update cell: {:?}", e)})),
-        ),
+    if !response.status().is_success() {
+        let body = response.text().await.unwrap_or_else(|e| e.to_string());
+        return Err(XlsError::FelderaUnavailable(format!(
+            "Failed to update cell: {body}"
+        )));
     }
+
+    Ok(())
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -198,32 +453,86 @@ struct ApiLimitRecord {
 
 pub(crate) fn api_limit_table(client: Client) -> Arc<DashSet<String>> {
     let ds = Arc::new(DashSet::new());
-    let ds_clone = ds.clone();
+    let reset = ds.clone();
+    let apply = ds.clone();
+    follow_view(
+        client,
+        "api_limit_reached",
+        move || reset.clear(),
+        move |record: ApiLimitRecord, inserted| {
+            if inserted {
+                apply.insert(record.ip);
+            } else {
+                apply.remove(&record.ip);
+            }
+        },
+    );
+    ds
+}
+
+/// A row of the `latest_cell_comments` view.
+#[derive(serde::Deserialize, Debug)]
+struct CellCommentRecord {
+    id: i64,
+    comment: String,
+}
+
+/// The latest comment of every cell that has one, kept up to date with the
+/// `latest_cell_comments` view.
+pub(crate) fn cell_comments(client: Client) -> Arc<DashMap<i64, String>> {
+    let comments = Arc::new(DashMap::new());
+    let reset = comments.clone();
+    let apply = comments.clone();
+    follow_view(
+        client,
+        "latest_cell_comments",
+        move || reset.clear(),
+        move |record: CellCommentRecord, inserted| {
+            if inserted {
+                apply.insert(record.id, record.comment);
+            } else {
+                // A replaced comment is deleted after the new one may have been inserted.
+                apply.remove_if(&record.id, |_, comment| *comment == record.comment);
+            }
+        },
+    );
+    comments
+}
+
+/// Follows the rows of a small view: every time the change stream (re)connects, `reset` is
+/// called and `apply` gets all rows of the view, then `apply` gets the rows inserted (`true`) and
+/// deleted (`false`) as they change.
+fn follow_view<T, R, A>(client: Client, view: &'static str, reset: R, apply: A)
+where
+    T: DeserializeOwned + Debug,
+    R: Fn() + Send + 'static,
+    A: Fn(T, bool) + Send + 'static,
+{
     let url = format!(
-        "{}/v0/pipelines/{PIPELINE_NAME}/egress/api_limit_reached",
-        &*FELDERA_HOST
+        "{}/v0/pipelines/{}/egress/{view}",
+        &*FELDERA_HOST, &*READ_PIPELINE
     );
 
     tokio::spawn(async move {
         loop {
-            ds.clear();
-            let snapshot = adhoc_query(client.clone(), "SELECT * FROM api_limit_reached")
+            reset();
+            let snapshot = adhoc_query(client.clone(), &format!("SELECT * FROM {view}"))
                 .await
                 .unwrap_or_else(|e| {
-                    error!("Failed to fetch initial api_limit data: {}", e);
+                    error!("Failed to fetch initial {view} data: {}", e);
                     String::new()
                 });
             for line in snapshot.trim().lines() {
                 if line.is_empty() {
                     continue;
                 }
-                match serde_json::from_str::<ApiLimitRecord>(line) {
+                match serde_json::from_str::<T>(line) {
                     Ok(record) => {
-                        log::debug!("Initial api limit: {record:?}");
-                        ds.insert(record.ip);
+                        log::debug!("Initial row of {view}: {record:?}");
+                        apply(record, true);
                     }
                     Err(e) => {
-                        error!("Failed to parse ApiLimitRecord: {}", e);
+                        error!("Failed to parse row of {view}: {}", e);
                     }
                 }
             }
@@ -242,6 +551,7 @@ pub(crate) fn api_limit_table(client: Client) -> Arc<DashSet<String>> {
 
             match response {
                 Ok(resp) if resp.status().is_success() => {
+                    let _open = GaugeGuard::new(&METRICS.feldera_streams_open);
                     let stream = resp
                         .bytes_stream()
                         .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
@@ -253,62 +563,33 @@ pub(crate) fn api_limit_table(client: Client) -> Arc<DashSet<String>> {
 
                     while let Some(line) = decoder.next().await {
                         match line {
-                            Ok(line) => {
-                                match serde_json::from_str::<Record>(&line) {
-                                    Ok(record) => {
-                                        // walk record.json_data in reverse and return first `insert`
-                                        for change in
-                                            record.json_data.unwrap_or_else(|| vec![]).into_iter()
-                                        {
-                                            match change {
-                                                Change::Insert(value) => {
-                                                    let record =
-                                                        serde_json::from_value::<ApiLimitRecord>(
-                                                            value,
-                                                        );
-                                                    match record {
-                                                        Ok(record) => {
-                                                            log::debug!(
-                                                                "Received api limit for: {record:?}"
-                                                            );
-                                                            ds.insert(record.ip);
-                                                        }
-                                                        Err(e) => {
-                                                            error!("Failed to parse ApiLimitRecord: {}", e);
-                                                        }
-                                                    }
-                                                }
-                                                Change::Delete(value) => {
-                                                    let record =
-                                                        serde_json::from_value::<ApiLimitRecord>(
-                                                            value,
-                                                        );
-                                                    match record {
-                                                        Ok(record) => {
-                                                            log::debug!(
-                                                                "Received api limit removal for: {record:?}"
-                                                            );
-                                                            ds.remove(&record.ip);
-                                                        }
-                                                        Err(e) => {
-                                                            error!("Failed to parse ApiLimitRecord: {}", e);
-                                                        }
-                                                    }
-                                                }
+                            Ok(line) => match serde_json::from_str::<Record>(&line) {
+                                Ok(record) => {
+                                    for change in record.json_data.unwrap_or_else(|| vec![]) {
+                                        let (value, inserted) = match change {
+                                            Change::Insert(value) => (value, true),
+                                            Change::Delete(value) => (value, false),
+                                        };
+                                        match serde_json::from_value::<T>(value) {
+                                            Ok(record) => {
+                                                log::debug!(
+                                                    "Received change of {view} ({inserted}): {record:?}"
+                                                );
+                                                apply(record, inserted);
+                                            }
+                                            Err(e) => {
+                                                error!("Failed to parse row of {view}: {}", e);
                                             }
                                         }
                                     }
-                                    Err(e) => {
-                                        error!(
-                                                "Failed to parse change record from api_limit_reached: {}",
-                                                e
-                                            );
-                                        break;
-                                    }
                                 }
-                            }
+                                Err(e) => {
+                                    error!("Failed to parse change record from {view}: {}", e);
+                                    break;
+                                }
+                            },
                             Err(e) => {
-                                error!("Failed to decode line from api_limit_reached: {:?}", e);
+                                error!("Failed to decode line from {view}: {:?}", e);
                                 break;
                             }
                         }
@@ -319,10 +600,11 @@ pub(crate) fn api_limit_table(client: Client) -> Arc<DashSet<String>> {
                 }
             }
 
-            warn!("Lost connection to change stream at {url}, wait 10 seconds before retrying to get changes again");
-            tokio::time::sleep(Duration::from_secs(10)).await;
+            warn!(
+                "Lost connection to change stream at {url}, wait {:?} before retrying to get changes again",
+                *FELDERA_RECONNECT_DELAY
+            );
+            tokio::time::sleep(*FELDERA_RECONNECT_DELAY).await;
         }
     });
-
-    ds_clone
 }
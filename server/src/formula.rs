@@ -0,0 +1,97 @@
+//! Checks formulas before they are stored, so mistakes are reported to the user instead of ending
+//! up as an opaque error value computed by the pipeline.
+
+use xls_protocol::address;
+use xls_protocol::functions::{self, FUNCTIONS};
+
+use crate::error::XlsError;
+
+/// Most cells the ranges of a formula can cover together, like in `feldera/udf`.
+const MAX_RANGE_CELLS: i64 = 1000;
+
+/// Rejects formulas that call a function the pipeline does not know about or that use invalid or
+/// too large ranges.
+pub(crate) fn validate(raw_value: &str) -> Result<(), XlsError> {
+    if !raw_value.starts_with('=') {
+        return Ok(());
+    }
+    let names = names(raw_value);
+    for (function, end) in names.iter().copied() {
+        if !raw_value[end..].trim_start_matches(' ').starts_with('(')
+            || functions::function(function).is_some()
+        {
+            continue;
+        }
+        let upper = function.to_ascii_uppercase();
+        return Err(XlsError::InvalidField(
+            "raw_value",
+            if functions::function(&upper).is_some() {
+                format!("Function names are upper case, use {upper}() instead of {function}()")
+            } else {
+                format!(
+                    "Unknown function {function}(), supported are {}",
+                    FUNCTIONS.map(|function| function.name).join(", ")
+                )
+            },
+        ));
+    }
+
+    let mut range_cells = 0;
+    for pair in names.windows(2) {
+        let [(from, from_end), (to, _)] = pair else {
+            continue;
+        };
+        let is_range = raw_value[*from_end..]
+            .strip_prefix(':')
+            .is_some_and(|rest| rest.starts_with(to));
+        if !is_range {
+            continue;
+        }
+        let (Some(from_cell), Some(to_cell)) = (cell_position(from), cell_position(to)) else {
+            return Err(XlsError::InvalidField(
+                "raw_value",
+                format!("Invalid range {from}:{to}"),
+            ));
+        };
+        range_cells +=
+            ((from_cell.0 - to_cell.0).abs() + 1) * ((from_cell.1 - to_cell.1).abs() + 1);
+        if range_cells > MAX_RANGE_CELLS {
+            return Err(XlsError::InvalidField(
+                "raw_value",
+                format!("Ranges can cover at most {MAX_RANGE_CELLS} cells"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The (row, column) of a cell reference like `B10`, if it is in the sheet.
+fn cell_position(reference: &str) -> Option<(i64, i64)> {
+    address::parse_cell_name(reference).map(address::position)
+}
+
+/// Names (of functions or cells) outside of string literals, with the index after them.
+fn names(formula: &str) -> Vec<(&str, usize)> {
+    let mut names = vec![];
+    let mut quote = None;
+    let mut name_start = None;
+    for (idx, c) in formula.char_indices().chain([(formula.len(), ' ')]) {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            name_start.get_or_insert(idx);
+            continue;
+        }
+        if let Some(start) = name_start.take() {
+            names.push((&formula[start..idx], idx));
+        }
+        if c == '"' || c == '\'' {
+            quote = Some(c);
+        }
+    }
+    names
+}
@@ -0,0 +1,264 @@
+//! GraphQL API: queries for cells, the edit history of a cell and the statistics, and a
+//! subscription for cell changes.
+//!
+//! Queries are served at `/api/graphql` (with GraphiQL on `GET`), subscriptions over the
+//! `graphql-ws` protocol at `/api/graphql/ws`. Both require a token like the websocket if
+//! `WS_TOKENS` is set, and subscriptions count towards `WS_MAX_CONNECTIONS_PER_IP`.
+//!
+//! Every field that queries Feldera or subscribes to changes takes a token of the region rate
+//! limit of the client and adds [`FELDERA_COMPLEXITY`] to the complexity of the query, so aliasing
+//! a field can't multiply the queries a request sends. The queries wait in the same pool as the
+//! queries of regions.
+
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+
+use async_graphql::http::{GraphiQLSource, ALL_WEBSOCKET_PROTOCOLS};
+use async_graphql::{
+    Context, Data, EmptyMutation, Error, ErrorExtensions, Object, ResultExt, Schema, SimpleObject,
+    Subscription,
+};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{self, ConnectInfo, State};
+use axum::http::header::ORIGIN;
+use axum::http::HeaderMap;
+use axum::response::{Html, IntoResponse, Response};
+use axum::Extension;
+use futures::{Stream, StreamExt};
+use log::debug;
+use serde::Deserialize;
+use tokio_stream::wrappers::BroadcastStream;
+use xls_protocol::{Cell, CellStyle, Stats, CELL_IDS};
+
+use crate::error::XlsError;
+use crate::feldera::{adhoc_query, parse_rows};
+use crate::metrics::METRICS;
+use crate::spreadsheet::{client_ip, query_region, CLIENT_IP_HEADER};
+use crate::ws_auth::{self, WsParams};
+use crate::{origin, privacy, AppState};
+
+pub(crate) type XlsSchema = Schema<Query, EmptyMutation, Subscription>;
+
+/// Maximum number of edits a `cellHistory` query can return.
+const MAX_HISTORY: u32 = 100;
+
+/// Complexity of a field querying Feldera, in addition to its selection.
+const FELDERA_COMPLEXITY: usize = 10;
+
+/// Highest complexity of a request, enough for a few fields querying Feldera.
+const MAX_COMPLEXITY: usize = 50;
+
+pub(crate) fn schema(state: AppState) -> XlsSchema {
+    Schema::build(Query, EmptyMutation, Subscription)
+        .data(state)
+        .limit_depth(8)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// Who sent a request: the name of its token, or its IP without tokens.
+struct Client(String);
+
+impl Client {
+    fn identify(
+        params: &WsParams,
+        headers: &HeaderMap,
+        addr: SocketAddr,
+    ) -> Result<Self, XlsError> {
+        let client_ip = privacy::anonymize(client_ip(
+            headers.get(CLIENT_IP_HEADER).map(|ip| ip.as_bytes()),
+            addr,
+        ));
+        Ok(match ws_auth::identify(params, headers)? {
+            Some(name) => Client(format!("token:{name}")),
+            None => Client(client_ip),
+        })
+    }
+}
+
+/// Takes a token of the region rate limit of the client of `ctx`.
+fn rate_limit(ctx: &Context<'_>) -> async_graphql::Result<()> {
+    let state = ctx.data::<AppState>()?;
+    let Client(client) = ctx.data::<Client>()?;
+    if !state.region_limiter.check(client) {
+        let retry_after = state.region_limiter.retry_after(client);
+        return Err(XlsError::RateLimited(Some(retry_after.as_secs())).extend());
+    }
+    Ok(())
+}
+
+pub(crate) async fn graphiql() -> impl IntoResponse {
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/api/graphql")
+            .subscription_endpoint("/api/graphql/ws")
+            .finish(),
+    )
+}
+
+/// Executes a query, the schema is an extension of the route.
+pub(crate) async fn query(
+    headers: HeaderMap,
+    extract::Query(params): extract::Query<WsParams>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(schema): Extension<XlsSchema>,
+    request: GraphQLRequest,
+) -> Result<GraphQLResponse, XlsError> {
+    let client = Client::identify(&params, &headers, addr)?;
+    Ok(schema
+        .execute(request.into_inner().data(client))
+        .await
+        .into())
+}
+
+/// Upgrades to a `graphql-ws` websocket after the same checks as the spreadsheet websocket.
+pub(crate) async fn subscriptions(
+    ws: WebSocketUpgrade,
+    protocol: GraphQLProtocol,
+    headers: HeaderMap,
+    extract::Query(params): extract::Query<WsParams>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    Extension(schema): Extension<XlsSchema>,
+) -> Result<Response, XlsError> {
+    let page_origin = headers.get(ORIGIN).and_then(|origin| origin.to_str().ok());
+    if !origin::is_allowed(page_origin) {
+        debug!("{addr} subscribed from a page on {page_origin:?}");
+        return Err(XlsError::OriginNotAllowed);
+    }
+    if state.drain.is_cancelled() {
+        return Err(XlsError::Draining);
+    }
+    let client = Client::identify(&params, &headers, addr)?;
+    let client_ip = privacy::anonymize(client_ip(
+        headers.get(CLIENT_IP_HEADER).map(|ip| ip.as_bytes()),
+        addr,
+    ));
+    let Some(subscriber) = state.subscribers.register(&client_ip) else {
+        debug!("{addr} has too many connections open");
+        METRICS
+            .ws_connections_rejected_total
+            .fetch_add(1, Ordering::Relaxed);
+        return Err(XlsError::RateLimited(None));
+    };
+    Ok(ws
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| async move {
+            // The connection counts until the socket is closed.
+            let _subscriber = subscriber;
+            let mut data = Data::default();
+            data.insert(client);
+            GraphQLWebSocket::new(socket, schema, protocol)
+                .with_data(data)
+                .serve()
+                .await
+        })
+        .into_response())
+}
+
+impl ErrorExtensions for XlsError {
+    fn extend(&self) -> Error {
+        Error::new(self.to_string()).extend_with(|_, e| e.set("code", self.code()))
+    }
+}
+
+/// A past value of a cell.
+#[derive(Deserialize, SimpleObject, Debug)]
+pub(crate) struct CellEdit {
+    id: i64,
+    raw_value: String,
+    background: i32,
+    #[serde(default)]
+    style: CellStyle,
+    /// When the edit was made (UTC).
+    ts: String,
+}
+
+pub(crate) struct Query;
+
+#[Object]
+impl Query {
+    /// Cells with content with an id in `[from, to)`.
+    #[graphql(complexity = "FELDERA_COMPLEXITY + child_complexity")]
+    async fn cells(
+        &self,
+        ctx: &Context<'_>,
+        from: i64,
+        to: i64,
+    ) -> async_graphql::Result<Vec<Cell>> {
+        rate_limit(ctx)?;
+        let state = ctx.data::<AppState>()?;
+        let region = query_region(from, to).extend()?;
+        let snapshot = state.spreadsheet_view.query(region).await.extend()?;
+        parse_rows(&snapshot).extend()
+    }
+
+    /// Edits of a cell, most recent first.
+    #[graphql(complexity = "FELDERA_COMPLEXITY + child_complexity")]
+    async fn cell_history(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        #[graphql(default = 20)] limit: u32,
+    ) -> async_graphql::Result<Vec<CellEdit>> {
+        rate_limit(ctx)?;
+        let state = ctx.data::<AppState>()?;
+        if !CELL_IDS.contains(&id) {
+            return Err(XlsError::Validation(String::from("Invalid cell ID")).extend());
+        }
+        let sql = format!(
+            "SELECT id, raw_value, background, style, ts FROM spreadsheet_data WHERE id = {id} ORDER BY ts DESC LIMIT {}",
+            limit.clamp(1, MAX_HISTORY)
+        );
+        let history = state
+            .spreadsheet_view
+            .run_adhoc(adhoc_query(state.http_client.clone(), &sql))
+            .await
+            .extend()?;
+        parse_rows(&history).extend()
+    }
+
+    /// The current statistics.
+    #[graphql(complexity = "FELDERA_COMPLEXITY + child_complexity")]
+    async fn stats(&self, ctx: &Context<'_>) -> async_graphql::Result<Stats> {
+        rate_limit(ctx)?;
+        let state = ctx.data::<AppState>()?;
+        let stats = state
+            .spreadsheet_view
+            .run_adhoc(adhoc_query(
+                state.http_client.clone(),
+                "SELECT * FROM spreadsheet_statistics",
+            ))
+            .await
+            .extend()?;
+        parse_rows(&stats)
+            .extend()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| XlsError::ParseError(String::from("No statistics available")).extend())
+    }
+}
+
+pub(crate) struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Changes to cells with an id in `[from, to)`.
+    #[graphql(complexity = "FELDERA_COMPLEXITY + child_complexity")]
+    async fn cell_changes(
+        &self,
+        ctx: &Context<'_>,
+        from: i64,
+        to: i64,
+    ) -> async_graphql::Result<impl Stream<Item = Cell>> {
+        rate_limit(ctx)?;
+        let state = ctx.data::<AppState>()?;
+        let region = query_region(from, to).extend()?;
+        let changes = BroadcastStream::new(state.xls_subscription.subscribe());
+        Ok(changes.filter_map(move |change| async move {
+            let cell = serde_json::from_str::<Cell>(&change.ok()?.ok()?).ok()?;
+            (region.from..region.to).contains(&cell.id).then_some(cell)
+        }))
+    }
+}
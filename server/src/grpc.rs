@@ -0,0 +1,218 @@
+//! gRPC service (see `proto/spreadsheet.proto`) for server-to-server integrations, served on
+//! `GRPC_ADDRESS` next to the HTTP API.
+//!
+//! Calls pass the IP filter and, if `WS_TOKENS` is set, need one of the tokens as
+//! `authorization: Bearer <token>` metadata. Streams count towards `WS_MAX_CONNECTIONS_PER_IP`.
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::LazyLock;
+
+use futures::{Stream, StreamExt};
+use log::{error, info};
+use tokio_stream::wrappers::BroadcastStream;
+use tonic::{Request, Response, Status};
+use xls_protocol::{CellStyle, UpdateRequest, FIRST_SHEET};
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::feldera::parse_rows;
+use crate::ip_filter;
+use crate::privacy;
+use crate::spreadsheet::{client_ip, query_region, update_batch, CLIENT_IP_HEADER};
+use crate::ws_auth::{self, WsParams};
+use crate::AppState;
+
+pub(crate) mod proto {
+    tonic::include_proto!("xls.v1");
+}
+
+use proto::spreadsheet_server::{Spreadsheet, SpreadsheetServer};
+
+/// Address of the gRPC service, an empty string disables it.
+static GRPC_ADDRESS: LazyLock<String> =
+    LazyLock::new(|| env_or("GRPC_ADDRESS", String::from("0.0.0.0:50051")));
+
+/// Tells clients of a throttled call when to try again, like the `Retry-After` header of HTTP.
+const RETRY_AFTER_METADATA: &str = "retry-after";
+
+impl From<XlsError> for Status {
+    fn from(e: XlsError) -> Self {
+        let message = e.to_string();
+        let retry_after = match e {
+            XlsError::RateLimited(Some(secs)) | XlsError::Busy(secs) => Some(secs),
+            _ => None,
+        };
+        let mut status = match e {
+            XlsError::FelderaUnavailable(_) | XlsError::ChangesMissed(_) | XlsError::Draining => {
+                Status::unavailable(message)
+            }
+            XlsError::QueryTimeout(_) => Status::deadline_exceeded(message),
+            XlsError::ParseError(_) => Status::internal(message),
+            XlsError::RateLimited(_) | XlsError::Busy(_) => Status::resource_exhausted(message),
+            XlsError::Validation(_) | XlsError::InvalidField(_, _) => {
+                Status::invalid_argument(message)
+            }
+            XlsError::Unauthorized => Status::unauthenticated(message),
+            XlsError::Forbidden | XlsError::OriginNotAllowed | XlsError::ReadOnly => {
+                Status::permission_denied(message)
+            }
+            XlsError::NotFound(_) => Status::not_found(message),
+        };
+        if let Some(secs) = retry_after {
+            status
+                .metadata_mut()
+                .insert(RETRY_AFTER_METADATA, secs.into());
+        }
+        status
+    }
+}
+
+impl From<xls_protocol::Cell> for proto::Cell {
+    fn from(cell: xls_protocol::Cell) -> Self {
+        proto::Cell {
+            id: cell.id,
+            background: cell.background,
+            raw_value: cell.raw_value,
+            computed_value: cell.computed_value,
+            cycle: cell.cycle.unwrap_or_default(),
+            value_type: proto::ValueType::from(cell.value_type).into(),
+        }
+    }
+}
+
+impl From<xls_protocol::ValueType> for proto::ValueType {
+    fn from(value_type: xls_protocol::ValueType) -> Self {
+        match value_type {
+            xls_protocol::ValueType::String => proto::ValueType::String,
+            xls_protocol::ValueType::Number => proto::ValueType::Number,
+            xls_protocol::ValueType::Bool => proto::ValueType::Bool,
+            xls_protocol::ValueType::Date => proto::ValueType::Date,
+            xls_protocol::ValueType::Error => proto::ValueType::Error,
+        }
+    }
+}
+
+struct SpreadsheetService {
+    state: AppState,
+}
+
+/// The anonymized IP of the client of `request`, if it may call the service.
+fn authorize<T>(request: &Request<T>) -> Result<String, XlsError> {
+    let addr = request
+        .remote_addr()
+        .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+    let header = request
+        .metadata()
+        .get(CLIENT_IP_HEADER)
+        .map(|ip| ip.as_bytes());
+    let client_ip = client_ip(header, addr);
+    if !ip_filter::is_allowed(&client_ip) {
+        return Err(XlsError::Forbidden);
+    }
+    ws_auth::identify(
+        &WsParams::default(),
+        &request.metadata().clone().into_headers(),
+    )?;
+    Ok(privacy::anonymize(client_ip))
+}
+
+#[tonic::async_trait]
+impl Spreadsheet for SpreadsheetService {
+    async fn read_range(
+        &self,
+        request: Request<proto::Range>,
+    ) -> Result<Response<proto::Cells>, Status> {
+        authorize(&request)?;
+        let range = request.into_inner();
+        let region = query_region(range.from, range.to)?;
+        let snapshot = self.state.spreadsheet_view.query(region).await?;
+        let cells = parse_rows::<xls_protocol::Cell>(&snapshot)?;
+        Ok(Response::new(proto::Cells {
+            cells: cells.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    type StreamChangesStream = Pin<Box<dyn Stream<Item = Result<proto::Cell, Status>> + Send>>;
+
+    async fn stream_changes(
+        &self,
+        request: Request<proto::Range>,
+    ) -> Result<Response<Self::StreamChangesStream>, Status> {
+        let client_ip = authorize(&request)?;
+        let range = request.into_inner();
+        let region = query_region(range.from, range.to)?;
+        let subscriber = self
+            .state
+            .subscribers
+            .register(&client_ip)
+            .ok_or(XlsError::RateLimited(None))?;
+        let changes = BroadcastStream::new(self.state.xls_subscription.subscribe());
+        let cells = changes.filter_map(move |change| {
+            // The stream counts as a connection until it is dropped.
+            let _subscriber = &subscriber;
+            async move {
+                let cell = serde_json::from_str::<xls_protocol::Cell>(&change.ok()?.ok()?).ok()?;
+                (region.from..region.to)
+                    .contains(&cell.id)
+                    .then(|| cell.into())
+                    .map(Ok)
+            }
+        });
+        Ok(Response::new(Box::pin(cells)))
+    }
+
+    async fn batch_update(
+        &self,
+        request: Request<proto::BatchUpdateRequest>,
+    ) -> Result<Response<proto::BatchUpdateResponse>, Status> {
+        let client_ip = authorize(&request)?;
+        if self.state.api_limits.contains(&client_ip) {
+            return Err(XlsError::RateLimited(None).into());
+        }
+
+        let updates = request
+            .into_inner()
+            .updates
+            .into_iter()
+            .map(|update| UpdateRequest {
+                id: update.id,
+                raw_value: update.raw_value,
+                background: update.background,
+                style: CellStyle::default(),
+                ttl_secs: update.ttl_secs,
+                sheet: FIRST_SHEET,
+            })
+            .collect();
+        let limiter = &self.state.update_limiter;
+        let (updated, _) = update_batch(&self.state, client_ip, updates, limiter).await?;
+        Ok(Response::new(proto::BatchUpdateResponse {
+            updated: updated as u32,
+        }))
+    }
+}
+
+/// Starts the gRPC service unless `GRPC_ADDRESS` is empty.
+pub(crate) fn spawn_server(state: AppState) {
+    if GRPC_ADDRESS.is_empty() {
+        return;
+    }
+    let addr: SocketAddr = match GRPC_ADDRESS.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!("Invalid GRPC_ADDRESS {}: {e}", &*GRPC_ADDRESS);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        info!("gRPC service listening on {addr}");
+        let result = tonic::transport::Server::builder()
+            .add_service(SpreadsheetServer::new(SpreadsheetService { state }))
+            .serve(addr)
+            .await;
+        if let Err(e) = result {
+            error!("gRPC service failed: {e}");
+        }
+    });
+}
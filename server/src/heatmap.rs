@@ -0,0 +1,68 @@
+//! Where the sheet is written right now, maintained by the `write_heatmap` view.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::Json;
+use serde::Deserialize;
+use xls_protocol::address::COLUMNS;
+use xls_protocol::{ErrorResponse, Region, RegionActivity, HEATMAP_REGION_ROWS};
+
+use crate::error::XlsError;
+use crate::feldera::{adhoc_query, parse_rows};
+use crate::AppState;
+
+/// How long the heatmap is answered from memory, clients poll it.
+const MAX_AGE: Duration = Duration::from_secs(5);
+
+/// The heatmap queried last, with when.
+static LAST: Mutex<Option<(Instant, Vec<RegionActivity>)>> = Mutex::new(None);
+
+/// A row of the `write_heatmap` view.
+#[derive(Deserialize, Debug)]
+struct HeatmapRow {
+    region: i64,
+    writes: u64,
+}
+
+impl From<HeatmapRow> for RegionActivity {
+    fn from(row: HeatmapRow) -> Self {
+        let cells = HEATMAP_REGION_ROWS * COLUMNS;
+        RegionActivity {
+            region: Region::new(row.region * cells, (row.region + 1) * cells),
+            writes: row.writes,
+        }
+    }
+}
+
+/// Lists the regions of `HEATMAP_REGION_ROWS` rows that were written in the last minute, with
+/// how many cells were written. Regions without writes are left out.
+#[utoipa::path(
+    get,
+    path = "/api/heatmap",
+    responses(
+        (status = 200, description = "The regions written in the last minute, ordered by region", body = [RegionActivity]),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn heatmap(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<RegionActivity>>, XlsError> {
+    if let Some((at, heatmap)) = LAST.lock().unwrap().as_ref() {
+        if at.elapsed() < MAX_AGE {
+            return Ok(Json(heatmap.clone()));
+        }
+    }
+    let rows = adhoc_query(
+        state.http_client,
+        "SELECT region, writes FROM write_heatmap ORDER BY region",
+    )
+    .await?;
+    let heatmap: Vec<RegionActivity> = parse_rows::<HeatmapRow>(&rows)?
+        .into_iter()
+        .map(RegionActivity::from)
+        .collect();
+    *LAST.lock().unwrap() = Some((Instant::now(), heatmap.clone()));
+    Ok(Json(heatmap))
+}
@@ -0,0 +1,134 @@
+//! Cell updates that couldn't be sent to the backend, retried in the background so edits made while
+//! Feldera is unavailable aren't lost. The client is answered with `202 Accepted` then.
+//!
+//! The queue holds at most `INGEST_QUEUE_MAX` rows, updates failing while it is full are rejected
+//! like before. If `INGEST_QUEUE_PATH` is set, the queue is stored in that file, one row per line,
+//! and survives restarts of the server.
+//!
+//! The order of the retried rows doesn't matter, the view keeps the row of a cell with the latest
+//! `ts`.
+
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::backend::SpreadsheetBackend;
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::metrics::METRICS;
+use crate::persist;
+
+static INGEST_QUEUE_MAX: LazyLock<usize> = LazyLock::new(|| env_or("INGEST_QUEUE_MAX", 10_000));
+/// File the queue is stored in, the queue is only kept in memory if it is empty.
+static INGEST_QUEUE_PATH: LazyLock<String> =
+    LazyLock::new(|| env_or("INGEST_QUEUE_PATH", String::new()));
+static INGEST_RETRY_INTERVAL: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("INGEST_RETRY_INTERVAL_SECS", 5)));
+
+/// Rows retried with a single request.
+const RETRY_BATCH_SIZE: usize = 1000;
+
+/// Whether rows were sent to the backend right away or queued.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Ingested {
+    Sent,
+    Queued,
+}
+
+impl Ingested {
+    /// The status of the response to the client.
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            Ingested::Sent => StatusCode::OK,
+            Ingested::Queued => StatusCode::ACCEPTED,
+        }
+    }
+}
+
+pub(crate) struct IngestQueue {
+    backend: Arc<dyn SpreadsheetBackend>,
+    rows: Mutex<VecDeque<Value>>,
+}
+
+impl IngestQueue {
+    /// Loads the rows queued before the server restarted and retries them in the background.
+    pub(crate) fn spawn(backend: Arc<dyn SpreadsheetBackend>) -> Arc<Self> {
+        let queue = Arc::new(IngestQueue {
+            backend,
+            rows: Mutex::new(load()),
+        });
+        queue.update_depth(queue.rows.lock().unwrap().len());
+        let retried = queue.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(*INGEST_RETRY_INTERVAL).await;
+                retried.retry().await;
+            }
+        });
+        queue
+    }
+
+    /// Inserts cell updates into the backend, queues them if that fails and the queue has room.
+    pub(crate) async fn insert<T: Serialize>(&self, rows: &[T]) -> Result<Ingested, XlsError> {
+        let rows: Vec<Value> = rows
+            .iter()
+            .map(|row| serde_json::to_value(row).unwrap())
+            .collect();
+        let error = match self.backend.insert_cells(&rows).await {
+            Ok(()) => return Ok(Ingested::Sent),
+            Err(e) => e,
+        };
+        let mut queued = self.rows.lock().unwrap();
+        if queued.len() + rows.len() > *INGEST_QUEUE_MAX {
+            warn!("Failed to insert cell updates and the retry queue is full: {error}");
+            return Err(error);
+        }
+        persist::append(&INGEST_QUEUE_PATH, &rows);
+        queued.extend(rows);
+        self.update_depth(queued.len());
+        debug!("Queued cell updates to retry them: {error}");
+        Ok(Ingested::Queued)
+    }
+
+    /// Sends the oldest queued rows to the backend.
+    async fn retry(&self) {
+        let batch: Vec<Value> = {
+            let queued = self.rows.lock().unwrap();
+            queued.iter().take(RETRY_BATCH_SIZE).cloned().collect()
+        };
+        if batch.is_empty() {
+            return;
+        }
+        if let Err(e) = self.backend.insert_cells(&batch).await {
+            debug!("Failed to retry {} queued cell updates: {e}", batch.len());
+            return;
+        }
+        // Rows are only removed here, so the batch is still at the front.
+        let mut queued = self.rows.lock().unwrap();
+        queued.drain(..batch.len());
+        persist::store(&INGEST_QUEUE_PATH, &*queued);
+        self.update_depth(queued.len());
+        info!("Sent {} queued cell updates to the backend", batch.len());
+    }
+
+    fn update_depth(&self, depth: usize) {
+        METRICS
+            .ingest_queue_depth
+            .store(depth as i64, Ordering::Relaxed);
+    }
+}
+
+/// The rows stored in `INGEST_QUEUE_PATH`.
+fn load() -> VecDeque<Value> {
+    let rows: VecDeque<Value> = persist::load(&INGEST_QUEUE_PATH).into();
+    if !rows.is_empty() {
+        info!("Loaded {} queued cell updates", rows.len());
+    }
+    rows
+}
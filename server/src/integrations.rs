@@ -0,0 +1,295 @@
+//! Integrations let external systems push values into cells, e.g. a job updating the stars of a
+//! repository every hour, so the sheet can serve as a live dashboard.
+//!
+//! Each integration can only write the regions it was set up for, with a quota of updated cells
+//! per minute. Its updates are stored with `integration:<name>` as their IP, so they can be told
+//! apart from the edits of users. Integrations are managed with the admin endpoints under
+//! `/api/admin/integrations`, they are kept in memory and stored in `INTEGRATIONS_PATH` if it is
+//! set.
+
+use std::sync::{Arc, LazyLock, RwLock};
+
+use axum::extract::{Path, Query, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use log::{debug, info};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use xls_protocol::{ErrorResponse, Region, UpdateRequest};
+
+use crate::admin::constant_time_eq;
+use crate::config::env_or;
+use crate::embed_tokens::validate_regions;
+use crate::error::{JsonBody, XlsError};
+use crate::persist;
+use crate::rate_limit::RateLimiter;
+use crate::spreadsheet::{update_batch, BatchUpdateResponse};
+use crate::AppState;
+
+static INTEGRATIONS_PATH: LazyLock<String> =
+    LazyLock::new(|| env_or("INTEGRATIONS_PATH", String::new()));
+
+fn default_updates_per_min() -> u32 {
+    60
+}
+
+/// An external system that can write some regions of the sheet.
+#[derive(Serialize, Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub(crate) struct Integration {
+    /// Identifies the integration, its updates are stored with `integration:<name>` as IP.
+    name: String,
+    /// Sent as `Authorization: Bearer <token>` or `?token=`, generated when the integration is
+    /// added.
+    #[serde(default)]
+    token: String,
+    /// The cells the integration can write.
+    regions: Vec<Region>,
+    /// Cells the integration can update per minute.
+    #[serde(default = "default_updates_per_min")]
+    updates_per_min: u32,
+}
+
+impl Integration {
+    fn validate(&self) -> Result<(), XlsError> {
+        if self.name.trim().is_empty() {
+            return Err(XlsError::InvalidField(
+                "name",
+                String::from("The name must not be empty"),
+            ));
+        }
+        validate_regions(&self.regions)?;
+        if self.updates_per_min == 0 {
+            return Err(XlsError::InvalidField(
+                "updates_per_min",
+                String::from("The quota must be positive"),
+            ));
+        }
+        Ok(())
+    }
+
+    fn can_write(&self, id: i64) -> bool {
+        self.regions
+            .iter()
+            .any(|region| (region.from..region.to).contains(&id))
+    }
+}
+
+/// An integration and its quota.
+struct Writer {
+    integration: Integration,
+    /// Refills the quota of a minute over the minute.
+    quota: RateLimiter,
+}
+
+impl Writer {
+    fn new(integration: Integration) -> Self {
+        let per_min = integration.updates_per_min as f64;
+        let quota = RateLimiter::new(per_min / 60.0, per_min);
+        Writer { integration, quota }
+    }
+}
+
+/// The integrations that were added.
+pub(crate) struct Integrations {
+    writers: RwLock<Vec<Arc<Writer>>>,
+}
+
+impl Integrations {
+    /// Loads the integrations stored in `INTEGRATIONS_PATH`.
+    pub(crate) fn load() -> Arc<Self> {
+        let integrations: Vec<Integration> = persist::load(&INTEGRATIONS_PATH);
+        if !integrations.is_empty() {
+            info!("Loaded {} integrations", integrations.len());
+        }
+        Arc::new(Integrations {
+            writers: RwLock::new(
+                integrations
+                    .into_iter()
+                    .map(Writer::new)
+                    .map(Arc::new)
+                    .collect(),
+            ),
+        })
+    }
+
+    fn find(&self, token: &str) -> Option<Arc<Writer>> {
+        self.writers
+            .read()
+            .unwrap()
+            .iter()
+            .find(|writer| constant_time_eq(token, &writer.integration.token))
+            .cloned()
+    }
+
+    fn integrations(&self) -> Vec<Integration> {
+        let writers = self.writers.read().unwrap();
+        writers
+            .iter()
+            .map(|writer| writer.integration.clone())
+            .collect()
+    }
+
+    fn add(&self, integration: Integration) -> Result<(), XlsError> {
+        let mut writers = self.writers.write().unwrap();
+        if writers
+            .iter()
+            .any(|writer| writer.integration.name == integration.name)
+        {
+            return Err(XlsError::InvalidField(
+                "name",
+                format!("Integration {} already exists", integration.name),
+            ));
+        }
+        writers.push(Arc::new(Writer::new(integration)));
+        persist::store(
+            &INTEGRATIONS_PATH,
+            writers.iter().map(|writer| &writer.integration),
+        );
+        Ok(())
+    }
+
+    /// Removes an integration, returns `false` if it doesn't exist.
+    fn remove(&self, name: &str) -> bool {
+        let mut writers = self.writers.write().unwrap();
+        let count = writers.len();
+        writers.retain(|writer| writer.integration.name != name);
+        persist::store(
+            &INTEGRATIONS_PATH,
+            writers.iter().map(|writer| &writer.integration),
+        );
+        writers.len() < count
+    }
+}
+
+/// Lists the integrations.
+#[utoipa::path(
+    get,
+    path = "/api/admin/integrations",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = [Integration]),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn list(State(state): State<AppState>) -> Json<Vec<Integration>> {
+    Json(state.integrations.integrations())
+}
+
+/// Adds an integration that can write the regions, its token is generated.
+#[utoipa::path(
+    post,
+    path = "/api/admin/integrations",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body = Integration,
+    responses(
+        (status = 201, description = "The integration was added", body = Integration),
+        (status = 400, body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn add(
+    State(state): State<AppState>,
+    JsonBody(mut integration): JsonBody<Integration>,
+) -> Result<(StatusCode, Json<Integration>), XlsError> {
+    integration.validate()?;
+    integration.name = integration.name.trim().to_string();
+    integration.token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    state.integrations.add(integration.clone())?;
+    info!(
+        "Added integration {} for {} regions",
+        integration.name,
+        integration.regions.len()
+    );
+    Ok((StatusCode::CREATED, Json(integration)))
+}
+
+/// Removes an integration, its token stops working.
+#[utoipa::path(
+    delete,
+    path = "/api/admin/integrations/{name}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("name" = String, Path, description = "Name of the integration")),
+    responses(
+        (status = 204, description = "The integration was removed"),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn remove(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, XlsError> {
+    if !state.integrations.remove(&name) {
+        return Err(XlsError::NotFound(format!(
+            "Integration {name} does not exist"
+        )));
+    }
+    info!("Removed integration {name}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct PushParams {
+    token: Option<String>,
+}
+
+/// Writes values pushed by an integration into its cells, at most 100 at once.
+#[utoipa::path(
+    post,
+    path = "/api/integrations/cells",
+    params(
+        ("token" = Option<String>, Query, description = "Token of the integration, if it can't send it as `Authorization: Bearer <token>`"),
+    ),
+    request_body = [UpdateRequest],
+    responses(
+        (status = 200, description = "The updates were sent to Feldera", body = BatchUpdateResponse),
+        (status = 202, description = "Feldera is unavailable, the updates are sent later", body = BatchUpdateResponse),
+        (status = 400, description = "A cell the integration can't write, or too many updates", body = ErrorResponse),
+        (status = 401, description = "The token is invalid", body = ErrorResponse),
+        (status = 403, description = "The deployment is read-only", body = ErrorResponse),
+        (status = 429, description = "The quota of the integration is used up", body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn push(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<PushParams>,
+    JsonBody(updates): JsonBody<Vec<UpdateRequest>>,
+) -> Result<(StatusCode, Json<BatchUpdateResponse>), XlsError> {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let writer = params
+        .token
+        .as_deref()
+        .or(bearer)
+        .and_then(|token| state.integrations.find(token))
+        .ok_or(XlsError::Unauthorized)?;
+    let integration = &writer.integration;
+    if let Some(update) = updates.iter().find(|u| !integration.can_write(u.id)) {
+        return Err(XlsError::InvalidField(
+            "id",
+            format!("The integration can't write cell {}", update.id),
+        ));
+    }
+    if updates.len() > integration.updates_per_min as usize {
+        return Err(XlsError::InvalidField(
+            "updates",
+            format!(
+                "The integration can update at most {} cells per minute",
+                integration.updates_per_min
+            ),
+        ));
+    }
+    // The quota replaces the update limit of IPs, which is lower than some quotas.
+    let ip = format!("integration:{}", integration.name);
+    let (updated, ingested) = update_batch(&state, ip, updates, &writer.quota).await?;
+    debug!("Integration {} updated {updated} cells", integration.name);
+    Ok((ingested.status(), Json(BatchUpdateResponse { updated })))
+}
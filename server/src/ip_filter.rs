@@ -0,0 +1,74 @@
+//! CIDR allow and deny lists for the endpoints that read and edit cells.
+//!
+//! A private deployment can restrict editing to an office network with `IP_ALLOW_LIST`, the
+//! public one can block abusive ranges with `IP_DENY_LIST`. Both are comma-separated lists of
+//! networks (`10.0.0.0/8`) or addresses (`192.0.2.1`). The deny list takes precedence.
+
+use std::env::var;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::LazyLock;
+
+use axum::extract::{ConnectInfo, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ipnet::IpNet;
+use log::warn;
+
+use crate::error::XlsError;
+use crate::spreadsheet::{client_ip, CLIENT_IP_HEADER};
+
+static IP_ALLOW_LIST: LazyLock<Vec<IpNet>> = LazyLock::new(|| networks("IP_ALLOW_LIST", ""));
+static IP_DENY_LIST: LazyLock<Vec<IpNet>> = LazyLock::new(|| networks("IP_DENY_LIST", ""));
+
+/// The networks in the environment variable `name`, `default` if it is unset.
+pub(crate) fn networks(name: &str, default: &str) -> Vec<IpNet> {
+    var(name)
+        .unwrap_or_else(|_| String::from(default))
+        .split(',')
+        .map(str::trim)
+        .filter(|network| !network.is_empty())
+        .filter_map(|network| {
+            let parsed = network
+                .parse::<IpNet>()
+                .or_else(|_| network.parse::<IpAddr>().map(IpNet::from));
+            if parsed.is_err() {
+                warn!("Ignoring invalid network {network:?} in {name}");
+            }
+            parsed.ok()
+        })
+        .collect()
+}
+
+/// Whether a client IP (as returned by [`client_ip`]) may use the spreadsheet.
+///
+/// IPs that can't be parsed are only allowed if there is no allow list.
+pub(crate) fn is_allowed(ip: &str) -> bool {
+    let Ok(ip) = ip.parse::<IpAddr>() else {
+        return IP_ALLOW_LIST.is_empty();
+    };
+    // IPv4 clients of a dual-stack listener show up as `::ffff:a.b.c.d`.
+    let ip = ip.to_canonical();
+    if IP_DENY_LIST.iter().any(|network| network.contains(&ip)) {
+        return false;
+    }
+    IP_ALLOW_LIST.is_empty() || IP_ALLOW_LIST.iter().any(|network| network.contains(&ip))
+}
+
+/// Middleware that rejects clients outside of the allow list or inside the deny list with `403`.
+pub(crate) async fn filter_ips(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = client_ip(
+        request
+            .headers()
+            .get(CLIENT_IP_HEADER)
+            .map(|ip| ip.as_bytes()),
+        addr,
+    );
+    if !is_allowed(&ip) {
+        return XlsError::Forbidden.into_response();
+    }
+    next.run(request).await
+}
@@ -0,0 +1,123 @@
+//! Measures the time from a cell update to its change arriving from Feldera.
+//!
+//! Updates are stamped with `ts` when the server receives them, and `spreadsheet_view` carries
+//! the `ts` of the latest update of a cell. When a change arrives, its age is recorded in the
+//! `xls_edit_latency_seconds` histogram. Changes of cells that were recomputed because a cell
+//! they mention changed keep the `ts` of their own update and are only recorded once.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::Json;
+use chrono::{NaiveDateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use xls_protocol::ErrorResponse;
+
+use crate::error::XlsError;
+use crate::metrics::{HISTOGRAM_BUCKETS, METRICS};
+
+/// Older changes are recomputations or replays, not the result of a recent update.
+const MAX_LATENCY: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct CellTimestamp {
+    id: i64,
+    ts: Option<String>,
+}
+
+pub(crate) fn parse_timestamp(ts: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(ts, "%Y-%m-%d %H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(ts, "%Y-%m-%dT%H:%M:%S%.f"))
+        .ok()
+}
+
+/// Records the latency of every change of `spreadsheet_view`.
+pub(crate) fn spawn_recorder(mut changes: Receiver<Result<String, XlsError>>) {
+    tokio::spawn(async move {
+        // The last recorded `ts` per cell, to skip recomputations.
+        let mut recorded: HashMap<i64, NaiveDateTime> = HashMap::new();
+        loop {
+            let change = match changes.recv().await {
+                Ok(Ok(change)) => change,
+                Ok(Err(_)) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Latency recorder fell behind, skipped {skipped} changes");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let Ok(CellTimestamp { id, ts: Some(ts) }) = serde_json::from_str(&change) else {
+                continue;
+            };
+            let Some(ts) = parse_timestamp(&ts) else {
+                continue;
+            };
+            let now = Utc::now().naive_utc();
+            let Ok(latency) = (now - ts).to_std() else {
+                continue;
+            };
+            if latency > MAX_LATENCY || recorded.get(&id) == Some(&ts) {
+                continue;
+            }
+            METRICS.edit_latency_seconds.observe(latency);
+            recorded.insert(id, ts);
+            if recorded.len() > 10_000 {
+                let max_age = chrono::Duration::from_std(MAX_LATENCY).unwrap();
+                recorded.retain(|_, ts| now - *ts <= max_age);
+            }
+        }
+    });
+}
+
+/// Summary of the `xls_edit_latency_seconds` histogram.
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct LatencySummary {
+    /// Number of recorded changes.
+    count: u64,
+    /// Average latency in seconds.
+    mean: f64,
+    /// Upper bounds of the buckets containing the 50th, 90th and 99th percentile, in seconds,
+    /// `null` if it is above the largest bucket or nothing was recorded.
+    p50: Option<f64>,
+    p90: Option<f64>,
+    p99: Option<f64>,
+}
+
+/// Latency from cell updates to their changes arriving from Feldera.
+#[utoipa::path(
+    get,
+    path = "/api/admin/latency",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = LatencySummary),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn summary() -> Json<LatencySummary> {
+    let counts = METRICS.edit_latency_seconds.counts();
+    let count: u64 = counts.iter().sum();
+    let percentile = |p: f64| {
+        let rank = (count as f64 * p).ceil().max(1.0) as u64;
+        let mut cumulative = 0;
+        counts.iter().enumerate().find_map(|(idx, bucket)| {
+            cumulative += bucket;
+            (cumulative >= rank).then(|| HISTOGRAM_BUCKETS.get(idx).copied())
+        })?
+    };
+    let mean = if count == 0 {
+        0.0
+    } else {
+        METRICS.edit_latency_seconds.sum().as_secs_f64() / count as f64
+    };
+    Json(LatencySummary {
+        count,
+        mean,
+        p50: percentile(0.5),
+        p90: percentile(0.9),
+        p99: percentile(0.99),
+    })
+}
@@ -1,69 +1,257 @@
+use crate::error::XlsError;
 use crate::spreadsheet::SpreadSheetView;
-use crate::stats::XlsError;
+use axum::http::header::RETRY_AFTER;
 use axum::http::Method;
-use axum::{routing::get, routing::post, Router};
-use dashmap::DashSet;
+use axum::middleware;
+use axum::{routing::get, routing::post, Extension, Router};
+use dashmap::{DashMap, DashSet};
 use reqwest::Client;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast::Sender;
+use tokio_util::sync::CancellationToken;
 use tower_http::cors::{AllowMethods, Any, CorsLayer};
 
+mod admin;
+mod backend;
+mod cached_ranges;
+mod coalesce;
+mod column_labels;
+mod column_statistics;
+mod column_values;
+mod comments;
+mod config;
+mod connectors;
+mod diff;
+mod embed_tokens;
+mod error;
+mod error_log;
+mod expiry;
+mod fanout;
 mod feldera;
+mod formula;
+mod graphql;
+mod grpc;
+mod heatmap;
+mod ingest_queue;
+mod integrations;
+mod ip_filter;
+mod latency;
+mod meta;
+mod metrics;
+mod moderation;
+mod moderator_actions;
+mod openapi;
+mod origin;
+mod persist;
+mod pipeline;
+mod presenter;
+mod privacy;
+mod query_pool;
+mod rate_limit;
+mod retention;
+mod scratch;
+mod search;
+mod seed;
 mod spreadsheet;
 mod stats;
+mod subscribers;
+mod volatile;
+mod ws_auth;
 #[derive(Clone)]
 struct AppState {
     stats_subscription: Sender<Result<String, XlsError>>,
     xls_subscription: Sender<Result<String, XlsError>>,
     spreadsheet_view: Arc<SpreadSheetView>,
+    search_index: Arc<search::SearchIndex>,
     api_limits: Arc<DashSet<String>>,
+    /// The latest comment of every cell that has one.
+    cell_comments: Arc<DashMap<i64, String>>,
+    pipeline_supervisor: Arc<pipeline::Supervisor>,
     http_client: Client,
+    ingest_queue: Arc<ingest_queue::IngestQueue>,
+    moderator: Arc<moderation::Moderator>,
+    update_limiter: Arc<rate_limit::RateLimiter>,
+    region_limiter: Arc<rate_limit::RateLimiter>,
+    embed_tokens: Arc<embed_tokens::EmbedTokens>,
+    integrations: Arc<integrations::Integrations>,
+    subscribers: Arc<subscribers::Subscribers>,
+    presenter: Sender<xls_protocol::Viewport>,
+    /// Cancelled by `POST /api/admin/shutdown`.
+    shutdown: CancellationToken,
+    /// Cancelled by `POST /api/admin/drain`.
+    drain: CancellationToken,
 }
 
 #[tokio::main]
 async fn main() {
-    let _r = env_logger::try_init();
+    error_log::init();
+
+    let http_client = feldera::http_client();
+    if let Err(e) = pipeline::bootstrap(&http_client).await {
+        log::error!("Failed to bootstrap the pipeline: {e}");
+    }
 
-    let http_client = Client::new();
     let stats_subscription =
-        feldera::subscribe_change_stream(http_client.clone(), "spreadsheet_statistics", 128);
-    let xls_subscription =
-        feldera::subscribe_change_stream(http_client.clone(), "spreadsheet_view", 4096);
+        fanout::subscribe_change_stream(http_client.clone(), "spreadsheet_statistics", 128);
+    let backend: Arc<dyn backend::SpreadsheetBackend> =
+        backend::from_env(http_client.clone()).into();
+    let xls_subscription = coalesce::coalesce_cells(backend.subscribe_changes(4096), 4096);
+    latency::spawn_recorder(xls_subscription.subscribe());
     let api_limits = feldera::api_limit_table(http_client.clone());
-    let spreadsheet_view =
-        Arc::new(SpreadSheetView::new(http_client.clone(), xls_subscription.subscribe()).await);
+    let cell_comments = feldera::cell_comments(http_client.clone());
+    let pipeline_supervisor = pipeline::spawn_supervisor(http_client.clone());
+    expiry::spawn_cleanup(http_client.clone());
+    scratch::spawn_cleanup(http_client.clone());
+    retention::spawn_cleanup(http_client.clone());
+    volatile::spawn_refresh(http_client.clone());
+    let search_index =
+        search::SearchIndex::spawn(http_client.clone(), xls_subscription.subscribe());
+    let spreadsheet_view = Arc::new(SpreadSheetView::new(
+        backend.clone(),
+        xls_subscription.subscribe(),
+    ));
 
     let state = AppState {
         stats_subscription,
         xls_subscription,
         spreadsheet_view,
+        search_index,
         api_limits,
+        cell_comments,
+        pipeline_supervisor,
+        ingest_queue: ingest_queue::IngestQueue::spawn(backend),
+        moderator: Arc::new(moderation::Moderator::new(http_client.clone())),
         http_client,
+        update_limiter: rate_limit::updates(),
+        region_limiter: rate_limit::regions(),
+        embed_tokens: embed_tokens::EmbedTokens::load(),
+        integrations: integrations::Integrations::load(),
+        subscribers: Arc::default(),
+        presenter: presenter::channel(),
+        shutdown: CancellationToken::new(),
+        drain: CancellationToken::new(),
     };
+    let shutdown = state.shutdown.clone();
+    let subscribers = state.subscribers.clone();
 
     let cors = CorsLayer::new()
         .allow_methods(AllowMethods::list(vec![Method::GET, Method::POST]))
-        .allow_origin([
-            "https://xls.feldera.io".parse().unwrap(),
-            "http://localhost:7777".parse().unwrap(),
-            "http://127.0.0.1:7777".parse().unwrap(),
-            "http://localhost:3000".parse().unwrap(),
-        ])
-        .allow_headers(Any);
+        .allow_origin(origin::DEFAULT_ORIGINS.map(|origin| origin.parse().unwrap()))
+        .allow_headers(Any)
+        // The client waits as long as the server asks it to before sending rate limited updates
+        // again.
+        .expose_headers([RETRY_AFTER]);
+    // Embed tokens can read from any origin.
+    let embed_cors = CorsLayer::new()
+        .allow_methods(AllowMethods::list(vec![Method::GET]))
+        .allow_origin(Any);
 
-    let app = Router::new()
-        .route("/", get(|| async { "xls app!" }))
-        .route("/api/stats", get(stats::stats))
-        .route("/api/spreadsheet", get(spreadsheet::ws_handler))
-        .route("/api/spreadsheet", post(spreadsheet::post_handler))
-        .layer(cors)
-        .with_state(state);
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    grpc::spawn_server(state.clone());
+    let schema = graphql::schema(state.clone());
+    let app =
+        Router::new()
+            .route("/", get(|| async { "xls app!" }))
+            .route("/api/stats", get(stats::stats))
+            .route("/api/meta", get(meta::meta))
+            .route("/api/pipeline/status", get(pipeline::status_handler))
+            .route("/metrics", get(metrics::metrics))
+            .route(
+                "/api/spreadsheet",
+                get(spreadsheet::ws_handler)
+                    .merge(post(spreadsheet::post_handler).route_layer(
+                        middleware::from_fn_with_state(state.clone(), rate_limit::limit_updates),
+                    ))
+                    .route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/spreadsheet/batch",
+                post(spreadsheet::batch_handler)
+                    .route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/integrations/cells",
+                post(integrations::push).route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/column-labels",
+                get(column_labels::list)
+                    .merge(
+                        post(column_labels::share).route_layer(middleware::from_fn_with_state(
+                            state.clone(),
+                            rate_limit::limit_updates,
+                        )),
+                    )
+                    .route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/comments",
+                post(comments::comment)
+                    .route_layer(middleware::from_fn_with_state(
+                        state.clone(),
+                        rate_limit::limit_updates,
+                    ))
+                    .route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/comments/:range",
+                get(comments::list).route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/column-statistics",
+                get(column_statistics::list)
+                    .route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/column-values",
+                get(column_values::list).route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/heatmap",
+                get(heatmap::heatmap).route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/diff",
+                get(diff::diff).route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/search",
+                get(search::search).route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/graphql",
+                get(graphql::graphiql)
+                    .post(graphql::query)
+                    .route_layer(Extension(schema.clone()))
+                    .route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route(
+                "/api/graphql/ws",
+                get(graphql::subscriptions)
+                    .route_layer(Extension(schema))
+                    .route_layer(middleware::from_fn(ip_filter::filter_ips)),
+            )
+            .route("/admin", get(admin::dashboard))
+            .nest("/api/admin", admin::router())
+            .merge(openapi::swagger_ui())
+            .layer(cors)
+            .route(
+                "/api/embed/cells",
+                get(embed_tokens::cells)
+                    .route_layer(middleware::from_fn(ip_filter::filter_ips))
+                    .layer(embed_cors),
+            )
+            .with_state(state);
+    let address = std::env::var("SERVER_ADDRESS").unwrap_or_else(|_| String::from("0.0.0.0:3000"));
+    let listener = tokio::net::TcpListener::bind(address).await.unwrap();
     axum::serve(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(shutdown.cancelled_owned())
     .await
     .unwrap();
+    // The websockets are closed by their handlers, give them time to send the close frames.
+    subscribers.closed(Duration::from_secs(5)).await;
 }
@@ -0,0 +1,57 @@
+//! What the deployment allows, served to clients at `GET /api/meta` so one build of the client
+//! adapts to differently configured deployments.
+//!
+//! `READ_ONLY` rejects all edits of cells, comments and column labels, e.g. for a kiosk showing
+//! the sheet. `EXPERIMENTAL_FEATURES` is a comma-separated list of features the clients of the
+//! deployment try out.
+
+use std::env::var;
+use std::sync::LazyLock;
+
+use axum::Json;
+use xls_protocol::address::COLUMNS;
+use xls_protocol::{Meta, CELL_IDS};
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::spreadsheet::MAX_CELL_CHARS;
+use crate::ws_auth;
+
+static READ_ONLY: LazyLock<bool> = LazyLock::new(|| env_or("READ_ONLY", false));
+
+static EXPERIMENTAL_FEATURES: LazyLock<Vec<String>> = LazyLock::new(|| {
+    var("EXPERIMENTAL_FEATURES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|feature| !feature.is_empty())
+        .map(String::from)
+        .collect()
+});
+
+/// Fails with [`XlsError::ReadOnly`] if the deployment doesn't accept edits.
+pub(crate) fn check_writable() -> Result<(), XlsError> {
+    match *READ_ONLY {
+        true => Err(XlsError::ReadOnly),
+        false => Ok(()),
+    }
+}
+
+/// Describes what the deployment allows.
+#[utoipa::path(
+    get,
+    path = "/api/meta",
+    responses(
+        (status = 200, description = "The configuration of the deployment", body = Meta),
+    )
+)]
+pub(crate) async fn meta() -> Json<Meta> {
+    Json(Meta {
+        writes_allowed: !*READ_ONLY,
+        max_cell_chars: MAX_CELL_CHARS as u32,
+        columns: COLUMNS as u64,
+        rows: (CELL_IDS.end / COLUMNS) as u64,
+        auth_required: ws_auth::required(),
+        experimental_features: EXPERIMENTAL_FEATURES.clone(),
+    })
+}
@@ -0,0 +1,383 @@
+//! Process-wide counters, exported in the Prometheus text format at `/metrics`.
+
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+use axum::response::IntoResponse;
+
+pub(crate) static METRICS: Metrics = Metrics::new();
+
+pub(crate) struct Metrics {
+    /// Requests sent to Feldera through the shared HTTP client.
+    pub(crate) feldera_requests_total: AtomicU64,
+    /// Requests to Feldera that failed or returned a non-success status.
+    pub(crate) feldera_request_errors_total: AtomicU64,
+    /// Requests to Feldera that are currently waiting for a response.
+    pub(crate) feldera_requests_in_flight: AtomicI64,
+    /// Long-lived egress connections to Feldera that are currently open.
+    pub(crate) feldera_streams_open: AtomicI64,
+    /// Whether the read pipeline was running at the last supervisor check.
+    pub(crate) pipeline_running: AtomicI64,
+    /// Attempts of the supervisor to resume or restart the pipeline.
+    pub(crate) pipeline_restarts_total: AtomicU64,
+    /// Change stream lines this instance published to the fan-out bus.
+    pub(crate) fanout_published_total: AtomicU64,
+    /// Change stream lines this instance received from the fan-out bus.
+    pub(crate) fanout_received_total: AtomicU64,
+    /// Requests rejected by the per-IP rate limiters.
+    pub(crate) rate_limited_total: AtomicU64,
+    /// Cell changes that were replaced by a newer change before being broadcast.
+    pub(crate) cell_changes_coalesced_total: AtomicU64,
+    /// Websocket connections closed right away because their IP has too many open.
+    pub(crate) ws_connections_rejected_total: AtomicU64,
+    /// Reloads of the cached cells after changes of `spreadsheet_view` were missed.
+    pub(crate) cache_resyncs_total: AtomicU64,
+    /// Cell updates waiting to be retried because Feldera was unavailable.
+    pub(crate) ingest_queue_depth: AtomicI64,
+    /// Region queries answered from the cached cells.
+    pub(crate) cache_hits_total: AtomicU64,
+    /// Region queries outside of the cached ranges, they are sent to Feldera.
+    pub(crate) cache_misses_total: AtomicU64,
+    /// Region queries in a cached range that were sent to Feldera because the cache was stale
+    /// or still loading.
+    pub(crate) cache_fallbacks_total: AtomicU64,
+    /// Cached ranges whose cells are still loading.
+    pub(crate) cache_ranges_loading: AtomicI64,
+    /// Region queries waiting for one of the running queries to Feldera to complete.
+    pub(crate) adhoc_queries_queued: AtomicI64,
+    /// Region queries rejected because too many queries were waiting.
+    pub(crate) adhoc_queries_shed_total: AtomicU64,
+    /// Cell updates flagged by the moderation heuristics but written.
+    pub(crate) moderation_flagged_total: AtomicU64,
+    /// Cell updates rejected by the moderation heuristics.
+    pub(crate) moderation_blocked_total: AtomicU64,
+    /// Time from a cell update to its change arriving from Feldera.
+    pub(crate) edit_latency_seconds: Histogram,
+    /// Time to query the cells of a region from the cache.
+    pub(crate) region_query_cache_seconds: Histogram,
+    /// Time to query the cells of a region from Feldera.
+    pub(crate) region_query_feldera_seconds: Histogram,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            feldera_requests_total: AtomicU64::new(0),
+            feldera_request_errors_total: AtomicU64::new(0),
+            feldera_requests_in_flight: AtomicI64::new(0),
+            feldera_streams_open: AtomicI64::new(0),
+            pipeline_running: AtomicI64::new(0),
+            pipeline_restarts_total: AtomicU64::new(0),
+            fanout_published_total: AtomicU64::new(0),
+            fanout_received_total: AtomicU64::new(0),
+            rate_limited_total: AtomicU64::new(0),
+            cell_changes_coalesced_total: AtomicU64::new(0),
+            ws_connections_rejected_total: AtomicU64::new(0),
+            cache_resyncs_total: AtomicU64::new(0),
+            ingest_queue_depth: AtomicI64::new(0),
+            cache_hits_total: AtomicU64::new(0),
+            cache_misses_total: AtomicU64::new(0),
+            cache_fallbacks_total: AtomicU64::new(0),
+            cache_ranges_loading: AtomicI64::new(0),
+            adhoc_queries_queued: AtomicI64::new(0),
+            adhoc_queries_shed_total: AtomicU64::new(0),
+            moderation_flagged_total: AtomicU64::new(0),
+            moderation_blocked_total: AtomicU64::new(0),
+            edit_latency_seconds: Histogram::new(),
+            region_query_cache_seconds: Histogram::new(),
+            region_query_feldera_seconds: Histogram::new(),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        write_metric(
+            &mut out,
+            "xls_feldera_requests_total",
+            "counter",
+            "Requests sent to Feldera.",
+            self.feldera_requests_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_feldera_request_errors_total",
+            "counter",
+            "Requests to Feldera that failed.",
+            self.feldera_request_errors_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_feldera_requests_in_flight",
+            "gauge",
+            "Requests to Feldera waiting for a response.",
+            self.feldera_requests_in_flight.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_feldera_streams_open",
+            "gauge",
+            "Open egress connections to Feldera.",
+            self.feldera_streams_open.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_pipeline_running",
+            "gauge",
+            "Whether the read pipeline was running at the last check.",
+            self.pipeline_running.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_pipeline_restarts_total",
+            "counter",
+            "Attempts to resume or restart the pipeline.",
+            self.pipeline_restarts_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_fanout_published_total",
+            "counter",
+            "Change stream lines published to the fan-out bus.",
+            self.fanout_published_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_fanout_received_total",
+            "counter",
+            "Change stream lines received from the fan-out bus.",
+            self.fanout_received_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_rate_limited_total",
+            "counter",
+            "Requests rejected by the per-IP rate limiters.",
+            self.rate_limited_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_cell_changes_coalesced_total",
+            "counter",
+            "Cell changes replaced by a newer change before being broadcast.",
+            self.cell_changes_coalesced_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_ws_connections_rejected_total",
+            "counter",
+            "Websocket connections closed right away because their IP has too many open.",
+            self.ws_connections_rejected_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_cache_resyncs_total",
+            "counter",
+            "Reloads of the cached cells after changes were missed.",
+            self.cache_resyncs_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_ingest_queue_depth",
+            "gauge",
+            "Cell updates waiting to be retried because Feldera was unavailable.",
+            self.ingest_queue_depth.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_cache_hits_total",
+            "counter",
+            "Region queries answered from the cached cells.",
+            self.cache_hits_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_cache_misses_total",
+            "counter",
+            "Region queries outside of the cached ranges.",
+            self.cache_misses_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_cache_fallbacks_total",
+            "counter",
+            "Region queries in a cached range sent to Feldera because the cache was stale or loading.",
+            self.cache_fallbacks_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_cache_ranges_loading",
+            "gauge",
+            "Cached ranges whose cells are still loading.",
+            self.cache_ranges_loading.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_adhoc_queries_queued",
+            "gauge",
+            "Region queries waiting for a running query to Feldera to complete.",
+            self.adhoc_queries_queued.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_adhoc_queries_shed_total",
+            "counter",
+            "Region queries rejected because too many queries were waiting.",
+            self.adhoc_queries_shed_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_moderation_flagged_total",
+            "counter",
+            "Cell updates flagged by the moderation heuristics but written.",
+            self.moderation_flagged_total.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut out,
+            "xls_moderation_blocked_total",
+            "counter",
+            "Cell updates rejected by the moderation heuristics.",
+            self.moderation_blocked_total.load(Ordering::Relaxed),
+        );
+        self.edit_latency_seconds.write(
+            &mut out,
+            "xls_edit_latency_seconds",
+            "Time from a cell update to its change arriving from Feldera.",
+        );
+        write_histogram_header(
+            &mut out,
+            "xls_region_query_seconds",
+            "Time to query the cells of a region, by where they came from.",
+        );
+        self.region_query_cache_seconds.write_series(
+            &mut out,
+            "xls_region_query_seconds",
+            "source=\"cache\"",
+        );
+        self.region_query_feldera_seconds.write_series(
+            &mut out,
+            "xls_region_query_seconds",
+            "source=\"feldera\"",
+        );
+        out
+    }
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl Display) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn write_histogram_header(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+}
+
+/// Upper bounds of the histogram buckets, in seconds.
+pub(crate) const HISTOGRAM_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// A Prometheus histogram of durations.
+pub(crate) struct Histogram {
+    /// Observations per bucket (not cumulative), the last one counts everything above the
+    /// largest bound.
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; HISTOGRAM_BUCKETS.len() + 1],
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = HISTOGRAM_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(HISTOGRAM_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Observations per bucket, not cumulative.
+    pub(crate) fn counts(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    pub(crate) fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+    }
+
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+        write_histogram_header(out, name, help);
+        self.write_series(out, name, "");
+    }
+
+    /// Writes the buckets, the sum and the count with `labels`, e.g. `source="cache"`, so
+    /// multiple histograms can be exported under one name.
+    fn write_series(&self, out: &mut String, name: &str, labels: &str) {
+        let (bucket_labels, labels) = match labels {
+            "" => (String::new(), String::new()),
+            labels => (format!("{labels},"), format!("{{{labels}}}")),
+        };
+        let mut cumulative = 0;
+        for (idx, count) in self.counts().into_iter().enumerate() {
+            cumulative += count;
+            match HISTOGRAM_BUCKETS.get(idx) {
+                Some(bound) => {
+                    let _ = writeln!(
+                        out,
+                        "{name}_bucket{{{bucket_labels}le=\"{bound}\"}} {cumulative}"
+                    );
+                }
+                None => {
+                    let _ = writeln!(
+                        out,
+                        "{name}_bucket{{{bucket_labels}le=\"+Inf\"}} {cumulative}"
+                    );
+                }
+            }
+        }
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum().as_secs_f64());
+        let _ = writeln!(out, "{name}_count{labels} {cumulative}");
+    }
+}
+
+/// Increments a gauge for as long as the guard is alive.
+pub(crate) struct GaugeGuard(&'static AtomicI64);
+
+impl GaugeGuard {
+    pub(crate) fn new(gauge: &'static AtomicI64) -> Self {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        GaugeGuard(gauge)
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Prometheus metrics of the server.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses((status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain"))
+)]
+pub(crate) async fn metrics() -> impl IntoResponse {
+    (
+        [("Content-Type", "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+}
@@ -0,0 +1,292 @@
+//! Heuristics for abusive content of cell updates: links to blocked domains, the same content
+//! written to many cells and random-looking spam strings.
+//!
+//! Updates matching a heuristic are flagged, and rejected if its reason is listed in
+//! `MODERATION_BLOCK`. Either way an event is inserted into the `moderation_events` table, so
+//! operators can review them with `GET /api/admin/moderation`.
+
+use std::collections::{HashMap, HashSet};
+use std::env::var;
+use std::sync::atomic::Ordering;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::Json;
+use chrono::Utc;
+use log::{info, warn};
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use xls_protocol::{ErrorResponse, UpdateRequest};
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::feldera::{adhoc_query, insert_batch, parse_rows};
+use crate::metrics::METRICS;
+use crate::spreadsheet::format_timestamp;
+use crate::AppState;
+
+/// Links to these domains and their subdomains are moderated as `blocked_url`.
+static MODERATION_BLOCKED_DOMAINS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    var("MODERATION_BLOCKED_DOMAINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|domain| domain.trim().trim_start_matches('.').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect()
+});
+/// Content written to more cells within `MODERATION_REPEAT_WINDOW_SECS` is flagged, `0`
+/// disables the check.
+static MODERATION_REPEAT_LIMIT: LazyLock<usize> =
+    LazyLock::new(|| env_or("MODERATION_REPEAT_LIMIT", 20));
+static MODERATION_REPEAT_WINDOW: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("MODERATION_REPEAT_WINDOW_SECS", 60)));
+/// Words with more bits of entropy per character look random and are flagged, `0` disables the
+/// check.
+static MODERATION_ENTROPY_BITS: LazyLock<f64> =
+    LazyLock::new(|| env_or("MODERATION_ENTROPY_BITS", 3.5));
+/// The reasons that reject an update instead of only flagging it.
+static MODERATION_BLOCK: LazyLock<Vec<Reason>> = LazyLock::new(|| {
+    var("MODERATION_BLOCK")
+        .unwrap_or_else(|_| String::from("blocked_url"))
+        .split(',')
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty())
+        .filter_map(|reason| {
+            let parsed = Reason::ALL.into_iter().find(|r| r.name() == reason);
+            if parsed.is_none() {
+                warn!("Ignoring unknown reason {reason:?} in MODERATION_BLOCK");
+            }
+            parsed
+        })
+        .collect()
+});
+
+/// Shorter content, e.g. numbers, is written to many cells legitimately.
+const MIN_REPEATED_CHARS: usize = 8;
+
+/// Shorter words aren't checked for entropy, they can't be told from ordinary ones.
+const MIN_RANDOM_WORD_CHARS: usize = 20;
+
+/// Contents tracked for repetitions before the expired ones are dropped.
+const MAX_TRACKED_CONTENTS: usize = 10_000;
+
+/// Events returned by `GET /api/admin/moderation`.
+const REVIEWED_EVENTS: usize = 100;
+
+/// Domains in links, with or without the scheme.
+static DOMAIN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(?:https?://)?((?:[a-z0-9-]+\.)+[a-z]{2,})(?:[/:?#]\S*)?").unwrap()
+});
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Reason {
+    BlockedUrl,
+    Repeated,
+    HighEntropy,
+}
+
+impl Reason {
+    const ALL: [Reason; 3] = [Reason::BlockedUrl, Reason::Repeated, Reason::HighEntropy];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Reason::BlockedUrl => "blocked_url",
+            Reason::Repeated => "repeated",
+            Reason::HighEntropy => "high_entropy",
+        }
+    }
+}
+
+/// A row of the `moderation_events` table.
+#[derive(Serialize, Deserialize, Debug, utoipa::ToSchema)]
+pub(crate) struct ModerationEvent {
+    /// The cell the update was for.
+    id: i64,
+    ip: String,
+    raw_value: String,
+    /// `blocked_url`, `repeated` or `high_entropy`.
+    reason: String,
+    /// Whether the update was rejected or only flagged.
+    blocked: bool,
+    ts: String,
+}
+
+/// Cells the same content was written to recently.
+struct Repeats {
+    since: Instant,
+    ids: HashSet<i64>,
+}
+
+pub(crate) struct Moderator {
+    client: Client,
+    repeats: Mutex<HashMap<String, Repeats>>,
+}
+
+impl Moderator {
+    pub(crate) fn new(client: Client) -> Self {
+        Moderator {
+            client,
+            repeats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks updates from `client_ip` before they are censored, fails if one of them is blocked.
+    pub(crate) fn check(&self, client_ip: &str, updates: &[UpdateRequest]) -> Result<(), XlsError> {
+        let mut events = Vec::new();
+        for update in updates {
+            let raw_value: String = update.raw_value.chars().take(64).collect();
+            for reason in self.reasons(update.id, &raw_value) {
+                events.push(ModerationEvent {
+                    id: update.id,
+                    ip: client_ip.to_string(),
+                    raw_value: raw_value.clone(),
+                    reason: reason.name().to_string(),
+                    blocked: MODERATION_BLOCK.contains(&reason),
+                    ts: format_timestamp(Utc::now()),
+                });
+            }
+        }
+        if events.is_empty() {
+            return Ok(());
+        }
+        let blocked = events.iter().any(|event| event.blocked);
+        for event in &events {
+            info!(
+                "{} update of cell {} from {}: {} ({:?})",
+                if event.blocked { "Blocked" } else { "Flagged" },
+                event.id,
+                event.ip,
+                event.reason,
+                event.raw_value
+            );
+            let counter = match event.blocked {
+                true => &METRICS.moderation_blocked_total,
+                false => &METRICS.moderation_flagged_total,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        // The update isn't delayed by logging the events.
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            if let Err(e) = insert_batch(client, "moderation_events", &events).await {
+                warn!("Failed to log {} moderation events: {e}", events.len());
+            }
+        });
+        match blocked {
+            true => Err(XlsError::InvalidField(
+                "raw_value",
+                String::from("The content looks like spam"),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// The heuristics an update matches.
+    fn reasons(&self, id: i64, raw_value: &str) -> Vec<Reason> {
+        let mut reasons = Vec::new();
+        if links_blocked_domain(raw_value) {
+            reasons.push(Reason::BlockedUrl);
+        }
+        if self.repeated(id, raw_value) {
+            reasons.push(Reason::Repeated);
+        }
+        if looks_random(raw_value) {
+            reasons.push(Reason::HighEntropy);
+        }
+        reasons
+    }
+
+    /// Whether the content was written to more than `MODERATION_REPEAT_LIMIT` cells within
+    /// `MODERATION_REPEAT_WINDOW_SECS`.
+    fn repeated(&self, id: i64, raw_value: &str) -> bool {
+        let content = raw_value.trim();
+        if *MODERATION_REPEAT_LIMIT == 0 || content.chars().count() < MIN_REPEATED_CHARS {
+            return false;
+        }
+        let mut repeats = self.repeats.lock().unwrap();
+        if repeats.len() >= MAX_TRACKED_CONTENTS {
+            repeats.retain(|_, repeats| repeats.since.elapsed() < *MODERATION_REPEAT_WINDOW);
+        }
+        let repeats = repeats
+            .entry(content.to_lowercase())
+            .or_insert_with(|| Repeats {
+                since: Instant::now(),
+                ids: HashSet::new(),
+            });
+        if repeats.since.elapsed() >= *MODERATION_REPEAT_WINDOW {
+            repeats.since = Instant::now();
+            repeats.ids.clear();
+        }
+        repeats.ids.insert(id);
+        repeats.ids.len() > *MODERATION_REPEAT_LIMIT
+    }
+}
+
+/// Whether the content links to one of `MODERATION_BLOCKED_DOMAINS`.
+fn links_blocked_domain(raw_value: &str) -> bool {
+    if MODERATION_BLOCKED_DOMAINS.is_empty() {
+        return false;
+    }
+    DOMAIN.captures_iter(raw_value).any(|caps| {
+        let domain = caps[1].to_lowercase();
+        MODERATION_BLOCKED_DOMAINS.iter().any(|blocked| {
+            domain == *blocked
+                || domain
+                    .strip_suffix(blocked.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+    })
+}
+
+/// Whether a long word of letters and digits has more than `MODERATION_ENTROPY_BITS` bits of
+/// entropy per character, like generated spam tokens. Formulas and links aren't checked.
+fn looks_random(raw_value: &str) -> bool {
+    if *MODERATION_ENTROPY_BITS <= 0.0 || raw_value.starts_with('=') {
+        return false;
+    }
+    raw_value.split_whitespace().any(|word| {
+        word.chars().count() >= MIN_RANDOM_WORD_CHARS
+            && !word.contains("://")
+            && word.chars().any(|c| c.is_alphabetic())
+            && word.chars().any(|c| c.is_numeric())
+            && entropy(word) >= *MODERATION_ENTROPY_BITS
+    })
+}
+
+/// Shannon entropy of the characters of a word, in bits per character.
+fn entropy(word: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in word.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let len = word.chars().count() as f64;
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Lists the latest moderation events for review, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/admin/moderation",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The latest moderation events", body = [ModerationEvent]),
+        (status = 401, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn events(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ModerationEvent>>, XlsError> {
+    let sql = format!("SELECT * FROM moderation_events ORDER BY ts DESC LIMIT {REVIEWED_EVENTS}");
+    let rows = adhoc_query(state.http_client, &sql).await?;
+    Ok(Json(parse_rows(&rows)?))
+}
@@ -0,0 +1,174 @@
+//! What moderators do about abuse they spot in the sheet: see who wrote cells, clear cells and
+//! ban editors. The moderator mode of the client uses these endpoints with the admin token.
+//!
+//! Bans are rows of the `banned_editors` table, which `api_limit_reached` includes, so banned
+//! editors are rejected wherever IPs over the API limit are.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::Utc;
+use log::info;
+use serde::Serialize;
+use serde_json::Value;
+use xls_protocol::{
+    BanRequest, Cell, CellAttribution, CellStyle, ClearRequest, ClearResponse, ErrorResponse,
+    UpdateRequest, CELL_IDS, FIRST_SHEET,
+};
+
+use crate::cached_ranges;
+use crate::error::XlsError;
+use crate::feldera::{adhoc_query, insert, parse_rows};
+use crate::spreadsheet::{format_timestamp, UpdatePayload};
+use crate::AppState;
+
+/// Largest range attributed at once, 400 rows like the largest region of a websocket.
+const MAX_ATTRIBUTED_CELLS: i64 = 400 * 26;
+
+/// Most cells cleared at once.
+const MAX_CLEARED_CELLS: i64 = 100 * 26;
+
+/// The IP of cells cleared by moderators.
+const MODERATOR_IP: &str = "moderator";
+
+/// A row of the `banned_editors` table.
+#[derive(Serialize, Debug)]
+struct BannedEditor {
+    ip: String,
+    ts: String,
+}
+
+/// Lists who wrote the current values of the cells in a range of at most 10400 cells, cells
+/// that were never written are left out.
+#[utoipa::path(
+    get,
+    path = "/api/admin/attribution/{range}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("range" = String, Path, description = "Range of cell ids, e.g. `0..2600`")),
+    responses(
+        (status = 200, description = "The latest writes, ordered by cell id", body = [CellAttribution]),
+        (status = 400, description = "Invalid or too large range", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn attribution(
+    State(state): State<AppState>,
+    Path(range): Path<String>,
+) -> Result<Json<Vec<CellAttribution>>, XlsError> {
+    let range = cached_ranges::parse(&range)?;
+    if range.end - range.start > MAX_ATTRIBUTED_CELLS {
+        return Err(XlsError::InvalidField(
+            "range",
+            format!("At most {MAX_ATTRIBUTED_CELLS} cells can be attributed at once"),
+        ));
+    }
+    let sql = format!(
+        "SELECT d.id, d.ip, d.ts FROM spreadsheet_data d \
+        JOIN (SELECT id, MAX(ts) AS ts FROM spreadsheet_data \
+        WHERE id >= {} AND id < {} GROUP BY id) l \
+        ON d.id = l.id AND d.ts = l.ts ORDER BY d.id",
+        range.start, range.end
+    );
+    let rows = adhoc_query(state.http_client, &sql).await?;
+    Ok(Json(parse_rows(&rows)?))
+}
+
+/// Clears the cells with content in the regions, at most 2600 cells at once.
+#[utoipa::path(
+    post,
+    path = "/api/admin/clear",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body = ClearRequest,
+    responses(
+        (status = 200, description = "The cells were cleared", body = ClearResponse),
+        (status = 202, description = "Feldera is unavailable, the cells are cleared later", body = ClearResponse),
+        (status = 400, description = "Invalid or too large regions", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn clear(
+    State(state): State<AppState>,
+    Json(request): Json<ClearRequest>,
+) -> Result<(StatusCode, Json<ClearResponse>), XlsError> {
+    let mut cells = 0;
+    for region in &request.regions {
+        if region.from >= region.to || !CELL_IDS.contains(&region.from) || region.to > CELL_IDS.end
+        {
+            return Err(XlsError::InvalidField(
+                "regions",
+                format!("Invalid region {}..{}", region.from, region.to),
+            ));
+        }
+        cells += region.to - region.from;
+    }
+    if cells > MAX_CLEARED_CELLS {
+        return Err(XlsError::InvalidField(
+            "regions",
+            format!("At most {MAX_CLEARED_CELLS} cells can be cleared at once"),
+        ));
+    }
+    let mut payloads = Vec::new();
+    for region in request.regions {
+        let snapshot = state.spreadsheet_view.query(region).await?;
+        payloads.extend(
+            parse_rows::<Cell>(&snapshot)?
+                .into_iter()
+                .filter(|cell| !cell.raw_value.is_empty())
+                .map(|cell| {
+                    let update = UpdateRequest {
+                        id: cell.id,
+                        raw_value: String::new(),
+                        background: 0,
+                        style: CellStyle::default(),
+                        ttl_secs: None,
+                        sheet: FIRST_SHEET,
+                    };
+                    UpdatePayload::generated(update, MODERATOR_IP)
+                }),
+        );
+    }
+    let cleared = payloads.len();
+    if cleared == 0 {
+        return Ok((StatusCode::OK, Json(ClearResponse { cleared })));
+    }
+    let ingested = state.ingest_queue.insert(&payloads).await?;
+    info!("A moderator cleared {cleared} cells");
+    Ok((ingested.status(), Json(ClearResponse { cleared })))
+}
+
+/// Bans an editor: their updates are rejected from now on.
+#[utoipa::path(
+    post,
+    path = "/api/admin/bans",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body = BanRequest,
+    responses(
+        (status = 200, description = "The editor is banned", body = Object),
+        (status = 400, description = "Invalid IP", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn ban(
+    State(state): State<AppState>,
+    Json(request): Json<BanRequest>,
+) -> Result<Json<Value>, XlsError> {
+    let ip = request.ip.trim();
+    if ip.is_empty() || ip.len() > 45 || ip == MODERATOR_IP {
+        return Err(XlsError::InvalidField("ip", format!("Invalid IP {ip:?}")));
+    }
+    let banned = BannedEditor {
+        ip: ip.to_string(),
+        ts: format_timestamp(Utc::now()),
+    };
+    let response = insert(state.http_client, "banned_editors", &banned).await?;
+    // Rejected right away, before Feldera updates `api_limit_reached`.
+    state.api_limits.insert(banned.ip.clone());
+    info!("A moderator banned {}", banned.ip);
+    Ok(response)
+}
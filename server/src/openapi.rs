@@ -0,0 +1,130 @@
+//! OpenAPI description of the REST API, served at `/api/openapi.json` and browsable at
+//! `/api/docs`.
+
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+use xls_protocol::{
+    BanRequest, Cell, CellAttribution, CellComment, ClearRequest, ClearResponse, CloseReason,
+    ColumnLabel, ColumnStatistics, ColumnValue, ColumnValues, ErrorResponse, Heartbeat, Meta,
+    ReconnectSoon, Region, RegionActivity, Sampling, Stats, StatsUpdate, UpdateRequest, ValueType,
+    WsError,
+};
+
+use crate::{
+    admin, cached_ranges, column_labels, column_statistics, column_values, comments, connectors,
+    diff, embed_tokens, heatmap, integrations, latency, meta, metrics, moderation,
+    moderator_actions, pipeline, retention, scratch, search, seed, spreadsheet, stats,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Billion Cell Spreadsheet",
+        description = "Backend of the Feldera spreadsheet techdemo."
+    ),
+    paths(
+        spreadsheet::post_handler,
+        spreadsheet::batch_handler,
+        integrations::push,
+        spreadsheet::ws_handler,
+        column_labels::list,
+        column_labels::share,
+        column_statistics::list,
+        column_values::list,
+        comments::list,
+        comments::comment,
+        heatmap::heatmap,
+        search::search,
+        diff::diff,
+        embed_tokens::cells,
+        stats::stats,
+        meta::meta,
+        pipeline::status_handler,
+        metrics::metrics,
+        connectors::list,
+        connectors::put,
+        connectors::delete,
+        cached_ranges::list,
+        cached_ranges::add,
+        cached_ranges::remove,
+        cached_ranges::refresh,
+        embed_tokens::list,
+        embed_tokens::issue,
+        embed_tokens::revoke,
+        integrations::list,
+        integrations::add,
+        integrations::remove,
+        scratch::trigger,
+        latency::summary,
+        moderation::events,
+        moderator_actions::attribution,
+        moderator_actions::clear,
+        moderator_actions::ban,
+        retention::policy,
+        seed::seed,
+        admin::overview,
+        admin::drain,
+        admin::shutdown,
+    ),
+    components(schemas(
+        Cell,
+        ValueType,
+        ColumnLabel,
+        ColumnStatistics,
+        ColumnValue,
+        ColumnValues,
+        Sampling,
+        CellComment,
+        Region,
+        RegionActivity,
+        UpdateRequest,
+        spreadsheet::BatchUpdateResponse,
+        diff::DiffResponse,
+        Stats,
+        StatsUpdate,
+        Meta,
+        ErrorResponse,
+        WsError,
+        CloseReason,
+        ReconnectSoon,
+        Heartbeat,
+        pipeline::PipelineStatus,
+        connectors::Connector,
+        connectors::ConnectorDefinition,
+        cached_ranges::CachedRange,
+        embed_tokens::EmbedToken,
+        integrations::Integration,
+        scratch::CleanupResponse,
+        latency::LatencySummary,
+        moderation::ModerationEvent,
+        CellAttribution,
+        ClearRequest,
+        ClearResponse,
+        BanRequest,
+        retention::RetentionPolicy,
+        seed::SeedResponse,
+        admin::Overview
+    )),
+    modifiers(&AdminToken)
+)]
+pub(crate) struct ApiDoc;
+
+/// The bearer token (`ADMIN_TOKEN`) required by the admin endpoints.
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
+/// Routes for the OpenAPI document and the Swagger UI.
+pub(crate) fn swagger_ui() -> SwaggerUi {
+    SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi())
+}
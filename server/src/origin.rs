@@ -0,0 +1,37 @@
+//! Origin checks for the websocket, CORS doesn't apply to it.
+//!
+//! Browsers send the `Origin` of the page opening a websocket, so without a check any page could
+//! stream the changes of the public deployment. `WS_ALLOWED_ORIGINS` is a comma-separated list
+//! of origins like `https://xls.feldera.io`, `*` allows every origin. Clients that aren't
+//! browsers send no `Origin` and are allowed, they are limited per IP or token instead.
+
+use std::env::var;
+use std::sync::LazyLock;
+
+/// Origins of the client, also allowed by the CORS layer of the REST API.
+pub(crate) const DEFAULT_ORIGINS: [&str; 4] = [
+    "https://xls.feldera.io",
+    "http://localhost:7777",
+    "http://127.0.0.1:7777",
+    "http://localhost:3000",
+];
+
+static WS_ALLOWED_ORIGINS: LazyLock<Vec<String>> =
+    LazyLock::new(|| match var("WS_ALLOWED_ORIGINS") {
+        Ok(origins) => origins
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect(),
+        Err(_) => DEFAULT_ORIGINS.map(String::from).to_vec(),
+    });
+
+/// Whether a page from `origin` may open a websocket, `None` if the client sent no origin.
+pub(crate) fn is_allowed(origin: Option<&str>) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    WS_ALLOWED_ORIGINS
+        .iter()
+        .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+}
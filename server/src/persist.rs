@@ -0,0 +1,66 @@
+//! Keeps lists in files, one JSON value per line, so they survive restarts of the server.
+//!
+//! An empty path disables storing, the lists are only kept in memory then.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use log::{error, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// The items stored in `path`, invalid lines are skipped.
+pub(crate) fn load<T: DeserializeOwned>(path: &str) -> Vec<T> {
+    if path.is_empty() {
+        return vec![];
+    }
+    let Ok(content) = fs::read_to_string(path) else {
+        return vec![];
+    };
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(item) => Some(item),
+            Err(e) => {
+                warn!("Ignoring invalid line in {path}: {e} (line {line})");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Replaces the content of `path` with the items.
+pub(crate) fn store<'a, T: Serialize + 'a>(path: &str, items: impl IntoIterator<Item = &'a T>) {
+    if path.is_empty() {
+        return;
+    }
+    // Written to another file first, so the items aren't lost if the server stops meanwhile.
+    let tmp = format!("{path}.tmp");
+    let result = fs::write(&tmp, lines(items)).and_then(|()| fs::rename(&tmp, path));
+    if let Err(e) = result {
+        error!("Failed to store {path}: {e}");
+    }
+}
+
+/// Adds items to the end of `path`.
+pub(crate) fn append<'a, T: Serialize + 'a>(path: &str, items: impl IntoIterator<Item = &'a T>) {
+    if path.is_empty() {
+        return;
+    }
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(lines(items).as_bytes()));
+    if let Err(e) = result {
+        error!("Failed to append to {path}: {e}");
+    }
+}
+
+fn lines<'a, T: Serialize + 'a>(items: impl IntoIterator<Item = &'a T>) -> String {
+    items
+        .into_iter()
+        .map(|item| format!("{}\n", serde_json::to_string(item).unwrap()))
+        .collect()
+}
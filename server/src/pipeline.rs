@@ -0,0 +1,458 @@
+//! Managing the lifecycle of the Feldera pipeline.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::Json;
+use log::{error, info, warn};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use xls_protocol::ErrorResponse;
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::feldera::{pipelines, send, FELDERA_API_KEY, FELDERA_HOST, READ_PIPELINE};
+use crate::metrics::METRICS;
+use crate::AppState;
+
+/// The pipeline program, used to create the pipeline if it doesn't exist yet.
+const PROGRAM_SQL: &str = include_str!("../../feldera/program.sql");
+const UDF_RUST: &str = include_str!("../../feldera/udf/src/lib.rs");
+const UDF_TOML: &str = include_str!("../../feldera/udf/udf.toml");
+
+/// Create and start the pipeline at startup if it is missing or not running.
+static FELDERA_BOOTSTRAP: LazyLock<bool> = LazyLock::new(|| env_or("FELDERA_BOOTSTRAP", false));
+/// How long we wait for the pipeline to compile and start before giving up.
+static FELDERA_BOOTSTRAP_TIMEOUT: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("FELDERA_BOOTSTRAP_TIMEOUT_SECS", 900)));
+/// How often the supervisor checks the pipeline, `0` disables the supervisor.
+static FELDERA_SUPERVISOR_INTERVAL: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("FELDERA_SUPERVISOR_INTERVAL_SECS", 30)));
+
+/// The parts of the Feldera pipeline descriptor we care about.
+#[derive(Deserialize, Debug)]
+pub(crate) struct Pipeline {
+    /// e.g., `Shutdown`, `Provisioning`, `Initializing`, `Paused`, `Running`, `Failed`.
+    pub(crate) deployment_status: String,
+    /// `Pending`, `CompilingSql`, ..., `Success` or an object describing the compilation error.
+    #[serde(default)]
+    pub(crate) program_status: Value,
+    #[serde(default)]
+    pub(crate) deployment_error: Option<Value>,
+    #[serde(default)]
+    pub(crate) program_code: String,
+    #[serde(default)]
+    runtime_config: Value,
+}
+
+impl Pipeline {
+    pub(crate) fn is_running(&self) -> bool {
+        self.deployment_status == "Running"
+    }
+
+    fn is_paused(&self) -> bool {
+        self.deployment_status == "Paused"
+    }
+
+    fn is_failed(&self) -> bool {
+        self.deployment_status == "Failed"
+    }
+
+    fn is_stopped(&self) -> bool {
+        matches!(self.deployment_status.as_str(), "Shutdown" | "Stopped")
+    }
+
+    fn program_compiled(&self) -> bool {
+        self.program_status == "Success"
+    }
+
+    fn program_failed(&self) -> bool {
+        self.program_status.is_object()
+    }
+
+    /// Whether the pipeline keeps its state on disk, i.e. restarting it doesn't lose the cells.
+    /// Storage is configured as `true` or with an object of options.
+    pub(crate) fn has_storage(&self) -> bool {
+        match &self.runtime_config["storage"] {
+            Value::Bool(storage) => *storage,
+            Value::Object(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Fetches the pipeline descriptor, returns `None` if the pipeline doesn't exist.
+pub(crate) async fn status(client: &Client, name: &str) -> Result<Option<Pipeline>, XlsError> {
+    let url = format!("{}/v0/pipelines/{name}", &*FELDERA_HOST);
+    let response = send(client.get(url).bearer_auth(&*FELDERA_API_KEY)).await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(XlsError::FelderaUnavailable(format!(
+            "Failed to get pipeline: HTTP {}: {:?}",
+            response.status(),
+            response.text().await.unwrap_or_else(|e| e.to_string())
+        )));
+    }
+    Ok(Some(response.json::<Pipeline>().await?))
+}
+
+async fn create(client: &Client, name: &str) -> Result<(), XlsError> {
+    let url = format!("{}/v0/pipelines/{name}", &*FELDERA_HOST);
+    let body = serde_json::json!({
+        "name": name,
+        "description": "1bln cell spreadsheet techdemo",
+        "runtime_config": { "workers": 8 },
+        "program_config": {},
+        "program_code": PROGRAM_SQL,
+        "udf_rust": UDF_RUST,
+        "udf_toml": UDF_TOML,
+    });
+    let response = send(client.put(url).bearer_auth(&*FELDERA_API_KEY).json(&body)).await?;
+    if !response.status().is_success() {
+        return Err(XlsError::FelderaUnavailable(format!(
+            "Failed to create pipeline: HTTP {}: {:?}",
+            response.status(),
+            response.text().await.unwrap_or_else(|e| e.to_string())
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) async fn start(client: &Client, name: &str) -> Result<(), XlsError> {
+    let url = format!("{}/v0/pipelines/{name}/start", &*FELDERA_HOST);
+    let response = send(client.post(url).bearer_auth(&*FELDERA_API_KEY)).await?;
+    if !response.status().is_success() {
+        return Err(XlsError::FelderaUnavailable(format!(
+            "Failed to start pipeline: HTTP {}: {:?}",
+            response.status(),
+            response.text().await.unwrap_or_else(|e| e.to_string())
+        )));
+    }
+    Ok(())
+}
+
+async fn shutdown(client: &Client, name: &str) -> Result<(), XlsError> {
+    let url = format!("{}/v0/pipelines/{name}/shutdown", &*FELDERA_HOST);
+    let response = send(client.post(url).bearer_auth(&*FELDERA_API_KEY)).await?;
+    if !response.status().is_success() {
+        return Err(XlsError::FelderaUnavailable(format!(
+            "Failed to shut down pipeline: HTTP {}: {:?}",
+            response.status(),
+            response.text().await.unwrap_or_else(|e| e.to_string())
+        )));
+    }
+    Ok(())
+}
+
+async fn update_program(client: &Client, name: &str, program_code: &str) -> Result<(), XlsError> {
+    let url = format!("{}/v0/pipelines/{name}", &*FELDERA_HOST);
+    let body = serde_json::json!({ "program_code": program_code });
+    let response = send(client.patch(url).bearer_auth(&*FELDERA_API_KEY).json(&body)).await?;
+    if !response.status().is_success() {
+        return Err(XlsError::FelderaUnavailable(format!(
+            "Failed to update pipeline program: HTTP {}: {:?}",
+            response.status(),
+            response.text().await.unwrap_or_else(|e| e.to_string())
+        )));
+    }
+    Ok(())
+}
+
+/// Polls the pipeline until `condition` holds, for at most `FELDERA_BOOTSTRAP_TIMEOUT_SECS`.
+async fn wait_for(
+    client: &Client,
+    name: &str,
+    what: &str,
+    condition: impl Fn(&Pipeline) -> bool,
+) -> Result<Pipeline, XlsError> {
+    let deadline = Instant::now() + *FELDERA_BOOTSTRAP_TIMEOUT;
+    loop {
+        match status(client, name).await? {
+            Some(pipeline) if condition(&pipeline) => return Ok(pipeline),
+            Some(_) => {}
+            None => {
+                return Err(XlsError::FelderaUnavailable(format!(
+                    "Pipeline {name} does not exist"
+                )))
+            }
+        }
+        if Instant::now() > deadline {
+            return Err(XlsError::QueryTimeout(format!(
+                "Pipeline {name} did not {what} within {:?}",
+                *FELDERA_BOOTSTRAP_TIMEOUT
+            )));
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Replaces the program of a pipeline: shuts it down, updates the program and starts it again
+/// once the new program compiled. The pipeline is unavailable in the meantime.
+pub(crate) async fn replace_program(
+    client: &Client,
+    supervisor: &Supervisor,
+    name: &str,
+    program_code: &str,
+) -> Result<(), XlsError> {
+    // Otherwise the supervisor restarts the pipeline while we're updating it.
+    supervisor.suspended.store(true, Ordering::Relaxed);
+    let result = async {
+        info!("Shutting down pipeline {name} to update its program");
+        shutdown(client, name).await?;
+        wait_for(client, name, "shut down", Pipeline::is_stopped).await?;
+        update_program(client, name, program_code).await?;
+        let pipeline = wait_for(client, name, "compile", |p| {
+            p.program_compiled() || p.program_failed()
+        })
+        .await?;
+        if pipeline.program_failed() {
+            return Err(XlsError::FelderaUnavailable(format!(
+                "Pipeline {name} failed to compile: {}",
+                pipeline.program_status
+            )));
+        }
+        start(client, name).await?;
+        wait_for(client, name, "start", Pipeline::is_running).await?;
+        info!("Pipeline {name} is running with the updated program");
+        Ok(())
+    }
+    .await;
+    supervisor.suspended.store(false, Ordering::Relaxed);
+    result
+}
+
+/// Number of records that were ingested but not processed yet by the pipeline.
+async fn lag(client: &Client, name: &str) -> Result<u64, XlsError> {
+    let url = format!("{}/v0/pipelines/{name}/stats", &*FELDERA_HOST);
+    let response = send(client.get(url).bearer_auth(&*FELDERA_API_KEY)).await?;
+    if !response.status().is_success() {
+        return Err(XlsError::FelderaUnavailable(format!(
+            "Failed to get pipeline stats: HTTP {}",
+            response.status(),
+        )));
+    }
+    let stats = response.json::<Value>().await?;
+    let metric = |key: &str| stats["global_metrics"][key].as_u64().unwrap_or(0);
+    Ok(metric("total_input_records").saturating_sub(metric("total_processed_records")))
+}
+
+/// Makes sure the pipelines exist and are running before the server starts using them.
+///
+/// Without `FELDERA_BOOTSTRAP=true` this only checks the pipelines and logs what's wrong,
+/// otherwise missing pipelines are created from the embedded program and started, and we wait
+/// until they are running.
+pub(crate) async fn bootstrap(client: &Client) -> Result<(), XlsError> {
+    for name in pipelines() {
+        bootstrap_pipeline(client, name).await?;
+    }
+    Ok(())
+}
+
+async fn bootstrap_pipeline(client: &Client, name: &str) -> Result<(), XlsError> {
+    let deadline = Instant::now() + *FELDERA_BOOTSTRAP_TIMEOUT;
+    let mut start_requested = false;
+
+    loop {
+        match status(client, name).await? {
+            Some(pipeline) if pipeline.is_running() => {
+                info!("Pipeline {name} is running");
+                return Ok(());
+            }
+            Some(pipeline) if !*FELDERA_BOOTSTRAP => {
+                warn!(
+                    "Pipeline {name} is {}, set FELDERA_BOOTSTRAP=true to start it on startup",
+                    pipeline.deployment_status
+                );
+                return Ok(());
+            }
+            None if !*FELDERA_BOOTSTRAP => {
+                warn!("Pipeline {name} does not exist, set FELDERA_BOOTSTRAP=true to create it on startup");
+                return Ok(());
+            }
+            None => {
+                info!("Pipeline {name} does not exist, creating it");
+                create(client, name).await?;
+            }
+            Some(pipeline) if pipeline.program_failed() => {
+                return Err(XlsError::FelderaUnavailable(format!(
+                    "Pipeline {name} failed to compile: {}",
+                    pipeline.program_status
+                )));
+            }
+            Some(pipeline) if pipeline.is_failed() => {
+                return Err(XlsError::FelderaUnavailable(format!(
+                    "Pipeline {name} failed: {}",
+                    pipeline.deployment_error.unwrap_or_default()
+                )));
+            }
+            Some(pipeline) if pipeline.program_compiled() && !start_requested => {
+                info!(
+                    "Pipeline {name} is {}, starting it",
+                    pipeline.deployment_status
+                );
+                start(client, name).await?;
+                start_requested = true;
+            }
+            Some(pipeline) => {
+                info!(
+                    "Waiting for pipeline {name} (program: {}, deployment: {})",
+                    pipeline.program_status, pipeline.deployment_status
+                );
+            }
+        }
+
+        if Instant::now() > deadline {
+            return Err(XlsError::QueryTimeout(format!(
+                "Pipeline {name} did not start within {:?}",
+                *FELDERA_BOOTSTRAP_TIMEOUT
+            )));
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Keeps the pipelines running: resumes them when they are paused and restarts them when they failed.
+#[derive(Default)]
+pub(crate) struct Supervisor {
+    last_error: RwLock<Option<String>>,
+    /// Set while the pipeline is deliberately stopped, e.g. to update its program.
+    suspended: AtomicBool,
+    /// Pipelines the supervisor shut down after a failure and still has to start again.
+    restarting: Mutex<HashSet<String>>,
+}
+
+impl Supervisor {
+    pub(crate) fn record_error(&self, message: String) {
+        error!("{message}");
+        *self.last_error.write().unwrap() = Some(message);
+    }
+
+    fn last_error(&self) -> Option<String> {
+        self.last_error.read().unwrap().clone()
+    }
+
+    async fn check(&self, client: &Client, name: &str) -> Result<(), XlsError> {
+        if self.suspended.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let is_read_pipeline = name == READ_PIPELINE.as_str();
+        let Some(pipeline) = status(client, name).await? else {
+            if is_read_pipeline {
+                METRICS.pipeline_running.store(0, Ordering::Relaxed);
+            }
+            self.record_error(format!("Pipeline {name} does not exist"));
+            return Ok(());
+        };
+        if is_read_pipeline {
+            METRICS
+                .pipeline_running
+                .store(pipeline.is_running() as i64, Ordering::Relaxed);
+        }
+
+        if pipeline.is_paused() {
+            warn!("Pipeline {name} is paused, resuming it");
+            METRICS
+                .pipeline_restarts_total
+                .fetch_add(1, Ordering::Relaxed);
+            start(client, name).await?;
+        } else if pipeline.is_failed() {
+            self.record_error(format!(
+                "Pipeline {name} failed, restarting it: {}",
+                pipeline.deployment_error.unwrap_or_default()
+            ));
+            METRICS
+                .pipeline_restarts_total
+                .fetch_add(1, Ordering::Relaxed);
+            shutdown(client, name).await?;
+            self.restarting.lock().unwrap().insert(name.to_string());
+        } else if pipeline.is_stopped()
+            && pipeline.program_compiled()
+            && self.restarting.lock().unwrap().contains(name)
+        {
+            // Second half of a restart after a failure; pipelines stopped by an operator stay stopped.
+            start(client, name).await?;
+            self.restarting.lock().unwrap().remove(name);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn spawn_supervisor(client: Client) -> Arc<Supervisor> {
+    let supervisor = Arc::new(Supervisor::default());
+    if FELDERA_SUPERVISOR_INTERVAL.is_zero() {
+        return supervisor;
+    }
+
+    let supervisor_clone = supervisor.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(*FELDERA_SUPERVISOR_INTERVAL).await;
+            for name in pipelines() {
+                if let Err(e) = supervisor.check(&client, name).await {
+                    supervisor.record_error(format!("Failed to supervise pipeline {name}: {e}"));
+                }
+            }
+        }
+    });
+
+    supervisor_clone
+}
+
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub(crate) struct PipelineStatus {
+    pipeline: String,
+    deployment_status: String,
+    running: bool,
+    paused: bool,
+    lag: Option<u64>,
+    last_error: Option<String>,
+}
+
+/// Status of the pipeline serving reads.
+#[utoipa::path(
+    get,
+    path = "/api/pipeline/status",
+    responses(
+        (status = 200, body = PipelineStatus),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn status_handler(
+    State(state): State<AppState>,
+) -> Result<Json<PipelineStatus>, XlsError> {
+    let Some(pipeline) = status(&state.http_client, &READ_PIPELINE).await? else {
+        return Ok(Json(PipelineStatus {
+            pipeline: READ_PIPELINE.clone(),
+            deployment_status: String::from("Missing"),
+            running: false,
+            paused: false,
+            lag: None,
+            last_error: state.pipeline_supervisor.last_error(),
+        }));
+    };
+    let lag = if pipeline.is_running() || pipeline.is_paused() {
+        lag(&state.http_client, &READ_PIPELINE).await.ok()
+    } else {
+        None
+    };
+    let last_error = pipeline
+        .deployment_error
+        .as_ref()
+        .map(|e| e.to_string())
+        .or_else(|| state.pipeline_supervisor.last_error());
+
+    Ok(Json(PipelineStatus {
+        pipeline: READ_PIPELINE.clone(),
+        running: pipeline.is_running(),
+        paused: pipeline.is_paused(),
+        deployment_status: pipeline.deployment_status,
+        lag,
+        last_error,
+    }))
+}
@@ -0,0 +1,27 @@
+//! Live walkthroughs: a presenter sends its viewport over the websocket and the server forwards
+//! it to the connections following the presenter.
+//!
+//! Presenting requires the `PRESENTER_KEY` and is disabled if it is not set. Viewports are only
+//! forwarded to the connections of the same server instance.
+
+use std::sync::LazyLock;
+
+use tokio::sync::broadcast;
+use xls_protocol::Viewport;
+
+use crate::admin::constant_time_eq;
+use crate::config::env_or;
+
+static PRESENTER_KEY: LazyLock<String> = LazyLock::new(|| env_or("PRESENTER_KEY", String::new()));
+
+/// Viewports that are sent faster than the followers receive them are skipped.
+const CHANNEL_CAPACITY: usize = 16;
+
+pub(crate) fn channel() -> broadcast::Sender<Viewport> {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
+
+/// Whether `key` allows to present.
+pub(crate) fn is_presenter(key: &str) -> bool {
+    !PRESENTER_KEY.is_empty() && constant_time_eq(key, &PRESENTER_KEY)
+}
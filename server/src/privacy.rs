@@ -0,0 +1,42 @@
+//! Option to keep client IPs out of Feldera for public deployments that mustn't store personal
+//! data.
+//!
+//! With `ANONYMIZE_IPS=true`, a salted hash replaces the IP of a client wherever the server
+//! stores it or rate limits by it, so `api_limit_reached` still counts the updates of a client.
+//! All instances need the same `IP_HASH_SALT` for their hashes to match. Only the IP filter sees
+//! the actual IP.
+
+use std::sync::LazyLock;
+
+use log::warn;
+use rand::distributions::{Alphanumeric, DistString};
+use sha2::{Digest, Sha256};
+
+use crate::config::env_or;
+
+static ANONYMIZE_IPS: LazyLock<bool> = LazyLock::new(|| env_or("ANONYMIZE_IPS", false));
+static IP_HASH_SALT: LazyLock<String> = LazyLock::new(|| {
+    let salt = env_or("IP_HASH_SALT", String::new());
+    if !salt.is_empty() {
+        return salt;
+    }
+    warn!("IP_HASH_SALT is not set, IPs are hashed with a random salt until the server restarts");
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 32)
+});
+
+/// Hex digits of the hash that are kept, they fit the `ip` columns.
+const HASH_CHARS: usize = 32;
+
+/// The IP a client is known by: the IP itself, or its salted hash if `ANONYMIZE_IPS` is set.
+pub(crate) fn anonymize(ip: String) -> String {
+    if !*ANONYMIZE_IPS {
+        return ip;
+    }
+    let hash = Sha256::new()
+        .chain_update(IP_HASH_SALT.as_bytes())
+        .chain_update(ip.as_bytes())
+        .finalize();
+    let mut hex = format!("{hash:x}");
+    hex.truncate(HASH_CHARS);
+    hex
+}
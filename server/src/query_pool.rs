@@ -0,0 +1,87 @@
+//! Bounds the adhoc queries sent to Feldera for regions that aren't cached, so clients
+//! scrolling through the sheet all at once can't overload the pipeline.
+//!
+//! `MAX_ADHOC_QUERIES` queries run at once and up to `MAX_QUEUED_ADHOC_QUERIES` more wait for
+//! their turn. Queries beyond that are shed with [`XlsError::Busy`], clients ask again after
+//! `retry_after_secs`. Waiting queries run in the order they arrived, and a websocket waits for
+//! its query before it handles the next message, so every connection holds at most one place in
+//! the queue.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::LazyLock;
+
+use tokio::sync::Semaphore;
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::metrics::METRICS;
+
+static MAX_ADHOC_QUERIES: LazyLock<usize> = LazyLock::new(|| env_or("MAX_ADHOC_QUERIES", 8));
+static MAX_QUEUED_ADHOC_QUERIES: LazyLock<usize> =
+    LazyLock::new(|| env_or("MAX_QUEUED_ADHOC_QUERIES", 32));
+
+/// When clients ask again for a region whose query was shed.
+const RETRY_AFTER_SECS: u64 = 1;
+
+pub(crate) struct QueryPool {
+    permits: Semaphore,
+    max_queued: u64,
+    /// Queries waiting for a permit.
+    queued: AtomicU64,
+}
+
+/// Takes a query out of the queue when it got its permit or was cancelled.
+struct Queued<'a>(&'a AtomicU64);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+        METRICS.adhoc_queries_queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl QueryPool {
+    pub(crate) fn new(max_running: usize, max_queued: usize) -> Self {
+        QueryPool {
+            permits: Semaphore::new(max_running.max(1)),
+            max_queued: max_queued as u64,
+            queued: AtomicU64::new(0),
+        }
+    }
+
+    /// A pool configured by `MAX_ADHOC_QUERIES` and `MAX_QUEUED_ADHOC_QUERIES`.
+    pub(crate) fn from_env() -> Self {
+        QueryPool::new(*MAX_ADHOC_QUERIES, *MAX_QUEUED_ADHOC_QUERIES)
+    }
+
+    /// Runs `query` once fewer than the maximum of queries are running, fails with
+    /// [`XlsError::Busy`] without running it if the queue is full.
+    pub(crate) async fn run<T>(
+        &self,
+        query: impl Future<Output = Result<T, XlsError>>,
+    ) -> Result<T, XlsError> {
+        let _permit = match self.permits.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let ahead = self.queued.fetch_add(1, Ordering::Relaxed);
+                METRICS.adhoc_queries_queued.fetch_add(1, Ordering::Relaxed);
+                let queued = Queued(&self.queued);
+                if ahead >= self.max_queued {
+                    METRICS
+                        .adhoc_queries_shed_total
+                        .fetch_add(1, Ordering::Relaxed);
+                    return Err(XlsError::Busy(RETRY_AFTER_SECS));
+                }
+                let permit = self
+                    .permits
+                    .acquire()
+                    .await
+                    .expect("semaphore is never closed");
+                drop(queued);
+                permit
+            }
+        };
+        query.await
+    }
+}
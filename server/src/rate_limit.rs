@@ -0,0 +1,177 @@
+//! Per-IP token buckets that throttle clients before their requests reach Feldera.
+//!
+//! This complements the `api_limit_reached` view, which only blocks an IP after Feldera has
+//! processed its updates.
+
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use dashmap::DashMap;
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::metrics::METRICS;
+use crate::privacy;
+use crate::spreadsheet::{client_ip, CLIENT_IP_HEADER};
+use crate::AppState;
+
+static RATE_LIMIT_UPDATES_PER_SEC: LazyLock<f64> =
+    LazyLock::new(|| env_or("RATE_LIMIT_UPDATES_PER_SEC", 10.0));
+static RATE_LIMIT_UPDATES_BURST: LazyLock<f64> =
+    LazyLock::new(|| env_or("RATE_LIMIT_UPDATES_BURST", 50.0));
+static RATE_LIMIT_REGIONS_PER_SEC: LazyLock<f64> =
+    LazyLock::new(|| env_or("RATE_LIMIT_REGIONS_PER_SEC", 20.0));
+static RATE_LIMIT_REGIONS_BURST: LazyLock<f64> =
+    LazyLock::new(|| env_or("RATE_LIMIT_REGIONS_BURST", 100.0));
+static RATE_LIMIT_CONNECTION_REGIONS_PER_SEC: LazyLock<f64> =
+    LazyLock::new(|| env_or("RATE_LIMIT_CONNECTION_REGIONS_PER_SEC", 10.0));
+static RATE_LIMIT_CONNECTION_REGIONS_BURST: LazyLock<f64> =
+    LazyLock::new(|| env_or("RATE_LIMIT_CONNECTION_REGIONS_BURST", 50.0));
+
+/// How often buckets that refilled completely are dropped.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets keyed by client IP: each IP may spend `burst` tokens at once, which refill at
+/// `rate` tokens per second. A rate of `0` disables the limiter.
+pub(crate) struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: DashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(rate: f64, burst: f64) -> Self {
+        RateLimiter {
+            rate,
+            burst: burst.max(1.0),
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Takes a token for `ip`, returns `false` if it has none left.
+    pub(crate) fn check(&self, ip: &str) -> bool {
+        self.take(ip, 1.0)
+    }
+
+    /// Takes `tokens` at once for `ip`, returns `false` and takes none if it has fewer left. More
+    /// tokens than the burst can be taken from a full bucket, which then refills from below zero.
+    pub(crate) fn take(&self, ip: &str, tokens: f64) -> bool {
+        if self.rate <= 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        let mut bucket = self.buckets.entry(ip.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refill(&bucket, now);
+        bucket.updated = now;
+        if bucket.tokens < tokens.min(self.burst) {
+            METRICS.rate_limited_total.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        bucket.tokens -= tokens;
+        true
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+
+    /// How long until `ip` has a token again, rounded up to whole seconds.
+    pub(crate) fn retry_after(&self, ip: &str) -> Duration {
+        let Some(bucket) = self.buckets.get(ip) else {
+            return Duration::ZERO;
+        };
+        let missing = 1.0 - self.refill(&bucket, Instant::now());
+        if self.rate <= 0.0 || missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs((missing / self.rate).ceil() as u64)
+    }
+
+    /// IPs that currently have no tokens left.
+    pub(crate) fn throttled(&self) -> Vec<String> {
+        let now = Instant::now();
+        let mut ips: Vec<String> = self
+            .buckets
+            .iter()
+            .filter(|entry| self.refill(entry.value(), now) < 1.0)
+            .map(|entry| entry.key().clone())
+            .collect();
+        ips.sort();
+        ips
+    }
+
+    /// Forgets IPs that could spend a full burst again.
+    fn cleanup(&self) {
+        let now = Instant::now();
+        self.buckets
+            .retain(|_, bucket| self.refill(bucket, now) < self.burst);
+    }
+}
+
+fn spawn_limiter(rate: f64, burst: f64) -> Arc<RateLimiter> {
+    let limiter = Arc::new(RateLimiter::new(rate, burst));
+    let cleanup = limiter.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+            cleanup.cleanup();
+        }
+    });
+    limiter
+}
+
+/// Limits cell updates, configured by `RATE_LIMIT_UPDATES_PER_SEC` and `RATE_LIMIT_UPDATES_BURST`.
+pub(crate) fn updates() -> Arc<RateLimiter> {
+    spawn_limiter(*RATE_LIMIT_UPDATES_PER_SEC, *RATE_LIMIT_UPDATES_BURST)
+}
+
+/// Limits region changes on websockets, configured by `RATE_LIMIT_REGIONS_PER_SEC` and
+/// `RATE_LIMIT_REGIONS_BURST`.
+pub(crate) fn regions() -> Arc<RateLimiter> {
+    spawn_limiter(*RATE_LIMIT_REGIONS_PER_SEC, *RATE_LIMIT_REGIONS_BURST)
+}
+
+/// Limits region changes of a single websocket, configured by
+/// `RATE_LIMIT_CONNECTION_REGIONS_PER_SEC` and `RATE_LIMIT_CONNECTION_REGIONS_BURST`. The
+/// connection is its only key, the limiter is dropped with it.
+pub(crate) fn connection_regions() -> RateLimiter {
+    RateLimiter::new(
+        *RATE_LIMIT_CONNECTION_REGIONS_PER_SEC,
+        *RATE_LIMIT_CONNECTION_REGIONS_BURST,
+    )
+}
+
+/// Middleware that rejects cell updates with `429` once the client ran out of tokens.
+pub(crate) async fn limit_updates(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = privacy::anonymize(client_ip(
+        request
+            .headers()
+            .get(CLIENT_IP_HEADER)
+            .map(|ip| ip.as_bytes()),
+        addr,
+    ));
+    if !state.update_limiter.check(&ip) {
+        let retry_after = state.update_limiter.retry_after(&ip);
+        return XlsError::RateLimited(Some(retry_after.as_secs())).into_response();
+    }
+    next.run(request).await
+}
@@ -0,0 +1,210 @@
+//! Enforces how long the history of cells and the IPs of clients are kept.
+//!
+//! Values of a cell older than `HISTORY_RETENTION_SECS` are deleted from `spreadsheet_data`,
+//! except its current value. IPs older than `IP_RETENTION_SECS` are cleared in
+//! `spreadsheet_data`, `column_labels` and `cell_comments`, and moderation events, which only
+//! serve to review the IPs, are deleted. Every `RETENTION_INTERVAL_SECS` the rows of each table
+//! are processed in batches, up to [`MAX_BATCHES_PER_RUN`] of them, `GET /api/admin/retention`
+//! shows the policy.
+//!
+//! It only runs on the publisher, see [`fanout::is_publisher`].
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use axum::Json;
+use log::{error, info};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::Value;
+use xls_protocol::ErrorResponse;
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::fanout;
+use crate::feldera::{adhoc_query, delete_batch, parse_rows, replace_batch};
+
+/// How long old values of cells are kept, `0` keeps them forever.
+static HISTORY_RETENTION: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("HISTORY_RETENTION_SECS", 0)));
+/// How long the IPs of clients are kept, `0` keeps them forever.
+static IP_RETENTION: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("IP_RETENTION_SECS", 0)));
+/// How often the policy is enforced, `0` disables the job.
+static RETENTION_INTERVAL: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("RETENTION_INTERVAL_SECS", 3600)));
+
+/// Rows deleted or changed with a single request.
+const CLEANUP_BATCH_SIZE: usize = 1000;
+
+/// Most batches of a table processed per run, the rest is left for the next run.
+const MAX_BATCHES_PER_RUN: usize = 50;
+
+/// Tables whose IPs are cleared, the rows stay.
+const IP_TABLES: [&str; 3] = ["spreadsheet_data", "column_labels", "cell_comments"];
+
+static HISTORY_DELETED: AtomicU64 = AtomicU64::new(0);
+static IPS_REMOVED: AtomicU64 = AtomicU64::new(0);
+
+/// Rows older than `max_age`.
+fn older_than(column: &str, max_age: Duration) -> String {
+    format!(
+        "{column} < NOW() - INTERVAL '{} seconds'",
+        max_age.as_secs()
+    )
+}
+
+/// Old values of cells, without the current ones.
+fn old_values() -> String {
+    format!(
+        "SELECT d.* FROM spreadsheet_data d \
+        JOIN (SELECT id, MAX(ts) AS ts FROM spreadsheet_data GROUP BY id) l \
+        ON d.id = l.id AND d.ts < l.ts \
+        WHERE {} LIMIT {CLEANUP_BATCH_SIZE}",
+        older_than("d.ts", *HISTORY_RETENTION)
+    )
+}
+
+pub(crate) fn spawn_cleanup(client: Client) {
+    if RETENTION_INTERVAL.is_zero() || (HISTORY_RETENTION.is_zero() && IP_RETENTION.is_zero()) {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(*RETENTION_INTERVAL).await;
+            if !fanout::is_publisher("spreadsheet_view") {
+                continue;
+            }
+            enforce(&client).await;
+        }
+    });
+}
+
+async fn enforce(client: &Client) {
+    if !HISTORY_RETENTION.is_zero() {
+        let delete = |rows: Vec<Value>| async move {
+            delete_batch(client.clone(), "spreadsheet_data", &rows).await
+        };
+        match in_batches(client, &old_values(), &HISTORY_DELETED, delete).await {
+            Ok(0) => {}
+            Ok(deleted) => info!("Deleted {deleted} old values of cells"),
+            Err(e) => error!("Failed to delete old values of cells: {e}"),
+        }
+    }
+    if !IP_RETENTION.is_zero() {
+        for table in IP_TABLES {
+            match clear_ips(client, table).await {
+                Ok(0) => {}
+                Ok(cleared) => info!("Cleared old IPs of {cleared} rows of {table}"),
+                Err(e) => error!("Failed to clear old IPs of {table}: {e}"),
+            }
+        }
+        let sql = format!(
+            "SELECT * FROM moderation_events WHERE {} LIMIT {CLEANUP_BATCH_SIZE}",
+            older_than("ts", *IP_RETENTION)
+        );
+        let delete = |rows: Vec<Value>| async move {
+            delete_batch(client.clone(), "moderation_events", &rows).await
+        };
+        match in_batches(client, &sql, &IPS_REMOVED, delete).await {
+            Ok(0) => {}
+            Ok(deleted) => info!("Deleted {deleted} old moderation events"),
+            Err(e) => error!("Failed to delete old moderation events: {e}"),
+        }
+    }
+}
+
+/// Replaces rows of `table` with old IPs by the same rows with an empty IP, returns how many.
+async fn clear_ips(client: &Client, table: &str) -> Result<usize, XlsError> {
+    let sql = format!(
+        "SELECT * FROM {table} WHERE ip <> '' AND {} LIMIT {CLEANUP_BATCH_SIZE}",
+        older_than("ts", *IP_RETENTION)
+    );
+    let replace = |rows: Vec<Value>| async move {
+        let cleared: Vec<Value> = rows
+            .iter()
+            .cloned()
+            .map(|mut row| {
+                row["ip"] = Value::from("");
+                row
+            })
+            .collect();
+        replace_batch(client.clone(), table, &rows, &cleared).await
+    };
+    in_batches(client, &sql, &IPS_REMOVED, replace).await
+}
+
+/// Hands the rows `sql` selects to `process` batch by batch, until a batch isn't full or after
+/// [`MAX_BATCHES_PER_RUN`] batches, and counts them in `counter`. Returns how many rows were
+/// processed.
+///
+/// Feldera might not have applied a batch yet when the next one is selected, rows selected again
+/// are skipped: processing a row twice corrupts the table.
+async fn in_batches<F: Future<Output = Result<(), XlsError>>>(
+    client: &Client,
+    sql: &str,
+    counter: &AtomicU64,
+    process: impl Fn(Vec<Value>) -> F,
+) -> Result<usize, XlsError> {
+    let mut processed = HashSet::new();
+    for _ in 0..MAX_BATCHES_PER_RUN {
+        let rows: Vec<Value> = parse_rows(&adhoc_query(client.clone(), sql).await?)?;
+        let full = rows.len() == CLEANUP_BATCH_SIZE;
+        let rows: Vec<Value> = rows
+            .into_iter()
+            .filter(|row| !processed.contains(&row.to_string()))
+            .collect();
+        if rows.is_empty() {
+            break;
+        }
+        processed.extend(rows.iter().map(Value::to_string));
+        counter.fetch_add(rows.len() as u64, Ordering::Relaxed);
+        process(rows).await?;
+        if !full {
+            break;
+        }
+    }
+    Ok(processed.len())
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct RetentionPolicy {
+    /// Seconds old values of cells are kept, `0` keeps them forever.
+    history_secs: u64,
+    /// Seconds the IPs of clients are kept, `0` keeps them forever.
+    ip_secs: u64,
+    /// How often the policy is enforced, `0` if this instance doesn't.
+    interval_secs: u64,
+    /// Old values of cells deleted since the server started.
+    history_deleted: u64,
+    /// Rows whose IP was cleared or that were deleted for their IP since the server started.
+    ips_removed: u64,
+}
+
+/// Shows how long the history of cells and the IPs of clients are kept.
+#[utoipa::path(
+    get,
+    path = "/api/admin/retention",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = RetentionPolicy),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+pub(crate) async fn policy() -> Json<RetentionPolicy> {
+    let enforced = !HISTORY_RETENTION.is_zero() || !IP_RETENTION.is_zero();
+    Json(RetentionPolicy {
+        history_secs: HISTORY_RETENTION.as_secs(),
+        ip_secs: IP_RETENTION.as_secs(),
+        interval_secs: match enforced {
+            true => RETENTION_INTERVAL.as_secs(),
+            false => 0,
+        },
+        history_deleted: HISTORY_DELETED.load(Ordering::Relaxed),
+        ips_removed: IPS_REMOVED.load(Ordering::Relaxed),
+    })
+}
@@ -0,0 +1,132 @@
+//! Clears cells in scratch regions that nobody touched for a while, so the public sheet
+//! doesn't accrete junk forever.
+//!
+//! Scratch regions are configured with `SCRATCH_REGIONS`, a comma-separated list of cell id
+//! ranges `from..to`. Cells in them whose latest update is older than `SCRATCH_MAX_AGE_SECS`
+//! are deleted every `SCRATCH_CLEANUP_INTERVAL_SECS`, or on demand with
+//! `POST /api/admin/cleanup`. The scheduled cleanup only runs on the publisher, see
+//! [`fanout::is_publisher`].
+
+use std::env::var;
+use std::ops::Range;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::Json;
+use log::{error, info, warn};
+use reqwest::Client;
+use serde::Serialize;
+use xls_protocol::{ErrorResponse, CELL_IDS};
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::fanout;
+use crate::feldera::delete_rows;
+use crate::AppState;
+
+static SCRATCH_REGIONS: LazyLock<Vec<Range<i64>>> = LazyLock::new(|| {
+    var("SCRATCH_REGIONS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|region| !region.is_empty())
+        .filter_map(|region| {
+            let parsed = parse_region(region);
+            if parsed.is_none() {
+                warn!("Ignoring invalid region {region:?} in SCRATCH_REGIONS");
+            }
+            parsed
+        })
+        .collect()
+});
+static SCRATCH_MAX_AGE: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("SCRATCH_MAX_AGE_SECS", 7 * 24 * 60 * 60)));
+/// How often stale cells are cleared, `0` disables the scheduled cleanup.
+static SCRATCH_CLEANUP_INTERVAL: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("SCRATCH_CLEANUP_INTERVAL_SECS", 3600)));
+
+/// Rows deleted with a single request.
+const CLEANUP_BATCH_SIZE: usize = 1000;
+
+/// Serializes the scheduled and the triggered cleanup, so rows aren't deleted twice.
+static CLEANUP: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+pub(crate) fn parse_region(region: &str) -> Option<Range<i64>> {
+    let (from, to) = region.split_once("..")?;
+    let (from, to) = (from.trim().parse().ok()?, to.trim().parse().ok()?);
+    (from < to && from >= CELL_IDS.start && to <= CELL_IDS.end).then_some(from..to)
+}
+
+/// All rows of stale cells in scratch regions.
+fn stale_rows() -> Option<String> {
+    if SCRATCH_REGIONS.is_empty() {
+        return None;
+    }
+    let regions = SCRATCH_REGIONS
+        .iter()
+        .map(|region| format!("(id >= {} AND id < {})", region.start, region.end))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    Some(format!(
+        "SELECT d.* FROM spreadsheet_data d \
+        JOIN (SELECT id FROM spreadsheet_data WHERE {regions} GROUP BY id \
+        HAVING MAX(ts) < NOW() - INTERVAL '{} seconds') s \
+        ON d.id = s.id LIMIT {CLEANUP_BATCH_SIZE}",
+        SCRATCH_MAX_AGE.as_secs()
+    ))
+}
+
+/// Deletes stale cells, returns the number of deleted rows.
+async fn cleanup(client: &Client) -> Result<usize, XlsError> {
+    let Some(sql) = stale_rows() else {
+        return Ok(0);
+    };
+    let _guard = CLEANUP.lock().await;
+    delete_rows(client, "spreadsheet_data", &sql).await
+}
+
+pub(crate) fn spawn_cleanup(client: Client) {
+    if SCRATCH_CLEANUP_INTERVAL.is_zero() || SCRATCH_REGIONS.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(*SCRATCH_CLEANUP_INTERVAL).await;
+            if !fanout::is_publisher("spreadsheet_view") {
+                continue;
+            }
+            match cleanup(&client).await {
+                Ok(0) => {}
+                Ok(deleted) => info!("Deleted {deleted} rows of stale scratch cells"),
+                Err(e) => error!("Failed to clear stale scratch cells: {e}"),
+            }
+        }
+    });
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct CleanupResponse {
+    /// Rows deleted from `spreadsheet_data`, at most 1000 per call.
+    deleted: usize,
+}
+
+/// Clears stale cells in the scratch regions now.
+#[utoipa::path(
+    post,
+    path = "/api/admin/cleanup",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = CleanupResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn trigger(
+    State(state): State<AppState>,
+) -> Result<Json<CleanupResponse>, XlsError> {
+    let deleted = cleanup(&state.http_client).await?;
+    info!("Deleted {deleted} rows of stale scratch cells on request");
+    Ok(Json(CleanupResponse { deleted }))
+}
@@ -0,0 +1,249 @@
+//! Searches the content of the cells for a text, backed by an in-memory trigram index of
+//! `spreadsheet_view`.
+//!
+//! Every three consecutive characters of the raw and computed value of a cell point to the cell,
+//! so a search only checks the cells that have all trigrams of the text instead of scanning the
+//! sheet. The index is loaded when the server starts and kept up to date by the change stream, it
+//! is loaded again if changes were missed.
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use log::{info, trace, warn};
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use xls_protocol::{Cell, ErrorResponse};
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::feldera::{adhoc_query, parse_rows};
+use crate::privacy;
+use crate::spreadsheet::{client_ip, CLIENT_IP_HEADER};
+use crate::AppState;
+
+/// Most cells a search returns.
+static SEARCH_MAX_RESULTS: LazyLock<usize> = LazyLock::new(|| env_or("SEARCH_MAX_RESULTS", 100));
+
+/// Time between attempts to load the index while Feldera is unavailable.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Searched texts have at least one trigram.
+const MIN_QUERY_CHARS: usize = 3;
+
+/// Most cells a search checks for the text, so a text with only common trigrams doesn't hold the
+/// index lock for long. The cells beyond it are missing from the results.
+const MAX_CANDIDATES: usize = 10_000;
+
+type Trigram = [char; 3];
+
+/// The trigrams of a lowercase text.
+fn trigrams(text: &str) -> impl Iterator<Item = Trigram> + '_ {
+    let chars: Vec<char> = text.chars().collect();
+    (0..chars.len().saturating_sub(2)).map(move |i| [chars[i], chars[i + 1], chars[i + 2]])
+}
+
+/// What a search matches for a cell.
+fn searched_text(cell: &Cell) -> String {
+    format!("{}\n{}", cell.raw_value, cell.computed_value).to_lowercase()
+}
+
+#[derive(Default)]
+struct Index {
+    cells: HashMap<i64, (Cell, String)>,
+    trigrams: HashMap<Trigram, HashSet<i64>>,
+}
+
+impl Index {
+    fn insert(&mut self, cell: Cell) {
+        self.remove(cell.id);
+        if cell.raw_value.is_empty() {
+            return;
+        }
+        let text = searched_text(&cell);
+        for trigram in trigrams(&text) {
+            self.trigrams.entry(trigram).or_default().insert(cell.id);
+        }
+        self.cells.insert(cell.id, (cell, text));
+    }
+
+    fn remove(&mut self, id: i64) {
+        let Some((_, text)) = self.cells.remove(&id) else {
+            return;
+        };
+        for trigram in trigrams(&text) {
+            if let Some(ids) = self.trigrams.get_mut(&trigram) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.trigrams.remove(&trigram);
+                }
+            }
+        }
+    }
+
+    /// The cells containing `text` ordered by id, at most `limit` of them.
+    fn search(&self, text: &str, limit: usize) -> Vec<Cell> {
+        let text = text.to_lowercase();
+        let mut postings = Vec::new();
+        for trigram in trigrams(&text) {
+            match self.trigrams.get(&trigram) {
+                Some(ids) => postings.push(ids),
+                None => return vec![],
+            }
+        }
+        // Candidates are the cells of the rarest trigram that have all other trigrams.
+        postings.sort_by_key(|ids| ids.len());
+        let Some((rarest, others)) = postings.split_first() else {
+            return vec![];
+        };
+        let mut ids: Vec<i64> = rarest
+            .iter()
+            .take(MAX_CANDIDATES)
+            .filter(|id| others.iter().all(|ids| ids.contains(id)))
+            .filter(|id| self.cells[id].1.contains(&text))
+            .copied()
+            .collect();
+        ids.sort_unstable();
+        ids.into_iter()
+            .take(limit)
+            .map(|id| self.cells[&id].0.clone())
+            .collect()
+    }
+}
+
+/// The trigram index, `None` until it is loaded for the first time.
+#[derive(Default)]
+pub(crate) struct SearchIndex(RwLock<Option<Index>>);
+
+impl SearchIndex {
+    /// Loads the index and applies the changes of `spreadsheet_view` to it in the background.
+    pub(crate) fn spawn(
+        client: Client,
+        mut changes: Receiver<Result<String, XlsError>>,
+    ) -> Arc<Self> {
+        let index = Arc::new(SearchIndex::default());
+        let updated = index.clone();
+        tokio::spawn(async move {
+            let mut stale = true;
+            loop {
+                if stale {
+                    match load(&client).await {
+                        Ok(loaded) => {
+                            *updated.0.write().unwrap() = Some(loaded);
+                            stale = false;
+                        }
+                        Err(e) => {
+                            warn!("Error loading the search index: {e}");
+                            tokio::time::sleep(RETRY_INTERVAL).await;
+                            continue;
+                        }
+                    }
+                }
+                // Changes arriving while the index is loaded are buffered by the subscription
+                // and applied afterwards.
+                match changes.recv().await {
+                    Ok(Ok(change)) => match serde_json::from_str::<Cell>(&change) {
+                        Ok(cell) => {
+                            if let Some(index) = updated.0.write().unwrap().as_mut() {
+                                index.insert(cell);
+                            }
+                        }
+                        Err(e) => warn!("Error parsing change: {e} (change {change})"),
+                    },
+                    Ok(Err(XlsError::ChangesMissed(e))) => {
+                        warn!("Search index is stale: {e}");
+                        stale = true;
+                    }
+                    Ok(Err(_)) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Search index is stale, skipped {skipped} changes");
+                        stale = true;
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+        index
+    }
+}
+
+/// Builds the index of all cells of `spreadsheet_view`.
+async fn load(client: &Client) -> Result<Index, XlsError> {
+    let started = Instant::now();
+    let rows = adhoc_query(client.clone(), "SELECT * FROM spreadsheet_view").await?;
+    let mut index = Index::default();
+    for cell in parse_rows::<Cell>(&rows)? {
+        index.insert(cell);
+    }
+    info!(
+        "Indexed {} cells for search in {:?}",
+        index.cells.len(),
+        started.elapsed()
+    );
+    Ok(index)
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct SearchParams {
+    /// The text to search for.
+    q: String,
+    /// Most cells to return.
+    limit: Option<usize>,
+}
+
+/// Lists the cells whose raw or computed value contains a text, ignoring case, ordered by id.
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    params(
+        ("q" = String, Query, description = "Text to search for, at least 3 characters"),
+        ("limit" = Option<usize>, Query, description = "Most cells to return, at most `SEARCH_MAX_RESULTS`"),
+    ),
+    responses(
+        (status = 200, description = "The cells containing the text", body = [Cell]),
+        (status = 400, description = "The text is too short", body = ErrorResponse),
+        (status = 429, body = ErrorResponse),
+        (status = 503, description = "The search index is loading", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn search(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<Cell>>, XlsError> {
+    if params.q.chars().count() < MIN_QUERY_CHARS {
+        return Err(XlsError::InvalidField(
+            "q",
+            format!("Search for at least {MIN_QUERY_CHARS} characters"),
+        ));
+    }
+    let client_ip = privacy::anonymize(client_ip(
+        headers.get(CLIENT_IP_HEADER).map(|ip| ip.as_bytes()),
+        addr,
+    ));
+    if !state.region_limiter.check(&client_ip) {
+        let retry_after = state.region_limiter.retry_after(&client_ip);
+        return Err(XlsError::RateLimited(Some(retry_after.as_secs())));
+    }
+    let limit = params
+        .limit
+        .unwrap_or(*SEARCH_MAX_RESULTS)
+        .min(*SEARCH_MAX_RESULTS);
+    let started = Instant::now();
+    let index = state.search_index.0.read().unwrap();
+    let Some(index) = index.as_ref() else {
+        return Err(XlsError::FelderaUnavailable(String::from(
+            "The search index is loading",
+        )));
+    };
+    let cells = index.search(&params.q, limit);
+    trace!("Searched {:?} in {:?}", params.q, started.elapsed());
+    Ok(Json(cells))
+}
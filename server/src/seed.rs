@@ -0,0 +1,139 @@
+//! Fills a region with generated sample content, so fresh deployments and screenshots don't
+//! start from an empty sheet.
+//!
+//! The region is laid out as small tables of four columns: an item, its price, a quantity and a
+//! formula multiplying the two, under a header row. Rows are striped in the color of their table.
+
+use std::ops::Range;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use log::info;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use xls_protocol::address::{self, cell_name, COLUMNS};
+use xls_protocol::{CellStyle, ErrorResponse, UpdateRequest, FIRST_SHEET};
+
+use crate::cached_ranges;
+use crate::error::XlsError;
+use crate::spreadsheet::UpdatePayload;
+use crate::AppState;
+
+/// Most cells seeded with a single request, i.e. 100 rows.
+const MAX_SEEDED_CELLS: i64 = 100 * COLUMNS;
+
+/// Stored as the IP of the seeded cells.
+const SEED_IP: &str = "seed";
+
+const HEADERS: [&str; 4] = ["Item", "Price", "Qty", "Total"];
+
+const ITEMS: [&str; 12] = [
+    "Apples", "Pears", "Plums", "Cherries", "Lemons", "Figs", "Dates", "Grapes", "Melons", "Kiwis",
+    "Limes", "Mangos",
+];
+
+/// Background colors of the tables, the header and the two stripes of the rows.
+const PALETTE: [[[u8; 3]; 3]; 4] = [
+    [[66, 133, 244], [232, 240, 254], [210, 227, 252]],
+    [[52, 168, 83], [230, 244, 234], [206, 234, 214]],
+    [[251, 188, 4], [254, 247, 224], [252, 232, 178]],
+    [[234, 67, 53], [252, 232, 230], [250, 210, 207]],
+];
+
+/// An opaque color as stored in `background`.
+fn background([r, g, b]: [u8; 3]) -> i32 {
+    i32::from_le_bytes([r, g, b, 255])
+}
+
+/// The sample content of the cells of `range`, the same `seed` generates the same content.
+fn generate(range: Range<i64>, seed: u64) -> Vec<UpdateRequest> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let first_row = address::position(range.start).0;
+    range
+        .map(|id| {
+            let (row, column) = address::position(id);
+            let row = row - first_row;
+            let colors = PALETTE[(column / 4) as usize % PALETTE.len()];
+            let raw_value = match (row, column % 4) {
+                (0, kind) => HEADERS[kind as usize].to_string(),
+                (_, 0) => ITEMS[rng.gen_range(0..ITEMS.len())].to_string(),
+                (_, 1) => format!("{:.2}", rng.gen_range(50..2000) as f64 / 100.0),
+                (_, 2) => rng.gen_range(1..=24).to_string(),
+                _ => format!("={}*{}", cell_name(id - 2), cell_name(id - 1)),
+            };
+            UpdateRequest {
+                id,
+                raw_value,
+                background: background(match row {
+                    0 => colors[0],
+                    row => colors[1 + row as usize % 2],
+                }),
+                style: CellStyle::default(),
+                ttl_secs: None,
+                sheet: FIRST_SHEET,
+            }
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct SeedParams {
+    /// Seed of the generated content, random if not set.
+    seed: Option<u64>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct SeedResponse {
+    /// Cells written.
+    seeded: usize,
+    /// Generates the same content again.
+    seed: u64,
+}
+
+/// Fills a range of at most 2600 cells with sample content: tables of items, prices,
+/// quantities and formulas, with colored rows. Existing cells in the range are overwritten.
+#[utoipa::path(
+    post,
+    path = "/api/admin/seed/{range}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(
+        ("range" = String, Path, description = "Range of cell ids, e.g. `0..520`"),
+        ("seed" = Option<u64>, Query, description = "Seed of the generated content, random if not set"),
+    ),
+    responses(
+        (status = 200, description = "The cells were sent to Feldera", body = SeedResponse),
+        (status = 202, description = "Feldera is unavailable, the cells are sent later", body = SeedResponse),
+        (status = 400, description = "Invalid or too large range", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn seed(
+    State(state): State<AppState>,
+    Path(range): Path<String>,
+    Query(params): Query<SeedParams>,
+) -> Result<(StatusCode, Json<SeedResponse>), XlsError> {
+    let range = cached_ranges::parse(&range)?;
+    if range.end - range.start > MAX_SEEDED_CELLS {
+        return Err(XlsError::InvalidField(
+            "range",
+            format!("At most {MAX_SEEDED_CELLS} cells can be seeded at once"),
+        ));
+    }
+    let seed = params.seed.unwrap_or_else(rand::random);
+    // Censoring would mangle some of the references, e.g. `=F2*G2`.
+    let payloads: Vec<UpdatePayload> = generate(range.clone(), seed)
+        .into_iter()
+        .map(|update| UpdatePayload::generated(update, SEED_IP))
+        .collect();
+    let ingested = state.ingest_queue.insert(&payloads).await?;
+    info!(
+        "Seeded cells {}..{} with seed {seed}",
+        range.start, range.end
+    );
+    let seeded = payloads.len();
+    Ok((ingested.status(), Json(SeedResponse { seeded, seed })))
+}
@@ -1,192 +1,776 @@
-use axum::http::HeaderMap;
+use axum::http::header::ORIGIN;
+use axum::http::{HeaderMap, StatusCode};
 use axum::{
-    extract::ws::{Message, WebSocket, WebSocketUpgrade},
-    extract::{connect_info::ConnectInfo, Json, State},
-    response::IntoResponse,
+    extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+    extract::{connect_info::ConnectInfo, Json, Query, State},
+    response::{IntoResponse, Response},
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use futures::{sink::SinkExt, stream::StreamExt};
+use ipnet::IpNet;
 use log::{debug, error, trace, warn};
+use rand::Rng;
 use regex::Regex;
-use reqwest::Client;
 use rustrict::Censor;
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
 use std::net::SocketAddr;
 use std::ops::{ControlFlow, Range};
-use std::sync::Arc;
-use tokio::sync::{broadcast::Receiver, mpsc, watch, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::sync::{mpsc, watch, RwLock};
+use tokio_util::sync::CancellationToken;
+use xls_protocol::{
+    Cell, ClientMessage, CloseReason, ErrorResponse, Heartbeat, PinnedRegions, PresenterMessage,
+    ReconnectSoon, Region, UpdateRequest, WsError, CELL_IDS, FIRST_SHEET,
+};
 
-use crate::feldera::{adhoc_query, insert};
-use crate::stats::XlsError;
+use crate::backend::SpreadsheetBackend;
+use crate::config::env_or;
+use crate::embed_tokens::Reader;
+use crate::error::{JsonBody, XlsError};
+use crate::formula;
+use crate::ingest_queue::Ingested;
+use crate::ip_filter;
+use crate::meta;
+use crate::metrics::METRICS;
+use crate::origin;
+use crate::presenter;
+use crate::privacy;
+use crate::query_pool::QueryPool;
+use crate::rate_limit::{self, RateLimiter};
+use crate::subscribers::Subscriber;
+use crate::ws_auth::{self, WsParams};
 use crate::AppState;
 
+/// Time within which the websockets of a drained server are closed.
+static WS_DRAIN: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("WS_DRAIN_SECS", 30)));
+
+/// Time between the heartbeats sent over websockets, `0` disables them.
+static WS_HEARTBEAT: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("WS_HEARTBEAT_SECS", 5)));
+
+/// Longest time-to-live of an ephemeral cell.
+static CELL_MAX_TTL_SECS: LazyLock<u64> =
+    LazyLock::new(|| env_or("CELL_MAX_TTL_SECS", 30 * 24 * 60 * 60));
+
+/// Most cell ids the cached ranges may span together.
+static CACHE_MAX_IDS: LazyLock<i64> = LazyLock::new(|| env_or("CACHE_MAX_IDS", 1_000_000));
+
+/// Region queries taking longer are logged as warnings.
+static REGION_QUERY_SLOW: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_millis(env_or("REGION_QUERY_SLOW_MS", 1000)));
+
+/// A range of cell ids whose cells are kept in memory.
+struct CachedRange {
+    range: Range<i64>,
+    /// Regions are only served from the cache once the cells of the range are loaded.
+    loaded: bool,
+}
+
+/// The cached ranges, they are locked briefly and never while awaiting.
+type CachedRanges = std::sync::RwLock<Vec<CachedRange>>;
+
+/// The cells of `spreadsheet_view`, the front and the back of the sheet are cached in memory.
+/// Operators can cache other ranges at runtime through the admin API.
+///
+/// If changes of the view are missed, e.g. because the change stream reconnected, the cache is
+/// stale and reloaded, regions are queried from the backend in the meantime.
 pub(crate) struct SpreadSheetView {
-    client: Client,
+    backend: Arc<dyn SpreadsheetBackend>,
     cells: Arc<RwLock<BTreeMap<i64, Cell>>>,
+    ranges: Arc<CachedRanges>,
+    stale: Arc<AtomicBool>,
+    /// Bounds the queries of regions that aren't answered from the cache.
+    pool: QueryPool,
 }
 
 impl SpreadSheetView {
     const CACHE_FRONT: Range<i64> = 0..100_000;
     const CACHE_BACK: Range<i64> = 1_039_900_000..1_040_000_000;
 
-    pub(crate) async fn new(
-        client: Client,
+    /// How long to wait before loading a cached range again after it failed to load.
+    const LOAD_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+    /// The front and the back of the sheet are loaded in the background, their regions are
+    /// queried from the backend until they are loaded.
+    pub(crate) fn new(
+        backend: Arc<dyn SpreadsheetBackend>,
         xls_subscription: Receiver<Result<String, XlsError>>,
     ) -> Self {
         let cells = Arc::new(RwLock::new(BTreeMap::new()));
-        Self::spawn_update_cache_task(xls_subscription, cells.clone());
-        Self::initialize_cache(client.clone(), cells.clone(), Self::CACHE_FRONT).await;
-        Self::initialize_cache(client.clone(), cells.clone(), Self::CACHE_BACK).await;
-        SpreadSheetView { client, cells }
-    }
-
-    fn id_is_cached(id: i64) -> bool {
-        Self::CACHE_FRONT.contains(&id) || Self::CACHE_BACK.contains(&id)
+        let ranges = Arc::new(CachedRanges::new(
+            [Self::CACHE_FRONT, Self::CACHE_BACK]
+                .into_iter()
+                .map(|range| CachedRange {
+                    range,
+                    loaded: false,
+                })
+                .collect(),
+        ));
+        let stale = Arc::new(AtomicBool::new(false));
+        Self::spawn_update_cache_task(
+            backend.clone(),
+            xls_subscription,
+            cells.clone(),
+            ranges.clone(),
+            stale.clone(),
+        );
+        Self::spawn_initial_load(backend.clone(), cells.clone(), ranges.clone());
+        SpreadSheetView {
+            backend,
+            cells,
+            ranges,
+            stale,
+            pool: QueryPool::from_env(),
+        }
     }
 
-    async fn initialize_cache(
-        client: Client,
+    /// Loads the cached ranges that aren't loaded yet one after the other, retrying the ones that
+    /// fail until they are loaded or removed.
+    fn spawn_initial_load(
+        backend: Arc<dyn SpreadsheetBackend>,
         cells: Arc<RwLock<BTreeMap<i64, Cell>>>,
-        range: Range<i64>,
+        ranges: Arc<CachedRanges>,
     ) {
-        let sql = format!(
-            "SELECT * FROM spreadsheet_view WHERE id >= {} and id < {}",
-            range.start, range.end
-        );
-        match adhoc_query(client, sql.as_str()).await {
-            Ok(snapshot) => {
-                for line in snapshot.trim().split('\n') {
-                    if line.is_empty() {
-                        continue;
-                    }
-                    match serde_json::from_str::<Cell>(&line) {
-                        Ok(cell) => {
-                            cells.write().await.insert(cell.id, cell);
-                        }
-                        Err(e) => {
-                            warn!("Error parsing change: {e} (change {line})");
+        let pending: Vec<Range<i64>> = ranges
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|cached| !cached.loaded)
+            .map(|cached| cached.range.clone())
+            .collect();
+        METRICS
+            .cache_ranges_loading
+            .fetch_add(pending.len() as i64, Ordering::Relaxed);
+        tokio::spawn(async move {
+            for range in pending {
+                loop {
+                    match Self::load_range(&*backend, &cells, range.clone()).await {
+                        Ok(()) => {
+                            Self::set_loaded(&ranges, &range);
+                            debug!("Loaded the cached cells {}..{}", range.start, range.end);
+                            break;
                         }
+                        Err(e) => warn!(
+                            "Error loading the cached cells {}..{}, retrying in {:?}: {e}",
+                            range.start,
+                            range.end,
+                            Self::LOAD_RETRY_DELAY
+                        ),
+                    }
+                    tokio::time::sleep(Self::LOAD_RETRY_DELAY).await;
+                    let cached = ranges
+                        .read()
+                        .unwrap()
+                        .iter()
+                        .any(|cached| cached.range == range);
+                    if !cached {
+                        break;
                     }
                 }
+                METRICS.cache_ranges_loading.fetch_sub(1, Ordering::Relaxed);
             }
-            Err(e) => {
-                panic!("Error filling spreadsheet cache: {e}");
+        });
+    }
+
+    /// Serves the regions of a cached range from the cache from now on.
+    fn set_loaded(ranges: &CachedRanges, range: &Range<i64>) {
+        for cached in ranges.write().unwrap().iter_mut() {
+            if cached.range == *range {
+                cached.loaded = true;
             }
         }
     }
 
+    /// Whether changes of a cell are applied to the cache, also while its range is loaded.
+    fn id_is_cached(ranges: &CachedRanges, id: i64) -> bool {
+        ranges
+            .read()
+            .unwrap()
+            .iter()
+            .any(|cached| cached.range.contains(&id))
+    }
+
+    /// Replaces the cached cells with the ones in the backend.
+    async fn load_cache(
+        backend: &dyn SpreadsheetBackend,
+        cells: &RwLock<BTreeMap<i64, Cell>>,
+        ranges: &CachedRanges,
+    ) -> Result<(), XlsError> {
+        let ranges: Vec<Range<i64>> = ranges
+            .read()
+            .unwrap()
+            .iter()
+            .map(|cached| cached.range.clone())
+            .collect();
+        for range in ranges {
+            Self::load_range(backend, cells, range).await?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the cached cells of a range with the ones in the backend.
+    async fn load_range(
+        backend: &dyn SpreadsheetBackend,
+        cells: &RwLock<BTreeMap<i64, Cell>>,
+        range: Range<i64>,
+    ) -> Result<(), XlsError> {
+        let snapshot = backend.query_range(range.clone()).await?;
+        let mut loaded = BTreeMap::new();
+        for line in snapshot.trim().split('\n') {
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Cell>(line) {
+                Ok(cell) => {
+                    loaded.insert(cell.id, cell);
+                }
+                Err(e) => {
+                    warn!("Error parsing change: {e} (change {line})");
+                }
+            }
+        }
+        let mut cells = cells.write().await;
+        cells.retain(|id, _| !range.contains(id));
+        cells.append(&mut loaded);
+        Ok(())
+    }
+
     fn spawn_update_cache_task(
+        backend: Arc<dyn SpreadsheetBackend>,
         mut xls_subscription: Receiver<Result<String, XlsError>>,
         cells: Arc<RwLock<BTreeMap<i64, Cell>>>,
+        ranges: Arc<CachedRanges>,
+        stale: Arc<AtomicBool>,
     ) {
         tokio::spawn(async move {
             loop {
-                match xls_subscription.recv().await {
-                    Ok(Ok(change)) => match serde_json::from_str::<Cell>(&change) {
-                        Ok(cell) => {
-                            if Self::id_is_cached(cell.id) {
-                                cells.write().await.insert(cell.id, cell);
+                let missed = match xls_subscription.recv().await {
+                    Ok(Ok(change)) => {
+                        match serde_json::from_str::<Cell>(&change) {
+                            Ok(cell) => {
+                                if Self::id_is_cached(&ranges, cell.id) {
+                                    cells.write().await.insert(cell.id, cell);
+                                }
+                            }
+                            Err(e) => {
+                                error!("Error parsing change: {e} (change {change})");
                             }
                         }
-                        Err(e) => {
-                            error!("Error parsing change: {e} (change {change})");
-                        }
-                    },
+                        false
+                    }
+                    Ok(Err(XlsError::ChangesMissed(e))) => {
+                        warn!("Cached cells are stale: {e}");
+                        true
+                    }
                     Ok(Err(e)) => {
                         warn!("Error receiving change: {e}");
+                        false
                     }
-                    Err(e) => {
-                        warn!("Error receiving change: {e}");
-                        break;
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Cached cells are stale, skipped {skipped} changes");
+                        true
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                // Changes arriving while the cache is loaded are buffered by the subscription
+                // and applied afterwards.
+                if missed || stale.load(Ordering::Relaxed) {
+                    stale.store(true, Ordering::Relaxed);
+                    METRICS.cache_resyncs_total.fetch_add(1, Ordering::Relaxed);
+                    match Self::load_cache(&*backend, &cells, &ranges).await {
+                        Ok(()) => stale.store(false, Ordering::Relaxed),
+                        Err(e) => warn!("Error reloading spreadsheet cache: {e}"),
                     }
                 }
             }
         });
     }
 
-    async fn query(&self, region: Region) -> Result<String, XlsError> {
-        if Self::id_is_cached(region.from) && Self::id_is_cached(region.to - 1) {
-            let mut snapshot = String::new();
-            for (_id, cell) in self.cells.read().await.range(region.from..region.to) {
-                snapshot.push_str(&serde_json::to_string(cell).unwrap());
-                snapshot.push('\n');
+    /// The cells of a region from the cache if it is in a loaded cached range, from Feldera
+    /// otherwise. Queries slower than `REGION_QUERY_SLOW_MS` are logged as warnings.
+    pub(crate) async fn query(&self, region: Region) -> Result<String, XlsError> {
+        let started = Instant::now();
+        let loaded = self
+            .ranges
+            .read()
+            .unwrap()
+            .iter()
+            .find(|cached| cached.range.start <= region.from && region.to <= cached.range.end)
+            .map(|cached| cached.loaded);
+        let cache = &METRICS.region_query_cache_seconds;
+        let feldera = &METRICS.region_query_feldera_seconds;
+        let (source, histogram, snapshot) = match loaded {
+            Some(true) if !self.stale.load(Ordering::Relaxed) => {
+                METRICS.cache_hits_total.fetch_add(1, Ordering::Relaxed);
+                ("cache", cache, Ok(self.query_cache(&region).await))
             }
-            return Ok(snapshot);
+            Some(_) => {
+                METRICS
+                    .cache_fallbacks_total
+                    .fetch_add(1, Ordering::Relaxed);
+                ("feldera", feldera, self.query_feldera(&region).await)
+            }
+            None => {
+                METRICS.cache_misses_total.fetch_add(1, Ordering::Relaxed);
+                ("feldera", feldera, self.query_feldera(&region).await)
+            }
+        };
+        let elapsed = started.elapsed();
+        histogram.observe(elapsed);
+        if elapsed >= *REGION_QUERY_SLOW {
+            warn!(
+                "Querying cells {}..{} from {source} took {elapsed:?}",
+                region.from, region.to
+            );
+        } else {
+            trace!(
+                "Queried cells {}..{} from {source} in {elapsed:?}",
+                region.from,
+                region.to
+            );
         }
+        snapshot
+    }
 
-        let sql = format!(
-            "SELECT * FROM spreadsheet_view WHERE id >= {} and id < {}",
-            region.from, region.to
-        );
-        adhoc_query(self.client.clone(), sql.as_str()).await
+    async fn query_cache(&self, region: &Region) -> String {
+        let mut snapshot = String::new();
+        for (_id, cell) in self.cells.read().await.range(region.from..region.to) {
+            snapshot.push_str(&serde_json::to_string(cell).unwrap());
+            snapshot.push('\n');
+        }
+        snapshot
+    }
+
+    async fn query_feldera(&self, region: &Region) -> Result<String, XlsError> {
+        self.pool
+            .run(self.backend.query_range(region.from..region.to))
+            .await
+    }
+
+    /// Runs another query of Feldera, e.g. of the edit history of a cell, in turn with the
+    /// queries of regions.
+    pub(crate) async fn run_adhoc<T>(
+        &self,
+        query: impl Future<Output = Result<T, XlsError>>,
+    ) -> Result<T, XlsError> {
+        self.pool.run(query).await
+    }
+
+    /// The cached ranges and the number of cells cached in each of them.
+    pub(crate) async fn cached_ranges(&self) -> Vec<(Range<i64>, usize)> {
+        let ranges: Vec<Range<i64>> = self
+            .ranges
+            .read()
+            .unwrap()
+            .iter()
+            .map(|cached| cached.range.clone())
+            .collect();
+        let cells = self.cells.read().await;
+        ranges
+            .into_iter()
+            .map(|range| {
+                let count = cells.range(range.clone()).count();
+                (range, count)
+            })
+            .collect()
+    }
+
+    /// Loads the cells of a range into the cache, the range must not overlap a cached one.
+    pub(crate) async fn cache_range(&self, range: Range<i64>) -> Result<(), XlsError> {
+        {
+            let mut ranges = self.ranges.write().unwrap();
+            if let Some(cached) = ranges
+                .iter()
+                .find(|cached| cached.range.start < range.end && range.start < cached.range.end)
+            {
+                return Err(XlsError::InvalidField(
+                    "range",
+                    format!(
+                        "Overlaps the cached range {}..{}",
+                        cached.range.start, cached.range.end
+                    ),
+                ));
+            }
+            let cached_ids: i64 = ranges
+                .iter()
+                .map(|cached| cached.range.end - cached.range.start)
+                .sum();
+            if cached_ids + range.end - range.start > *CACHE_MAX_IDS {
+                return Err(XlsError::InvalidField(
+                    "range",
+                    format!(
+                        "The cached ranges can't span more than {} cell ids",
+                        *CACHE_MAX_IDS
+                    ),
+                ));
+            }
+            // Changes are applied to the range while it is loaded, so none are lost.
+            ranges.push(CachedRange {
+                range: range.clone(),
+                loaded: false,
+            });
+        }
+        METRICS.cache_ranges_loading.fetch_add(1, Ordering::Relaxed);
+        let loaded = Self::load_range(&*self.backend, &self.cells, range.clone()).await;
+        METRICS.cache_ranges_loading.fetch_sub(1, Ordering::Relaxed);
+        match loaded {
+            Ok(()) => {
+                Self::set_loaded(&self.ranges, &range);
+                Ok(())
+            }
+            Err(e) => {
+                self.remove_range(&range);
+                self.cells.write().await.retain(|id, _| !range.contains(id));
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes a cached range and its cells from the cache.
+    pub(crate) async fn uncache_range(&self, range: Range<i64>) -> Result<(), XlsError> {
+        if !self.remove_range(&range) {
+            return Err(not_cached(&range));
+        }
+        self.cells.write().await.retain(|id, _| !range.contains(id));
+        Ok(())
+    }
+
+    /// Reloads the cells of a cached range from the backend.
+    pub(crate) async fn refresh_range(&self, range: Range<i64>) -> Result<(), XlsError> {
+        let cached = self
+            .ranges
+            .read()
+            .unwrap()
+            .iter()
+            .any(|cached| cached.range == range);
+        if !cached {
+            return Err(not_cached(&range));
+        }
+        Self::load_range(&*self.backend, &self.cells, range).await
+    }
+
+    /// Returns whether the range was cached.
+    fn remove_range(&self, range: &Range<i64>) -> bool {
+        let mut ranges = self.ranges.write().unwrap();
+        let before = ranges.len();
+        ranges.retain(|cached| cached.range != *range);
+        ranges.len() != before
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
-#[allow(dead_code)]
-struct Cell {
-    id: i64,
-    background: i32,
-    raw_value: String,
-    computed_value: String,
+fn not_cached(range: &Range<i64>) -> XlsError {
+    XlsError::NotFound(format!(
+        "Range {}..{} is not cached",
+        range.start, range.end
+    ))
 }
 
-#[derive(serde::Deserialize, Debug, Copy, Clone)]
-struct Region {
-    from: i64,
-    to: i64,
+/// Maximum number of cells that can be queried at once through the GraphQL and gRPC APIs.
+const MAX_CELLS_PER_QUERY: i64 = 26 * 1000;
+
+/// Validates a range of cells `[from, to)` requested through the GraphQL or gRPC API.
+pub(crate) fn query_region(from: i64, to: i64) -> Result<Region, XlsError> {
+    if from >= to || !CELL_IDS.contains(&from) || to > CELL_IDS.end {
+        return Err(XlsError::Validation(String::from("Invalid cell range")));
+    }
+    if to - from > MAX_CELLS_PER_QUERY {
+        return Err(XlsError::Validation(format!(
+            "At most {MAX_CELLS_PER_QUERY} cells can be queried at once"
+        )));
+    }
+    Ok(Region::new(from, to))
 }
 
-impl Default for Region {
-    fn default() -> Self {
-        Region { from: 0, to: 2500 }
+/// Largest region a websocket client can subscribe to, larger regions are truncated.
+static WS_MAX_REGION_CELLS: LazyLock<i64> =
+    LazyLock::new(|| env_or("WS_MAX_REGION_CELLS", 26 * 400));
+
+/// Validates a region a websocket client subscribes to and truncates it to
+/// `WS_MAX_REGION_CELLS`.
+pub(crate) fn subscription_region(region: Region) -> Result<Region, XlsError> {
+    if region.from >= region.to || !CELL_IDS.contains(&region.from) {
+        return Err(XlsError::Validation(String::from("Invalid cell range")));
     }
+    check_sheet(region.sheet)?;
+    Ok(Region {
+        from: region.from,
+        to: region
+            .to
+            .min(region.from.saturating_add(*WS_MAX_REGION_CELLS))
+            .min(CELL_IDS.end),
+        sheet: region.sheet,
+    })
+}
+
+/// Only the first sheet is stored, regions and updates of the other tabs of a client are
+/// rejected until the backends keep cells per sheet.
+fn check_sheet(sheet: u32) -> Result<(), XlsError> {
+    if sheet != FIRST_SHEET {
+        return Err(XlsError::InvalidField(
+            "sheet",
+            format!("Only sheet {FIRST_SHEET} is served"),
+        ));
+    }
+    Ok(())
 }
 
+/// Most regions a websocket client can pin.
+static WS_MAX_PINNED_REGIONS: LazyLock<usize> =
+    LazyLock::new(|| env_or("WS_MAX_PINNED_REGIONS", 4));
+
+/// Validates the regions a websocket client pins and truncates each of them like
+/// [`subscription_region`].
+fn pinned_regions(pinned: Vec<Region>) -> Result<Vec<Region>, XlsError> {
+    if pinned.len() > *WS_MAX_PINNED_REGIONS {
+        return Err(XlsError::Validation(format!(
+            "At most {} regions can be pinned",
+            *WS_MAX_PINNED_REGIONS
+        )));
+    }
+    pinned.into_iter().map(subscription_region).collect()
+}
+
+/// Opens the websocket that streams cells of a region.
+///
+/// The client sends a [`Region`] to subscribe to, the server answers with a snapshot of the
+/// region as arrays of [`Cell`]s followed by all changes to it, one [`Cell`] per message. Invalid
+/// regions are answered with a [`WsError`]. Changes of the [`PinnedRegions`] are sent as well,
+/// regardless of the region the client is looking at. Clients following a live walkthrough also
+/// receive the viewport of the presenter, see [`PresenterMessage`]. A [`Heartbeat`] is sent every
+/// `WS_HEARTBEAT_SECS`.
+///
+/// Private deployments can require a token to connect, see [`ws_auth`]. Browsers can only connect
+/// from pages on the allowed origins, see [`origin`], unless they pass an embed token, which only
+/// reads the regions it was issued for, see [`embed_tokens`](crate::embed_tokens).
+///
+/// When the server closes the connection, it sends a [`WsError`] with the [`CloseReason`] and
+/// closes with its code, e.g. if the client IP has too many connections open or the server shuts
+/// down. A drained server sends [`ReconnectSoon`] first and closes the connection at a random
+/// time within `WS_DRAIN_SECS`.
+///
 /// The handler for the HTTP request (this gets called when the HTTP request lands at the start
 /// of websocket negotiation). After this completes, the actual switching from HTTP to
 /// websocket protocol will occur.
 /// This is the last point where we can extract TCP/IP metadata such as IP address of the client
 /// as well as things from HTTP headers such as user-agent of the browser etc.
+#[utoipa::path(
+    get,
+    path = "/api/spreadsheet",
+    params(
+        ("token" = Option<String>, Query, description = "Client token, if the server requires one, or embed token"),
+    ),
+    responses(
+        (status = 101, description = "Switching to the websocket protocol"),
+        (status = 403, description = "The client IP or origin is not allowed", body = ErrorResponse),
+        (status = 503, description = "The server is drained", body = ErrorResponse),
+    )
+)]
 pub(crate) async fn ws_handler(
     ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    Query(params): Query<WsParams>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<AppState>,
-) -> impl IntoResponse {
+) -> Response {
     debug!("{addr} connected.");
+    let page_origin = headers.get(ORIGIN).and_then(|origin| origin.to_str().ok());
+    let embed = params
+        .token()
+        .and_then(|token| state.embed_tokens.find(token));
+    if embed.is_none() && !origin::is_allowed(page_origin) {
+        debug!("{addr} connected from a page on {page_origin:?}");
+        return XlsError::OriginNotAllowed.into_response();
+    }
+    if state.drain.is_cancelled() {
+        debug!("{addr} connected while the server is drained");
+        return XlsError::Draining.into_response();
+    }
+    let client_ip = privacy::anonymize(client_ip(
+        headers.get(CLIENT_IP_HEADER).map(|ip| ip.as_bytes()),
+        addr,
+    ));
+    // Identified clients are rate limited by name, others by IP.
+    let client = match (&embed, ws_auth::identify(&params, &headers)) {
+        (Some(reader), _) => format!("embed:{}", reader.name()),
+        (None, Ok(Some(name))) => format!("token:{name}"),
+        (None, Ok(None)) => client_ip.clone(),
+        (None, Err(e)) => {
+            debug!("{addr} sent no valid token: {e}");
+            let error = String::from("A valid token is required to connect");
+            return reject(ws, CloseReason::AuthRequired, error);
+        }
+    };
+    let Some(subscriber) = state.subscribers.register(&client_ip) else {
+        debug!("{addr} has too many connections open");
+        METRICS
+            .ws_connections_rejected_total
+            .fetch_add(1, Ordering::Relaxed);
+        let error = String::from("Too many connections from this IP");
+        return reject(ws, CloseReason::RateLimited, error);
+    };
     ws.on_upgrade(move |socket| {
         handle_socket(
-            state.spreadsheet_view.clone(),
+            subscriber,
             state.xls_subscription.subscribe(),
+            state,
+            client,
+            embed,
             socket,
             addr,
         )
     })
+    .into_response()
+}
+
+/// Accepts the websocket only to close it for `reason`, browsers would just see a failed
+/// connection otherwise.
+fn reject(ws: WebSocketUpgrade, reason: CloseReason, error: String) -> Response {
+    ws.on_upgrade(move |mut socket| async move {
+        for message in close_messages(reason, error) {
+            if let Err(e) = socket.send(message).await {
+                debug!("Error closing websocket: {e}");
+                return;
+            }
+        }
+    })
+    .into_response()
+}
+
+/// The [`WsError`] and the close frame that close a websocket for `reason`.
+fn close_messages(reason: CloseReason, error: String) -> [Message; 2] {
+    let frame = CloseFrame {
+        code: reason.code(),
+        reason: error.clone().into(),
+    };
+    let error = WsError {
+        error,
+        code: String::from(reason.name()),
+        close: Some(reason),
+        retry_after_secs: None,
+    };
+    [
+        Message::Text(serde_json::to_string(&error).unwrap()),
+        Message::Close(Some(frame)),
+    ]
 }
 
 /// Actual websocket state-machine (one will be spawned per connection)
 async fn handle_socket(
-    spreadsheet_view: Arc<SpreadSheetView>,
+    subscriber: Subscriber,
     mut xls_changes: Receiver<Result<String, XlsError>>,
+    state: AppState,
+    // The name of an identified client, the IP otherwise.
+    client: String,
+    // Restricts the regions and rate of a client reading with an embed token.
+    embed: Option<Arc<Reader>>,
     socket: WebSocket,
     who: SocketAddr,
 ) {
+    let AppState {
+        spreadsheet_view,
+        region_limiter,
+        presenter,
+        shutdown,
+        drain,
+        ..
+    } = state;
+    let reader = embed.clone();
+    // Shared with the sender task, so the connection counts as open until it is closed.
+    let subscriber = Arc::new(subscriber);
     let (mut sender, mut receiver) = socket.split();
     let (region_tx, mut region_rx) = watch::channel(Region::default());
-    let (change_sender, mut change_receiver) = mpsc::channel::<String>(128);
-
-    // spawn a task that forwards messages from the mpsc to the sink
-    tokio::spawn(async move {
-        while let Some(message) = change_receiver.recv().await {
-            match sender.send(Message::Text(message.trim().to_string())).await {
-                Ok(_) => {
-                    trace!("{message} sent to {who}");
+    let (pinned_tx, pinned_rx) = watch::channel(Vec::<Region>::new());
+    // Live changes as `(cell id, message)`, errors have no cell id.
+    let (change_sender, mut change_receiver) = mpsc::channel::<(Option<i64>, String)>(128);
+    let (snapshot_sender, mut snapshot_receiver) = mpsc::channel::<SnapshotChunk>(2);
+    let (close_sender, mut close_receiver) = mpsc::channel::<(CloseReason, String)>(1);
+
+    // spawn a task that forwards messages from the mpsc to the sink, live changes take
+    // precedence over snapshots so they aren't delayed behind large snapshots
+    let connection = subscriber.clone();
+    let subscribed = region_tx.subscribe();
+    let send_task = tokio::spawn(async move {
+        let _connection = connection;
+        // Cells that were sent as a live change while a snapshot is delivered, their snapshot
+        // value is outdated.
+        let mut changed = HashSet::new();
+        let mut heartbeats = heartbeat_interval();
+        // The cells sent so far, told to the client with each heartbeat.
+        let mut seq = 0;
+        loop {
+            let messages = tokio::select! {
+                biased;
+                close = close_receiver.recv() => {
+                    let Some((reason, error)) = close else { break };
+                    // Messages queued before the close, e.g. a `ReconnectSoon`, are sent first.
+                    let mut messages = vec![];
+                    while let Ok((_, message)) = change_receiver.try_recv() {
+                        messages.push(Message::Text(message));
+                    }
+                    messages.extend(close_messages(reason, error));
+                    for message in messages {
+                        if let Err(e) = sender.send(message).await {
+                            debug!("Error closing websocket: {e}");
+                            break;
+                        }
+                    }
+                    break;
                 }
-                Err(e) => {
+                change = change_receiver.recv() => {
+                    let Some((id, message)) = change else { break };
+                    if id.is_some() {
+                        seq += 1;
+                    }
+                    changed.extend(id);
+                    vec![message]
+                }
+                _ = next_heartbeat(&mut heartbeats) => {
+                    let heartbeat = Heartbeat {
+                        server_time_ms: Utc::now().timestamp_millis(),
+                        region: *subscribed.borrow(),
+                        seq,
+                        interval_secs: WS_HEARTBEAT.as_secs(),
+                    };
+                    vec![serde_json::to_string(&heartbeat).unwrap()]
+                }
+                chunk = snapshot_receiver.recv() => {
+                    let Some(chunk) = chunk else { break };
+                    // The cells of a chunk are sent as one array, which is much cheaper than a
+                    // message per cell for large snapshots.
+                    let cells: Vec<String> = chunk
+                        .cells
+                        .into_iter()
+                        .filter(|(id, _)| !changed.contains(id))
+                        .map(|(_, message)| message.trim().to_string())
+                        .collect();
+                    seq += cells.len() as u64;
+                    let mut messages = vec![];
+                    if !cells.is_empty() {
+                        messages.push(format!("[{}]", cells.join(",")));
+                    }
+                    if chunk.last {
+                        messages.push(String::new());
+                        changed.clear();
+                    }
+                    messages
+                }
+            };
+            for message in messages {
+                if let Err(e) = sender.feed(Message::Text(message.trim().to_string())).await {
                     warn!("Error sending change to client: {e}");
                 }
             }
+            if let Err(e) = sender.flush().await {
+                warn!("Error sending change to client: {e}");
+            }
+            trace!("Messages sent to {who}");
+            tokio::task::yield_now().await;
         }
     });
 
@@ -200,8 +784,10 @@ async fn handle_socket(
                 Ok(Ok(change)) => match serde_json::from_str::<Cell>(&change) {
                     Ok(cell) => {
                         let region = { *region_rx.borrow_and_update() };
-                        if cell.id >= region.from && cell.id < region.to {
-                            match change_fwder.send(change).await {
+                        let contains =
+                            |region: &Region| (region.from..region.to).contains(&cell.id);
+                        if contains(&region) || pinned_rx.borrow().iter().any(contains) {
+                            match change_fwder.send((Some(cell.id), change)).await {
                                 Ok(_) => {}
                                 Err(e) => {
                                     warn!("Error sending change to sender task: {e}");
@@ -214,6 +800,10 @@ async fn handle_socket(
                         error!("Error parsing change: {e} (change {change})");
                     }
                 },
+                // Clients stay connected, their cells are updated again with the next change.
+                Ok(Err(XlsError::ChangesMissed(e))) => {
+                    debug!("Changes missed while sending to {who}: {e}");
+                }
                 Ok(Err(e)) => {
                     warn!("Error receiving change: {e}");
                     return cnt;
@@ -226,33 +816,187 @@ async fn handle_socket(
         }
     });
 
+    // Spawn a task that will push the viewport of the presenter to the client while it follows
+    let (follow_tx, follow_rx) = watch::channel(false);
+    let change_fwder = change_sender.clone();
+    let mut viewports = presenter.subscribe();
+    let presenter_task = tokio::spawn(async move {
+        loop {
+            let viewport = match viewports.recv().await {
+                Ok(viewport) => viewport,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+            if !*follow_rx.borrow() {
+                continue;
+            }
+            let key = String::new();
+            let message = serde_json::to_string(&PresenterMessage::Present { viewport, key });
+            if let Err(e) = change_fwder.send((None, message.unwrap())).await {
+                warn!("Error sending viewport to sender task: {e}");
+                return;
+            }
+        }
+    });
+
     // This second task will receive messages from the client and push snapshots
     let change_fwder = change_sender.clone();
+    let closer = close_sender.clone();
     let mut recv_task = tokio::spawn(async move {
         let mut cnt = 0;
+        const CONNECTION_KEY: &str = "connection";
+        // Readers with an embed token share the rate limit of the token, others the one of their
+        // IP. Every connection has a budget of its own on top.
+        let connection_limiter = rate_limit::connection_regions();
+        let limited = |limiter: &RateLimiter, key: &str| {
+            XlsError::RateLimited(Some(limiter.retry_after(key).as_secs()))
+        };
+        let allowed = |client: &str| {
+            if !connection_limiter.check(CONNECTION_KEY) {
+                return Err(limited(&connection_limiter, CONNECTION_KEY));
+            }
+            match &embed {
+                Some(reader) => reader.check(),
+                None if region_limiter.check(client) => Ok(()),
+                None => Err(limited(&region_limiter, client)),
+            }
+        };
+        // Until when the client was told to wait for its next region change.
+        let mut throttled_until: Option<Instant> = None;
+        let readable = |region: Region| match &embed {
+            Some(reader) => reader.restrict(region),
+            None => Ok(region),
+        };
         while let Some(Ok(msg)) = receiver.next().await {
             cnt += 1;
-            match process_message(msg, who) {
-                ControlFlow::Continue(Some(region)) => match spreadsheet_view.query(region).await {
-                    Ok(snapshot) => {
-                        region_tx.send_replace(region);
-                        for line in snapshot.split('\n') {
-                            match change_fwder.send(line.to_string()).await {
-                                Ok(_) => {}
-                                Err(e) => {
+            let message = process_message(msg, who);
+            if let ControlFlow::Continue(Some(
+                change @ (ClientMessage::Region(_) | ClientMessage::Pinned(_)),
+            )) = &message
+            {
+                if let Err(e) = allowed(&client) {
+                    // Keep sending changes of the previous regions, the client asks again once
+                    // the time it was told to wait is over.
+                    debug!("{who} changed regions too often, ignoring {change:?}");
+                    let now = Instant::now();
+                    if throttled_until.is_none_or(|until| until <= now) {
+                        let wait = Duration::from_secs(e.retry_after_secs().unwrap_or(1));
+                        throttled_until = Some(now + wait);
+                        let error = serde_json::to_string(&WsError::from(&e)).unwrap();
+                        if let Err(e) = change_fwder.send((None, error)).await {
+                            warn!("Error sending change to sender task: {e}");
+                            return cnt;
+                        }
+                    }
+                    continue;
+                }
+            }
+            match message {
+                ControlFlow::Continue(Some(ClientMessage::Presenter(
+                    PresenterMessage::Follow(follow),
+                ))) => {
+                    follow_tx.send_replace(follow);
+                }
+                ControlFlow::Continue(Some(ClientMessage::Presenter(
+                    PresenterMessage::Present { viewport, key },
+                ))) => {
+                    if !presenter::is_presenter(&key) {
+                        debug!("{who} sent an invalid presenter key");
+                    } else if allowed(&client).is_ok() {
+                        // Nobody follows if the send fails.
+                        let _ = presenter.send(viewport);
+                    }
+                }
+                ControlFlow::Continue(Some(ClientMessage::Region(region))) => {
+                    match subscription_region(region).and_then(readable) {
+                        Err(e) => {
+                            debug!("{who} sent invalid region {region:?}: {e}");
+                            let error = serde_json::to_string(&WsError::from(&e)).unwrap();
+                            if let Err(e) = change_fwder.send((None, error)).await {
+                                warn!("Error sending change to sender task: {e}");
+                                return cnt;
+                            }
+                        }
+                        Ok(region) => match spreadsheet_view.query(region).await {
+                            Ok(snapshot) => {
+                                region_tx.send_replace(region);
+                                subscriber.subscribe(region);
+                                for chunk in SnapshotChunk::split(&snapshot) {
+                                    if let Err(e) = snapshot_sender.send(chunk).await {
+                                        warn!("Error sending snapshot to sender task: {e}");
+                                        return cnt;
+                                    }
+                                }
+                            }
+                            Err(e @ XlsError::Busy(_)) => {
+                                // The client asks again, the connection stays open.
+                                debug!("{who} has to wait for region {region:?}: {e}");
+                                let error = serde_json::to_string(&WsError::from(&e)).unwrap();
+                                if let Err(e) = change_fwder.send((None, error)).await {
                                     warn!("Error sending change to sender task: {e}");
                                     return cnt;
                                 }
                             }
-                        }
+                            Err(e) => {
+                                warn!("Error querying spreadsheet_view: {e}");
+                                return cnt;
+                            }
+                        },
                     }
-                    Err(e) => {
-                        warn!("Error querying spreadsheet_view: {e}");
-                        return cnt;
+                }
+                ControlFlow::Continue(Some(ClientMessage::Pinned(PinnedRegions { pinned }))) => {
+                    let pinned = pinned_regions(pinned).and_then(|pinned| {
+                        pinned
+                            .into_iter()
+                            .map(readable)
+                            .collect::<Result<Vec<_>, _>>()
+                    });
+                    match pinned {
+                        Err(e) => {
+                            debug!("{who} pinned invalid regions: {e}");
+                            let error = serde_json::to_string(&WsError::from(&e)).unwrap();
+                            if let Err(e) = change_fwder.send((None, error)).await {
+                                warn!("Error sending change to sender task: {e}");
+                                return cnt;
+                            }
+                        }
+                        Ok(pinned) => {
+                            pinned_tx.send_replace(pinned.clone());
+                            for region in pinned {
+                                let snapshot = match spreadsheet_view.query(region).await {
+                                    Ok(snapshot) => snapshot,
+                                    Err(e @ XlsError::Busy(_)) => {
+                                        debug!("{who} has to wait for pinned {region:?}: {e}");
+                                        let error =
+                                            serde_json::to_string(&WsError::from(&e)).unwrap();
+                                        if let Err(e) = change_fwder.send((None, error)).await {
+                                            warn!("Error sending change to sender task: {e}");
+                                            return cnt;
+                                        }
+                                        break;
+                                    }
+                                    Err(e) => {
+                                        warn!("Error querying spreadsheet_view: {e}");
+                                        return cnt;
+                                    }
+                                };
+                                for chunk in SnapshotChunk::split(&snapshot) {
+                                    if let Err(e) = snapshot_sender.send(chunk).await {
+                                        warn!("Error sending snapshot to sender task: {e}");
+                                        return cnt;
+                                    }
+                                }
+                            }
+                        }
                     }
-                },
+                }
                 ControlFlow::Continue(None) => {}
-                ControlFlow::Break(_) => {
+                ControlFlow::Break(Some(error)) => {
+                    // The sender task closes the connection.
+                    let _ = closer.send((CloseReason::InvalidRegion, error)).await;
+                    break;
+                }
+                ControlFlow::Break(None) => {
                     break;
                 }
             }
@@ -276,27 +1020,145 @@ async fn handle_socket(
             }
             change_task.abort();
         }
+        _ = shutdown.cancelled() => {
+            let error = String::from("The server is shutting down");
+            let _ = close_sender.send((CloseReason::ServerShutdown, error)).await;
+            change_task.abort();
+            recv_task.abort();
+        }
+        _ = drained(&drain, &change_sender) => {
+            let error = String::from("The server is restarting");
+            let _ = close_sender.send((CloseReason::ServerShutdown, error)).await;
+            change_task.abort();
+            recv_task.abort();
+        }
+        _ = revoked(reader.as_deref()) => {
+            let error = String::from("The token was revoked");
+            let _ = close_sender.send((CloseReason::AuthRequired, error)).await;
+            change_task.abort();
+            recv_task.abort();
+        }
+    }
+
+    presenter_task.abort();
+    // The sender task stops once the other tasks dropped their senders, after closing the
+    // connection if it was asked to.
+    drop((change_sender, close_sender));
+    if let Err(e) = send_task.await {
+        warn!("Error sending messages {e:?}");
     }
 
     trace!("Websocket context {who} destroyed");
 }
 
+/// Ticks every `WS_HEARTBEAT_SECS`, starting one interval after the connection opened.
+fn heartbeat_interval() -> Option<tokio::time::Interval> {
+    if WS_HEARTBEAT.is_zero() {
+        return None;
+    }
+    let start = tokio::time::Instant::now() + *WS_HEARTBEAT;
+    let mut interval = tokio::time::interval_at(start, *WS_HEARTBEAT);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    Some(interval)
+}
+
+/// Completes when the next heartbeat is due, never if heartbeats are disabled.
+async fn next_heartbeat(heartbeats: &mut Option<tokio::time::Interval>) {
+    match heartbeats {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Completes when the embed token of the connection is revoked, never without one.
+async fn revoked(reader: Option<&Reader>) {
+    match reader {
+        Some(reader) => reader.revoked().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Tells the client to reconnect soon once the server is drained and completes at a random time
+/// within `WS_DRAIN_SECS`, when the connection should be closed.
+async fn drained(drain: &CancellationToken, change_sender: &mpsc::Sender<(Option<i64>, String)>) {
+    drain.cancelled().await;
+    let notice = ReconnectSoon {
+        reconnect_within_secs: WS_DRAIN.as_secs(),
+    };
+    // The connection is closed anyway if the sender task stopped.
+    let _ = change_sender
+        .send((None, serde_json::to_string(&notice).unwrap()))
+        .await;
+    let delay = rand::thread_rng().gen_range(Duration::ZERO..=*WS_DRAIN);
+    tokio::time::sleep(delay).await;
+}
+
+/// Cells of a snapshot sent to the client at once, as one array message.
+const SNAPSHOT_CHUNK_SIZE: usize = 256;
+
+/// Part of a region snapshot, as `(cell id, message)`.
+struct SnapshotChunk {
+    cells: Vec<(i64, String)>,
+    /// The client is told the snapshot is complete after the last chunk.
+    last: bool,
+}
+
+impl SnapshotChunk {
+    /// Splits the newline-delimited cells returned by [`SpreadSheetView::query`].
+    fn split(snapshot: &str) -> Vec<SnapshotChunk> {
+        let cells: Vec<(i64, String)> = snapshot
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str::<Cell>(line) {
+                Ok(cell) => Some((cell.id, line.to_string())),
+                Err(e) => {
+                    warn!("Error parsing snapshot: {e} (cell {line})");
+                    None
+                }
+            })
+            .collect();
+        let mut chunks: Vec<SnapshotChunk> = cells
+            .chunks(SNAPSHOT_CHUNK_SIZE)
+            .map(|cells| SnapshotChunk {
+                cells: cells.to_vec(),
+                last: false,
+            })
+            .collect();
+        match chunks.last_mut() {
+            Some(chunk) => chunk.last = true,
+            None => chunks.push(SnapshotChunk {
+                cells: vec![],
+                last: true,
+            }),
+        }
+        chunks
+    }
+}
+
 /// helper to print contents of messages to stdout. Has special treatment for Close.
-fn process_message(msg: Message, who: SocketAddr) -> ControlFlow<(), Option<Region>> {
+///
+/// Breaks with an error for messages the server doesn't understand, the connection is closed
+/// with [`CloseReason::InvalidRegion`] then.
+fn process_message(
+    msg: Message,
+    who: SocketAddr,
+) -> ControlFlow<Option<String>, Option<ClientMessage>> {
     match msg {
-        Message::Text(t) => match serde_json::from_str::<Region>(&t) {
-            Ok(region) => {
-                debug!("{who} sent range: {region:?}");
-                ControlFlow::Continue(Some(region))
+        Message::Text(t) => match serde_json::from_str::<ClientMessage>(&t) {
+            Ok(message) => {
+                debug!("{who} sent: {message:?}");
+                ControlFlow::Continue(Some(message))
             }
             Err(e) => {
-                warn!("{who} sent invalid region JSON: {t:?} {e}");
-                ControlFlow::Continue(None)
+                warn!("{who} sent invalid JSON: {t:?} {e}");
+                ControlFlow::Break(Some(format!("Invalid message: {e}")))
             }
         },
         Message::Close(c) => {
             debug!("{who} closed connection: {c:?}");
-            ControlFlow::Break(())
+            ControlFlow::Break(None)
         }
         _ => ControlFlow::Continue(None),
     }
@@ -304,26 +1166,102 @@ fn process_message(msg: Message, who: SocketAddr) -> ControlFlow<(), Option<Regi
 
 // Insert/Update a cell
 
-// Data structure to represent incoming JSON payload
-#[derive(Deserialize, Debug)]
-pub(crate) struct UpdateRequest {
-    id: i64,
-    raw_value: String,
-    background: i32,
-}
-
-impl UpdateRequest {
-    const ID_RANGE: Range<i64> = 0i64..1_040_000_000i64;
-}
-
 // Data structure to represent outgoing JSON payload
 #[derive(Serialize, Debug)]
-struct UpdatePayload {
+pub(crate) struct UpdatePayload {
     id: i64,
     raw_value: String,
     background: i32,
+    /// [`CellStyle`](xls_protocol::CellStyle) in the encoding of the `style` column.
+    style: i64,
     ip: String,
     ts: String,
+    expires_at: Option<String>,
+}
+
+impl UpdatePayload {
+    /// Validates and censors an update from `client_ip`.
+    pub(crate) fn new(update_request: UpdateRequest, client_ip: String) -> Result<Self, XlsError> {
+        if !CELL_IDS.contains(&update_request.id) {
+            return Err(XlsError::InvalidField(
+                "id",
+                String::from("Invalid cell ID"),
+            ));
+        }
+        check_sheet(update_request.sheet)?;
+        let user_value = update_request
+            .raw_value
+            .chars()
+            .take(MAX_CELL_CHARS)
+            .collect::<String>();
+        formula::validate(&user_value)?;
+        let censored_urls = replace_domain_in_urls(&user_value, "*REDACTED*");
+        let censored_input = Censor::new(censored_urls.chars()).censor();
+        let now = Utc::now();
+        let expires_at = match update_request.ttl_secs {
+            Some(ttl) if ttl == 0 || ttl > *CELL_MAX_TTL_SECS => {
+                return Err(XlsError::InvalidField(
+                    "ttl_secs",
+                    format!("ttl_secs must be between 1 and {}", *CELL_MAX_TTL_SECS),
+                ));
+            }
+            Some(ttl) => Some(now + chrono::Duration::seconds(ttl as i64)),
+            None => None,
+        };
+        Ok(UpdatePayload {
+            id: update_request.id,
+            raw_value: censored_input,
+            background: update_request.background,
+            style: update_request.style.into(),
+            ip: client_ip,
+            ts: format_timestamp(now),
+            expires_at: expires_at.map(format_timestamp),
+        })
+    }
+
+    /// Content the server generated itself, it isn't validated or censored.
+    pub(crate) fn generated(update: UpdateRequest, ip: &str) -> Self {
+        UpdatePayload {
+            id: update.id,
+            raw_value: update.raw_value,
+            background: update.background,
+            style: update.style.into(),
+            ip: ip.to_string(),
+            ts: format_timestamp(Utc::now()),
+            expires_at: None,
+        }
+    }
+}
+
+/// Timestamps in the format of the `spreadsheet_data` table.
+pub(crate) fn format_timestamp(ts: DateTime<Utc>) -> String {
+    ts.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+/// The header the load balancer puts the client IP in.
+pub(crate) const CLIENT_IP_HEADER: &str = "Fly-Client-IP";
+
+/// Peers whose [`CLIENT_IP_HEADER`] is honored, i.e. the load balancer. Anyone else could pick
+/// their IP with the header and bypass the rate limits.
+///
+/// Only loopback by default, deployments behind a proxy configure its networks, e.g. in
+/// `fly.toml`.
+static TRUSTED_PROXIES: LazyLock<Vec<IpNet>> =
+    LazyLock::new(|| ip_filter::networks("TRUSTED_PROXIES", "127.0.0.0/8,::1/128"));
+
+/// The IP of a client, from the load balancer header if present and the peer is a trusted proxy,
+/// or the peer address otherwise. It is stored and rate limited as [`privacy::anonymize`]d.
+pub(crate) fn client_ip(header: Option<&[u8]>, addr: SocketAddr) -> String {
+    let peer = addr.ip().to_canonical();
+    header
+        .filter(|_| TRUSTED_PROXIES.iter().any(|proxy| proxy.contains(&peer)))
+        .map(|ip| {
+            String::from_utf8_lossy(ip)
+                .chars()
+                .take(45)
+                .collect::<String>()
+        })
+        .unwrap_or(addr.ip().to_string().chars().take(45).collect::<String>())
 }
 
 fn replace_domain_in_urls(input: &str, new_domain: &str) -> String {
@@ -341,50 +1279,126 @@ fn replace_domain_in_urls(input: &str, new_domain: &str) -> String {
         .to_string()
 }
 
+/// Updates a cell.
+#[utoipa::path(
+    post,
+    path = "/api/spreadsheet",
+    request_body = UpdateRequest,
+    responses(
+        (status = 200, description = "The update was sent to Feldera", body = Object),
+        (status = 202, description = "Feldera is unavailable, the update is sent later", body = Object),
+        (status = 400, description = "Invalid cell or TTL", body = ErrorResponse),
+        (status = 403, description = "The client IP is not allowed or the deployment is read-only", body = ErrorResponse),
+        (status = 429, description = "API limit exceeded", body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
 pub(crate) async fn post_handler(
     headers: HeaderMap,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<AppState>,
-    Json(update_request): Json<UpdateRequest>,
-) -> impl IntoResponse {
+    JsonBody(update_request): JsonBody<UpdateRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), XlsError> {
     // Load balancer puts the client IP in the HTTP header
-    const CLIENT_IP_HEADER: &str = "Fly-Client-IP";
-    let client_ip = headers
-        .get(CLIENT_IP_HEADER)
-        .map(|ip| {
-            String::from_utf8_lossy(ip.as_bytes())
-                .chars()
-                .take(45)
-                .collect::<String>()
-        })
-        .unwrap_or(addr.ip().to_string().chars().take(45).collect::<String>());
+    let client_ip = privacy::anonymize(client_ip(
+        headers.get(CLIENT_IP_HEADER).map(|ip| ip.as_bytes()),
+        addr,
+    ));
 
+    meta::check_writable()?;
     if state.api_limits.contains(&client_ip) {
-        return (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({"error": "API limit exceeded"})),
-        );
+        return Err(XlsError::RateLimited(None));
     }
-    if !UpdateRequest::ID_RANGE.contains(&update_request.id) {
-        return (
-            axum::http::StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "Invalid cell ID"})),
-        );
+    state
+        .moderator
+        .check(&client_ip, std::slice::from_ref(&update_request))?;
+    let payload = UpdatePayload::new(update_request, client_ip)?;
+
+    let ingested = state.ingest_queue.insert(&[payload]).await?;
+    Ok((
+        ingested.status(),
+        Json(serde_json::json!({"success": true})),
+    ))
+}
+
+/// Longest raw value of a cell, longer values are truncated.
+pub(crate) const MAX_CELL_CHARS: usize = 64;
+
+/// Maximum number of cells a batch can update.
+pub(crate) const MAX_BATCH_SIZE: usize = 100;
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct BatchUpdateResponse {
+    /// Cells updated, only the last update of a cell in the batch counts.
+    pub(crate) updated: usize,
+}
+
+/// Validates and sends several updates from `client_ip` to Feldera with a single request,
+/// returns how many cells were updated and whether the updates were queued. Every updated cell
+/// takes a token of `client_ip` from `limiter`, the update limiter or the quota of an
+/// integration, before the updates are moderated.
+pub(crate) async fn update_batch(
+    state: &AppState,
+    client_ip: String,
+    updates: Vec<UpdateRequest>,
+    limiter: &RateLimiter,
+) -> Result<(usize, Ingested), XlsError> {
+    meta::check_writable()?;
+    if updates.len() > MAX_BATCH_SIZE {
+        return Err(XlsError::InvalidField(
+            "updates",
+            format!("At most {MAX_BATCH_SIZE} cells can be updated at once"),
+        ));
     }
-    let user_value = update_request
-        .raw_value
-        .chars()
-        .take(64)
-        .collect::<String>();
-    let censored_urls = replace_domain_in_urls(&user_value, "*REDACTED*");
-    let censored_input = Censor::new(censored_urls.chars()).censor();
-    let payload = UpdatePayload {
-        id: update_request.id,
-        raw_value: censored_input,
-        background: update_request.background,
-        ip: client_ip,
-        ts: Utc::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
-    };
+    // All rows of a batch get the same timestamp, so only the last update of a cell is kept.
+    let updates: BTreeMap<i64, UpdateRequest> = updates.into_iter().map(|u| (u.id, u)).collect();
+    let updates: Vec<UpdateRequest> = updates.into_values().collect();
+    if updates.is_empty() {
+        return Ok((0, Ingested::Sent));
+    }
+    // Throttled updates don't count towards the moderation heuristics.
+    if !limiter.take(&client_ip, updates.len() as f64) {
+        let retry_after = limiter.retry_after(&client_ip);
+        return Err(XlsError::RateLimited(Some(retry_after.as_secs())));
+    }
+    state.moderator.check(&client_ip, &updates)?;
+    let payloads = updates
+        .into_iter()
+        .map(|update| UpdatePayload::new(update, client_ip.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let ingested = state.ingest_queue.insert(&payloads).await?;
+    Ok((payloads.len(), ingested))
+}
 
-    insert(state.http_client, "spreadsheet_data", payload).await
+/// Updates several cells at once, e.g. to move a cell by clearing it and writing its content
+/// elsewhere.
+#[utoipa::path(
+    post,
+    path = "/api/spreadsheet/batch",
+    request_body = [UpdateRequest],
+    responses(
+        (status = 200, description = "The updates were sent to Feldera", body = BatchUpdateResponse),
+        (status = 202, description = "Feldera is unavailable, the updates are sent later", body = BatchUpdateResponse),
+        (status = 400, description = "Invalid cell or TTL, or too many updates", body = ErrorResponse),
+        (status = 403, description = "The client IP is not allowed or the deployment is read-only", body = ErrorResponse),
+        (status = 429, description = "API limit exceeded", body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn batch_handler(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    JsonBody(updates): JsonBody<Vec<UpdateRequest>>,
+) -> Result<(StatusCode, Json<BatchUpdateResponse>), XlsError> {
+    let client_ip = privacy::anonymize(client_ip(
+        headers.get(CLIENT_IP_HEADER).map(|ip| ip.as_bytes()),
+        addr,
+    ));
+    if state.api_limits.contains(&client_ip) {
+        return Err(XlsError::RateLimited(None));
+    }
+    let limiter = &state.update_limiter;
+    let (updated, ingested) = update_batch(&state, client_ip, updates, limiter).await?;
+    Ok((ingested.status(), Json(BatchUpdateResponse { updated })))
 }
@@ -1,106 +1,106 @@
-use std::fmt::Display;
-use std::io;
-
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::{body::Body, response::IntoResponse, response::Response};
 use futures::StreamExt;
 use log::debug;
-use serde::de::StdError;
-use tokio::sync::broadcast::error::SendError;
-use tokio_util::codec::LinesCodecError;
+use serde::Deserialize;
+use xls_protocol::{ErrorResponse, Stats, StatsUpdate};
 
+use crate::error::XlsError;
 use crate::feldera::adhoc_query;
 use crate::AppState;
 
-#[derive(Clone, Debug)]
-pub(crate) struct XlsError {
-    message: String,
+#[derive(Deserialize, Debug)]
+pub(crate) struct StatsParams {
+    /// Comma-separated names of the fields to send, all fields if not set.
+    fields: Option<String>,
 }
 
-impl Display for XlsError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
-    }
-}
-
-impl From<io::Error> for XlsError {
-    fn from(e: io::Error) -> Self {
-        XlsError {
-            message: e.to_string(),
-        }
-    }
-}
-
-impl From<SendError<Result<String, Self>>> for XlsError {
-    fn from(e: SendError<Result<String, Self>>) -> Self {
-        XlsError {
-            message: e.to_string(),
-        }
-    }
-}
-
-impl From<LinesCodecError> for XlsError {
-    fn from(e: LinesCodecError) -> Self {
-        XlsError {
-            message: e.to_string(),
-        }
-    }
-}
-
-impl From<&str> for XlsError {
-    fn from(e: &str) -> Self {
-        XlsError {
-            message: e.to_string(),
-        }
+/// The fields named in `fields`, all fields if it is not set or empty.
+fn selected_fields(fields: Option<&str>) -> Result<Vec<&'static str>, XlsError> {
+    let names: Vec<&str> = fields
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    if names.is_empty() {
+        return Ok(StatsUpdate::FIELDS.to_vec());
     }
+    names
+        .into_iter()
+        .map(|name| {
+            StatsUpdate::FIELDS
+                .into_iter()
+                .find(|field| *field == name)
+                .ok_or_else(|| {
+                    let expected = StatsUpdate::FIELDS.join(", ");
+                    let message = format!("Unknown field {name:?}, expected one of {expected}");
+                    XlsError::InvalidField("fields", message)
+                })
+        })
+        .collect()
 }
 
-impl From<String> for XlsError {
-    fn from(e: String) -> Self {
-        XlsError { message: e }
-    }
-}
-
-impl From<reqwest::Error> for XlsError {
-    fn from(e: reqwest::Error) -> Self {
-        XlsError {
-            message: e.to_string(),
-        }
-    }
-}
-
-impl StdError for XlsError {
-    fn source(&self) -> Option<&(dyn StdError + 'static)> {
-        None
-    }
-}
-
-pub(crate) async fn stats(State(state): State<AppState>) -> impl IntoResponse {
+/// Streams the spreadsheet statistics as newline-delimited JSON: the current row first, then
+/// the fields that changed whenever the statistics change.
+///
+/// With `?fields=filled_total,currently_active_users` only these fields are sent, and only
+/// when one of them changed.
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    params(
+        ("fields" = Option<String>, Query, description = "Comma-separated fields to send, e.g. `filled_total,currently_active_users`"),
+    ),
+    responses(
+        (status = 200, description = "Stream of statistics updates", body = StatsUpdate),
+        (status = 400, description = "Unknown field", body = ErrorResponse),
+        (status = 503, description = "Feldera is unavailable", body = ErrorResponse),
+    )
+)]
+pub(crate) async fn stats(
+    State(state): State<AppState>,
+    Query(params): Query<StatsParams>,
+) -> impl IntoResponse {
+    let fields = match selected_fields(params.fields.as_deref()) {
+        Ok(fields) => fields,
+        Err(e) => return e.into_response(),
+    };
     let initial_data = adhoc_query(state.http_client, "SELECT * FROM spreadsheet_statistics").await;
 
-    if let Err(e) = initial_data {
-        return Response::builder()
-            .status(500)
-            .body(Body::from(format!(
-                "{{\"error\": \"{}\"}}",
-                e.message.trim()
-            )))
-            .unwrap();
-    }
+    let initial_data = match initial_data {
+        Ok(initial_data) => initial_data,
+        Err(e) => return e.into_response(),
+    };
+    let last = serde_json::from_str::<Stats>(initial_data.trim()).ok();
+    // Rows that can't be parsed are passed through.
+    let initial_data = match &last {
+        Some(stats) => {
+            let update = StatsUpdate::from(stats).select(&fields);
+            format!("{}\n", serde_json::to_string(&update).unwrap())
+        }
+        None => initial_data,
+    };
 
-    let initial_stream = futures::stream::once(async move { initial_data });
+    let initial_stream = futures::stream::once(async move { Ok(initial_data) });
 
     let change_stream_rx = state.stats_subscription.subscribe();
     let change_stream = tokio_stream::wrappers::BroadcastStream::new(change_stream_rx);
-    let stream = initial_stream.chain(change_stream.filter_map(|result| async move {
-        match result {
-            Ok(value) => Some(value),
-            Err(e) => {
-                debug!("BroadcastStream error: {:?}", e);
-                None // Discard errors
+    let changes = change_stream
+        .filter_map(|result| async move {
+            match result {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    debug!("BroadcastStream error: {:?}", e);
+                    None // Discard errors
+                }
             }
-        }
-    }));
+        })
+        .scan(last, move |last, result| {
+            futures::future::ready(Some(result.map(|change| diff(last, change, &fields))))
+        })
+        .filter_map(|result| async move { result.transpose() });
+    let stream = initial_stream.chain(changes);
 
     Response::builder()
         .status(200)
@@ -109,3 +109,19 @@ pub(crate) async fn stats(State(state): State<AppState>) -> impl IntoResponse {
         .body(Body::from_stream(stream))
         .unwrap()
 }
+
+/// The changed `fields` of a statistics row, `None` if none of them changed. Rows that can't
+/// be parsed are passed through.
+fn diff(last: &mut Option<Stats>, change: String, fields: &[&str]) -> Option<String> {
+    let Ok(stats) = serde_json::from_str::<Stats>(change.trim()) else {
+        return Some(change);
+    };
+    let update = match last.replace(stats.clone()) {
+        Some(last) => last.diff(&stats)?.select(fields),
+        None => StatsUpdate::from(&stats).select(fields),
+    };
+    if update == StatsUpdate::default() {
+        return None;
+    }
+    Some(format!("{}\n", serde_json::to_string(&update).unwrap()))
+}
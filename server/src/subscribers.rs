@@ -0,0 +1,112 @@
+//! Registry of the open websocket connections and the regions they subscribed to, shown on the
+//! admin dashboard.
+//!
+//! It also limits the open connections per client IP to `WS_MAX_CONNECTIONS_PER_IP`, so a single
+//! script can't exhaust the file descriptors and change stream receivers of the server.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use serde::Serialize;
+use xls_protocol::Region;
+
+use crate::config::env_or;
+
+/// Most open websocket connections of a client IP, `0` disables the limit.
+static WS_MAX_CONNECTIONS_PER_IP: LazyLock<usize> =
+    LazyLock::new(|| env_or("WS_MAX_CONNECTIONS_PER_IP", 20));
+
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    next_id: AtomicU64,
+    regions: DashMap<u64, Option<Region>>,
+    /// Open connections per client IP.
+    per_ip: DashMap<String, usize>,
+}
+
+/// Number of websocket connections subscribed to a region.
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub(crate) struct RegionSubscribers {
+    region: Region,
+    subscribers: usize,
+}
+
+impl Subscribers {
+    /// Registers a connection of `client_ip` until the returned guard is dropped, `None` if the
+    /// IP has too many connections open already.
+    pub(crate) fn register(self: &Arc<Self>, client_ip: &str) -> Option<Subscriber> {
+        {
+            let mut connections = self.per_ip.entry(client_ip.to_string()).or_insert(0);
+            if *WS_MAX_CONNECTIONS_PER_IP > 0 && *connections >= *WS_MAX_CONNECTIONS_PER_IP {
+                return None;
+            }
+            *connections += 1;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.regions.insert(id, None);
+        Some(Subscriber {
+            id,
+            client_ip: client_ip.to_string(),
+            subscribers: self.clone(),
+        })
+    }
+
+    pub(crate) fn connections(&self) -> usize {
+        self.regions.len()
+    }
+
+    /// Waits until all connections are closed, at most `timeout`.
+    pub(crate) async fn closed(&self, timeout: Duration) {
+        let start = Instant::now();
+        while self.connections() > 0 && start.elapsed() < timeout {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Regions with their number of subscribers, most subscribed first.
+    pub(crate) fn by_region(&self) -> Vec<RegionSubscribers> {
+        let mut counts: HashMap<(i64, i64), usize> = HashMap::new();
+        for entry in self.regions.iter() {
+            if let Some(region) = entry.value() {
+                *counts.entry((region.from, region.to)).or_default() += 1;
+            }
+        }
+        let mut regions: Vec<RegionSubscribers> = counts
+            .into_iter()
+            .map(|((from, to), subscribers)| RegionSubscribers {
+                region: Region::new(from, to),
+                subscribers,
+            })
+            .collect();
+        regions.sort_by_key(|r| (std::cmp::Reverse(r.subscribers), r.region.from));
+        regions
+    }
+}
+
+/// An open websocket connection, unregistered on drop.
+pub(crate) struct Subscriber {
+    id: u64,
+    client_ip: String,
+    subscribers: Arc<Subscribers>,
+}
+
+impl Subscriber {
+    pub(crate) fn subscribe(&self, region: Region) {
+        self.subscribers.regions.insert(self.id, Some(region));
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.subscribers.regions.remove(&self.id);
+        if let Some(mut connections) = self.subscribers.per_ip.get_mut(&self.client_ip) {
+            *connections -= 1;
+        }
+        self.subscribers
+            .per_ip
+            .remove_if(&self.client_ip, |_, connections| *connections == 0);
+    }
+}
@@ -0,0 +1,48 @@
+//! Moves the clock of cells with volatile functions (`NOW()`, `TODAY()`), so clocks and countdowns
+//! built in the sheet actually move.
+//!
+//! The pipeline evaluates `NOW()` as the time in its `clock` table, which keeps the evaluation
+//! deterministic. This job overwrites that time with the current one every
+//! `VOLATILE_REFRESH_SECS`, the rows users wrote are left alone. It only runs on the publisher,
+//! see [`fanout::is_publisher`].
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::error;
+use reqwest::Client;
+use serde_json::json;
+
+use crate::config::env_or;
+use crate::error::XlsError;
+use crate::fanout;
+use crate::feldera::insert_batch;
+use crate::spreadsheet::format_timestamp;
+
+/// How often volatile cells are refreshed, `0` disables the job.
+static VOLATILE_REFRESH_INTERVAL: LazyLock<Duration> =
+    LazyLock::new(|| Duration::from_secs(env_or("VOLATILE_REFRESH_SECS", 60)));
+
+pub(crate) fn spawn_refresh(client: Client) {
+    if VOLATILE_REFRESH_INTERVAL.is_zero() {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(*VOLATILE_REFRESH_INTERVAL).await;
+            if !fanout::is_publisher("spreadsheet_view") {
+                continue;
+            }
+            if let Err(e) = refresh(&client).await {
+                error!("Failed to refresh volatile cells: {e}");
+            }
+        }
+    });
+}
+
+/// Sets the clock to the current time, the primary key makes the row replace the previous one.
+async fn refresh(client: &Client) -> Result<(), XlsError> {
+    let now = json!({"id": 0, "ts": format_timestamp(Utc::now())});
+    insert_batch(client.clone(), "clock", &[now]).await
+}
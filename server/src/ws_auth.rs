@@ -0,0 +1,77 @@
+//! Tokens that identify websocket clients, e.g. the users of a private deployment or bots.
+//!
+//! `WS_TOKENS` is a comma-separated list of `name:token` pairs. If it is set, the websocket
+//! requires one of the tokens, passed as `?token=` (browsers can't set headers on websockets) or
+//! as `Authorization: Bearer <token>`. Connections without a valid token are closed with the
+//! `auth_required` close reason. The rate limits of an identified connection apply to its
+//! name instead of its IP.
+
+use std::env::var;
+use std::sync::LazyLock;
+
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
+use log::warn;
+use serde::Deserialize;
+
+use crate::admin::constant_time_eq;
+use crate::error::XlsError;
+
+static WS_TOKENS: LazyLock<Vec<(String, String)>> = LazyLock::new(|| tokens("WS_TOKENS"));
+
+fn tokens(name: &str) -> Vec<(String, String)> {
+    var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| match pair.split_once(':') {
+            Some((name, token)) if !name.trim().is_empty() && !token.trim().is_empty() => {
+                Some((name.trim().to_string(), token.trim().to_string()))
+            }
+            _ => {
+                warn!("Ignoring invalid entry in {name}, expected `name:token`");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Query parameters of the websocket upgrade.
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct WsParams {
+    token: Option<String>,
+}
+
+impl WsParams {
+    /// The token passed as `?token=`.
+    pub(crate) fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+}
+
+/// Whether the websocket requires a token.
+pub(crate) fn required() -> bool {
+    !WS_TOKENS.is_empty()
+}
+
+/// The name of the client presenting a token, `None` if no tokens are configured.
+pub(crate) fn identify(params: &WsParams, headers: &HeaderMap) -> Result<Option<String>, XlsError> {
+    if WS_TOKENS.is_empty() {
+        return Ok(None);
+    }
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let token = params
+        .token
+        .as_deref()
+        .or(bearer)
+        .ok_or(XlsError::Unauthorized)?;
+    WS_TOKENS
+        .iter()
+        .find(|(_, known)| constant_time_eq(token, known))
+        .map(|(name, _)| Some(name.clone()))
+        .ok_or(XlsError::Unauthorized)
+}
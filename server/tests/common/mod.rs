@@ -0,0 +1,933 @@
+//! Test harness: an in-process mock of the Feldera endpoints the server uses and helpers to
+//! run the server binary against it.
+
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::{SinkExt, StreamExt};
+use regex::Regex;
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// How long tests wait for something to happen before failing.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct MockState {
+    /// Rows of `spreadsheet_view`.
+    cells: Mutex<BTreeMap<i64, Value>>,
+    /// Rows of `api_limit_reached`.
+    api_limits: Mutex<Vec<String>>,
+    /// Rows of `latest_cell_comments`.
+    comments: Mutex<BTreeMap<i64, String>>,
+    /// The single row of `spreadsheet_statistics`.
+    stats: Mutex<Value>,
+    /// Everything that was posted to an ingress endpoint, as `(table, record)`.
+    ingress: Mutex<Vec<(String, Value)>>,
+    /// Set while the ingress endpoint answers with `503`.
+    ingress_unavailable: Mutex<bool>,
+    /// Open egress streams per view.
+    streams: Mutex<HashMap<String, broadcast::Sender<String>>>,
+    /// The next sequence number of the egress records per view.
+    sequence_numbers: Mutex<HashMap<String, i64>>,
+    /// `program_code` of the pipeline.
+    program_code: Mutex<String>,
+    /// `deployment_status` of the pipeline.
+    deployment_status: Mutex<String>,
+    /// Whether the pipeline has storage configured.
+    storage: Mutex<bool>,
+    /// How long adhoc queries of a range of `spreadsheet_view` take.
+    query_delay: Mutex<Duration>,
+}
+
+impl MockState {
+    fn stream(&self, view: &str) -> broadcast::Sender<String> {
+        self.streams
+            .lock()
+            .unwrap()
+            .entry(view.to_string())
+            .or_insert_with(|| broadcast::channel(1024).0)
+            .clone()
+    }
+
+    fn next_sequence_number(&self, view: &str) -> i64 {
+        let mut sequence_numbers = self.sequence_numbers.lock().unwrap();
+        let next = sequence_numbers.entry(view.to_string()).or_default();
+        *next += 1;
+        *next - 1
+    }
+
+    fn emit(&self, view: &str, change: Value) {
+        let record = json!({
+            "sequence_number": self.next_sequence_number(view),
+            "json_data": [change],
+        });
+        let _ = self.stream(view).send(format!("{record}\n"));
+    }
+}
+
+/// Mock of the Feldera pipeline API: adhoc queries, ingress and egress of the `xls` pipeline.
+///
+/// Inserts into `spreadsheet_data` are turned into `spreadsheet_view` rows with
+/// `computed_value` = `raw_value`, cells disappear once all their rows were deleted.
+pub struct MockFeldera {
+    pub addr: SocketAddr,
+    state: Arc<MockState>,
+}
+
+impl MockFeldera {
+    pub async fn start() -> Self {
+        let state = Arc::new(MockState::default());
+        *state.stats.lock().unwrap() = json!({
+            "filled_total": 0,
+            "filled_this_hour": 0,
+            "filled_today": 0,
+            "filled_this_week": 0,
+            "currently_active_users": 0,
+        });
+        *state.program_code.lock().unwrap() =
+            String::from("create table spreadsheet_data (id bigint);\n");
+        *state.deployment_status.lock().unwrap() = String::from("Running");
+        let app = Router::new()
+            .route(
+                "/v0/pipelines/:pipeline",
+                get(pipeline).patch(update_program),
+            )
+            .route("/v0/pipelines/:pipeline/shutdown", post(shutdown))
+            .route("/v0/pipelines/:pipeline/start", post(start))
+            .route("/v0/pipelines/:pipeline/query", get(query))
+            .route("/v0/pipelines/:pipeline/egress/:view", post(egress))
+            .route("/v0/pipelines/:pipeline/ingress/:table", post(ingress))
+            .with_state(state.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        MockFeldera { addr, state }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Stores a cell without emitting a change (i.e., it exists before the server starts).
+    pub fn set_cell(&self, id: i64, raw_value: &str) {
+        self.state
+            .cells
+            .lock()
+            .unwrap()
+            .insert(id, cell(id, raw_value));
+    }
+
+    /// Updates a cell and emits the change on the `spreadsheet_view` stream.
+    pub fn push_cell(&self, id: i64, raw_value: &str) {
+        let cell = cell(id, raw_value);
+        self.state.cells.lock().unwrap().insert(id, cell.clone());
+        self.state
+            .emit("spreadsheet_view", json!({ "insert": cell }));
+    }
+
+    /// Updates a cell but drops its change, like the pipeline does for changes the server
+    /// doesn't read fast enough.
+    pub fn drop_cell_change(&self, id: i64, raw_value: &str) {
+        self.state
+            .cells
+            .lock()
+            .unwrap()
+            .insert(id, cell(id, raw_value));
+        self.state.next_sequence_number("spreadsheet_view");
+    }
+
+    /// Updates cells that reference each other as `(id, raw_value)`, like the pipeline does for
+    /// a reference cycle, and emits the changes.
+    pub fn push_cycle(&self, cells: &[(i64, &str)]) {
+        let cycle: Vec<i64> = cells.iter().map(|(id, _)| *id).collect();
+        for (id, raw_value) in cells {
+            let mut cell = cell(*id, raw_value);
+            cell["computed_value"] = json!("#CYCLE!");
+            cell["value_type"] = json!("error");
+            cell["cycle"] = json!(cycle);
+            self.state.cells.lock().unwrap().insert(*id, cell.clone());
+            self.state
+                .emit("spreadsheet_view", json!({ "insert": cell }));
+        }
+    }
+
+    /// Adds an IP to `api_limit_reached` without emitting a change.
+    pub fn set_api_limit(&self, ip: &str) {
+        self.state.api_limits.lock().unwrap().push(ip.to_string());
+    }
+
+    /// Adds an IP to `api_limit_reached` and emits the change.
+    pub fn push_api_limit(&self, ip: &str) {
+        self.state.api_limits.lock().unwrap().push(ip.to_string());
+        self.state
+            .emit("api_limit_reached", json!({ "insert": { "ip": ip } }));
+    }
+
+    /// Sets the row of a cell in `latest_cell_comments` and emits the change.
+    pub fn push_comment(&self, id: i64, comment: &str) {
+        let replaced = self
+            .state
+            .comments
+            .lock()
+            .unwrap()
+            .insert(id, comment.to_string());
+        if let Some(replaced) = replaced {
+            self.state.emit(
+                "latest_cell_comments",
+                json!({ "delete": { "id": id, "comment": replaced } }),
+            );
+        }
+        self.state.emit(
+            "latest_cell_comments",
+            json!({ "insert": { "id": id, "comment": comment } }),
+        );
+    }
+
+    /// Replaces the row of `spreadsheet_statistics` and emits the change.
+    pub fn push_stats(&self, stats: Value) {
+        *self.state.stats.lock().unwrap() = stats.clone();
+        self.state
+            .emit("spreadsheet_statistics", json!({ "insert": stats }));
+    }
+
+    /// Records inserted into `table` through the ingress endpoint.
+    pub fn ingress(&self, table: &str) -> Vec<Value> {
+        self.state
+            .ingress
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(t, _)| t == table)
+            .map(|(_, record)| record.clone())
+            .collect()
+    }
+
+    /// Makes adhoc queries of a range of `spreadsheet_view` take `delay`, like a busy pipeline.
+    pub fn set_query_delay(&self, delay: Duration) {
+        *self.state.query_delay.lock().unwrap() = delay;
+    }
+
+    /// Makes the ingress endpoint fail with `503` until it is available again.
+    pub fn set_ingress_available(&self, available: bool) {
+        *self.state.ingress_unavailable.lock().unwrap() = !available;
+    }
+
+    /// The current program of the pipeline.
+    pub fn program_code(&self) -> String {
+        self.state.program_code.lock().unwrap().clone()
+    }
+
+    /// The current deployment status of the pipeline.
+    pub fn deployment_status(&self) -> String {
+        self.state.deployment_status.lock().unwrap().clone()
+    }
+
+    /// Configures storage for the pipeline, so it keeps its state when it restarts.
+    pub fn set_storage(&self, storage: bool) {
+        *self.state.storage.lock().unwrap() = storage;
+    }
+
+    /// Ends all open egress streams, as if the pipeline restarted.
+    pub fn disconnect_streams(&self) {
+        self.state.streams.lock().unwrap().clear();
+    }
+
+    /// Waits until the server has an egress stream open for `view`.
+    pub async fn wait_for_egress(&self, view: &str) {
+        let stream = self.state.stream(view);
+        wait_until(|| stream.receiver_count() > 0).await;
+    }
+}
+
+fn cell(id: i64, raw_value: &str) -> Value {
+    let value_type = match raw_value.parse::<f64>() {
+        Ok(_) => "number",
+        Err(_) => "string",
+    };
+    json!({
+        "id": id,
+        "background": 0,
+        "raw_value": raw_value,
+        "computed_value": raw_value,
+        "value_type": value_type,
+    })
+}
+
+async fn pipeline(
+    State(state): State<Arc<MockState>>,
+    Path(pipeline): Path<String>,
+) -> Json<Value> {
+    Json(json!({
+        "name": pipeline,
+        "deployment_status": *state.deployment_status.lock().unwrap(),
+        "program_status": "Success",
+        "program_code": *state.program_code.lock().unwrap(),
+        "runtime_config": {"storage": *state.storage.lock().unwrap()},
+    }))
+}
+
+/// Programs compile instantly.
+async fn update_program(
+    State(state): State<Arc<MockState>>,
+    Json(body): Json<Value>,
+) -> StatusCode {
+    if *state.deployment_status.lock().unwrap() != "Shutdown" {
+        return StatusCode::BAD_REQUEST;
+    }
+    let Some(program_code) = body["program_code"].as_str() else {
+        return StatusCode::BAD_REQUEST;
+    };
+    *state.program_code.lock().unwrap() = program_code.to_string();
+    StatusCode::OK
+}
+
+async fn shutdown(State(state): State<Arc<MockState>>) -> StatusCode {
+    *state.deployment_status.lock().unwrap() = String::from("Shutdown");
+    StatusCode::ACCEPTED
+}
+
+async fn start(State(state): State<Arc<MockState>>) -> StatusCode {
+    *state.deployment_status.lock().unwrap() = String::from("Running");
+    StatusCode::ACCEPTED
+}
+
+async fn query(
+    State(state): State<Arc<MockState>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let sql = params.get("sql").cloned().unwrap_or_default();
+    let mut rows = Vec::new();
+    if sql.contains("FROM spreadsheet_view") {
+        // Without a range, e.g. to build the search index, all cells are returned.
+        let range = Regex::new(r"id >= (\d+) and id < (\d+)").unwrap();
+        let (from, to) = match range.captures(&sql) {
+            Some(caps) => (caps[1].parse().unwrap(), caps[2].parse().unwrap()),
+            None => (i64::MIN, i64::MAX),
+        };
+        let delay = *state.query_delay.lock().unwrap();
+        tokio::time::sleep(delay).await;
+        // Timestamps have a fixed format, so they compare like strings.
+        let since = Regex::new(r"ts > TIMESTAMP '([^']+)'").unwrap();
+        let since = since.captures(&sql).map(|caps| caps[1].to_string());
+        // Column stripes, optionally of every `step`th row.
+        let column = Regex::new(r"id % 26 = (\d+)").unwrap();
+        let column = column
+            .captures(&sql)
+            .map(|caps| caps[1].parse::<i64>().unwrap());
+        let step = Regex::new(r"\(id / 26 - (\d+)\) % (\d+) = 0").unwrap();
+        let step = step.captures(&sql).map(|caps| {
+            (
+                caps[1].parse::<i64>().unwrap(),
+                caps[2].parse::<i64>().unwrap(),
+            )
+        });
+        let numbers_only = sql.contains("value_type = 'number'");
+        let non_empty = sql.contains("raw_value != ''");
+        rows.extend(
+            state
+                .cells
+                .lock()
+                .unwrap()
+                .range(from..to)
+                .map(|(_, c)| c.clone())
+                .filter(|c| {
+                    since
+                        .as_deref()
+                        .is_none_or(|since| c["ts"].as_str().is_some_and(|ts| ts > since))
+                })
+                .filter(|c| {
+                    let id = c["id"].as_i64().unwrap();
+                    column.is_none_or(|column| id % 26 == column)
+                        && step.is_none_or(|(from_row, step)| (id / 26 - from_row) % step == 0)
+                        && (!numbers_only || c["value_type"] == "number")
+                        && (!non_empty || c["raw_value"] != "")
+                }),
+        );
+    } else if sql.contains("FROM api_limit_reached") {
+        rows.extend(
+            state
+                .api_limits
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|ip| json!({ "ip": ip })),
+        );
+    } else if sql.contains("FROM latest_cell_comments") {
+        rows.extend(
+            state
+                .comments
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(id, comment)| json!({ "id": id, "comment": comment })),
+        );
+    } else if let Some(caps) = Regex::new(r"d\.expires_at > TIMESTAMP '([^']+)'")
+        .unwrap()
+        .captures(&sql)
+    {
+        // Cells whose latest row expired after `since`.
+        let since = caps[1].to_string();
+        let now = chrono::Utc::now()
+            .format("%Y-%m-%d %H:%M:%S%.3f")
+            .to_string();
+        let ingress = state.ingress.lock().unwrap();
+        let mut latest: BTreeMap<i64, &Value> = BTreeMap::new();
+        for (_, record) in ingress
+            .iter()
+            .filter(|(table, _)| table == "spreadsheet_data")
+        {
+            let id = record["id"].as_i64().unwrap();
+            if latest
+                .get(&id)
+                .is_none_or(|row| row["ts"].as_str() <= record["ts"].as_str())
+            {
+                latest.insert(id, record);
+            }
+        }
+        rows.extend(latest.into_values().filter_map(|row| {
+            let expires_at = row["expires_at"].as_str()?;
+            (*expires_at > *since && *expires_at <= *now)
+                .then(|| json!({ "id": row["id"], "expires_at": expires_at }))
+        }));
+    } else if sql.contains("expires_at <= NOW()") {
+        // Timestamps have a fixed format, so they compare like strings.
+        let now = chrono::Utc::now()
+            .format("%Y-%m-%d %H:%M:%S%.3f")
+            .to_string();
+        let ingress = state.ingress.lock().unwrap();
+        let data = || {
+            ingress
+                .iter()
+                .filter(|(table, _)| table == "spreadsheet_data")
+                .map(|(_, record)| record)
+        };
+        let mut expired: BTreeMap<i64, String> = BTreeMap::new();
+        for record in data() {
+            if record["expires_at"].as_str().is_some_and(|at| *at <= *now) {
+                let ts = record["ts"].as_str().unwrap().to_string();
+                let max_ts = expired.entry(record["id"].as_i64().unwrap()).or_default();
+                *max_ts = ts.max(max_ts.clone());
+            }
+        }
+        rows.extend(
+            data()
+                .filter(|record| {
+                    expired
+                        .get(&record["id"].as_i64().unwrap())
+                        .is_some_and(|ts| record["ts"].as_str().unwrap() <= ts.as_str())
+                })
+                .cloned(),
+        );
+        rows.sort_by_key(|row| row["ts"].as_str().unwrap().to_string());
+    } else if let Some(caps) = Regex::new(r"HAVING MAX\(ts\) < NOW\(\) - INTERVAL '(\d+) seconds'")
+        .unwrap()
+        .captures(&sql)
+    {
+        let max_age = chrono::Duration::seconds(caps[1].parse().unwrap());
+        let stale_before = (chrono::Utc::now() - max_age)
+            .format("%Y-%m-%d %H:%M:%S%.3f")
+            .to_string();
+        let regions: Vec<(i64, i64)> = Regex::new(r"id >= (\d+) AND id < (\d+)")
+            .unwrap()
+            .captures_iter(&sql)
+            .map(|caps| (caps[1].parse().unwrap(), caps[2].parse().unwrap()))
+            .collect();
+        let ingress = state.ingress.lock().unwrap();
+        let data = || {
+            ingress
+                .iter()
+                .filter(|(table, _)| table == "spreadsheet_data")
+                .map(|(_, record)| record)
+        };
+        let mut latest: BTreeMap<i64, String> = BTreeMap::new();
+        for record in data() {
+            let id = record["id"].as_i64().unwrap();
+            if regions.iter().any(|(from, to)| (*from..*to).contains(&id)) {
+                let ts = record["ts"].as_str().unwrap().to_string();
+                let max_ts = latest.entry(id).or_default();
+                *max_ts = ts.max(max_ts.clone());
+            }
+        }
+        rows.extend(
+            data()
+                .filter(|record| {
+                    latest
+                        .get(&record["id"].as_i64().unwrap())
+                        .is_some_and(|ts| *ts < stale_before)
+                })
+                .cloned(),
+        );
+    } else if let Some(caps) =
+        Regex::new(r"d\.ts < l\.ts WHERE d\.ts < NOW\(\) - INTERVAL '(\d+) seconds'")
+            .unwrap()
+            .captures(&sql)
+    {
+        let max_age = chrono::Duration::seconds(caps[1].parse().unwrap());
+        let old_before = (chrono::Utc::now() - max_age)
+            .format("%Y-%m-%d %H:%M:%S%.3f")
+            .to_string();
+        let ingress = state.ingress.lock().unwrap();
+        let data = || {
+            ingress
+                .iter()
+                .filter(|(table, _)| table == "spreadsheet_data")
+                .map(|(_, record)| record)
+        };
+        let mut latest: BTreeMap<i64, String> = BTreeMap::new();
+        for record in data() {
+            let ts = record["ts"].as_str().unwrap().to_string();
+            let max_ts = latest.entry(record["id"].as_i64().unwrap()).or_default();
+            *max_ts = ts.max(max_ts.clone());
+        }
+        rows.extend(
+            data()
+                .filter(|record| {
+                    let ts = record["ts"].as_str().unwrap();
+                    ts < latest[&record["id"].as_i64().unwrap()].as_str() && *ts < *old_before
+                })
+                .cloned(),
+        );
+    } else if let Some(caps) =
+        Regex::new(r"FROM (\w+) WHERE (ip <> '' AND )?ts < NOW\(\) - INTERVAL '(\d+) seconds'")
+            .unwrap()
+            .captures(&sql)
+    {
+        let max_age = chrono::Duration::seconds(caps[3].parse().unwrap());
+        let old_before = (chrono::Utc::now() - max_age)
+            .format("%Y-%m-%d %H:%M:%S%.3f")
+            .to_string();
+        let with_ip = caps.get(2).is_some();
+        rows.extend(
+            state
+                .ingress
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(table, _)| *table == caps[1])
+                .map(|(_, record)| record)
+                .filter(|record| *record["ts"].as_str().unwrap() < *old_before)
+                .filter(|record| !with_ip || record["ip"] != "")
+                .cloned(),
+        );
+    } else if sql.contains("SELECT d.id, d.ip, d.ts") {
+        // Who wrote the latest rows in a range, for moderators.
+        let range = Regex::new(r"id >= (\d+) AND id < (\d+)").unwrap();
+        let Some(caps) = range.captures(&sql) else {
+            return (StatusCode::BAD_REQUEST, "unsupported query").into_response();
+        };
+        let (from, to): (i64, i64) = (caps[1].parse().unwrap(), caps[2].parse().unwrap());
+        let ingress = state.ingress.lock().unwrap();
+        let mut latest: BTreeMap<i64, &Value> = BTreeMap::new();
+        for (_, record) in ingress
+            .iter()
+            .filter(|(table, _)| table == "spreadsheet_data")
+        {
+            let id = record["id"].as_i64().unwrap();
+            if (from..to).contains(&id)
+                && latest
+                    .get(&id)
+                    .is_none_or(|row| row["ts"].as_str() <= record["ts"].as_str())
+            {
+                latest.insert(id, record);
+            }
+        }
+        rows.extend(
+            latest
+                .into_values()
+                .map(|row| json!({ "id": row["id"], "ip": row["ip"], "ts": row["ts"] })),
+        );
+    } else if sql.contains("FROM spreadsheet_data") {
+        let id = Regex::new(r"WHERE id = (\d+)").unwrap();
+        let Some(caps) = id.captures(&sql) else {
+            return (StatusCode::BAD_REQUEST, "unsupported query").into_response();
+        };
+        let id: i64 = caps[1].parse().unwrap();
+        // Most recent first.
+        rows.extend(
+            state
+                .ingress
+                .lock()
+                .unwrap()
+                .iter()
+                .rev()
+                .filter(|(table, record)| table == "spreadsheet_data" && record["id"] == id)
+                .map(|(_, record)| record.clone()),
+        );
+    } else if sql.contains("FROM latest_column_labels") {
+        let ingress = state.ingress.lock().unwrap();
+        let mut labels: BTreeMap<i64, &Value> = BTreeMap::new();
+        for (_, record) in ingress.iter().filter(|(table, _)| table == "column_labels") {
+            labels.insert(record["col"].as_i64().unwrap(), record);
+        }
+        rows.extend(
+            labels
+                .into_values()
+                .filter(|row| row["label"] != "")
+                .map(|row| json!({ "col": row["col"], "label": row["label"] })),
+        );
+    } else if sql.contains("FROM column_statistics") {
+        let cells = state.cells.lock().unwrap();
+        let mut columns: BTreeMap<i64, Vec<&Value>> = BTreeMap::new();
+        for cell in cells.values().filter(|cell| cell["raw_value"] != "") {
+            columns
+                .entry(cell["id"].as_i64().unwrap() % 26)
+                .or_default()
+                .push(cell);
+        }
+        rows.extend(columns.into_iter().map(|(col, cells)| {
+            let numbers: Vec<f64> = cells
+                .iter()
+                .filter(|cell| cell["value_type"] == "number")
+                .map(|cell| cell["computed_value"].as_str().unwrap().parse().unwrap())
+                .collect();
+            let total: f64 = numbers.iter().sum();
+            let average = (!numbers.is_empty()).then(|| total / numbers.len() as f64);
+            json!({
+                "col": col,
+                "filled": cells.len(),
+                "numbers": numbers.len(),
+                "total": total,
+                "average": average,
+            })
+        }));
+    } else if sql.contains("FROM write_heatmap") {
+        // All writes count, the mock has no clock.
+        let mut regions: BTreeMap<i64, u64> = BTreeMap::new();
+        for (_, record) in state
+            .ingress
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(table, _)| table == "spreadsheet_data")
+        {
+            *regions
+                .entry(record["id"].as_i64().unwrap() / 26000)
+                .or_default() += 1;
+        }
+        rows.extend(
+            regions
+                .into_iter()
+                .map(|(region, writes)| json!({ "region": region, "writes": writes })),
+        );
+    } else if sql.contains("FROM moderation_events") {
+        // Most recent first.
+        rows.extend(
+            state
+                .ingress
+                .lock()
+                .unwrap()
+                .iter()
+                .rev()
+                .filter(|(table, _)| table == "moderation_events")
+                .map(|(_, record)| record.clone()),
+        );
+    } else if sql.contains("FROM spreadsheet_statistics") {
+        rows.push(state.stats.lock().unwrap().clone());
+    } else {
+        return (StatusCode::BAD_REQUEST, "unsupported query").into_response();
+    }
+
+    let body: String = rows.iter().map(|row| format!("{row}\n")).collect();
+    body.into_response()
+}
+
+async fn egress(
+    State(state): State<Arc<MockState>>,
+    Path((_pipeline, view)): Path<(String, String)>,
+) -> Response {
+    let changes = tokio_stream::wrappers::BroadcastStream::new(state.stream(&view).subscribe());
+    let body = changes.filter_map(|line| async move { line.ok().map(Ok::<_, Infallible>) });
+    Body::from_stream(body).into_response()
+}
+
+async fn ingress(
+    State(state): State<Arc<MockState>>,
+    Path((_pipeline, table)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+    Json(data): Json<Value>,
+) -> StatusCode {
+    if *state.ingress_unavailable.lock().unwrap() {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+    let records = match data {
+        Value::Array(records) if params.get("array").is_some_and(|a| a == "true") => records,
+        record => vec![record],
+    };
+    if params
+        .get("update_format")
+        .is_some_and(|f| f == "insert_delete")
+    {
+        let mut ingress = state.ingress.lock().unwrap();
+        let mut deleted_ids = Vec::new();
+        for record in records {
+            if let Some(inserted) = record.get("insert") {
+                ingress.push((table.clone(), inserted.clone()));
+                continue;
+            }
+            let Some(deleted) = record.get("delete") else {
+                return StatusCode::BAD_REQUEST;
+            };
+            let Some(idx) = ingress
+                .iter()
+                .position(|(t, row)| *t == table && row == deleted)
+            else {
+                return StatusCode::BAD_REQUEST;
+            };
+            ingress.remove(idx);
+            if table == "spreadsheet_data" {
+                deleted_ids.push(deleted["id"].as_i64().unwrap());
+            }
+        }
+        // Cells without rows disappear from the view, once the whole batch is applied.
+        for id in deleted_ids {
+            let remaining = ingress
+                .iter()
+                .any(|(t, row)| *t == table && row["id"] == id);
+            if !remaining {
+                if let Some(cell) = state.cells.lock().unwrap().remove(&id) {
+                    state.emit("spreadsheet_view", json!({ "delete": cell }));
+                }
+            }
+        }
+        return StatusCode::OK;
+    }
+    for record in records {
+        state
+            .ingress
+            .lock()
+            .unwrap()
+            .push((table.clone(), record.clone()));
+        if table == "spreadsheet_data" {
+            let id = record["id"].as_i64().unwrap();
+            let mut cell = cell(id, record["raw_value"].as_str().unwrap());
+            cell["background"] = record["background"].clone();
+            cell["style"] = record["style"].clone();
+            cell["ts"] = record["ts"].clone();
+            state.cells.lock().unwrap().insert(id, cell.clone());
+            state.emit("spreadsheet_view", json!({ "insert": cell }));
+        }
+    }
+    StatusCode::OK
+}
+
+/// The server binary, running against a [`MockFeldera`]. Killed on drop.
+pub struct Server {
+    child: Child,
+    pub addr: SocketAddr,
+}
+
+impl Server {
+    pub async fn start(feldera: &MockFeldera) -> Self {
+        Self::start_with_env(feldera, &[]).await
+    }
+
+    /// Starts the server and waits until the cached ranges are loaded.
+    pub async fn start_with_env(feldera: &MockFeldera, env: &[(&str, &str)]) -> Self {
+        let server = Self::start_loading(feldera, env).await;
+        server.wait_for_cache().await;
+        server
+    }
+
+    /// Starts the server without waiting for the cached ranges to be loaded.
+    pub async fn start_loading(feldera: &MockFeldera, env: &[(&str, &str)]) -> Self {
+        let addr = free_addr();
+        let child = Command::new(env!("CARGO_BIN_EXE_generic-rust"))
+            .env("SERVER_ADDRESS", addr.to_string())
+            .env("GRPC_ADDRESS", "")
+            .env("FELDERA_HOST", feldera.url())
+            .env("FELDERA_SUPERVISOR_INTERVAL_SECS", "0")
+            .env("FELDERA_RECONNECT_DELAY_SECS", "1")
+            .env("CHANGE_COALESCE_WINDOW_MS", "0")
+            .env_remove("FANOUT_REDIS_URL")
+            .envs(env.iter().copied())
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to start server");
+        let server = Server { child, addr };
+
+        let client = reqwest::Client::new();
+        let url = server.url("/");
+        wait_until_async(|| {
+            let request = client.get(&url).send();
+            async move { request.await.is_ok_and(|r| r.status().is_success()) }
+        })
+        .await;
+        feldera.wait_for_egress("spreadsheet_statistics").await;
+        // The memory backend doesn't follow `spreadsheet_view`.
+        if !env.contains(&("SPREADSHEET_BACKEND", "memory")) {
+            feldera.wait_for_egress("spreadsheet_view").await;
+        }
+        feldera.wait_for_egress("api_limit_reached").await;
+        feldera.wait_for_egress("latest_cell_comments").await;
+        server
+    }
+
+    /// Waits until the cells of all cached ranges are loaded.
+    pub async fn wait_for_cache(&self) {
+        let url = self.url("/metrics");
+        wait_until_async(|| {
+            let request = reqwest::get(&url);
+            async move {
+                match request.await {
+                    Ok(response) => response
+                        .text()
+                        .await
+                        .is_ok_and(|metrics| metrics.contains("xls_cache_ranges_loading 0")),
+                    Err(_) => false,
+                }
+            }
+        })
+        .await;
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+
+    pub async fn connect(&self) -> WsClient {
+        let url = format!("ws://{}/api/spreadsheet", self.addr);
+        let (socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        WsClient {
+            socket,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Connects with a custom request, e.g. with a token, `None` if the server refuses.
+    pub async fn try_connect(&self, request: impl IntoClientRequest + Unpin) -> Option<WsClient> {
+        let (socket, _) = tokio_tungstenite::connect_async(request).await.ok()?;
+        Some(WsClient {
+            socket,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub struct WsClient {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    /// Cells of an array message that weren't returned yet.
+    pending: VecDeque<Value>,
+}
+
+impl WsClient {
+    pub async fn send_region(&mut self, from: i64, to: i64) {
+        let msg = json!({ "from": from, "to": to }).to_string();
+        self.socket.send(Message::Text(msg)).await.unwrap();
+    }
+
+    pub async fn send_json(&mut self, message: Value) {
+        self.socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    /// Next non-empty JSON message, or `None` if nothing arrives within `timeout`. The cells of
+    /// snapshot arrays are returned one by one, heartbeats are skipped.
+    pub async fn next_within(&mut self, timeout: Duration) -> Option<Value> {
+        loop {
+            if let Some(cell) = self.pending.pop_front() {
+                return Some(cell);
+            }
+            match self.next_message_within(timeout).await? {
+                Value::Array(cells) => self.pending.extend(cells),
+                msg if msg.get("interval_secs").is_some() => {}
+                msg => return Some(msg),
+            }
+        }
+    }
+
+    /// Next non-empty JSON message as it was sent, or `None` if nothing arrives within
+    /// `timeout`.
+    pub async fn next_message_within(&mut self, timeout: Duration) -> Option<Value> {
+        loop {
+            let msg = tokio::time::timeout(timeout, self.socket.next())
+                .await
+                .ok()??
+                .ok()?;
+            if let Message::Text(text) = msg {
+                if !text.trim().is_empty() {
+                    return Some(serde_json::from_str(&text).unwrap());
+                }
+            }
+        }
+    }
+
+    pub async fn next(&mut self) -> Value {
+        self.next_within(TIMEOUT)
+            .await
+            .expect("No message received")
+    }
+
+    /// The code and reason the server closed the connection with.
+    pub async fn close_frame(&mut self) -> (u16, String) {
+        loop {
+            let msg = tokio::time::timeout(TIMEOUT, self.socket.next())
+                .await
+                .expect("Connection not closed")
+                .expect("Connection closed without a close frame")
+                .unwrap();
+            if let Message::Close(frame) = msg {
+                let frame = frame.expect("Close frame without code");
+                return (frame.code.into(), frame.reason.to_string());
+            }
+        }
+    }
+
+    /// Reads messages until a cell with `id` arrives and returns it.
+    pub async fn next_cell(&mut self, id: i64) -> Value {
+        loop {
+            let msg = self.next().await;
+            if msg["id"] == id {
+                return msg;
+            }
+        }
+    }
+}
+
+/// A local address that is free to listen on.
+pub fn free_addr() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+pub async fn wait_until(mut condition: impl FnMut() -> bool) {
+    wait_until_async(|| std::future::ready(condition())).await
+}
+
+pub async fn wait_until_async<F: std::future::Future<Output = bool>>(
+    mut condition: impl FnMut() -> F,
+) {
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while !condition().await {
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "Condition not met in time"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
@@ -0,0 +1,2696 @@
+//! Tests the HTTP/websocket protocol of the server against a mock Feldera instance.
+
+mod common;
+
+use std::time::Duration;
+
+use common::{wait_until_async, MockFeldera, Server};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn ws_snapshot_from_cache() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "cached");
+    feldera.set_cell(2, "also cached");
+    let server = Server::start(&feldera).await;
+
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    assert_eq!(ws.next().await["raw_value"], "cached");
+    assert_eq!(ws.next().await["raw_value"], "also cached");
+}
+
+#[tokio::test]
+async fn ws_snapshot_from_adhoc_query() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    // Outside of the cached ranges, so it is queried when requested.
+    feldera.set_cell(500_000_000, "not cached");
+
+    let mut ws = server.connect().await;
+    ws.send_region(500_000_000 - 26, 500_000_000 + 26).await;
+    let cell = ws.next().await;
+    assert_eq!(cell["id"], 500_000_000);
+    assert_eq!(cell["computed_value"], "not cached");
+}
+
+#[tokio::test]
+async fn changes_are_sent_to_subscribed_regions_only() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+
+    let mut first = server.connect().await;
+    first.send_region(0, 2600).await;
+    let mut second = server.connect().await;
+    second.send_region(2600, 5200).await;
+    // Empty regions send an empty snapshot, give the server a moment to register both regions.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    feldera.push_cell(10, "first");
+    feldera.push_cell(2610, "second");
+
+    assert_eq!(first.next_cell(10).await["raw_value"], "first");
+    assert_eq!(second.next_cell(2610).await["raw_value"], "second");
+    assert_eq!(first.next_within(Duration::from_millis(500)).await, None);
+    assert_eq!(second.next_within(Duration::from_millis(500)).await, None);
+}
+
+#[tokio::test]
+async fn value_types_are_sent_with_cells() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "42");
+    feldera.set_cell(2, "forty-two");
+    let server = Server::start(&feldera).await;
+
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    assert_eq!(ws.next().await["value_type"], "number");
+    assert_eq!(ws.next().await["value_type"], "string");
+}
+
+#[tokio::test]
+async fn cycles_are_sent_with_cells() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let mut ws = server.connect().await;
+    ws.send_region(0, 2600).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    feldera.push_cycle(&[(26, "=B1"), (27, "=A1")]);
+    let change = ws.next_cell(27).await;
+    assert_eq!(change["computed_value"], "#CYCLE!");
+    assert_eq!(change["cycle"], json!([26, 27]));
+
+    // Also from the cache.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut ws = server.connect().await;
+    ws.send_region(0, 2600).await;
+    assert_eq!(ws.next_cell(26).await["cycle"], json!([26, 27]));
+    assert_eq!(ws.next_cell(27).await["cycle"], json!([26, 27]));
+}
+
+#[tokio::test]
+async fn post_updates_cell() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let mut ws = server.connect().await;
+    ws.send_region(0, 2600).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let response = reqwest::Client::new()
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": 42, "raw_value": "=1+1", "background": 7}))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let ingress = feldera.ingress("spreadsheet_data");
+    assert_eq!(ingress.len(), 1);
+    assert_eq!(ingress[0]["id"], 42);
+    assert_eq!(ingress[0]["raw_value"], "=1+1");
+    assert_eq!(ingress[0]["background"], 7);
+    assert_eq!(ingress[0]["ip"], "127.0.0.1");
+
+    let cell = ws.next_cell(42).await;
+    assert_eq!(cell["raw_value"], "=1+1");
+    assert_eq!(cell["background"], 7);
+}
+
+#[tokio::test]
+async fn cells_are_kept_in_memory_with_the_memory_backend() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &[("SPREADSHEET_BACKEND", "memory")]).await;
+    let mut ws = server.connect().await;
+    ws.send_region(0, 2600).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let update = |id: i64, raw_value: &'static str| {
+        reqwest::Client::new()
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": id, "raw_value": raw_value, "background": 7}))
+            .send()
+    };
+
+    assert!(update(42, "12").await.unwrap().status().is_success());
+    let cell = ws.next_cell(42).await;
+    assert_eq!(cell["raw_value"], "12");
+    assert_eq!(cell["computed_value"], "12");
+    assert_eq!(cell["background"], 7);
+
+    // Cells outside of the cached ranges are queried from the backend.
+    assert!(update(500_000_000, "far")
+        .await
+        .unwrap()
+        .status()
+        .is_success());
+    let mut other = server.connect().await;
+    other.send_region(500_000_000, 500_000_026).await;
+    assert_eq!(other.next_cell(500_000_000).await["raw_value"], "far");
+    assert!(feldera.ingress("spreadsheet_data").is_empty());
+}
+
+#[tokio::test]
+async fn styles_are_stored_with_the_cell() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let mut ws = server.connect().await;
+    ws.send_region(0, 2600).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Bold and red, see `CellStyle`.
+    let style = 0xff0000ff_i64 << 32 | 0b101;
+    for (id, update) in [
+        (
+            1,
+            json!({"id": 1, "raw_value": "x", "background": 0, "style": style}),
+        ),
+        (2, json!({"id": 2, "raw_value": "y", "background": 0})),
+    ] {
+        let response = reqwest::Client::new()
+            .post(server.url("/api/spreadsheet"))
+            .json(&update)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let cell = ws.next_cell(id).await;
+        // Unstyled cells have no style or 0.
+        assert_eq!(
+            cell["style"].as_i64(),
+            Some(update["style"].as_i64().unwrap_or(0))
+        );
+    }
+
+    let ingress = feldera.ingress("spreadsheet_data");
+    assert_eq!(ingress[0]["style"], style);
+    assert_eq!(ingress[1]["style"], 0);
+}
+
+#[tokio::test]
+async fn client_ip_header_is_only_trusted_from_proxies() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &[("TRUSTED_PROXIES", "10.0.0.0/8")]).await;
+
+    let response = reqwest::Client::new()
+        .post(server.url("/api/spreadsheet"))
+        .header("Fly-Client-IP", "192.0.2.1")
+        .json(&json!({"id": 42, "raw_value": "spoofed", "background": 0}))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(feldera.ingress("spreadsheet_data")[0]["ip"], "127.0.0.1");
+}
+
+#[tokio::test]
+async fn post_rejects_invalid_cell_id() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+
+    let response = reqwest::Client::new()
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": -1, "raw_value": "x", "background": 0}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "validation");
+    assert_eq!(body["field"], "id");
+    assert!(feldera.ingress("spreadsheet_data").is_empty());
+
+    // Malformed bodies get the same error body.
+    let response = reqwest::Client::new()
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": "A1", "raw_value": "x", "background": 0}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "validation");
+}
+
+#[tokio::test]
+async fn post_rejects_unknown_functions() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+    let post = |raw_value: &'static str| {
+        client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": 1, "raw_value": raw_value, "background": 0}))
+            .send()
+    };
+
+    for raw_value in [
+        "=COUNT(A0, MAX(1, 2))",
+        "=ROUND(A1) & \"FOO(\"",
+        "FOO(1)",
+        "=TEXT(DATE(2025, 1, 5), \"dd.mm.yyyy\")",
+        "=RANDBETWEEN(1, 6) + RAND()",
+    ] {
+        assert!(post(raw_value).await.unwrap().status().is_success());
+    }
+
+    let response = post("=FOO(1)").await.unwrap();
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "validation");
+    assert_eq!(body["field"], "raw_value");
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("Unknown function FOO()"));
+
+    let response = post("=count(A0)").await.unwrap();
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("COUNT()"));
+    assert_eq!(feldera.ingress("spreadsheet_data").len(), 5);
+}
+
+#[tokio::test]
+async fn post_validates_ranges() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+    let post = |raw_value: &'static str| {
+        client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": 1, "raw_value": raw_value, "background": 0}))
+            .send()
+    };
+
+    for raw_value in [
+        "=SUM(A1:B10)+MAX(C3:A0)",
+        "=VLOOKUP(\"b\", A0:Z37, 2)",
+        "A0:Z39999999",
+    ] {
+        assert!(post(raw_value).await.unwrap().status().is_success());
+    }
+    for (raw_value, error) in [
+        ("=SUM(A0:Z38)", "at most 1000 cells"),
+        ("=SUM(A0:Z20, B1:B500)", "at most 1000 cells"),
+        ("=HLOOKUP(1, A0:Z100, 2)", "at most 1000 cells"),
+        ("=SUM(A1:AA2)", "Invalid range A1:AA2"),
+        ("=SUM(a1:B2)", "Invalid range a1:B2"),
+        ("=SUM(A0:A1040000000)", "Invalid range"),
+    ] {
+        let response = post(raw_value).await.unwrap();
+        assert_eq!(response.status(), 400);
+        let body: Value = response.json().await.unwrap();
+        assert!(body["error"].as_str().unwrap().contains(error), "{body}");
+    }
+    assert_eq!(feldera.ingress("spreadsheet_data").len(), 3);
+}
+
+#[tokio::test]
+async fn batch_updates_cells() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(server.url("/api/spreadsheet/batch"))
+        .json(&json!([
+            {"id": 1, "raw_value": "", "background": 0},
+            {"id": 2, "raw_value": "=1+1", "background": 7},
+        ]))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["updated"], 2);
+    let ingress = feldera.ingress("spreadsheet_data");
+    assert_eq!(ingress.len(), 2);
+    assert_eq!(ingress[1]["raw_value"], "=1+1");
+    assert_eq!(ingress[1]["ip"], "127.0.0.1");
+
+    // A single invalid update rejects the whole batch.
+    let response = client
+        .post(server.url("/api/spreadsheet/batch"))
+        .json(&json!([
+            {"id": 3, "raw_value": "x", "background": 0},
+            {"id": -1, "raw_value": "x", "background": 0},
+        ]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    assert_eq!(feldera.ingress("spreadsheet_data").len(), 2);
+}
+
+#[tokio::test]
+async fn column_labels_are_shared() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+    let share = |column: u32, label: &'static str| {
+        client
+            .post(server.url("/api/column-labels"))
+            .json(&json!({"column": column, "label": label}))
+            .send()
+    };
+
+    assert!(share(0, " Price ").await.unwrap().status().is_success());
+    assert!(share(1, "Amount").await.unwrap().status().is_success());
+    assert!(share(1, "").await.unwrap().status().is_success());
+    let response = share(26, "Z+1").await.unwrap();
+    assert_eq!(response.status(), 400);
+
+    let ingress = feldera.ingress("column_labels");
+    assert_eq!(ingress.len(), 3);
+    assert_eq!(ingress[0]["label"], "Price");
+    assert_eq!(ingress[0]["ip"], "127.0.0.1");
+
+    let labels: Value = client
+        .get(server.url("/api/column-labels"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(labels, json!([{"column": 0, "label": "Price"}]));
+}
+
+#[tokio::test]
+async fn meta_describes_the_deployment() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let meta: Value = reqwest::get(server.url("/api/meta"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        meta,
+        json!({
+            "writes_allowed": true,
+            "max_cell_chars": 64,
+            "columns": 26,
+            "rows": 40_000_000,
+            "auth_required": false,
+            "experimental_features": [],
+        })
+    );
+}
+
+#[tokio::test]
+async fn read_only_deployments_reject_edits() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(
+        &feldera,
+        &[
+            ("READ_ONLY", "true"),
+            ("WS_TOKENS", "alice:secret"),
+            ("EXPERIMENTAL_FEATURES", "heatmap, ,sheets"),
+        ],
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let meta: Value = client
+        .get(server.url("/api/meta"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(meta["writes_allowed"], false);
+    assert_eq!(meta["auth_required"], true);
+    assert_eq!(meta["experimental_features"], json!(["heatmap", "sheets"]));
+
+    let edits = [
+        (
+            "/api/spreadsheet",
+            json!({"id": 1, "raw_value": "x", "background": 0}),
+        ),
+        (
+            "/api/spreadsheet/batch",
+            json!([{"id": 1, "raw_value": "x", "background": 0}]),
+        ),
+        ("/api/comments", json!({"id": 1, "comment": "x"})),
+        ("/api/column-labels", json!({"column": 0, "label": "x"})),
+    ];
+    for (path, body) in edits {
+        let response = client
+            .post(server.url(path))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403, "{path}");
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "read_only");
+    }
+    assert!(feldera.ingress("spreadsheet_data").is_empty());
+    assert!(feldera.ingress("cell_comments").is_empty());
+    assert!(feldera.ingress("column_labels").is_empty());
+}
+
+#[tokio::test]
+async fn cells_are_commented() {
+    let feldera = MockFeldera::start().await;
+    feldera.push_comment(3, "Check this total");
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+    let comments = |range: &'static str| {
+        let request = client
+            .get(server.url(&format!("/api/comments/{range}")))
+            .send();
+        async move { request.await.unwrap().json::<Value>().await.unwrap() }
+    };
+
+    let response = client
+        .post(server.url("/api/comments"))
+        .json(&json!({"id": 27, "comment": " Source: 2023 report "}))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let response = client
+        .post(server.url("/api/comments"))
+        .json(&json!({"id": -1, "comment": "Nowhere"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let ingress = feldera.ingress("cell_comments");
+    assert_eq!(ingress.len(), 1);
+    assert_eq!(ingress[0]["comment"], "Source: 2023 report");
+    assert_eq!(ingress[0]["ip"], "127.0.0.1");
+
+    assert_eq!(
+        comments("0..26").await,
+        json!([{"id": 3, "comment": "Check this total"}])
+    );
+    // Feldera sends the comment back through `latest_cell_comments`.
+    feldera.push_comment(27, "Source: 2023 report");
+    feldera.push_comment(3, "Fixed");
+    wait_until_async(|| async {
+        comments("0..52").await
+            == json!([
+                {"id": 3, "comment": "Fixed"},
+                {"id": 27, "comment": "Source: 2023 report"},
+            ])
+    })
+    .await;
+    let response = client
+        .get(server.url("/api/comments/0..100000"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn column_statistics() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(0, "1");
+    feldera.set_cell(26, "2.5");
+    feldera.set_cell(52, "text");
+    feldera.set_cell(1, "text");
+    let server = Server::start(&feldera).await;
+
+    let statistics: Value = reqwest::Client::new()
+        .get(server.url("/api/column-statistics"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        statistics,
+        json!([
+            {"column": 0, "filled": 3, "numbers": 2, "sum": 3.5, "average": 1.75},
+            {"column": 1, "filled": 1, "numbers": 0, "sum": 0.0, "average": null},
+        ])
+    );
+}
+
+#[tokio::test]
+async fn column_values_are_sampled() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(0, "100");
+    for (row, raw_value) in ["5", "1", "text", "9", "3", "3", "", "4"]
+        .iter()
+        .enumerate()
+    {
+        feldera.set_cell(row as i64 * 26 + 1, raw_value);
+    }
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+
+    let every: Value = client
+        .get(server.url("/api/column-values?column=1&from_row=0&to_row=8&points=4"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        every,
+        json!({
+            "column": 1,
+            "sampling": "every",
+            "step": 2,
+            "values": [
+                {"id": 1, "computed_value": "5"},
+                {"id": 53, "computed_value": "text"},
+                {"id": 105, "computed_value": "3"},
+            ],
+        })
+    );
+
+    let min_max: Value = client
+        .get(
+            server.url("/api/column-values?column=1&from_row=0&to_row=8&points=4&sampling=min_max"),
+        )
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(min_max["step"], 4);
+    assert_eq!(
+        min_max["values"],
+        json!([
+            {"id": 27, "computed_value": "1"},
+            {"id": 79, "computed_value": "9"},
+            {"id": 105, "computed_value": "3"},
+            {"id": 183, "computed_value": "4"},
+        ])
+    );
+
+    for (query, field) in [
+        ("column=26&from_row=0&to_row=8", "column"),
+        ("column=1&from_row=0&to_row=8&points=0", "points"),
+        ("column=1&from_row=0&to_row=2000000", "to_row"),
+        (
+            "column=1&from_row=0&to_row=200000&sampling=min_max",
+            "to_row",
+        ),
+    ] {
+        let response = client
+            .get(server.url(&format!("/api/column-values?{query}")))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["field"], field);
+    }
+}
+
+#[tokio::test]
+async fn heatmap_counts_writes_per_region() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+    for id in [1, 2, 26_000 * 3 + 5] {
+        let response = client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": id, "raw_value": "x", "background": 0}))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+    }
+
+    let heatmap: Value = client
+        .get(server.url("/api/heatmap"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        heatmap,
+        json!([
+            {"region": {"from": 0, "to": 26_000}, "writes": 2},
+            {"region": {"from": 78_000, "to": 104_000}, "writes": 1},
+        ])
+    );
+}
+
+#[tokio::test]
+async fn search_finds_cells_containing_a_text() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(900_000_000, "Hello World");
+    feldera.set_cell(5, "world peace");
+    feldera.set_cell(6, "word");
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+    let search = |query: &'static str| {
+        let request = client.get(server.url("/api/search")).query(&[("q", query)]);
+        async move { request.send().await.unwrap() }
+    };
+    let ids = |query: &'static str| async move {
+        let cells: Vec<Value> = search(query).await.json().await.unwrap();
+        cells
+            .iter()
+            .map(|cell| cell["id"].as_i64().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    wait_until_async(|| async { search("world").await.status() == 200 }).await;
+    assert_eq!(ids("WORLD").await, [5, 900_000_000]);
+    assert_eq!(ids("o wor").await, [900_000_000]);
+    assert_eq!(ids("nowhere").await, Vec::<i64>::new());
+
+    // The index follows the changes of the cells.
+    feldera.push_cell(6, "new world");
+    feldera.push_cell(5, "");
+    wait_until_async(|| async { ids("world").await == [6, 900_000_000] }).await;
+
+    let response = search("wo").await;
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["field"], "q");
+}
+
+#[tokio::test]
+async fn searches_are_rate_limited_per_ip() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(
+        &feldera,
+        &[
+            ("RATE_LIMIT_REGIONS_PER_SEC", "0.01"),
+            ("RATE_LIMIT_REGIONS_BURST", "1"),
+        ],
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let search = || {
+        client
+            .get(server.url("/api/search"))
+            .query(&[("q", "hello")])
+            .send()
+    };
+
+    // The first search is answered, or refused while the index is loading.
+    assert_ne!(search().await.unwrap().status(), 429);
+    let response = search().await.unwrap();
+    assert_eq!(response.status(), 429);
+    let error: Value = response.json().await.unwrap();
+    assert!(error["retry_after_secs"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn abusive_updates_are_flagged_or_blocked() {
+    let feldera = MockFeldera::start().await;
+    let env = [
+        ("ADMIN_TOKEN", "secret"),
+        ("MODERATION_BLOCKED_DOMAINS", "spam.example"),
+        ("MODERATION_REPEAT_LIMIT", "2"),
+    ];
+    let server = Server::start_with_env(&feldera, &env).await;
+    let client = reqwest::Client::new();
+    let update = |id: i64, raw_value: &str| {
+        client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": id, "raw_value": raw_value, "background": 0}))
+            .send()
+    };
+
+    let response = update(1, "Buy now at www.spam.example/deals")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["field"], "raw_value");
+    assert!(feldera.ingress("spreadsheet_data").is_empty());
+
+    // Flagged updates are written.
+    for id in 2..5 {
+        assert_eq!(update(id, "hello friends").await.unwrap().status(), 200);
+    }
+    let token = "aZ3kQ9xW2pL7mN4vB8cR1tY6sD5fG0hJ";
+    assert_eq!(update(5, token).await.unwrap().status(), 200);
+    assert_eq!(
+        update(6, "an ordinary sentence").await.unwrap().status(),
+        200
+    );
+    assert_eq!(feldera.ingress("spreadsheet_data").len(), 5);
+
+    common::wait_until(|| feldera.ingress("moderation_events").len() == 3).await;
+    let events: Vec<Value> = client
+        .get(server.url("/api/admin/moderation"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let mut events: Vec<(i64, &str, bool)> = events
+        .iter()
+        .map(|event| {
+            let id = event["id"].as_i64().unwrap();
+            (
+                id,
+                event["reason"].as_str().unwrap(),
+                event["blocked"] == true,
+            )
+        })
+        .collect();
+    events.sort();
+    assert_eq!(
+        events,
+        [
+            (1, "blocked_url", true),
+            (4, "repeated", false),
+            (5, "high_entropy", false)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn rate_limited_ip_from_snapshot() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_api_limit("127.0.0.1");
+    let server = Server::start(&feldera).await;
+
+    let response = reqwest::Client::new()
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": 1, "raw_value": "x", "background": 0}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 429);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "rate_limited");
+    assert!(feldera.ingress("spreadsheet_data").is_empty());
+}
+
+#[tokio::test]
+async fn anonymized_ips_are_stored_and_rate_limited_as_hashes() {
+    let feldera = MockFeldera::start().await;
+    let env = [("ANONYMIZE_IPS", "true"), ("IP_HASH_SALT", "pepper")];
+    let server = Server::start_with_env(&feldera, &env).await;
+    let client = reqwest::Client::new();
+    let update = |id: i64| {
+        client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": id, "raw_value": "x", "background": 0}))
+            .send()
+    };
+
+    assert_eq!(update(1).await.unwrap().status(), 200);
+    assert_eq!(update(2).await.unwrap().status(), 200);
+    let ips: Vec<Value> = feldera
+        .ingress("spreadsheet_data")
+        .iter()
+        .map(|row| row["ip"].clone())
+        .collect();
+    let hash = ips[0].as_str().unwrap().to_string();
+    assert_ne!(hash, "127.0.0.1");
+    assert_eq!(hash.len(), 32);
+    assert_eq!(ips[1], ips[0]);
+
+    // `api_limit_reached` has the hashes, they are rate limited.
+    feldera.push_api_limit(&hash);
+    wait_until_async(|| async { update(3).await.unwrap().status() == 429 }).await;
+}
+
+#[tokio::test]
+async fn old_history_and_ips_are_removed() {
+    let feldera = MockFeldera::start().await;
+    let env = [
+        ("ADMIN_TOKEN", "secret"),
+        ("HISTORY_RETENTION_SECS", "1"),
+        ("IP_RETENTION_SECS", "1"),
+        ("RETENTION_INTERVAL_SECS", "1"),
+        ("MODERATION_BLOCK", ""),
+        ("MODERATION_BLOCKED_DOMAINS", "spam.example"),
+    ];
+    let server = Server::start_with_env(&feldera, &env).await;
+    let client = reqwest::Client::new();
+    for raw_value in ["old", "current", "spam.example"] {
+        let id = if raw_value == "spam.example" { 2 } else { 1 };
+        client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": id, "raw_value": raw_value, "background": 0}))
+            .send()
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    client
+        .post(server.url("/api/column-labels"))
+        .json(&json!({"column": 0, "label": "Price"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(feldera.ingress("moderation_events").len(), 1);
+
+    // The current values stay, without their IPs.
+    common::wait_until(|| {
+        let data = feldera.ingress("spreadsheet_data");
+        let labels = feldera.ingress("column_labels");
+        data.len() == 2
+            && data.iter().all(|row| row["ip"] == "")
+            && labels.iter().all(|row| row["ip"] == "")
+            && feldera.ingress("moderation_events").is_empty()
+    })
+    .await;
+    let mut values: Vec<Value> = feldera
+        .ingress("spreadsheet_data")
+        .iter()
+        .map(|row| row["raw_value"].clone())
+        .collect();
+    values.sort_by_key(|value| value.to_string());
+    assert_eq!(values, vec![json!("current"), json!("spam.example")]);
+    assert_eq!(feldera.ingress("column_labels")[0]["label"], "Price");
+
+    let policy: Value = client
+        .get(server.url("/api/admin/retention"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(policy["history_secs"], 1);
+    assert_eq!(policy["ip_secs"], 1);
+    assert_eq!(policy["interval_secs"], 1);
+    assert_eq!(policy["history_deleted"], 1);
+    assert!(policy["ips_removed"].as_u64().unwrap() >= 4);
+}
+
+#[tokio::test]
+async fn rate_limited_ip_from_change_stream() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+
+    feldera.push_api_limit("127.0.0.1");
+    wait_until_async(|| {
+        let request = client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": 1, "raw_value": "x", "background": 0}))
+            .send();
+        async move { request.await.is_ok_and(|r| r.status() == 429) }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reconnects_to_change_stream() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let mut ws = server.connect().await;
+    ws.send_region(0, 2600).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    feldera.disconnect_streams();
+    feldera.wait_for_egress("spreadsheet_view").await;
+    feldera.push_cell(5, "after reconnect");
+
+    assert_eq!(ws.next_cell(5).await["raw_value"], "after reconnect");
+}
+
+#[tokio::test]
+async fn cache_is_reloaded_after_missed_changes() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "before");
+    let server = Server::start(&feldera).await;
+
+    feldera.push_cell(2, "seen");
+    feldera.drop_cell_change(1, "missed");
+    feldera.push_cell(3, "seen after the gap");
+    wait_until_async(|| async {
+        let mut ws = server.connect().await;
+        ws.send_region(0, 26).await;
+        ws.next_cell(1).await["raw_value"] == "missed"
+    })
+    .await;
+
+    // Changes missed while reconnecting are reloaded as well.
+    feldera.disconnect_streams();
+    feldera.drop_cell_change(1, "missed while disconnected");
+    feldera.wait_for_egress("spreadsheet_view").await;
+    wait_until_async(|| async {
+        let mut ws = server.connect().await;
+        ws.send_region(0, 26).await;
+        ws.next_cell(1).await["raw_value"] == "missed while disconnected"
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn metrics_are_exported() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+
+    let metrics = reqwest::get(server.url("/metrics"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.contains("xls_feldera_requests_total"));
+    assert!(metrics.contains("xls_feldera_streams_open 4"));
+}
+
+#[tokio::test]
+async fn cache_hits_and_misses_are_counted() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "front");
+    feldera.set_cell(500_001, "middle");
+    let server = Server::start(&feldera).await;
+
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    ws.next_cell(1).await;
+    ws.send_region(500_000, 500_026).await;
+    ws.next_cell(500_001).await;
+
+    let metrics = reqwest::get(server.url("/metrics"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let value = |name: &str| -> f64 {
+        metrics
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("{name} is not exported"))
+            .parse()
+            .unwrap()
+    };
+    assert!(value("xls_cache_hits_total") >= 1.0);
+    assert!(value("xls_cache_misses_total") >= 1.0);
+    assert_eq!(value("xls_cache_fallbacks_total"), 0.0);
+    assert!(value("xls_region_query_seconds_count{source=\"cache\"}") >= 1.0);
+    assert!(value("xls_region_query_seconds_count{source=\"feldera\"}") >= 1.0);
+}
+
+#[tokio::test]
+async fn regions_are_served_while_the_cache_loads() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "front");
+    feldera.set_query_delay(Duration::from_secs(3));
+    let server = Server::start_loading(&feldera, &[]).await;
+    let metrics = || async {
+        reqwest::get(server.url("/metrics"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    };
+    assert!(metrics().await.contains("xls_cache_ranges_loading 2"));
+
+    // Queried from Feldera until the front of the sheet is cached.
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    assert_eq!(ws.next_cell(1).await["raw_value"], "front");
+    assert!(!metrics().await.contains("xls_cache_fallbacks_total 0"));
+
+    // Then from the cache, which doesn't see cells changed without a change of the view.
+    server.wait_for_cache().await;
+    feldera.set_cell(1, "not cached");
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    assert_eq!(ws.next_cell(1).await["raw_value"], "front");
+}
+
+#[tokio::test]
+async fn openapi_spec_is_served() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+
+    let spec: Value = reqwest::get(server.url("/api/openapi.json"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(spec["paths"]["/api/spreadsheet"]["post"].is_object());
+    assert!(spec["paths"]["/api/stats"]["get"].is_object());
+    assert!(spec["components"]["schemas"]["Cell"].is_object());
+
+    let docs = reqwest::get(server.url("/api/docs/")).await.unwrap();
+    assert!(docs.status().is_success());
+}
+
+#[tokio::test]
+async fn graphql_queries() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(3, "three");
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+    for raw_value in ["first", "second"] {
+        client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": 7, "raw_value": raw_value, "background": 0}))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let query = r#"{
+        cells(from: 0, to: 26) { id rawValue computedValue }
+        cellHistory(id: 7) { rawValue }
+        stats { filledTotal }
+    }"#;
+    let response: Value = client
+        .post(server.url("/api/graphql"))
+        .json(&json!({ "query": query }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(response["errors"], Value::Null, "{response}");
+    let data = &response["data"];
+    assert_eq!(data["cells"][0]["id"], 3);
+    assert_eq!(data["cells"][0]["computedValue"], "three");
+    assert_eq!(
+        data["cellHistory"],
+        json!([{"rawValue": "second"}, {"rawValue": "first"}])
+    );
+    assert_eq!(data["stats"]["filledTotal"], 0);
+}
+
+#[tokio::test]
+async fn graphql_rejects_invalid_range() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+
+    let response: Value = reqwest::Client::new()
+        .post(server.url("/api/graphql"))
+        .json(&json!({ "query": "{ cells(from: 0, to: 1000000) { id } }" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(response["errors"][0]["extensions"]["code"], "validation");
+}
+
+#[tokio::test]
+async fn graphql_limits_queries_of_feldera() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(
+        &feldera,
+        &[
+            ("RATE_LIMIT_REGIONS_PER_SEC", "0.01"),
+            ("RATE_LIMIT_REGIONS_BURST", "2"),
+        ],
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let query = |aliases: usize| {
+        let fields: Vec<String> = (0..aliases)
+            .map(|alias| format!("s{alias}: stats {{ filledTotal }}"))
+            .collect();
+        client
+            .post(server.url("/api/graphql"))
+            .json(&json!({ "query": format!("{{ {} }}", fields.join(" ")) }))
+            .send()
+    };
+
+    // Aliases add up to the complexity of the query.
+    let response: Value = query(10).await.unwrap().json().await.unwrap();
+    assert_eq!(response["data"], Value::Null, "{response}");
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("complex"));
+
+    // Every query of Feldera takes a token of the region rate limit.
+    let response: Value = query(3).await.unwrap().json().await.unwrap();
+    let codes: Vec<&Value> = response["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| &error["extensions"]["code"])
+        .collect();
+    assert_eq!(codes, [&json!("rate_limited")]);
+}
+
+mod proto {
+    tonic::include_proto!("xls.v1");
+}
+
+/// Connects to the gRPC service once the server started it.
+async fn grpc_client(
+    addr: &str,
+) -> proto::spreadsheet_client::SpreadsheetClient<tonic::transport::Channel> {
+    let deadline = tokio::time::Instant::now() + common::TIMEOUT;
+    loop {
+        match proto::spreadsheet_client::SpreadsheetClient::connect(format!("http://{addr}")).await
+        {
+            Ok(client) => return client,
+            Err(e) if tokio::time::Instant::now() > deadline => panic!("{e}"),
+            Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+        }
+    }
+}
+
+#[tokio::test]
+async fn grpc_service() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(3, "three");
+    let grpc_addr = common::free_addr().to_string();
+    let _server = Server::start_with_env(&feldera, &[("GRPC_ADDRESS", &grpc_addr)]).await;
+    let mut client = grpc_client(&grpc_addr).await;
+
+    let cells = client
+        .read_range(proto::Range { from: 0, to: 26 })
+        .await
+        .unwrap()
+        .into_inner()
+        .cells;
+    assert_eq!(cells.len(), 1);
+    assert_eq!(cells[0].computed_value, "three");
+
+    let mut changes = client
+        .stream_changes(proto::Range { from: 0, to: 26 })
+        .await
+        .unwrap()
+        .into_inner();
+    let update = |id, raw_value: &str| proto::CellUpdate {
+        id,
+        raw_value: raw_value.to_string(),
+        background: 0,
+        ttl_secs: None,
+    };
+    let response = client
+        .batch_update(proto::BatchUpdateRequest {
+            updates: vec![update(1, "a"), update(2, "b"), update(1, "c")],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.updated, 2);
+    let ingress = feldera.ingress("spreadsheet_data");
+    assert_eq!(ingress.len(), 2);
+    assert_eq!(ingress[0]["raw_value"], "c");
+    assert_eq!(ingress[1]["raw_value"], "b");
+
+    let change = tokio::time::timeout(common::TIMEOUT, changes.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(change.id, 1);
+
+    let status = client
+        .batch_update(proto::BatchUpdateRequest {
+            updates: vec![update(-1, "invalid")],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn grpc_updates_are_rate_limited() {
+    let feldera = MockFeldera::start().await;
+    let grpc_addr = common::free_addr().to_string();
+    let _server = Server::start_with_env(
+        &feldera,
+        &[
+            ("GRPC_ADDRESS", &grpc_addr),
+            ("RATE_LIMIT_UPDATES_PER_SEC", "0.01"),
+            ("RATE_LIMIT_UPDATES_BURST", "2"),
+        ],
+    )
+    .await;
+    let mut client = grpc_client(&grpc_addr).await;
+    let batch = |ids: &[i64]| proto::BatchUpdateRequest {
+        updates: ids
+            .iter()
+            .map(|id| proto::CellUpdate {
+                id: *id,
+                raw_value: String::from("x"),
+                background: 0,
+                ttl_secs: None,
+            })
+            .collect(),
+    };
+
+    let response = client.batch_update(batch(&[1, 2])).await.unwrap();
+    assert_eq!(response.into_inner().updated, 2);
+    let status = client.batch_update(batch(&[3])).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    let retry_after: u64 = status
+        .metadata()
+        .get("retry-after")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 90);
+    assert_eq!(feldera.ingress("spreadsheet_data").len(), 2);
+}
+
+#[tokio::test]
+async fn graphql_and_grpc_require_tokens() {
+    let feldera = MockFeldera::start().await;
+    let grpc_addr = common::free_addr().to_string();
+    let server = Server::start_with_env(
+        &feldera,
+        &[("GRPC_ADDRESS", &grpc_addr), ("WS_TOKENS", "alice:secret")],
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let query = json!({ "query": "{ cells(from: 0, to: 26) { id } }" });
+    let response = client
+        .post(server.url("/api/graphql"))
+        .json(&query)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    let response = client
+        .post(server.url("/api/graphql"))
+        .bearer_auth("secret")
+        .json(&query)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let mut grpc = grpc_client(&grpc_addr).await;
+    let status = grpc
+        .stream_changes(proto::Range { from: 0, to: 26 })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    let mut request = tonic::Request::new(proto::Range { from: 0, to: 26 });
+    request
+        .metadata_mut()
+        .insert("authorization", "Bearer secret".parse().unwrap());
+    assert!(grpc.read_range(request).await.is_ok());
+}
+
+#[tokio::test]
+async fn admin_connectors() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &[("ADMIN_TOKEN", "secret")]).await;
+    let client = reqwest::Client::new();
+    let connector = json!({
+        "view": "spreadsheet_view",
+        "config": {
+            "transport": {"name": "kafka_output", "config": {"topic": "cells"}},
+            "format": {"name": "json"},
+        },
+    });
+
+    let response = client
+        .put(server.url("/api/admin/connectors/cells"))
+        .json(&connector)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    let response = client
+        .put(server.url("/api/admin/connectors/cells"))
+        .bearer_auth("wrong")
+        .json(&connector)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    // The pipeline restarts, which needs to be confirmed and storage not to lose the sheet.
+    let put = |url: &str| {
+        client
+            .put(server.url(url))
+            .bearer_auth("secret")
+            .json(&connector)
+            .send()
+    };
+    let response = put("/api/admin/connectors/cells").await.unwrap();
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["field"], "restart");
+    let response = put("/api/admin/connectors/cells?restart=true")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    assert!(!feldera.program_code().contains("connector_cells"));
+
+    feldera.set_storage(true);
+    let response = put("/api/admin/connectors/cells?restart=true")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 202);
+    common::wait_until(|| feldera.program_code().contains("connector_cells")).await;
+    common::wait_until(|| feldera.deployment_status() == "Running").await;
+
+    let connectors: Value = client
+        .get(server.url("/api/admin/connectors"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(connectors[0]["name"], "cells");
+    assert_eq!(connectors[0]["config"], connector["config"]);
+
+    let response = client
+        .delete(server.url("/api/admin/connectors/unknown?restart=true"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let response = client
+        .delete(server.url("/api/admin/connectors/cells?restart=true"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 202);
+    common::wait_until(|| !feldera.program_code().contains("connector_cells")).await;
+}
+
+#[tokio::test]
+async fn admin_cached_ranges() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(500_001, "before");
+    let server = Server::start_with_env(&feldera, &[("ADMIN_TOKEN", "secret")]).await;
+    let client = reqwest::Client::new();
+    let admin = |request: reqwest::RequestBuilder| async move {
+        request.bearer_auth("secret").send().await.unwrap()
+    };
+    let server = &server;
+    let cell = move || async move {
+        let mut ws = server.connect().await;
+        ws.send_region(500_000, 500_026).await;
+        ws.next_cell(500_001).await["raw_value"].clone()
+    };
+
+    let ranges: Value = admin(client.get(server.url("/api/admin/cache")))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(ranges[0]["from"], 0);
+    assert_eq!(ranges[0]["to"], 100_000);
+    assert_eq!(ranges.as_array().unwrap().len(), 2);
+
+    let response = admin(client.put(server.url("/api/admin/cache/500000..500100"))).await;
+    assert_eq!(response.status(), 200);
+    let range: Value = response.json().await.unwrap();
+    assert_eq!(range, json!({"from": 500_000, "to": 500_100, "cells": 1}));
+
+    // Cells changed without a change of the view aren't seen until the range is refreshed.
+    feldera.set_cell(500_001, "after");
+    assert_eq!(cell().await, "before");
+    let response = admin(client.post(server.url("/api/admin/cache/500000..500100/refresh"))).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(cell().await, "after");
+    feldera.push_cell(500_001, "changed");
+    wait_until_async(|| async { cell().await == "changed" }).await;
+
+    let response = admin(client.put(server.url("/api/admin/cache/500050..600000"))).await;
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["field"], "range");
+    let response = admin(client.put(server.url("/api/admin/cache/600000..9000000"))).await;
+    assert_eq!(response.status(), 400);
+    let response = admin(client.put(server.url("/api/admin/cache/10..5"))).await;
+    assert_eq!(response.status(), 400);
+    let response = admin(client.post(server.url("/api/admin/cache/1..2/refresh"))).await;
+    assert_eq!(response.status(), 404);
+
+    let response = admin(client.delete(server.url("/api/admin/cache/500000..500100"))).await;
+    assert_eq!(response.status(), 204);
+    feldera.set_cell(500_001, "uncached");
+    assert_eq!(cell().await, "uncached");
+    let response = admin(client.delete(server.url("/api/admin/cache/500000..500100"))).await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn updates_are_retried_while_feldera_is_unavailable() {
+    let path = std::env::temp_dir().join(format!(
+        "xls-ingest-queue-{}.jsonl",
+        common::free_addr().port()
+    ));
+    let env = [
+        ("INGEST_QUEUE_PATH", path.to_str().unwrap()),
+        ("INGEST_RETRY_INTERVAL_SECS", "1"),
+    ];
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &env).await;
+    feldera.set_ingress_available(false);
+
+    let response = reqwest::Client::new()
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": 42, "raw_value": "queued", "background": 0}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 202);
+    let metrics = reqwest::get(server.url("/metrics"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.contains("xls_ingest_queue_depth 1"));
+
+    // The queue survives a restart of the server.
+    drop(server);
+    feldera.set_ingress_available(true);
+    let server = Server::start_with_env(&feldera, &env).await;
+    common::wait_until(|| {
+        feldera
+            .ingress("spreadsheet_data")
+            .iter()
+            .any(|row| row["id"] == 42 && row["raw_value"] == "queued")
+    })
+    .await;
+    let mut ws = server.connect().await;
+    ws.send_region(26, 52).await;
+    assert_eq!(ws.next_cell(42).await["raw_value"], "queued");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn updates_are_rate_limited_per_ip() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(
+        &feldera,
+        &[
+            ("RATE_LIMIT_UPDATES_PER_SEC", "0.01"),
+            ("RATE_LIMIT_UPDATES_BURST", "2"),
+        ],
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let post = |ip: &'static str| {
+        client
+            .post(server.url("/api/spreadsheet"))
+            .header("Fly-Client-IP", ip)
+            .json(&json!({"id": 1, "raw_value": "x", "background": 0}))
+            .send()
+    };
+
+    assert!(post("10.0.0.1").await.unwrap().status().is_success());
+    assert!(post("10.0.0.1").await.unwrap().status().is_success());
+    let response = post("10.0.0.1").await.unwrap();
+    assert_eq!(response.status(), 429);
+    let retry_after = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "rate_limited");
+    assert_eq!(body["retry_after_secs"].to_string(), retry_after);
+    assert!(body["retry_after_secs"].as_u64().unwrap() > 90);
+    // Other IPs have their own bucket.
+    assert!(post("10.0.0.2").await.unwrap().status().is_success());
+    assert_eq!(feldera.ingress("spreadsheet_data").len(), 3);
+
+    // Batches take a token per cell.
+    let batch = |cells: &[i64]| {
+        let updates: Vec<Value> = cells
+            .iter()
+            .map(|id| json!({"id": id, "raw_value": "x", "background": 0}))
+            .collect();
+        client
+            .post(server.url("/api/spreadsheet/batch"))
+            .header("Fly-Client-IP", "10.0.0.3")
+            .json(&updates)
+            .send()
+    };
+    assert!(batch(&[1, 2, 3]).await.unwrap().status().is_success());
+    assert_eq!(batch(&[4]).await.unwrap().status(), 429);
+    assert_eq!(feldera.ingress("spreadsheet_data").len(), 6);
+}
+
+#[tokio::test]
+async fn region_changes_are_rate_limited_per_ip() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "first");
+    feldera.set_cell(27, "second");
+    let server = Server::start_with_env(
+        &feldera,
+        &[
+            ("RATE_LIMIT_REGIONS_PER_SEC", "0.01"),
+            ("RATE_LIMIT_REGIONS_BURST", "1"),
+        ],
+    )
+    .await;
+
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    assert_eq!(ws.next().await["raw_value"], "first");
+    ws.send_region(26, 52).await;
+    let throttled = ws.next().await;
+    assert_eq!(throttled["code"], "rate_limited");
+    assert!(throttled["retry_after_secs"].as_u64().unwrap() > 0);
+    assert_eq!(ws.next_within(Duration::from_millis(500)).await, None);
+}
+
+#[tokio::test]
+async fn region_changes_are_rate_limited_per_connection() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "first");
+    feldera.set_cell(27, "second");
+    let server = Server::start_with_env(
+        &feldera,
+        &[
+            ("RATE_LIMIT_CONNECTION_REGIONS_PER_SEC", "0.01"),
+            ("RATE_LIMIT_CONNECTION_REGIONS_BURST", "1"),
+        ],
+    )
+    .await;
+
+    let mut spammer = server.connect().await;
+    spammer.send_region(0, 26).await;
+    assert_eq!(spammer.next().await["raw_value"], "first");
+    for _ in 0..5 {
+        spammer.send_region(26, 52).await;
+    }
+    // The client is told once when to ask again instead of getting an answer to every change.
+    let throttled = spammer.next().await;
+    assert_eq!(throttled["code"], "rate_limited");
+    assert!(throttled["close"].is_null());
+    assert!(throttled["retry_after_secs"].as_u64().unwrap() > 0);
+    assert_eq!(spammer.next_within(Duration::from_millis(500)).await, None);
+
+    // Other connections of the IP have budgets of their own.
+    let mut other = server.connect().await;
+    other.send_region(26, 52).await;
+    assert_eq!(other.next().await["raw_value"], "second");
+}
+
+#[tokio::test]
+async fn presenter_viewport_is_sent_to_followers() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &[("PRESENTER_KEY", "secret")]).await;
+
+    let mut follower = server.connect().await;
+    follower.send_json(json!({"follow": true})).await;
+    let mut viewer = server.connect().await;
+    viewer.send_region(0, 26).await;
+    let mut presenter = server.connect().await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let viewport = json!({"top_row": 500, "focused": 13_002});
+    let present = |key| json!({"present": {"viewport": viewport, "key": key}});
+    presenter.send_json(present("wrong")).await;
+    assert_eq!(follower.next_within(Duration::from_millis(500)).await, None);
+    presenter.send_json(present("secret")).await;
+    assert_eq!(
+        follower.next().await,
+        json!({"present": {"viewport": viewport}})
+    );
+    assert_eq!(viewer.next_within(Duration::from_millis(500)).await, None);
+
+    follower.send_json(json!({"follow": false})).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    presenter.send_json(present("secret")).await;
+    assert_eq!(follower.next_within(Duration::from_millis(500)).await, None);
+}
+
+#[tokio::test]
+async fn ip_allow_and_deny_lists() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(
+        &feldera,
+        &[
+            ("IP_ALLOW_LIST", "127.0.0.1, 10.0.0.0/8"),
+            ("IP_DENY_LIST", "10.66.0.0/16"),
+        ],
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let post = |ip: &'static str| {
+        client
+            .post(server.url("/api/spreadsheet"))
+            .header("Fly-Client-IP", ip)
+            .json(&json!({"id": 1, "raw_value": "x", "background": 0}))
+            .send()
+    };
+
+    assert!(post("10.1.2.3").await.unwrap().status().is_success());
+    let response = post("10.66.1.1").await.unwrap();
+    assert_eq!(response.status(), 403);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "forbidden");
+    assert_eq!(post("192.0.2.1").await.unwrap().status(), 403);
+    assert_eq!(feldera.ingress("spreadsheet_data").len(), 1);
+
+    // Connecting without the header uses the peer address, which is allowed.
+    server.connect().await;
+    let mut request = format!("ws://{}/api/spreadsheet", server.addr)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("Fly-Client-IP", "192.0.2.1".parse().unwrap());
+    assert!(tokio_tungstenite::connect_async(request).await.is_err());
+}
+
+#[tokio::test]
+async fn ws_requires_a_token_if_configured() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "private");
+    let server = Server::start_with_env(&feldera, &[("WS_TOKENS", "alice:secret, bot:beep")]).await;
+    let url = format!("ws://{}/api/spreadsheet", server.addr);
+
+    for url in [url.clone(), format!("{url}?token=guess")] {
+        let mut ws = server.try_connect(url).await.unwrap();
+        assert_eq!(ws.next().await["close"], "auth_required");
+        assert_eq!(ws.close_frame().await.0, 4401);
+    }
+
+    let mut ws = server
+        .try_connect(format!("{url}?token=secret"))
+        .await
+        .unwrap();
+    ws.send_region(0, 26).await;
+    assert_eq!(ws.next().await["raw_value"], "private");
+
+    let mut request = url.into_client_request().unwrap();
+    request
+        .headers_mut()
+        .insert("Authorization", "Bearer beep".parse().unwrap());
+    assert!(server.try_connect(request).await.is_some());
+}
+
+#[tokio::test]
+async fn ws_only_accepts_pages_on_allowed_origins() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let feldera = MockFeldera::start().await;
+    let server =
+        Server::start_with_env(&feldera, &[("WS_ALLOWED_ORIGINS", "https://xls.example/")]).await;
+    let request = |origin: &str| {
+        let mut request = format!("ws://{}/api/spreadsheet", server.addr)
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert("Origin", origin.parse().unwrap());
+        request
+    };
+
+    assert!(server
+        .try_connect(request("https://evil.example"))
+        .await
+        .is_none());
+    assert!(server
+        .try_connect(request("https://xls.example"))
+        .await
+        .is_some());
+    // Clients that aren't browsers send no origin.
+    server.connect().await;
+}
+
+#[tokio::test]
+async fn embed_tokens_read_their_regions_from_any_origin() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(30, "score");
+    feldera.set_cell(100, "private");
+    let env = [
+        ("ADMIN_TOKEN", "secret"),
+        ("WS_ALLOWED_ORIGINS", "https://xls.example/"),
+    ];
+    let server = Server::start_with_env(&feldera, &env).await;
+    let client = reqwest::Client::new();
+    let issue = |name: &str| {
+        client
+            .post(server.url("/api/admin/embed-tokens"))
+            .bearer_auth("secret")
+            .json(&json!({
+                "name": name,
+                "regions": [{"from": 26, "to": 78}],
+                "requests_per_sec": 0.01,
+                "burst": 4,
+            }))
+            .send()
+    };
+    let response = issue("scoreboard").await.unwrap();
+    assert_eq!(response.status(), 201);
+    let issued: Value = response.json().await.unwrap();
+    let token = issued["token"].as_str().unwrap().to_string();
+    assert_eq!(token.len(), 32);
+    assert_eq!(issue("scoreboard").await.unwrap().status(), 400);
+    let tokens: Value = client
+        .get(server.url("/api/admin/embed-tokens"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(tokens[0]["name"], "scoreboard");
+
+    let cells = |token: &str, from: i64, to: i64| {
+        client
+            .get(server.url(&format!(
+                "/api/embed/cells?token={token}&from={from}&to={to}"
+            )))
+            .header("Origin", "https://evil.example")
+            .send()
+    };
+    // Only the cells the token can read are returned, to any origin.
+    let response = cells(&token, 0, 2600).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["access-control-allow-origin"], "*");
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["raw_value"], "score");
+    assert_eq!(cells(&token, 78, 2600).await.unwrap().status(), 400);
+    assert_eq!(cells("guess", 0, 2600).await.unwrap().status(), 401);
+
+    let mut request = format!("ws://{}/api/spreadsheet?token={token}", server.addr)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("Origin", "https://evil.example".parse().unwrap());
+    let mut ws = server.try_connect(request).await.unwrap();
+    ws.send_region(0, 2600).await;
+    assert_eq!(ws.next().await["raw_value"], "score");
+    feldera.push_cell(100, "still private");
+    feldera.push_cell(30, "new score");
+    assert_eq!(ws.next().await["raw_value"], "new score");
+    ws.send_region(78, 2600).await;
+    assert_eq!(ws.next().await["code"], "validation");
+
+    // The websocket spent the rest of the burst of the token.
+    let response = cells(&token, 0, 2600).await.unwrap();
+    assert_eq!(response.status(), 429);
+
+    let revoke = || {
+        client
+            .delete(server.url("/api/admin/embed-tokens/scoreboard"))
+            .bearer_auth("secret")
+            .send()
+    };
+    assert_eq!(revoke().await.unwrap().status(), 204);
+    // The websocket reading with the token is closed.
+    assert_eq!(ws.next().await["close"], "auth_required");
+    assert_eq!(ws.close_frame().await.0, 4401);
+    assert_eq!(revoke().await.unwrap().status(), 404);
+    assert_eq!(cells(&token, 0, 2600).await.unwrap().status(), 401);
+}
+
+#[tokio::test]
+async fn region_queries_are_shed_when_feldera_is_busy() {
+    let feldera = MockFeldera::start().await;
+    let env = [
+        ("MAX_ADHOC_QUERIES", "1"),
+        ("MAX_QUEUED_ADHOC_QUERIES", "1"),
+    ];
+    let server = Server::start_with_env(&feldera, &env).await;
+    feldera.set_cell(500_000_000, "not cached");
+    feldera.set_query_delay(Duration::from_millis(500));
+
+    let mut clients = vec![];
+    for _ in 0..3 {
+        let mut ws = server.connect().await;
+        ws.send_region(500_000_000, 500_000_026).await;
+        // The queries arrive in order.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        clients.push(ws);
+    }
+    // One query runs, one waits and the third is shed.
+    let busy = clients[2].next().await;
+    assert_eq!(busy["code"], "busy");
+    assert_eq!(busy["retry_after_secs"], 1);
+    assert_eq!(clients[0].next().await["raw_value"], "not cached");
+    assert_eq!(clients[1].next().await["raw_value"], "not cached");
+
+    // The connection stays open, so the client can ask again.
+    clients[2].send_region(500_000_000, 500_000_026).await;
+    assert_eq!(clients[2].next().await["raw_value"], "not cached");
+    let metrics = reqwest::get(server.url("/metrics"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.contains("xls_adhoc_queries_shed_total 1"));
+}
+
+#[tokio::test]
+async fn ws_connections_are_limited_per_ip() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "cell");
+    let server = Server::start_with_env(&feldera, &[("WS_MAX_CONNECTIONS_PER_IP", "2")]).await;
+
+    let first = server.connect().await;
+    let mut second = server.connect().await;
+    let mut third = server.connect().await;
+    let error = third.next().await;
+    assert_eq!(error["code"], "rate_limited");
+    assert_eq!(error["error"], "Too many connections from this IP");
+    assert_eq!(third.close_frame().await.0, 4429);
+    second.send_region(0, 26).await;
+    assert_eq!(second.next().await["raw_value"], "cell");
+
+    drop(first);
+    wait_until_async(|| async {
+        let mut ws = server.connect().await;
+        ws.send_region(0, 26).await;
+        ws.next_within(Duration::from_millis(200)).await.is_some()
+    })
+    .await;
+    let metrics = reqwest::get(server.url("/metrics")).await.unwrap();
+    let metrics = metrics.text().await.unwrap();
+    let rejected = metrics
+        .lines()
+        .find_map(|line| line.strip_prefix("xls_ws_connections_rejected_total "))
+        .unwrap();
+    assert!(rejected.parse::<u64>().unwrap() >= 1);
+}
+
+#[tokio::test]
+async fn ephemeral_cells_are_cleared() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &[("CELL_CLEANUP_INTERVAL_SECS", "1")]).await;
+    let client = reqwest::Client::new();
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let response = client
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": 3, "raw_value": "x", "background": 0, "ttl_secs": 0}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    for (raw_value, ttl_secs) in [("kept", None), ("old", None), ("ephemeral", Some(1))] {
+        let id = if raw_value == "kept" { 4 } else { 3 };
+        let response = client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": id, "raw_value": raw_value, "background": 0, "ttl_secs": ttl_secs}))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+    }
+    let ingress = feldera.ingress("spreadsheet_data");
+    assert_eq!(ingress[0]["expires_at"], Value::Null);
+    assert!(ingress[2]["expires_at"].is_string());
+    assert_eq!(ws.next_cell(3).await["raw_value"], "old");
+    assert_eq!(ws.next_cell(3).await["raw_value"], "ephemeral");
+
+    // Both rows of the expired cell are deleted and clients clear it.
+    let cleared = ws.next_cell(3).await;
+    assert_eq!(cleared["raw_value"], "");
+    assert_eq!(cleared["computed_value"], "");
+    let ingress = feldera.ingress("spreadsheet_data");
+    assert_eq!(ingress.len(), 1);
+    assert_eq!(ingress[0]["raw_value"], "kept");
+}
+
+#[tokio::test]
+async fn stale_scratch_cells_are_cleared_on_request() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(
+        &feldera,
+        &[
+            ("ADMIN_TOKEN", "secret"),
+            ("SCRATCH_REGIONS", "0..26, 52..78"),
+            ("SCRATCH_MAX_AGE_SECS", "1"),
+        ],
+    )
+    .await;
+    let client = reqwest::Client::new();
+    for id in [1, 2, 30, 60] {
+        client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": id, "raw_value": "junk", "background": 0}))
+            .send()
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    // Touched recently, so it is kept.
+    client
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": 2, "raw_value": "fresh", "background": 0}))
+        .send()
+        .await
+        .unwrap();
+
+    let response = client
+        .post(server.url("/api/admin/cleanup"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    let response: Value = client
+        .post(server.url("/api/admin/cleanup"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(response["deleted"], 2);
+
+    let ids: Vec<Value> = feldera
+        .ingress("spreadsheet_data")
+        .iter()
+        .map(|row| row["id"].clone())
+        .collect();
+    assert_eq!(ids, vec![json!(2), json!(30), json!(2)]);
+}
+
+#[tokio::test]
+async fn regions_are_seeded_with_sample_content() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &[("ADMIN_TOKEN", "secret")]).await;
+    let client = reqwest::Client::new();
+    let seed = |range: &str| {
+        client
+            .post(server.url(&format!("/api/admin/seed/{range}?seed=42")))
+            .bearer_auth("secret")
+            .send()
+    };
+
+    let response = seed("0..78").await.unwrap();
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body, json!({"seeded": 78, "seed": 42}));
+    let rows = feldera.ingress("spreadsheet_data");
+    assert_eq!(rows.len(), 78);
+    assert_eq!(rows[0]["raw_value"], "Item");
+    assert_eq!(rows[3]["raw_value"], "Total");
+    // Totals multiply their neighbors.
+    assert_eq!(rows[26 + 3]["raw_value"], "=B1*C1");
+    assert_eq!(rows[52 + 7]["raw_value"], "=F2*G2");
+    assert!(rows[26 + 2]["raw_value"]
+        .as_str()
+        .unwrap()
+        .parse::<u32>()
+        .is_ok());
+    // Rows are striped.
+    assert_ne!(rows[0]["background"], rows[26]["background"]);
+    assert_ne!(rows[26]["background"], rows[52]["background"]);
+
+    // The same seed generates the same content.
+    assert_eq!(seed("0..78").await.unwrap().status(), 200);
+    let rows = feldera.ingress("spreadsheet_data");
+    let values = |rows: &[Value]| -> Vec<Value> {
+        rows.iter().map(|row| row["raw_value"].clone()).collect()
+    };
+    assert_eq!(values(&rows[..78]), values(&rows[78..]));
+
+    assert_eq!(seed("0..2601").await.unwrap().status(), 400);
+    let response = client
+        .post(server.url("/api/admin/seed/0..26"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+}
+
+#[tokio::test]
+async fn moderators_see_editors_clear_cells_and_ban_editors() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &[("ADMIN_TOKEN", "secret")]).await;
+    let client = reqwest::Client::new();
+    let update = |id: i64| {
+        client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": id, "raw_value": "spam", "background": 0}))
+            .send()
+    };
+    assert_eq!(update(1).await.unwrap().status(), 200);
+    assert_eq!(update(27).await.unwrap().status(), 200);
+
+    let response = client
+        .get(server.url("/api/admin/attribution/0..26"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let attribution: Vec<Value> = response.json().await.unwrap();
+    assert_eq!(attribution.len(), 1);
+    assert_eq!(attribution[0]["id"], 1);
+    assert_eq!(attribution[0]["ip"], "127.0.0.1");
+    let response = client
+        .get(server.url("/api/admin/attribution/0..10401"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let response = client
+        .post(server.url("/api/admin/clear"))
+        .bearer_auth("secret")
+        .json(&json!({"regions": [{"from": 0, "to": 52}]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body, json!({"cleared": 2}));
+    let rows = feldera.ingress("spreadsheet_data");
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[2]["raw_value"], "");
+    assert_eq!(rows[3]["raw_value"], "");
+    assert_eq!(rows[3]["ip"], "moderator");
+
+    let response = client
+        .post(server.url("/api/admin/bans"))
+        .bearer_auth("secret")
+        .json(&json!({"ip": "127.0.0.1"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(feldera.ingress("banned_editors")[0]["ip"], "127.0.0.1");
+    let response = update(2).await.unwrap();
+    assert_eq!(response.status(), 429);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "rate_limited");
+
+    let response = client
+        .post(server.url("/api/admin/bans"))
+        .json(&json!({"ip": "10.0.0.1"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+}
+
+#[tokio::test]
+async fn integrations_push_values_into_their_cells() {
+    let feldera = MockFeldera::start().await;
+    // The quota of the integration replaces the lower update limit of IPs.
+    let env = [
+        ("ADMIN_TOKEN", "secret"),
+        ("RATE_LIMIT_UPDATES_PER_SEC", "0.01"),
+        ("RATE_LIMIT_UPDATES_BURST", "1"),
+    ];
+    let server = Server::start_with_env(&feldera, &env).await;
+    let client = reqwest::Client::new();
+    let response = client
+        .post(server.url("/api/admin/integrations"))
+        .bearer_auth("secret")
+        .json(&json!({"name": "stars", "regions": [{"from": 0, "to": 26}], "updates_per_min": 3}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+    let integration: Value = response.json().await.unwrap();
+    let token = integration["token"].as_str().unwrap().to_string();
+
+    let push = |token: &str, ids: &[i64]| {
+        let updates: Vec<Value> = ids
+            .iter()
+            .map(|id| json!({"id": id, "raw_value": "1234", "background": 0}))
+            .collect();
+        client
+            .post(server.url("/api/integrations/cells"))
+            .bearer_auth(token)
+            .json(&updates)
+            .send()
+    };
+    let response = push(&token, &[1]).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.json::<Value>().await.unwrap()["updated"], 1);
+    let rows = feldera.ingress("spreadsheet_data");
+    assert_eq!(rows[0]["raw_value"], "1234");
+    assert_eq!(rows[0]["ip"], "integration:stars");
+
+    assert_eq!(push(&token, &[30]).await.unwrap().status(), 400);
+    assert_eq!(push("guess", &[1]).await.unwrap().status(), 401);
+    // The quota of 3 cells a minute has 2 left.
+    assert_eq!(push(&token, &[2, 3, 4]).await.unwrap().status(), 429);
+    let response = client
+        .post(server.url(&format!("/api/integrations/cells?token={token}")))
+        .json(&json!([{"id": 2, "raw_value": "5678", "background": 0}]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let response = client
+        .delete(server.url("/api/admin/integrations/stars"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 204);
+    assert_eq!(push(&token, &[1]).await.unwrap().status(), 401);
+}
+
+#[tokio::test]
+async fn diff_returns_cells_changed_since_the_last_request() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let client = reqwest::Client::new();
+    let post = |id: i64, raw_value: &str| {
+        client
+            .post(server.url("/api/spreadsheet"))
+            .json(&json!({"id": id, "raw_value": raw_value, "background": 0}))
+            .send()
+    };
+    let diff = |query: String| client.get(server.url(&query)).send();
+    assert!(post(1, "first").await.unwrap().status().is_success());
+    assert!(post(2, "second").await.unwrap().status().is_success());
+
+    let body = diff(String::from("/api/diff?range=0..26"))
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    let ids: Vec<&Value> = body["cells"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| &c["id"])
+        .collect();
+    assert_eq!(ids, [1, 2]);
+    let until = body["until"].as_str().unwrap().to_string();
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert!(post(3, "third").await.unwrap().status().is_success());
+    let body = diff(format!("/api/diff?range=0..26&since={until}"))
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    let cells = body["cells"].as_array().unwrap();
+    assert_eq!(cells.len(), 1);
+    assert_eq!(cells[0]["raw_value"], "third");
+    assert_ne!(body["until"], until.as_str());
+
+    // Nothing changed since, the timestamp is kept.
+    let since = body["until"].as_str().unwrap().to_string();
+    let body = diff(format!("/api/diff?range=0..26&since={since}"))
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert!(body["cells"].as_array().unwrap().is_empty());
+    assert_eq!(body["until"], since.as_str());
+
+    // A cell that expires after `since` is returned cleared, once.
+    let response = client
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": 4, "raw_value": "ephemeral", "background": 0, "ttl_secs": 1}))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let body = diff(format!("/api/diff?range=0..26&since={since}"))
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(body["cells"][0]["raw_value"], "ephemeral");
+    let since = body["until"].as_str().unwrap().to_string();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let body = diff(format!("/api/diff?range=0..26&since={since}"))
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(
+        body["cells"],
+        json!([{"id": 4, "background": 0, "raw_value": "", "computed_value": "", "value_type": "string"}])
+    );
+    let since = body["until"].as_str().unwrap().to_string();
+    let body = diff(format!("/api/diff?range=0..26&since={since}"))
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert!(body["cells"].as_array().unwrap().is_empty());
+
+    let response = client
+        .get(server.url("/api/diff?range=0..26&since=yesterday"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let response = client
+        .get(server.url("/api/diff?range=0..100000000"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn ws_rejects_invalid_regions_and_truncates_large_ones() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "inside");
+    feldera.set_cell(60, "outside");
+    let server = Server::start_with_env(&feldera, &[("WS_MAX_REGION_CELLS", "52")]).await;
+
+    let mut ws = server.connect().await;
+    ws.send_region(26, 0).await;
+    let error = ws.next().await;
+    assert_eq!(error["code"], "validation");
+    ws.send_region(-26, 26).await;
+    assert_eq!(ws.next().await["code"], "validation");
+
+    ws.send_region(0, 1_040_000_000).await;
+    assert_eq!(ws.next().await["raw_value"], "inside");
+    assert_eq!(ws.next_within(Duration::from_millis(500)).await, None);
+    // Changes outside of the truncated region aren't sent either.
+    feldera.push_cell(60, "changed");
+    assert_eq!(ws.next_within(Duration::from_millis(500)).await, None);
+}
+
+#[tokio::test]
+async fn only_the_first_sheet_is_served() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "first");
+    let server = Server::start(&feldera).await;
+
+    let mut ws = server.connect().await;
+    ws.send_json(json!({"from": 0, "to": 26, "sheet": 1})).await;
+    let error = ws.next().await;
+    assert_eq!(error["code"], "validation");
+    ws.send_json(json!({"from": 0, "to": 26, "sheet": 0})).await;
+    assert_eq!(ws.next().await["raw_value"], "first");
+
+    let response = reqwest::Client::new()
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": 1, "raw_value": "x", "background": 0, "sheet": 1}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["field"], "sheet");
+    assert!(feldera.ingress("spreadsheet_data").is_empty());
+}
+
+#[tokio::test]
+async fn ws_is_closed_with_a_reason() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(1, "cell");
+    let server = Server::start_with_env(&feldera, &[("ADMIN_TOKEN", "secret")]).await;
+
+    let mut ws = server.connect().await;
+    ws.send_json(json!({"from": "A1"})).await;
+    let error = ws.next().await;
+    assert_eq!(error["close"], "invalid_region");
+    assert_eq!(ws.close_frame().await.0, 4400);
+
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    assert_eq!(ws.next().await["raw_value"], "cell");
+    let response = reqwest::Client::new()
+        .post(server.url("/api/admin/shutdown"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 202);
+    let error = ws.next().await;
+    assert_eq!(error["close"], "server_shutdown");
+    assert_eq!(ws.close_frame().await.0, 4503);
+    let url = server.url("/");
+    wait_until_async(|| {
+        let request = reqwest::get(&url);
+        async move { request.await.is_err() }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn drained_server_closes_websockets_gradually() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(0, "cell");
+    let env = [("ADMIN_TOKEN", "secret"), ("WS_DRAIN_SECS", "1")];
+    let server = Server::start_with_env(&feldera, &env).await;
+
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    assert_eq!(ws.next().await["raw_value"], "cell");
+    let response = reqwest::Client::new()
+        .post(server.url("/api/admin/drain"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 202);
+    assert_eq!(ws.next().await, json!({"reconnect_within_secs": 1}));
+
+    // New connections go to other instances.
+    let url = format!("ws://{}/api/spreadsheet", server.addr);
+    assert!(server.try_connect(url).await.is_none());
+
+    let error = ws.next().await;
+    assert_eq!(error["close"], "server_shutdown");
+    assert_eq!(ws.close_frame().await.0, 4503);
+    // The server keeps running until it is shut down.
+    assert_eq!(reqwest::get(server.url("/")).await.unwrap().status(), 200);
+}
+
+#[tokio::test]
+async fn changes_of_pinned_regions_are_sent_wherever_the_client_looks() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_cell(13_000, "scoreboard");
+    let server = Server::start_with_env(&feldera, &[("WS_MAX_PINNED_REGIONS", "2")]).await;
+
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    let region = |from: i64| json!({"from": from, "to": from + 26});
+    ws.send_json(json!({"pinned": [region(13_000)]})).await;
+    assert_eq!(ws.next_cell(13_000).await["raw_value"], "scoreboard");
+
+    feldera.push_cell(13_001, "pinned");
+    feldera.push_cell(2600, "elsewhere");
+    assert_eq!(ws.next().await["raw_value"], "pinned");
+    assert_eq!(ws.next_within(Duration::from_millis(500)).await, None);
+
+    let too_many = [region(0), region(26), region(52)];
+    ws.send_json(json!({ "pinned": too_many })).await;
+    assert_eq!(ws.next().await["code"], "validation");
+    // Unpinning stops the changes.
+    ws.send_json(json!({"pinned": []})).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    feldera.push_cell(13_002, "unpinned");
+    assert_eq!(ws.next_within(Duration::from_millis(500)).await, None);
+}
+
+#[tokio::test]
+async fn large_snapshots_are_delivered_in_chunks() {
+    let feldera = MockFeldera::start().await;
+    for id in 0..1000 {
+        feldera.set_cell(id, &format!("cell {id}"));
+    }
+    let server = Server::start(&feldera).await;
+
+    let mut ws = server.connect().await;
+    ws.send_region(0, 1000).await;
+    for id in 0..1000 {
+        assert_eq!(ws.next().await["id"], id);
+    }
+    // Changes arriving while a snapshot is delivered are not lost.
+    ws.send_region(0, 2600).await;
+    feldera.push_cell(2000, "changed");
+    let mut ids = Vec::new();
+    while let Some(cell) = ws.next_within(Duration::from_millis(500)).await {
+        ids.push(cell["id"].as_i64().unwrap());
+    }
+    assert!(ids.contains(&2000));
+    assert_eq!(ids.iter().filter(|id| **id < 1000).count(), 1000);
+}
+
+#[tokio::test]
+async fn snapshots_are_sent_as_arrays_of_cells() {
+    let feldera = MockFeldera::start().await;
+    for id in 0..300 {
+        feldera.set_cell(id, &format!("cell {id}"));
+    }
+    let server = Server::start(&feldera).await;
+
+    let mut ws = server.connect().await;
+    ws.send_region(0, 300).await;
+    let mut sizes = Vec::new();
+    while sizes.iter().sum::<usize>() < 300 {
+        let cells = ws.next_message_within(common::TIMEOUT).await.unwrap();
+        let cells = cells
+            .as_array()
+            .expect("Snapshot cells not sent as an array");
+        assert_eq!(cells[0]["id"], sizes.iter().sum::<usize>());
+        sizes.push(cells.len());
+    }
+    assert_eq!(sizes, [256, 44]);
+
+    // Live changes are still sent one cell per message.
+    feldera.push_cell(5, "changed");
+    assert_eq!(
+        ws.next_message_within(common::TIMEOUT).await.unwrap()["raw_value"],
+        "changed"
+    );
+}
+
+#[tokio::test]
+async fn heartbeats_tell_the_region_and_the_cells_sent() {
+    let feldera = MockFeldera::start().await;
+    for id in 26..36 {
+        feldera.set_cell(id, &format!("cell {id}"));
+    }
+    let server = Server::start_with_env(&feldera, &[("WS_HEARTBEAT_SECS", "1")]).await;
+
+    let mut ws = server.connect().await;
+    ws.send_region(26, 52).await;
+    for _ in 0..10 {
+        ws.next().await;
+    }
+    feldera.push_cell(30, "changed");
+    assert_eq!(ws.next().await["raw_value"], "changed");
+
+    let heartbeat = loop {
+        let msg = ws.next_message_within(common::TIMEOUT).await.unwrap();
+        if msg.get("interval_secs").is_some() {
+            break msg;
+        }
+    };
+    assert_eq!(heartbeat["region"], json!({"from": 26, "to": 52}));
+    assert_eq!(heartbeat["seq"], 11);
+    assert_eq!(heartbeat["interval_secs"], 1);
+    assert!(heartbeat["server_time_ms"].as_i64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn rapid_changes_of_a_cell_are_coalesced() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &[("CHANGE_COALESCE_WINDOW_MS", "300")]).await;
+    let mut ws = server.connect().await;
+    ws.send_region(0, 26).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    for color in ["red", "green", "blue"] {
+        feldera.push_cell(5, color);
+    }
+    feldera.push_cell(6, "other");
+    let mut cells = [ws.next().await, ws.next().await];
+    cells.sort_by_key(|cell| cell["id"].as_i64());
+    assert_eq!(cells[0]["raw_value"], "blue");
+    assert_eq!(cells[1]["raw_value"], "other");
+    assert_eq!(ws.next_within(Duration::from_millis(500)).await, None);
+}
+
+#[tokio::test]
+async fn stats_stream_sends_changed_fields() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let mut response = reqwest::get(server.url("/api/stats")).await.unwrap();
+    let mut next = async || {
+        let chunk = tokio::time::timeout(common::TIMEOUT, response.chunk())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        serde_json::from_slice::<Value>(&chunk).unwrap()
+    };
+
+    let initial = next().await;
+    assert_eq!(initial["filled_total"], 0);
+    assert_eq!(initial["currently_active_users"], 0);
+
+    let stats = |filled_today, active_users| {
+        json!({
+            "filled_total": 0,
+            "filled_this_hour": 0,
+            "filled_today": filled_today,
+            "filled_this_week": 0,
+            "currently_active_users": active_users,
+        })
+    };
+    // Identical rows are skipped.
+    feldera.push_stats(stats(0, 0));
+    feldera.push_stats(stats(1, 0));
+    assert_eq!(next().await, json!({"filled_today": 1}));
+    feldera.push_stats(stats(1, 2));
+    assert_eq!(next().await, json!({"currently_active_users": 2}));
+}
+
+#[tokio::test]
+async fn stats_stream_sends_only_selected_fields() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start(&feldera).await;
+    let response = reqwest::get(server.url("/api/stats?fields=filled_total,unknown"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["field"], "fields");
+
+    let url = server.url("/api/stats?fields=filled_total,currently_active_users");
+    let mut response = reqwest::get(url).await.unwrap();
+    let mut next = async || {
+        let chunk = tokio::time::timeout(common::TIMEOUT, response.chunk())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        serde_json::from_slice::<Value>(&chunk).unwrap()
+    };
+    assert_eq!(
+        next().await,
+        json!({"filled_total": 0, "currently_active_users": 0})
+    );
+
+    let stats = |filled_today, filled_total| {
+        json!({
+            "filled_total": filled_total,
+            "filled_this_hour": 0,
+            "filled_today": filled_today,
+            "filled_this_week": 0,
+            "currently_active_users": 0,
+        })
+    };
+    // Changes of other fields aren't sent.
+    feldera.push_stats(stats(1, 0));
+    feldera.push_stats(stats(2, 2));
+    assert_eq!(next().await, json!({"filled_total": 2}));
+}
+
+#[tokio::test]
+async fn edit_latency_is_recorded() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &[("ADMIN_TOKEN", "secret")]).await;
+    let client = reqwest::Client::new();
+    client
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": 1, "raw_value": "x", "background": 0}))
+        .send()
+        .await
+        .unwrap();
+    // Changes without a timestamp are not recorded.
+    feldera.push_cell(2, "without timestamp");
+
+    let latency = || async {
+        client
+            .get(server.url("/api/admin/latency"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap()
+    };
+    wait_until_async(|| async { latency().await["count"] == 1 }).await;
+    let summary = latency().await;
+    assert!(summary["p50"].as_f64().unwrap() <= 1.0, "{summary}");
+
+    let metrics = reqwest::get(server.url("/metrics"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.contains("xls_edit_latency_seconds_count 1"));
+    assert!(metrics.contains("xls_edit_latency_seconds_bucket{le=\"+Inf\"} 1"));
+}
+
+#[tokio::test]
+async fn admin_overview() {
+    let feldera = MockFeldera::start().await;
+    feldera.set_api_limit("10.0.0.1");
+    let server = Server::start_with_env(&feldera, &[("ADMIN_TOKEN", "secret")]).await;
+    let client = reqwest::Client::new();
+    let mut first = server.connect().await;
+    first.send_region(0, 2600).await;
+    let mut second = server.connect().await;
+    second.send_region(0, 2600).await;
+    let _idle = server.connect().await;
+
+    let overview = || async {
+        client
+            .get(server.url("/api/admin/overview"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap()
+    };
+    wait_until_async(|| async {
+        let overview = overview().await;
+        overview["connections"] == 3 && overview["regions"][0]["subscribers"] == 2
+    })
+    .await;
+    let overview = overview().await;
+    assert_eq!(
+        overview["regions"][0]["region"],
+        json!({"from": 0, "to": 2600})
+    );
+    assert_eq!(overview["api_limited_ips"], json!(["10.0.0.1"]));
+    assert_eq!(overview["pipeline"]["deployment_status"], "Running");
+
+    let dashboard = reqwest::get(server.url("/admin")).await.unwrap();
+    assert!(dashboard.status().is_success());
+    assert!(dashboard
+        .text()
+        .await
+        .unwrap()
+        .contains("/api/admin/overview"));
+}
+
+#[tokio::test]
+async fn volatile_cells_are_refreshed() {
+    let feldera = MockFeldera::start().await;
+    let server = Server::start_with_env(&feldera, &[("VOLATILE_REFRESH_SECS", "1")]).await;
+
+    let response = reqwest::Client::new()
+        .post(server.url("/api/spreadsheet"))
+        .json(&json!({"id": 5, "raw_value": "=TEXT(NOW(), \"hh:mm:ss\")", "background": 0}))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    // The clock moves, the row of the cell stays as it was written.
+    let clock = feldera.ingress("clock");
+    assert!(clock.len() >= 2, "{clock:?}");
+    assert!(clock.iter().all(|row| row["id"] == 0));
+    assert!(clock[1]["ts"].as_str() > clock[0]["ts"].as_str());
+    let cells = feldera.ingress("spreadsheet_data");
+    assert_eq!(cells.len(), 1);
+    assert_eq!(cells[0]["ip"], "127.0.0.1");
+}